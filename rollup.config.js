@@ -25,6 +25,8 @@ export default {
                 { src: 'static/img/interactive_brokers.svg', dest: 'dist/img/' },
                 { src: 'static/img/favicon.ico', dest: 'dist/img/' },
                 { src: 'static/img/burocratin.svg', dest: 'dist/img/' },
+                { src: 'static/pwa/manifest.webmanifest', dest: 'dist/' },
+                { src: 'static/pwa/sw.js', dest: 'dist/' },
             ]
         }),
 
@@ -1,80 +1,407 @@
 use std::sync::Arc;
 
-use anyhow::Result;
-use dominator::{clone, events, html, with_node, Dom};
+use anyhow::{anyhow, Result};
+use dominator::{clone, events, html, with_node, Dom, EventOptions};
 use futures_signals::{
     map_ref,
     signal::{Mutable, Signal, SignalExt},
+    signal_vec::{MutableVec, SignalVecExt},
 };
-use gloo_file::{futures::read_as_bytes, Blob};
+use gloo_file::{futures::read_as_bytes, Blob, File};
+use num_format::Locale;
+use rust_decimal::Decimal;
 use wasm_bindgen::{JsCast, UnwrapThrowExt};
 use wasm_bindgen_futures::spawn_local;
-use web_sys::{Element, HtmlAnchorElement, HtmlElement, HtmlInputElement};
+use web_sys::{
+    Element, FileList, HtmlAnchorElement, HtmlElement, HtmlInputElement, HtmlSelectElement,
+    HtmlTextAreaElement,
+};
 
 use crate::{
-    css::{MODAL_CONTENT_STYLE, MODAL_STYLE},
-    data::{Aeat720Information, PersonalInformation},
+    bank_accounts::BankAccountViewer,
+    css::{
+        Theme, ERROR_PARAGRAPH_CLASS, MODAL_CONTENT_STYLE, MODAL_STYLE, TABLE_CAPTION,
+        TABLE_HEADER, TABLE_STYLE, THEME_DARK_CLASS, THEME_LIGHT_CLASS,
+    },
+    data::{
+        isin_list, Aeat720Diff, Aeat720Information, Aeat720Record, Aeat720Records,
+        CountryDividends, CryptoBalanceNote, DividendNote, ParseError, ParseErrors,
+        PersonalInformation, DEFAULT_LOCALE,
+    },
+    parsers::pdf::PdfPasswordError,
     personal_info::PersonalInfoViewer,
+    record_template::RecordTemplateViewer,
+    reports::{
+        aeat_720::{is_720_file, parse_720_file, LineEnding},
+        dividends::DividendsReport,
+    },
     table::Table,
-    utils::{file_importer, web},
+    utils::{
+        crypto_importer,
+        decimal::{decimal_to_str_locale, str_locale_to_decimal, valuation_to_str_locale},
+        default_year, dividends_importer, file_importer, fx, ib_html_importer,
+        icons::render_svg_spinner_icon,
+        is_crypto_statement, is_dividends_statement, is_ib_html, is_pdf, pdf_importer, strip_bom,
+        text_importer,
+        ticker::{fill_missing_isins, parse_ticker_mapping},
+        web,
+    },
 };
 
 pub struct App {
     current_error: Mutable<Option<String>>,
     personal_info: Mutable<PersonalInformation>,
+    co_holder: Mutable<Option<PersonalInformation>>,
     aeat720_form_path: Mutable<Option<String>>,
+    archive_path: Mutable<Option<String>>,
+    preview_path: Mutable<Option<String>>,
     personal_info_viewer: Arc<PersonalInfoViewer>,
+    bank_account_viewer: Arc<BankAccountViewer>,
+    record_template_viewer: Arc<RecordTemplateViewer>,
     table: Arc<Table>,
     modal_visible: Mutable<bool>,
+    loading: Mutable<bool>,
+    include_domestic: Mutable<bool>,
+    crlf_line_endings: Mutable<bool>,
+    broker_filter: Mutable<Option<String>>,
+    // Brokers of the imported records, still listed in the summary when all their rows are
+    // deleted.
+    imported_brokers: MutableVec<String>,
+    dividends: MutableVec<DividendNote>,
+    dividends_by_country: MutableVec<CountryDividends>,
+    crypto: MutableVec<CryptoBalanceNote>,
+    parse_errors: MutableVec<ParseError>,
+    // Name of each file of the last import and its error, if any.
+    import_results: MutableVec<(String, Option<String>)>,
+    pasted_text: Mutable<String>,
+    broker_country: Mutable<String>,
+    ticker_mapping: Mutable<String>,
+    report_warnings: MutableVec<String>,
+    previous_info: Mutable<Option<Aeat720Information>>,
+    // Year of the first imported report, offered as the year of the declaration.
+    suggested_year: Mutable<Option<usize>>,
+    // Years of a report imported after others from a different year and of the previous ones.
+    year_mismatch: Mutable<Option<(usize, usize)>>,
+    locale: Mutable<Locale>,
+    theme: Mutable<Theme>,
 }
 
+const THEME_SETTING_KEY: &str = "theme";
+
 impl App {
     pub fn new() -> Arc<Self> {
-        let personal_info = Mutable::new(PersonalInformation::default());
+        let personal_info = Mutable::new(PersonalInformation {
+            year: default_year(),
+            ..PersonalInformation::default()
+        });
+        let co_holder = Mutable::new(None);
+        let locale = Mutable::new(*DEFAULT_LOCALE);
+        let template = Mutable::new(RecordTemplateViewer::load_template());
+        let table = Table::new(locale.clone(), template.clone());
 
         Arc::new(Self {
             current_error: Mutable::new(None),
             personal_info: personal_info.clone(),
+            co_holder: co_holder.clone(),
             aeat720_form_path: Mutable::new(None),
-            personal_info_viewer: PersonalInfoViewer::new(personal_info.clone()),
-            table: Table::new(),
+            archive_path: Mutable::new(None),
+            preview_path: Mutable::new(None),
+            personal_info_viewer: PersonalInfoViewer::new(personal_info.clone(), co_holder),
+            bank_account_viewer: BankAccountViewer::new(
+                personal_info.clone(),
+                locale.clone(),
+                table.clone(),
+            ),
+            record_template_viewer: RecordTemplateViewer::new(template, locale.clone()),
+            table,
             modal_visible: Mutable::new(false),
+            loading: Mutable::new(false),
+            include_domestic: Mutable::new(false),
+            crlf_line_endings: Mutable::new(false),
+            broker_filter: Mutable::new(None),
+            imported_brokers: MutableVec::new(),
+            dividends: MutableVec::new(),
+            dividends_by_country: MutableVec::new(),
+            crypto: MutableVec::new(),
+            parse_errors: MutableVec::new(),
+            import_results: MutableVec::new(),
+            pasted_text: Mutable::new(String::new()),
+            broker_country: Mutable::new(String::new()),
+            ticker_mapping: Mutable::new(String::new()),
+            report_warnings: MutableVec::new(),
+            previous_info: Mutable::new(None),
+            suggested_year: Mutable::new(None),
+            year_mismatch: Mutable::new(None),
+            locale,
+            theme: Mutable::new(
+                web::load_setting(THEME_SETTING_KEY)
+                    .map_or(Theme::default(), |name| Theme::from_name(&name)),
+            ),
         })
     }
 
     fn is_needed_to_generate_report(this: &Arc<Self>) -> impl Signal<Item = bool> {
         map_ref! {
             let _personal_info_changed = this.personal_info.signal_ref(|_| true),
-            let records_changed = this.table.table_rows_not_empty() =>
-            *records_changed // || *personal_info_changed
+            let records_changed = this.table.table_rows_not_empty(),
+            let rows_valid = this.table.rows_valid_signal() =>
+            *records_changed && *rows_valid // || *personal_info_changed
         }
     }
 
-    fn import_file(this: &Arc<Self>, content: Vec<u8>) {
-        let import_data = file_importer(content);
-        match import_data {
-            Ok(records) => {
-                this.table.extend_rows(records);
+    async fn import_file(this: &Arc<Self>, content: Vec<u8>) -> Result<()> {
+        let content = strip_bom(content);
+        if is_dividends_statement(&content) {
+            let (dividends, errors) = dividends_importer(content)?;
+            this.dividends.lock_mut().extend(dividends);
+            this.parse_errors.lock_mut().extend(errors);
+            return Ok(());
+        }
+        if is_crypto_statement(&content) {
+            let (crypto, errors) = crypto_importer(content)?;
+            this.crypto.lock_mut().extend(crypto);
+            this.parse_errors.lock_mut().extend(errors);
+            return Ok(());
+        }
+        if is_pdf(&content) {
+            let mut password = None;
+            let import_data = loop {
+                match pdf_importer(&content, password.as_deref()) {
+                    Err(error) if error.downcast_ref::<PdfPasswordError>().is_some() => {
+                        match web::prompt_password(&error.to_string()) {
+                            Some(value) => password = Some(value),
+                            None => break Err(error),
+                        }
+                    }
+                    result => break result,
+                }
+            }
+            .map(|(records, errors, dividends)| {
+                this.dividends_by_country.lock_mut().extend(dividends);
+                (records, errors)
+            })?;
+            App::add_records(this, import_data);
+            return Ok(());
+        }
+        if is_ib_html(&content) {
+            let (mut records, errors) = ib_html_importer(content, |records, errors| {
+                App::add_records(this, (records, errors));
+            })
+            .await?;
+            App::prepare_records(this, &mut records);
+            this.table.complete_rows(records);
+            this.parse_errors.lock_mut().extend(errors);
+            return Ok(());
+        }
+        App::add_records(this, file_importer(content)?);
+        Ok(())
+    }
+
+    // Files are imported one after the other, an error in one doesn't stop the rest. The
+    // error modal is only shown for a single file, several ones list their result.
+    fn import_files(this: &Arc<Self>, file_list: Option<FileList>) {
+        let files: Vec<File> = file_list.map_or(vec![], |file_list| {
+            (0..file_list.length())
+                .filter_map(|i| file_list.get(i))
+                .map(File::from)
+                .collect()
+        });
+        if files.is_empty() {
+            *this.current_error.lock_mut() = Some("Error obteniendo fichero".to_string());
+            this.modal_visible.set(true);
+            return;
+        }
+        this.import_results.lock_mut().clear();
+        this.loading.set(true);
+        spawn_local(clone!(this => async move {
+            let single_file = files.len() == 1;
+            for file in files {
+                let result = match read_as_bytes(&file).await {
+                    Ok(content) => {
+                        web::yield_to_browser().await;
+                        App::import_file(&this, content).await
+                    }
+                    Err(_) => Err(anyhow!("Error leyendo fichero")),
+                };
+                let error = result.err().map(|error| {
+                    log::error!("Import of {} failed: {:?}", file.name(), error);
+                    error.to_string()
+                });
+                if single_file && error.is_some() {
+                    *this.current_error.lock_mut() = error.clone();
+                    this.modal_visible.set(true);
+                }
+                this.import_results.lock_mut().push_cloned((file.name(), error));
             }
+            this.loading.set(false);
+        }));
+    }
+
+    fn import_text(this: &Arc<Self>) {
+        let import_data = text_importer(&this.pasted_text.lock_ref());
+        if import_data.is_ok() {
+            this.pasted_text.set(String::new());
+        }
+        App::import_records(this, import_data);
+    }
+
+    fn prepare_records(this: &Arc<Self>, records: &mut [Aeat720Record]) {
+        let country_code = this.broker_country.get_cloned();
+        if country_code.len() == 2 {
+            records
+                .iter_mut()
+                .for_each(|record| record.set_broker_country(&country_code));
+        }
+        fill_missing_isins(
+            records,
+            &parse_ticker_mapping(&this.ticker_mapping.lock_ref()),
+        );
+    }
+
+    fn add_imported_brokers(this: &Arc<Self>, records: &[Aeat720Record]) {
+        let mut brokers = this.imported_brokers.lock_mut();
+        for record in records {
+            if !brokers.contains(&record.broker.name) {
+                brokers.push_cloned(record.broker.name.clone());
+            }
+        }
+    }
+
+    fn add_records(this: &Arc<Self>, (mut records, errors): (Aeat720Records, ParseErrors)) {
+        App::prepare_records(this, &mut records);
+        App::add_imported_brokers(this, &records);
+        App::check_import_year(this, &records);
+        this.table.extend_rows(records);
+        this.parse_errors.lock_mut().extend(errors);
+    }
+
+    fn import_records(this: &Arc<Self>, import_data: Result<(Aeat720Records, ParseErrors)>) {
+        match import_data {
+            Ok(import_data) => App::add_records(this, import_data),
             Err(error) => {
+                log::error!("Import failed: {:?}", error);
                 *this.current_error.lock_mut() = Some(error.to_string());
                 this.modal_visible.set(true);
             }
         }
     }
 
-    fn generate_720_file(this: &Arc<Self>) -> Result<()> {
+    // The first import proposes its year for the declaration, later ones should match it.
+    fn check_import_year(this: &Arc<Self>, records: &[Aeat720Record]) {
+        let Some(year) = records.iter().map(|record| record.year).max() else {
+            return;
+        };
+        let previous_year = this
+            .table
+            .get_records()
+            .iter()
+            .map(|record| record.year)
+            .max();
+        match previous_year {
+            None if year != this.personal_info.lock_ref().year => {
+                this.suggested_year.set(Some(year));
+            }
+            Some(previous_year) if previous_year != year => {
+                log::warn!(
+                    "Imported report of {} after others of {}",
+                    year,
+                    previous_year
+                );
+                this.year_mismatch.set(Some((year, previous_year)));
+            }
+            _ => {}
+        }
+    }
+
+    fn import_previous_session(this: &Arc<Self>, content: Vec<u8>) {
+        // Either the session saved last year or the 720 file submitted with it.
+        let previous = if is_720_file(&content) {
+            parse_720_file(&content).map(|records| Aeat720Information {
+                records,
+                ..Default::default()
+            })
+        } else {
+            serde_json::from_slice::<Aeat720Information>(&content).map_err(anyhow::Error::from)
+        };
+        match previous {
+            Ok(previous) => {
+                this.table
+                    .add_first_tx_dates(&previous.first_tx_dates, &previous.records);
+                let diff = App::aeat720_information(this).diff(&previous);
+                this.table.set_acquisition_types(&diff);
+                this.previous_info.set(Some(previous));
+            }
+            Err(error) => {
+                log::error!("Unable to read previous session: {}", error);
+                *this.current_error.lock_mut() =
+                    Some("Error leyendo la sesión del año anterior".to_string());
+                this.modal_visible.set(true);
+            }
+        }
+    }
+
+    fn aeat720_information(this: &Arc<Self>) -> Aeat720Information {
+        Aeat720Information {
+            records: this.table.get_selected_records(),
+            personal_info: this.personal_info.get_cloned(),
+            include_domestic: this.include_domestic.get(),
+            co_holder: this.co_holder.get_cloned(),
+            first_tx_dates: this.table.first_tx_dates(),
+        }
+    }
+
+    // Only the preview can be limited to one broker, the form always declares them all.
+    fn previewed_information(this: &Arc<Self>) -> Aeat720Information {
+        let info = App::aeat720_information(this);
+        match this.broker_filter.get_cloned() {
+            Some(broker) => info.filter_records(|record| record.broker.name == broker),
+            None => info,
+        }
+    }
+
+    fn line_ending(this: &Arc<Self>) -> LineEnding {
+        if this.crlf_line_endings.get() {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    // Returns the name to download the form with, a zip with one per declarant if there's
+    // a second holder.
+    fn generate_720_file(this: &Arc<Self>) -> Result<&'static str> {
         let old_path = (*this.aeat720_form_path.lock_ref()).clone();
         let old_path = old_path.map_or("".to_owned(), |x| x);
-        let path = web::generate_720(&Aeat720Information {
-            records: this.table.get_records(),
-            personal_info: this.personal_info.get_cloned(),
-        })?;
+        let info = App::aeat720_information(this);
+        let (path, file_name) = web::generate_720(&info, App::line_ending(this))?;
         if !old_path.is_empty() {
             let _ = web::delete_path(old_path);
         }
 
         *this.aeat720_form_path.lock_mut() = Some(path);
+        this.report_warnings
+            .lock_mut()
+            .replace_cloned(web::generate_720_warnings(&info)?);
+        Ok(file_name)
+    }
+
+    fn generate_archive_file(this: &Arc<Self>) -> Result<()> {
+        if let Some(old_path) = this.archive_path.replace(None) {
+            let _ = web::delete_path(old_path);
+        }
+        let path = web::generate_archive(&App::aeat720_information(this), App::line_ending(this))?;
+
+        *this.archive_path.lock_mut() = Some(path);
+        Ok(())
+    }
+
+    fn generate_preview_file(this: &Arc<Self>) -> Result<()> {
+        if let Some(old_path) = this.preview_path.replace(None) {
+            let _ = web::delete_path(old_path);
+        }
+        let path = web::generate_720_preview(&App::previewed_information(this))?;
+
+        *this.preview_path.lock_mut() = Some(path);
         Ok(())
     }
 
@@ -94,22 +421,302 @@ impl App {
           .child(
             html!("input" => HtmlInputElement, {
               .attr("id", "import_report")
-              .attr("alt", "Botón para importar ficheros de Interactive brokers o Degiro")
-              .attr("accept", "text/html,text/csv,application/pdf,application/zip,.zip,.pdf,.csv,.html")
+              .attr("alt", "Botón para importar ficheros de Interactive brokers, Degiro, eToro, XTB, MyInvestor, Revolut, Trading 212, brókers estadounidenses, Binance o Coinbase")
+              .attr("accept", "text/html,text/csv,application/pdf,application/zip,application/vnd.openxmlformats-officedocument.spreadsheetml.sheet,.zip,.pdf,.csv,.html,.xlsx")
+              .attr("type", "file")
+              .attr("multiple", "")
+              .style("display", "none")
+              .with_node!(element => {
+                .event(clone!(this => move |_: events::Change| {
+                  App::import_files(&this, element.files());
+                  element.set_value("");
+                }))
+              })
+            })
+          )
+          .child(App::render_drop_zone(this))
+        })
+    }
+
+    // Dropping has to be prevented on dragover, otherwise the browser opens the file.
+    fn render_drop_zone(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .style("margin-left", "5px")
+          .style("padding", "5px 10px")
+          .style("border", "1px dashed")
+          .text("o arrastra aquí uno o varios informes")
+          .event_with_options(&EventOptions::preventable(), |event: events::DragOver| {
+            event.prevent_default();
+          })
+          .event_with_options(&EventOptions::preventable(), clone!(this => move |event: events::Drop| {
+            event.prevent_default();
+            App::import_files(&this, event.data_transfer().and_then(|data| data.files()));
+          }))
+        })
+    }
+
+    fn render_import_results(this: &Arc<Self>) -> Dom {
+        html!("section", {
+          .visible_signal(this.import_results.signal_vec_cloned().to_signal_map(|x| x.len() > 1))
+          .child(html!("p", {
+            .text("Ficheros importados:")
+          }))
+          .child(html!("ul", {
+            .children_signal_vec(this.import_results.signal_vec_cloned().map(|(name, error)| {
+              html!("li", {
+                .apply_if(error.is_some(), |dom| dom.class(&*ERROR_PARAGRAPH_CLASS))
+                .text(&match error {
+                  Some(error) => format!("{}: {}", name, error),
+                  None => format!("{}: importado", name),
+                })
+              })
+            }))
+          }))
+        })
+    }
+
+    fn render_broker_country(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .child(html!("label", {
+            .attr("for", "broker_country")
+            .text(" País del broker (opcional): ")
+          }))
+          .child(html!("input" => HtmlInputElement, {
+            .attr("id", "broker_country")
+            .attr("type", "text")
+            .attr("size", "2")
+            .attr("maxlength", "2")
+            .attr("placeholder", "IE")
+            .attr("title", "Código de país que sustituye al detectado en los siguientes ficheros importados")
+            .prop_signal("value", this.broker_country.signal_cloned())
+            .with_node!(element => {
+              .event(clone!(this => move |_: events::Input| {
+                let country_code: String = element.value()
+                  .chars()
+                  .filter(|c| c.is_ascii_alphabetic())
+                  .map(|c| c.to_ascii_uppercase())
+                  .collect();
+                this.broker_country.set(country_code);
+              }))
+            })
+          }))
+        })
+    }
+
+    // Only needed until the rate of the year is embedded, it's used by the next imports and
+    // the values typed in dollars.
+    fn render_fx_rate(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .child_signal(this.personal_info.signal_ref(|info| info.year).dedupe().map(clone!(this => move |year| {
+            (!fx::has_embedded_rate("USD", year)).then(|| html!("span", {
+              .child(html!("label", {
+                .attr("for", "fx_rate")
+                .text(&format!(" Dólares por euro a 31/12/{}: ", year))
+              }))
+              .child(html!("input" => HtmlInputElement, {
+                .attr("id", "fx_rate")
+                .attr("type", "text")
+                .attr("size", "6")
+                .attr("placeholder", "1,0389")
+                .attr("title", "Tipo de cambio del BCE del último día del año, sin él los valores en dólares se dejan a cero")
+                .with_node!(element => {
+                  .event(clone!(this => move |_: events::Change| {
+                    let rate = str_locale_to_decimal(&element.value(), &this.locale.get()).ok();
+                    fx::set_user_rate("USD", year, rate);
+                  }))
+                })
+              }))
+            }))
+          })))
+        })
+    }
+
+    fn render_paste_import(this: &Arc<Self>) -> Dom {
+        html!("section", {
+          .child(html!("label", {
+            .attr("for", "paste_report")
+            .text("También puedes pegar aquí una tabla de posiciones copiada de la web del broker:")
+          }))
+          .child(html!("textarea" => HtmlTextAreaElement, {
+            .attr("id", "paste_report")
+            .attr("rows", "4")
+            .attr("cols", "80")
+            .prop_signal("value", this.pasted_text.signal_cloned())
+            .with_node!(element => {
+              .event(clone!(this => move |_: events::Input| {
+                this.pasted_text.set(element.value());
+              }))
+            })
+          }))
+          .child(html!("button", {
+            .attr("type", "button")
+            .attr_signal("disabled", this.pasted_text.signal_ref(|text| {
+              if text.trim().is_empty() { Some("true") } else { None }
+            }))
+            .text("Importar texto pegado")
+            .event(clone!(this => move |_: events::Click| {
+              App::import_text(&this);
+            }))
+          }))
+        })
+    }
+
+    fn render_paste_rows(this: &Arc<Self>) -> Dom {
+        let pasted_rows = Mutable::new(String::new());
+        html!("details", {
+          .child(html!("summary", {
+            .text("Pegar filas desde una hoja de cálculo")
+          }))
+          .child(html!("label", {
+            .attr("for", "paste_rows")
+            .text("Una fila por línea con las columnas Nombre, ISIN, Valor (€), Nº acciones y opcionalmente Porcentaje:")
+          }))
+          .child(html!("textarea" => HtmlTextAreaElement, {
+            .attr("id", "paste_rows")
+            .attr("rows", "4")
+            .attr("cols", "80")
+            .prop_signal("value", pasted_rows.signal_cloned())
+            .with_node!(element => {
+              .event(clone!(pasted_rows => move |_: events::Input| {
+                pasted_rows.set(element.value());
+              }))
+            })
+          }))
+          .child(html!("button", {
+            .attr("type", "button")
+            .attr_signal("disabled", pasted_rows.signal_ref(|text| {
+              if text.trim().is_empty() { Some("true") } else { None }
+            }))
+            .text("Añadir filas pegadas")
+            .event(clone!(this, pasted_rows => move |_: events::Click| {
+              let text = pasted_rows.get_cloned();
+              let errors = this.table.paste_rows(&text);
+              // Only the failed lines are kept, adding them again once fixed won't repeat the rest.
+              let failed_lines: Vec<&str> = text
+                .lines()
+                .enumerate()
+                .filter(|(index, _)| errors.iter().any(|error| error.line == index + 1))
+                .map(|(_, line)| line)
+                .collect();
+              pasted_rows.set(failed_lines.join("\n"));
+              this.parse_errors.lock_mut().extend(errors);
+            }))
+          }))
+        })
+    }
+
+    fn render_ticker_mapping(this: &Arc<Self>) -> Dom {
+        html!("details", {
+          .child(html!("summary", {
+            .text("Correspondencia de tickers sin ISIN")
+          }))
+          .child(html!("label", {
+            .attr("for", "ticker_mapping")
+            .text("Una por línea como TICKER,ISIN o TICKER:MERCADO,ISIN, se usa en las siguientes importaciones:")
+          }))
+          .child(html!("textarea" => HtmlTextAreaElement, {
+            .attr("id", "ticker_mapping")
+            .attr("rows", "3")
+            .attr("cols", "40")
+            .attr("placeholder", "ILA,US0000000000")
+            .prop_signal("value", this.ticker_mapping.signal_cloned())
+            .with_node!(element => {
+              .event(clone!(this => move |_: events::Input| {
+                this.ticker_mapping.set(element.value());
+              }))
+            })
+          }))
+        })
+    }
+
+    fn render_import_previous_button(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .child(
+            html!("button", {
+              .child(
+                html!("label", {
+                  .style("cursor", "pointer")
+                  .attr("for", "import_previous")
+                  .text("Comparar con el año anterior")
+              })
+            )})
+          )
+          .child(
+            html!("input" => HtmlInputElement, {
+              .attr("id", "import_previous")
+              .attr("alt", "Botón para importar el fichero sesion.json o el fichero del modelo 720 del año anterior")
+              .attr("accept", "application/json,text/plain,.json,.txt")
               .attr("type", "file")
               .style("display", "none")
               .with_node!(element => {
                 .event(clone!(this => move |_: events::Change| {
-                    let file_list = match element.files() {
-                    Some(file_list) => file_list,
+                  let file_data = match element.files().and_then(|file_list| file_list.get(0)) {
+                    Some(data) => data,
                     None => {
                       *this.current_error.lock_mut() = Some(
-                        "Error subiendo fichero".to_string());
+                        "Error obteniendo fichero".to_string());
                       this.modal_visible.set(true);
                       return;
                     }
                   };
-                  let file_data = match file_list.get(0) {
+                  let blob = Blob::from(file_data);
+                  spawn_local(clone!(this => async move {
+                    match read_as_bytes(&blob).await {
+                      Ok(content) => App::import_previous_session(&this, content),
+                      Err(_) => {
+                        *this.current_error.lock_mut() = Some(
+                          "Error leyendo fichero".to_string());
+                        this.modal_visible.set(true);
+                      }
+                    }
+                  }));
+                  element.set_value("");
+                }))
+              })
+            })
+          )
+        })
+    }
+
+    // Advisors upload a zip with clientes.json and a report per client, see utils::batch.
+    fn process_batch_file(this: &Arc<Self>, content: Vec<u8>) {
+        match web::generate_batch_archive(content) {
+            Ok(file_path) => {
+                let elem: Element = gloo_utils::document().create_element("a").unwrap_throw();
+                let link: HtmlAnchorElement = elem.dyn_into().unwrap_throw();
+                link.set_href(&file_path);
+                let _ = link.set_attribute("download", "burocratin-clientes.zip");
+                link.click();
+            }
+            Err(error) => {
+                *this.current_error.lock_mut() = Some(error.to_string());
+                this.modal_visible.set(true);
+            }
+        }
+    }
+
+    fn render_batch_button(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .child(
+            html!("button", {
+              .child(
+                html!("label", {
+                  .style("cursor", "pointer")
+                  .attr("for", "import_batch")
+                  .text("Asesores: generar el 720 de varios clientes")
+              })
+            )})
+          )
+          .child(
+            html!("input" => HtmlInputElement, {
+              .attr("id", "import_batch")
+              .attr("alt", "Botón para importar un ZIP con el fichero clientes.json y el informe de cada cliente")
+              .attr("accept", "application/zip,.zip")
+              .attr("type", "file")
+              .style("display", "none")
+              .with_node!(element => {
+                .event(clone!(this => move |_: events::Change| {
+                  let file_data = match element.files().and_then(|file_list| file_list.get(0)) {
                     Some(data) => data,
                     None => {
                       *this.current_error.lock_mut() = Some(
@@ -119,8 +726,20 @@ impl App {
                     }
                   };
                   let blob = Blob::from(file_data);
+                  this.loading.set(true);
                   spawn_local(clone!(this => async move {
-                    App::import_file(&this, read_as_bytes(&blob).await.unwrap());
+                    match read_as_bytes(&blob).await {
+                      Ok(content) => {
+                        web::yield_to_browser().await;
+                        App::process_batch_file(&this, content);
+                      }
+                      Err(_) => {
+                        *this.current_error.lock_mut() = Some(
+                          "Error leyendo fichero".to_string());
+                        this.modal_visible.set(true);
+                      }
+                    }
+                    this.loading.set(false);
                   }));
                   element.set_value("");
                 }))
@@ -130,6 +749,14 @@ impl App {
         })
     }
 
+    fn render_loading(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .visible_signal(this.loading.signal())
+          .child(render_svg_spinner_icon("black", "16"))
+          .text(" Procesando fichero...")
+        })
+    }
+
     fn render_clear_button(this: &Arc<Self>) -> Dom {
         html!("span", {
           .child(html!("input" => HtmlInputElement, {
@@ -138,6 +765,12 @@ impl App {
             .with_node!(_element => {
               .event(clone!(this => move |_: events::Click| {
                 this.table.clear();
+                this.imported_brokers.lock_mut().clear();
+                this.dividends.lock_mut().clear();
+                this.dividends_by_country.lock_mut().clear();
+                this.crypto.lock_mut().clear();
+                this.suggested_year.set(None);
+                this.year_mismatch.set(None);
               }))
             })
           }))
@@ -158,6 +791,270 @@ impl App {
         })
     }
 
+    fn render_merge_button(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .child(html!("input" => HtmlInputElement, {
+            .attr("type", "button")
+            .attr("value", "Fusionar filas seleccionadas")
+            .attr("title", "Suma las filas marcadas de un mismo valor y broker en una sola")
+            .with_node!(_element => {
+              .event(clone!(this => move |_: events::Click| {
+                if let Err(err_msg) = this.table.merge_selected() {
+                  *this.current_error.lock_mut() = Some(err_msg.to_string());
+                  this.modal_visible.set(true);
+                }
+              }))
+            })
+          }))
+        })
+    }
+
+    // Pasted in the broker's site to check nothing is missing, names optional as a second column.
+    fn render_copy_isins_button(this: &Arc<Self>) -> Dom {
+        let with_names = Mutable::new(false);
+        html!("span", {
+          .child(html!("input" => HtmlInputElement, {
+            .attr("type", "button")
+            .attr("value", "Copiar ISINs")
+            .attr("title", "Copia al portapapeles los ISIN de la tabla, uno por línea")
+            .with_node!(_element => {
+              .event(clone!(this, with_names => move |_: events::Click| {
+                let isins = isin_list(&this.table.get_records(), with_names.get());
+                spawn_local(clone!(this => async move {
+                  if let Err(err) = web::copy_to_clipboard(&isins).await {
+                    log::error!("Unable to copy ISINs: {}", err);
+                    *this.current_error.lock_mut() =
+                      Some("No se han podido copiar los ISIN al portapapeles".to_string());
+                    this.modal_visible.set(true);
+                  }
+                }));
+              }))
+            })
+          }))
+          .child(html!("input" => HtmlInputElement, {
+            .attr("id", "copy_isin_names")
+            .attr("type", "checkbox")
+            .with_node!(element => {
+              .event(clone!(with_names => move |_: events::Change| {
+                with_names.set(element.checked());
+              }))
+            })
+          }))
+          .child(html!("label", {
+            .attr("for", "copy_isin_names")
+            .text("con nombre")
+          }))
+        })
+    }
+
+    fn render_undo_redo_buttons(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .child(html!("input" => HtmlInputElement, {
+            .attr("type", "button")
+            .attr("value", "Deshacer")
+            .attr_signal("disabled", this.table.can_undo_signal().map(|x| (!x).then_some("true")))
+            .with_node!(_element => {
+              .event(clone!(this => move |_: events::Click| {
+                this.table.undo();
+              }))
+            })
+          }))
+          .child(html!("input" => HtmlInputElement, {
+            .attr("type", "button")
+            .attr("value", "Rehacer")
+            .attr_signal("disabled", this.table.can_redo_signal().map(|x| (!x).then_some("true")))
+            .with_node!(_element => {
+              .event(clone!(this => move |_: events::Click| {
+                this.table.redo();
+              }))
+            })
+          }))
+        })
+    }
+
+    fn render_text_filter(this: &Arc<Self>) -> Dom {
+        html!("p", {
+          .child(html!("label", {
+            .attr("for", "text_filter")
+            .text("Buscar: ")
+          }))
+          .child(html!("input" => HtmlInputElement, {
+            .attr("id", "text_filter")
+            .attr("type", "search")
+            .attr("placeholder", "Nombre o ISIN")
+            .with_node!(element => {
+              .event(clone!(this => move |_: events::Input| {
+                this.table.set_text_filter(element.value());
+              }))
+            })
+          }))
+        })
+    }
+
+    fn render_year_filter(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .child(html!("label", {
+            .attr("for", "year_filter")
+            .text("Año: ")
+          }))
+          .child(html!("select" => HtmlSelectElement, {
+            .attr("id", "year_filter")
+            .child(html!("option", {
+              .attr("value", "")
+              .text("Todos")
+            }))
+            .children_signal_vec(this.table.years_signal().to_signal_vec().map(|year| {
+              html!("option", {
+                .attr("value", &year.to_string())
+                .text(&year.to_string())
+              })
+            }))
+            .with_node!(element => {
+              .event(clone!(this => move |_: events::Change| {
+                this.table.set_year_filter(element.value().parse::<usize>().ok());
+              }))
+            })
+          }))
+        })
+    }
+
+    // Only changes how amounts are shown and typed, the 720 file always uses the AEAT format.
+    fn render_locale_selector(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .child(html!("label", {
+            .attr("for", "locale")
+            .text(" Formato numérico: ")
+          }))
+          .child(html!("select" => HtmlSelectElement, {
+            .attr("id", "locale")
+            .children([(Locale::es, "1234,56"), (Locale::en, "1234.56")].iter().map(|(locale, label)| {
+              html!("option", {
+                .attr("value", locale.name())
+                .text(label)
+              })
+            }))
+            .with_node!(element => {
+              .event(clone!(this => move |_: events::Change| {
+                if let Ok(locale) = Locale::from_name(element.value()) {
+                  this.locale.set_neq(locale);
+                }
+              }))
+            })
+          }))
+        })
+    }
+
+    fn render_theme_toggle(this: &Arc<Self>) -> Dom {
+        html!("button", {
+          .attr("type", "button")
+          .text_signal(this.theme.signal().map(|theme| match theme {
+            Theme::Light => "Modo oscuro",
+            Theme::Dark => "Modo claro",
+          }))
+          .event(clone!(this => move |_: events::Click| {
+            let theme = this.theme.get().toggle();
+            this.theme.set(theme);
+            web::save_setting(THEME_SETTING_KEY, theme.name());
+          }))
+        })
+    }
+
+    fn render_offline_notice() -> Dom {
+        html!("p", {
+          .visible_signal(web::is_offline())
+          .text("Sin conexión: puedes seguir importando informes y generando el modelo 720, ")
+          .text("todo se procesa en tu navegador.")
+        })
+    }
+
+    fn render_crlf_checkbox(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .child(html!("input" => HtmlInputElement, {
+            .attr("id", "crlf_line_endings")
+            .attr("type", "checkbox")
+            .with_node!(element => {
+              .event(clone!(this => move |_: events::Change| {
+                this.crlf_line_endings.set_neq(element.checked());
+              }))
+            })
+          }))
+          .child(html!("label", {
+            .attr("for", "crlf_line_endings")
+            .text("Fin de línea de Windows (CRLF)")
+          }))
+        })
+    }
+
+    fn render_include_domestic_checkbox(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .child(html!("input" => HtmlInputElement, {
+            .attr("id", "include_domestic")
+            .attr("type", "checkbox")
+            .with_node!(element => {
+              .event(clone!(this => move |_: events::Change| {
+                this.include_domestic.set_neq(element.checked());
+              }))
+            })
+          }))
+          .child(html!("label", {
+            .attr("for", "include_domestic")
+            .text("Incluir activos españoles en el 720")
+          }))
+        })
+    }
+
+    fn render_broker_filter(this: &Arc<Self>) -> Dom {
+        html!("span", {
+          .child(html!("label", {
+            .attr("for", "broker_filter")
+            .text(" Resumen de: ")
+          }))
+          .child(html!("select" => HtmlSelectElement, {
+            .attr("id", "broker_filter")
+            .children_signal_vec(this.table.records_signal().map(clone!(this => move |records| {
+              let brokers = Aeat720Information {
+                records,
+                ..Default::default()
+              }.summary_by_broker(&[]);
+              // The rows of the chosen broker were deleted, the preview goes back to all of them.
+              let mut selected = this.broker_filter.get_cloned();
+              if selected.as_ref().is_some_and(|selected| brokers.iter().all(|(broker, _, _)| broker != selected)) {
+                selected = None;
+                this.broker_filter.set(None);
+              }
+              let mut options = vec![html!("option", {
+                .attr("value", "")
+                .text("Todos los brokers")
+              })];
+              options.extend(brokers.into_iter().map(|(broker, _, _)| {
+                html!("option", {
+                  .attr("value", &broker)
+                  .prop("selected", selected.as_deref() == Some(broker.as_str()))
+                  .text(&broker)
+                })
+              }));
+              options
+            })).to_signal_vec())
+            .with_node!(element => {
+              .event(clone!(this => move |_: events::Change| {
+                let broker = element.value();
+                this.broker_filter.set((!broker.is_empty()).then_some(broker));
+              }))
+            })
+          }))
+          .child_signal(this.broker_filter.signal_cloned().map(|broker| {
+            broker.map(|broker| {
+              html!("p", {
+                .text(&format!(
+                  "El resumen solo mostrará los bienes de {}. El fichero del 720 incluye siempre los de todos los brokers.",
+                  broker
+                ))
+              })
+            })
+          }))
+        })
+    }
+
     fn render_download_button(this: &Arc<Self>) -> Dom {
         html!("section", {
          .child_signal(
@@ -170,12 +1067,12 @@ impl App {
                       .with_node!(_element => {
                         .event(clone!(this => move |_: events::Click| {
                           let result = App::generate_720_file(&this);
-                          if result.is_ok() {
+                          if let Ok(file_name) = result {
                             let file_path = this.aeat720_form_path.lock_ref().clone().unwrap();
                             let elem: Element = gloo_utils::document().create_element("a").unwrap_throw();
                             let link: HtmlAnchorElement = elem.dyn_into().unwrap_throw();
                             link.set_href(&file_path);
-                            let _ = link.set_attribute("download", "fichero-720.txt");
+                            let _ = link.set_attribute("download", file_name);
                             link.click();
                             /* let file_path = this.aeat720_form_path.lock_ref().clone().unwrap();
                             let _ = web_sys::window().unwrap_throw().open_with_url_and_target(&file_path, "_self"); */
@@ -196,6 +1093,451 @@ impl App {
         })
     }
 
+    fn render_preview_button(this: &Arc<Self>) -> Dom {
+        html!("button" => HtmlElement, {
+          .attr("type", "button")
+          .attr_signal("disabled", Self::is_needed_to_generate_report(this).map(|x| (!x).then_some("true")))
+          .text("Ver resumen del 720")
+          .with_node!(_element => {
+            .event(clone!(this => move |_: events::Click| {
+              match App::generate_preview_file(&this) {
+                Ok(()) => {
+                  let file_path = this.preview_path.lock_ref().clone().unwrap();
+                  let _ = gloo_utils::window().open_with_url_and_target(&file_path, "_blank");
+                }
+                Err(error) => {
+                  *this.current_error.lock_mut() = Some(error.to_string());
+                  this.modal_visible.set(true);
+                }
+              }
+            }))
+          })
+        })
+    }
+
+    fn render_download_archive_button(this: &Arc<Self>) -> Dom {
+        html!("button" => HtmlElement, {
+          .attr("type", "button")
+          .attr_signal("disabled", Self::is_needed_to_generate_report(this).map(|x| (!x).then_some("true")))
+          .text("Descargar todo (ZIP)")
+          .with_node!(_element => {
+            .event(clone!(this => move |_: events::Click| {
+              match App::generate_archive_file(&this) {
+                Ok(()) => {
+                  let file_path = this.archive_path.lock_ref().clone().unwrap();
+                  let file_name = format!("burocratin-{}.zip", this.personal_info.lock_ref().year);
+                  let elem: Element = gloo_utils::document().create_element("a").unwrap_throw();
+                  let link: HtmlAnchorElement = elem.dyn_into().unwrap_throw();
+                  link.set_href(&file_path);
+                  let _ = link.set_attribute("download", &file_name);
+                  link.click();
+                }
+                Err(error) => {
+                  *this.current_error.lock_mut() = Some(error.to_string());
+                  this.modal_visible.set(true);
+                }
+              }
+            }))
+          })
+        })
+    }
+
+    fn render_broker_summary(this: &Arc<Self>) -> Dom {
+        html!("table", {
+          .class(&*TABLE_STYLE)
+          .child(
+            html!("caption", {
+              .class(&*TABLE_CAPTION)
+              .text("Resumen por broker.")
+            })
+          )
+          .child(
+            html!("thead", {
+              .class(&*TABLE_HEADER)
+              .child(html!("tr", {
+                .children(["Broker", "Nº registros", "Valor (€)"].iter().map(|header| {
+                  html!("th", {
+                    .attr("scope", "col")
+                    .style("font-weight", "bold")
+                    .style("background-color", "var(--header-background-color)")
+                    .text(header)
+                  })
+                }))
+              }))
+            })
+          )
+          .child_signal(map_ref! {
+            let records = this.table.records_signal(),
+            let brokers = this.imported_brokers.signal_vec_cloned().to_signal_cloned(),
+            let locale = this.locale.signal() => (records.clone(), brokers.clone(), *locale)
+          }.map(|(records, brokers, locale)| {
+            let summary = Aeat720Information {
+              records,
+              ..Default::default()
+            }.summary_by_broker(&brokers);
+            Some(html!("tbody", {
+              .children(summary.iter().map(|(broker, holdings, value)| {
+                html!("tr", {
+                  .child(html!("td", { .text(broker) }))
+                  .child(html!("td", { .text(&holdings.to_string()) }))
+                  .child(html!("td", { .text(&valuation_to_str_locale(value, &locale)) }))
+                })
+              }))
+            }))
+          }))
+        })
+    }
+
+    fn render_diff_list(title: &str, items: Vec<String>) -> Dom {
+        html!("div", {
+          .visible(!items.is_empty())
+          .child(html!("p", { .text(title) }))
+          .child(html!("ul", {
+            .children(items.iter().map(|item| html!("li", { .text(item) })))
+          }))
+        })
+    }
+
+    fn render_diff(this: &Arc<Self>) -> Dom {
+        html!("section", {
+          .visible_signal(this.previous_info.signal_ref(|x| x.is_some()))
+          .child_signal(map_ref! {
+            let records = this.table.records_signal(),
+            let previous = this.previous_info.signal_cloned(),
+            let locale = this.locale.signal() => {
+              previous.as_ref().map(|previous| {
+                let diff: Aeat720Diff = Aeat720Information {
+                  records: records.clone(),
+                  ..Default::default()
+                }.diff(previous);
+                let describe = |record: &Aeat720Record| format!(
+                  "{} ({}): {} acciones, {} €",
+                  record.company.name,
+                  record.company.isin,
+                  decimal_to_str_locale(&record.quantity, locale),
+                  valuation_to_str_locale(&record.value_in_euro, locale),
+                );
+                html!("div", {
+                  .child(html!("p", {
+                    .visible(diff.is_empty())
+                    .text("No hay cambios respecto al año anterior.")
+                  }))
+                  .child(App::render_diff_list(
+                    "Nuevas posiciones (A):",
+                    diff.added.iter().map(describe).collect(),
+                  ))
+                  .child(App::render_diff_list(
+                    "Posiciones cerradas (C):",
+                    diff.removed.iter().map(describe).collect(),
+                  ))
+                  .child(App::render_diff_list(
+                    "Posiciones modificadas (M):",
+                    diff.changed.iter().map(|(old, new)| format!(
+                      "{} ({}): {} → {} acciones, {} → {} €",
+                      new.company.name,
+                      new.company.isin,
+                      decimal_to_str_locale(&old.quantity, locale),
+                      decimal_to_str_locale(&new.quantity, locale),
+                      valuation_to_str_locale(&old.value_in_euro, locale),
+                      valuation_to_str_locale(&new.value_in_euro, locale),
+                    )).collect(),
+                  ))
+                })
+              })
+            }
+          })
+        })
+    }
+
+    fn render_dividends(this: &Arc<Self>) -> Dom {
+        html!("table", {
+          .class(&*TABLE_STYLE)
+          .visible_signal(this.dividends.signal_vec_cloned().to_signal_map(|x| !x.is_empty()))
+          .child(
+            html!("caption", {
+              .class(&*TABLE_CAPTION)
+              .text("Dividendos extranjeros.")
+            })
+          )
+          .child(
+            html!("thead", {
+              .class(&*TABLE_HEADER)
+              .child(html!("tr", {
+                .children(["Fecha", "Nombre compañía", "ISIN", "Divisa", "Bruto", "Retención", "Neto"].iter().map(|header| {
+                  html!("th", {
+                    .attr("scope", "col")
+                    .style("font-weight", "bold")
+                    .style("background-color", "var(--header-background-color)")
+                    .text(header)
+                  })
+                }))
+              }))
+            })
+          )
+          .child(html!("tbody", {
+            .children_signal_vec(this.dividends.signal_vec_cloned().map(clone!(this => move |note| {
+              html!("tr", {
+                .child(html!("td", { .text(&note.date.format("%Y-%m-%d").to_string()) }))
+                .child(html!("td", { .text(&note.company.name) }))
+                .child(html!("td", { .text(&note.company.isin) }))
+                .child(html!("td", { .text(&note.currency) }))
+                .child(html!("td", { .text_signal(this.locale.signal().map(clone!(note => move |locale| decimal_to_str_locale(&note.gross, &locale)))) }))
+                .child(html!("td", { .text_signal(this.locale.signal().map(clone!(note => move |locale| decimal_to_str_locale(&note.withholding, &locale)))) }))
+                .child(html!("td", { .text_signal(this.locale.signal().map(clone!(note => move |locale| decimal_to_str_locale(&note.net(), &locale)))) }))
+              })
+            })))
+          }))
+        })
+    }
+
+    fn render_amount_cell(locale: &Mutable<Locale>, amount: Decimal) -> Dom {
+        html!("td", {
+          .text_signal(locale.signal().map(move |locale| decimal_to_str_locale(&amount, &locale)))
+        })
+    }
+
+    // Base of the deduction for international double taxation of the modelo 100.
+    fn render_dividends_by_country(this: &Arc<Self>) -> Dom {
+        let report_signal = this
+            .dividends_by_country
+            .signal_vec_cloned()
+            .to_signal_cloned()
+            .map(|dividends| DividendsReport::new(&dividends));
+        html!("table", {
+          .class(&*TABLE_STYLE)
+          .visible_signal(this.dividends_by_country.signal_vec_cloned().to_signal_map(|x| !x.is_empty()))
+          .child(
+            html!("caption", {
+              .class(&*TABLE_CAPTION)
+              .text("Dividendos extranjeros por país (deducción por doble imposición, modelo 100).")
+            })
+          )
+          .child(
+            html!("thead", {
+              .class(&*TABLE_HEADER)
+              .child(html!("tr", {
+                .children(["País", "Bruto", "Retención", "Neto", "Deducible (máx. 15%)"].iter().map(|header| {
+                  html!("th", {
+                    .attr("scope", "col")
+                    .style("font-weight", "bold")
+                    .style("background-color", "var(--header-background-color)")
+                    .text(header)
+                  })
+                }))
+              }))
+            })
+          )
+          .child(html!("tbody", {
+            .children_signal_vec(report_signal.map(clone!(this => move |report| {
+              let total = report.total();
+              report.countries().iter().chain(std::iter::once(&total)).map(|country| {
+                html!("tr", {
+                  .child(html!("td", {
+                    .text(if country.country_code.is_empty() { "Total" } else { &country.country_code })
+                  }))
+                  .child(App::render_amount_cell(&this.locale, country.gross))
+                  .child(App::render_amount_cell(&this.locale, country.withholding))
+                  .child(App::render_amount_cell(&this.locale, country.net))
+                  .child(App::render_amount_cell(&this.locale, if country.country_code.is_empty() {
+                    report.total_deductible()
+                  } else {
+                    DividendsReport::deductible(country)
+                  }))
+                })
+              }).collect()
+            })).to_signal_vec())
+          }))
+        })
+    }
+
+    // Cryptoassets abroad go to the modelo 721, they're listed apart and never
+    // written to the 720 file.
+    fn render_crypto(this: &Arc<Self>) -> Dom {
+        html!("table", {
+          .class(&*TABLE_STYLE)
+          .visible_signal(this.crypto.signal_vec_cloned().to_signal_map(|x| !x.is_empty()))
+          .child(
+            html!("caption", {
+              .class(&*TABLE_CAPTION)
+              .text("Criptoactivos (modelo 721, no se incluyen en el 720). Revisa el país de la plataforma y el valor a 31/12.")
+            })
+          )
+          .child(
+            html!("thead", {
+              .class(&*TABLE_HEADER)
+              .child(html!("tr", {
+                .children(["Criptoactivo", "Plataforma", "País", "Cantidad", "Valor en euros a 31/12"].iter().map(|header| {
+                  html!("th", {
+                    .attr("scope", "col")
+                    .style("font-weight", "bold")
+                    .style("background-color", "var(--header-background-color)")
+                    .text(header)
+                  })
+                }))
+              }))
+            })
+          )
+          .child(html!("tbody", {
+            .children_signal_vec(this.crypto.signal_vec_cloned().enumerate().map(clone!(this => move |(index, note)| {
+              let index = index.get().unwrap_or(usize::MAX);
+              html!("tr", {
+                .child(html!("td", { .text(&note.asset) }))
+                .child(html!("td", { .text(&note.platform) }))
+                .child(html!("td", {
+                  .child(html!("input" => HtmlInputElement, {
+                    .attr("type", "text")
+                    .attr("size", "2")
+                    .attr("maxlength", "2")
+                    .attr("aria-label", &format!("País de {} en {}", note.asset, note.platform))
+                    .attr("value", &note.platform_country)
+                    .with_node!(element => {
+                      .event(clone!(this, note => move |_: events::Change| {
+                        let mut note = note.clone();
+                        note.platform_country = element.value().trim().to_uppercase();
+                        this.crypto.lock_mut().set_cloned(index, note);
+                      }))
+                    })
+                  }))
+                }))
+                .child(html!("td", { .text_signal(this.locale.signal().map(clone!(note => move |locale| decimal_to_str_locale(&note.quantity, &locale)))) }))
+                .child(html!("td", {
+                  .child(html!("input" => HtmlInputElement, {
+                    .attr("type", "text")
+                    .attr("size", "12")
+                    .attr("aria-label", &format!("Valor en euros de {} a 31/12", note.asset))
+                    .attr("placeholder", "Sin precio")
+                    .prop_signal("value", this.locale.signal().map(clone!(note => move |locale| {
+                      if note.value_in_euro.is_zero() {
+                        String::new()
+                      } else {
+                        valuation_to_str_locale(&note.value_in_euro, &locale)
+                      }
+                    })))
+                    .with_node!(element => {
+                      .event(clone!(this, note => move |_: events::Change| {
+                        let mut note = note.clone();
+                        match str_locale_to_decimal(&element.value(), &this.locale.get()) {
+                          Ok(value) if !value.is_sign_negative() => {
+                            note.value_in_euro = value;
+                            this.crypto.lock_mut().set_cloned(index, note);
+                          }
+                          _ => element.set_value(""),
+                        }
+                      }))
+                    })
+                  }))
+                }))
+              })
+            })))
+          }))
+        })
+    }
+
+    fn render_parse_errors(this: &Arc<Self>) -> Dom {
+        html!("section", {
+          .visible_signal(this.parse_errors.signal_vec_cloned().to_signal_map(|x| !x.is_empty()))
+          .child(html!("p", {
+            .text("Las siguientes líneas no se han podido importar:")
+          }))
+          .child(html!("ul", {
+            .children_signal_vec(this.parse_errors.signal_vec_cloned().map(|error| {
+              html!("li", {
+                .text(&if error.line > 0 {
+                    format!("Línea {}: {}", error.line, error.reason)
+                } else {
+                    error.reason
+                })
+              })
+            }))
+          }))
+          .child(html!("input" => HtmlInputElement, {
+            .attr("type", "button")
+            .attr("value", "Cerrar")
+            .with_node!(_element => {
+              .event(clone!(this => move |_: events::Click| {
+                this.parse_errors.lock_mut().clear();
+              }))
+            })
+          }))
+        })
+    }
+
+    fn render_import_year(this: &Arc<Self>) -> Dom {
+        html!("section", {
+          .child_signal(this.suggested_year.signal().map(clone!(this => move |year| {
+            year.map(|year| {
+              html!("p", {
+                .text(&format!("Los informes importados son del año {}. ", year))
+                .child(html!("input" => HtmlInputElement, {
+                  .attr("type", "button")
+                  .attr("value", &format!("Usar {} como año de la declaración", year))
+                  .with_node!(_element => {
+                    .event(clone!(this => move |_: events::Click| {
+                      this.personal_info_viewer.set_year(year);
+                      this.suggested_year.set(None);
+                    }))
+                  })
+                }))
+                .child(html!("input" => HtmlInputElement, {
+                  .attr("type", "button")
+                  .attr("value", "Ignorar")
+                  .with_node!(_element => {
+                    .event(clone!(this => move |_: events::Click| {
+                      this.suggested_year.set(None);
+                    }))
+                  })
+                }))
+              })
+            })
+          })))
+          .child_signal(this.year_mismatch.signal().map(clone!(this => move |mismatch| {
+            mismatch.map(|(year, previous_year)| {
+              html!("p", {
+                .class(&*ERROR_PARAGRAPH_CLASS)
+                .text(&format!(
+                  "El último fichero importado es del año {} y los anteriores del {}, revisa que sean del mismo ejercicio. ",
+                  year, previous_year
+                ))
+                .child(html!("input" => HtmlInputElement, {
+                  .attr("type", "button")
+                  .attr("value", "Cerrar")
+                  .with_node!(_element => {
+                    .event(clone!(this => move |_: events::Click| {
+                      this.year_mismatch.set(None);
+                    }))
+                  })
+                }))
+              })
+            })
+          })))
+        })
+    }
+
+    fn render_report_warnings(this: &Arc<Self>) -> Dom {
+        html!("section", {
+          .visible_signal(this.report_warnings.signal_vec_cloned().to_signal_map(|x| !x.is_empty()))
+          .child(html!("p", {
+            .text("Revisa estos valores, se alejan mucho del coste de adquisición de los movimientos importados:")
+          }))
+          .child(html!("ul", {
+            .children_signal_vec(this.report_warnings.signal_vec_cloned().map(|warning| {
+              html!("li", {
+                .text(&warning)
+              })
+            }))
+          }))
+          .child(html!("input" => HtmlInputElement, {
+            .attr("type", "button")
+            .attr("value", "Cerrar")
+            .with_node!(_element => {
+              .event(clone!(this => move |_: events::Click| {
+                this.report_warnings.lock_mut().clear();
+              }))
+            })
+          }))
+        })
+    }
+
     fn render_modal_error(this: &Arc<Self>) -> Dom {
         html!("div", {
             .class(&*MODAL_STYLE)
@@ -206,36 +1548,149 @@ impl App {
             .child(html!("p", {
               .class(&*MODAL_CONTENT_STYLE)
               .text_signal(this.current_error.signal_ref(|v| v.clone().unwrap_or("".to_string())))
+              .child(html!("br"))
+              .child(html!("button", {
+                .attr("type", "button")
+                .attr("title", "Descarga el registro de la importación para adjuntarlo al reportar el problema")
+                .text("Descargar diagnóstico")
+                .event(clone!(this => move |_: events::Click| {
+                  let error = this.current_error.get_cloned().unwrap_or_default();
+                  match web::generate_diagnostics(&error) {
+                    Ok(file_path) => {
+                      let elem: Element = gloo_utils::document().create_element("a").unwrap_throw();
+                      let link: HtmlAnchorElement = elem.dyn_into().unwrap_throw();
+                      link.set_href(&file_path);
+                      let _ = link.set_attribute("download", "burocratin-diagnostico.txt");
+                      link.click();
+                    }
+                    Err(err) => log::error!("Unable to generate diagnostics: {}", err),
+                  }
+                }))
+              }))
             }))
         })
     }
     pub fn render(this: Arc<Self>) -> Dom {
         html!("div", {
+            .class_signal(&*THEME_LIGHT_CLASS, this.theme.signal().map(|theme| theme == Theme::Light))
+            .class_signal(&*THEME_DARK_CLASS, this.theme.signal().map(|theme| theme == Theme::Dark))
+            .child(App::render_theme_toggle(&this))
+            .child(App::render_offline_notice())
             .child(App::render_modal_error(&this))
             .child(html!("h2", {
                 .text("Paso 1: Rellena datos personales.")
             }))
             .child(PersonalInfoViewer::render(&this.personal_info_viewer))
+            .child(PersonalInfoViewer::render_co_holder(&this.personal_info_viewer))
+            .child(PersonalInfoViewer::render_proxy(&this.personal_info_viewer))
+            .child(PersonalInfoViewer::render_declaration(&this.personal_info_viewer))
             .child(html!("h2", {
                 .text("Paso 2: Descarga los informes de Interactive brokers y/o Degiro ")
                 .text("e importalos o añade movimientos manualmente.")
             }))
+            .child(
+               App::render_text_filter(&this)
+            )
+            .child(
+               Table::render_column_selector(&this.table)
+            )
             .child(
                Table::render(&this.table)
             )
+            .child(
+               Table::render_validation_summary(&this.table)
+            )
+            .child(
+               Table::render_same_name_notice(&this.table)
+            )
+            .child(
+                App::render_import_year(&this)
+            )
+            .child(
+                App::render_parse_errors(&this)
+            )
             .child(
                 App::render_import_button(&this)
             )
+            .child(
+                App::render_import_results(&this)
+            )
+            .child(
+                App::render_broker_country(&this)
+            )
+            .child(
+                App::render_fx_rate(&this)
+            )
+            .child(
+                App::render_paste_import(&this)
+            )
+            .child(
+                App::render_paste_rows(&this)
+            )
+            .child(
+                App::render_ticker_mapping(&this)
+            )
+            .child(
+                App::render_import_previous_button(&this)
+            )
+            .child(
+                App::render_batch_button(&this)
+            )
+            .child(
+                App::render_loading(&this)
+            )
             .child(
                 App::render_clear_button(&this)
             )
             .child(
                 App::render_insert_button(&this)
             )
+            .child(
+                App::render_merge_button(&this)
+            )
+            .child(
+                App::render_undo_redo_buttons(&this)
+            )
+            .child(
+                App::render_copy_isins_button(&this)
+            )
+            .child(
+                BankAccountViewer::render(&this.bank_account_viewer)
+            )
+            .child(
+                RecordTemplateViewer::render(&this.record_template_viewer)
+            )
+            .child(
+                App::render_year_filter(&this)
+            )
+            .child(
+                App::render_locale_selector(&this)
+            )
+            .child(
+                App::render_broker_summary(&this)
+            )
+            .child(
+                App::render_diff(&this)
+            )
+            .child(
+                App::render_dividends(&this)
+            )
+            .child(
+                App::render_dividends_by_country(&this)
+            )
+            .child(
+                App::render_crypto(&this)
+            )
             .child(html!("h2", {
                 .text("Paso 3: Revisa las fechas de 1º adquisición y los datos importados y descarga el fichero generado.")
             }))
+            .child(App::render_include_domestic_checkbox(&this))
+            .child(App::render_crlf_checkbox(&this))
+            .child(App::render_broker_filter(&this))
+            .child(App::render_preview_button(&this))
             .child(App::render_download_button(&this))
+            .child(App::render_report_warnings(&this))
+            .child(App::render_download_archive_button(&this))
             .child(html!("h2", {
                 .text("Paso 4: Finalmente importe el fichero descargado con el modelo 720 en la ")
                 .child(html!("a", {
@@ -0,0 +1,204 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use chrono::NaiveDate;
+use dominator::{clone, events, html, with_node, Dom};
+use futures_signals::signal::{Mutable, SignalExt};
+use num_format::Locale;
+use rust_decimal::Decimal;
+use web_sys::HtmlInputElement;
+
+use crate::{
+    css::{FLEX_CONTAINER_CLASS, FLEX_CONTAINER_ITEM_20_CLASS},
+    data::{validate_country_code, validate_iban, ForeignBankAccount, PersonalInformation},
+    table::Table,
+    utils::{
+        decimal::{str_locale_to_decimal, valid_str_number_with_decimals},
+        usize_to_date,
+    },
+};
+
+const DEFAULT_NUMBER_OF_DECIMALS: u16 = 2;
+const INPUT_NOT_VALID_ERR_MSG: &str = "No válido";
+const IBAN_NOT_VALID_ERR_MSG: &str = "IBAN no válido";
+const BANK_NAME_NOT_VALID_ERR_MSG: &str = "Nombre del banco no válido";
+const COUNTRY_NOT_VALID_ERR_MSG: &str = "País no válido";
+const OPENING_DATE_NOT_VALID_ERR_MSG: &str = "Indica la fecha de apertura";
+const FIELDS_NOT_VALID_ERR_MSG: &str = "Revisa los campos no válidos";
+
+type UpdateAccount = fn(&mut ForeignBankAccount, &str, &Locale) -> bool;
+
+/// Manual entry of foreign bank accounts, e.g. N26, declared as accounts in the 720.
+pub struct BankAccountViewer {
+    account: Mutable<ForeignBankAccount>,
+    invalid_inputs: Mutable<BTreeSet<&'static str>>,
+    err_msg: Mutable<Option<&'static str>>,
+    personal_info: Mutable<PersonalInformation>,
+    locale: Mutable<Locale>,
+    table: Arc<Table>,
+}
+
+impl BankAccountViewer {
+    pub fn new(
+        personal_info: Mutable<PersonalInformation>,
+        locale: Mutable<Locale>,
+        table: Arc<Table>,
+    ) -> Arc<Self> {
+        Arc::new(BankAccountViewer {
+            account: Mutable::new(ForeignBankAccount::default()),
+            invalid_inputs: Mutable::new(BTreeSet::new()),
+            err_msg: Mutable::new(None),
+            personal_info,
+            locale,
+            table,
+        })
+    }
+
+    fn parse_balance(value: &str, locale: &Locale) -> Option<Decimal> {
+        if !valid_str_number_with_decimals(value, DEFAULT_NUMBER_OF_DECIMALS, locale) {
+            return None;
+        }
+        str_locale_to_decimal(value, locale).ok()
+    }
+
+    fn check_account(account: &ForeignBankAccount) -> Option<&'static str> {
+        if !validate_iban(&account.iban) {
+            Some(IBAN_NOT_VALID_ERR_MSG)
+        } else if account.bank_name.trim().is_empty() {
+            Some(BANK_NAME_NOT_VALID_ERR_MSG)
+        } else if !validate_country_code(&account.country_code) {
+            Some(COUNTRY_NOT_VALID_ERR_MSG)
+        } else if usize_to_date(account.opening_date).is_none() {
+            Some(OPENING_DATE_NOT_VALID_ERR_MSG)
+        } else {
+            None
+        }
+    }
+
+    fn add_account(&self) {
+        if !self.invalid_inputs.lock_ref().is_empty() {
+            self.err_msg.set(Some(FIELDS_NOT_VALID_ERR_MSG));
+            return;
+        }
+        let account = self.account.get_cloned();
+        if let Some(err_msg) = Self::check_account(&account) {
+            self.err_msg.set(Some(err_msg));
+            return;
+        }
+
+        self.err_msg.set(None);
+        let year = self.personal_info.lock_ref().year;
+        log::info!("Adding foreign bank account of {}", account.bic);
+        self.table
+            .extend_rows(vec![account.to_aeat720_record(year)]);
+    }
+
+    fn render_input(
+        this: &Arc<Self>,
+        id: &'static str,
+        placeholder: &str,
+        input_type: &str,
+        update: UpdateAccount,
+    ) -> Dom {
+        html!("span", {
+            .class(&*FLEX_CONTAINER_ITEM_20_CLASS)
+            .child(html!("input" => HtmlInputElement, {
+                .attr("id", id)
+                .attr("alt", placeholder)
+                .attr("type", input_type)
+                .attr("placeholder", placeholder)
+                .attr("title", placeholder)
+                .style("height", "24px")
+                .with_node!(element => {
+                    .event(clone!(this => move |_: events::Input| {
+                        let locale = this.locale.get();
+                        let valid = update(&mut this.account.lock_mut(), element.value().trim(), &locale);
+                        let mut invalid_inputs = this.invalid_inputs.lock_mut();
+                        if valid {
+                            invalid_inputs.remove(&id);
+                        } else {
+                            invalid_inputs.insert(id);
+                        }
+                    }))
+                })
+            }))
+            .child(html!("span", {
+                .style("color", "var(--error-color)")
+                .style("font-size", "small")
+                .visible_signal(this.invalid_inputs.signal_ref(move |ids| ids.contains(&id)))
+                .text(INPUT_NOT_VALID_ERR_MSG)
+            }))
+        })
+    }
+
+    pub fn render(this: &Arc<Self>) -> Dom {
+        html!("details", {
+            .child(html!("summary", {
+                .text("Cuentas bancarias en el extranjero (N26, Wise, ...)")
+            }))
+            .child(html!("div", {
+                .class(&*FLEX_CONTAINER_CLASS)
+                .child(Self::render_input(this, "account_iban", "IBAN", "text", |account, value, _| {
+                    account.iban = value.replace(' ', "").to_uppercase();
+                    validate_iban(&account.iban)
+                }))
+                .child(Self::render_input(this, "account_bic", "BIC", "text", |account, value, _| {
+                    account.bic = value.to_uppercase();
+                    account.bic.is_empty() || matches!(account.bic.len(), 8 | 11)
+                }))
+                .child(Self::render_input(this, "account_bank", "Nombre del banco", "text", |account, value, _| {
+                    account.bank_name = value.to_string();
+                    !value.is_empty()
+                }))
+                .child(Self::render_input(this, "account_country", "País del banco", "text", |account, value, _| {
+                    account.country_code = value.to_uppercase();
+                    validate_country_code(&account.country_code)
+                }))
+            }))
+            .child(html!("div", {
+                .class(&*FLEX_CONTAINER_CLASS)
+                .child(Self::render_input(this, "account_opening_date", "Fecha de apertura", "date", |account, value, _| {
+                    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+                        Ok(date) => {
+                            account.opening_date = date.format("%Y%m%d").to_string().parse::<usize>().unwrap_or(0);
+                            true
+                        }
+                        Err(_) => false,
+                    }
+                }))
+                .child(Self::render_input(this, "account_year_end_balance", "Saldo a 31/12 (€)", "text", |account, value, locale| {
+                    match Self::parse_balance(value, locale) {
+                        Some(balance) => {
+                            account.year_end_balance = balance;
+                            true
+                        }
+                        None => false,
+                    }
+                }))
+                .child(Self::render_input(this, "account_q4_average_balance", "Saldo medio 4º trimestre (€)", "text", |account, value, locale| {
+                    match Self::parse_balance(value, locale) {
+                        Some(balance) => {
+                            account.q4_average_balance = balance;
+                            true
+                        }
+                        None => false,
+                    }
+                }))
+                .child(html!("span", {
+                    .class(&*FLEX_CONTAINER_ITEM_20_CLASS)
+                    .child(html!("input" => HtmlInputElement, {
+                        .attr("type", "button")
+                        .attr("value", "Añadir cuenta")
+                        .event(clone!(this => move |_: events::Click| {
+                            this.add_account();
+                        }))
+                    }))
+                    .child(html!("span", {
+                        .style("color", "var(--error-color)")
+                        .style("font-size", "small")
+                        .text_signal(this.err_msg.signal_ref(|t| t.unwrap_or("")))
+                    }))
+                }))
+            }))
+        })
+    }
+}
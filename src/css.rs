@@ -1,6 +1,68 @@
 use dominator::{class, pseudo};
 use std::sync::LazyLock;
 
+/// Color theme of the page, its colors are custom properties of the root element used by
+/// the rest of classes and inline styles.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+impl Theme {
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        if name == Theme::Dark.name() {
+            Theme::Dark
+        } else {
+            Theme::Light
+        }
+    }
+
+    pub fn toggle(self) -> Self {
+        match self {
+            Theme::Light => Theme::Dark,
+            Theme::Dark => Theme::Light,
+        }
+    }
+}
+
+pub static THEME_LIGHT_CLASS: LazyLock<String> = LazyLock::new(|| {
+    class! {
+        .style("--background-color", "#fff")
+        .style("--text-color", "#000")
+        .style("--header-background-color", "#ddd")
+        .style("--even-row-background-color", "#f2f2f2")
+        .style("--repeated-row-background-color", "#fff3cd")
+        .style("--modal-background-color", "#eee")
+        .style("--error-color", "red")
+        .style("background-color", "var(--background-color)")
+        .style("color", "var(--text-color)")
+    }
+});
+
+// Error text keeps a contrast ratio above 7:1 against the dark background.
+pub static THEME_DARK_CLASS: LazyLock<String> = LazyLock::new(|| {
+    class! {
+        .style("--background-color", "#121212")
+        .style("--text-color", "#e8e8e8")
+        .style("--header-background-color", "#333")
+        .style("--even-row-background-color", "#1e1e1e")
+        .style("--repeated-row-background-color", "#4a3f14")
+        .style("--modal-background-color", "#2a2a2a")
+        .style("--error-color", "#ff8a80")
+        .style("background-color", "var(--background-color)")
+        .style("color", "var(--text-color)")
+    }
+});
+
 pub static ROOT_CLASS: LazyLock<String> = LazyLock::new(|| {
     class! {
         .style("padding", "10px")
@@ -24,7 +86,7 @@ pub static MODAL_CONTENT_STYLE: LazyLock<String> = LazyLock::new(|| {
         .style("position", "relative")
         .style("top", "35%")
         .style("padding", "15px")
-        .style("background", "#eee")
+        .style("background", "var(--modal-background-color)")
         .style("border-radius", "5px")
         .style("display", "inline-block")
         .style("vertical-align", "middle")
@@ -113,11 +175,30 @@ pub static TABLE_HEADER: LazyLock<String> = LazyLock::new(|| {
     }
 });
 
+pub static ICON_BUTTON: LazyLock<String> = LazyLock::new(|| {
+    class! {
+        .style("border", "none")
+        .style("background", "none")
+        .style("padding", "0")
+        .style("cursor", "pointer")
+        .pseudo!(":focus-visible", {
+            .style("outline", "2px solid #005fcc")
+        })
+    }
+});
+
 pub static TABLE_ROW: LazyLock<String> = LazyLock::new(|| {
     class! {
         .style("font-size", "small")
         .pseudo!(":nth-child(even)", {
-            .style("background-color", "#f2f2f2")
+            .style("background-color", "var(--even-row-background-color)")
         })
     }
 });
+
+// Overrides the background of even rows too.
+pub static TABLE_ROW_REPEATED: LazyLock<String> = LazyLock::new(|| {
+    class! {
+        .style_important("background-color", "var(--repeated-row-background-color)")
+    }
+});
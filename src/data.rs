@@ -1,8 +1,9 @@
-use chrono::NaiveDate;
+use chrono::{Datelike, NaiveDate};
 use num_format::Locale;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, BTreeSet},
     convert::From,
     sync::{Arc, LazyLock},
 };
@@ -10,8 +11,13 @@ use std::{
 pub type AccountNotes = Vec<AccountNote>;
 pub type BalanceNotes = Vec<BalanceNote>;
 pub type Aeat720Records = Vec<Aeat720Record>;
+pub type DividendNotes = Vec<DividendNote>;
+pub type CryptoBalanceNotes = Vec<CryptoBalanceNote>;
+pub type ParseErrors = Vec<ParseError>;
 
 pub const DEFAULT_YEAR: usize = 2024;
+/// First acquisition date of holdings without purchases in the imported transactions.
+pub const DEFAULT_FIRST_TX_DATE: usize = DEFAULT_YEAR * 10000 + 101;
 pub const SPAIN_COUNTRY_CODE: &str = "ES";
 pub const DEFAULT_LOCALE: &Locale = &Locale::es;
 pub const DEFAULT_NUMBER_OF_DECIMALS: u16 = 2;
@@ -33,19 +39,193 @@ impl From<&str> for BrokerOperation {
     fn from(item: &str) -> Self {
         let c = item.chars().next().unwrap();
         match c {
-            'V' | 'v' => BrokerOperation::Sell,
-            'C' | 'c' => BrokerOperation::Buy,
+            'V' | 'v' | 'S' | 's' => BrokerOperation::Sell,
+            'C' | 'c' | 'B' | 'b' => BrokerOperation::Buy,
             _ => unimplemented!("no other broker operations supported"),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub enum ProductType {
+    #[default]
+    Stock,
+    Fund,
+    Bond,
+    /// Cash in a bank account, identified by its IBAN instead of an ISIN.
+    Account,
+}
+
+impl ProductType {
+    pub const ALL: [ProductType; 4] = [
+        ProductType::Stock,
+        ProductType::Fund,
+        ProductType::Bond,
+        ProductType::Account,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProductType::Stock => "Acción",
+            ProductType::Fund => "ETF/Fondo",
+            ProductType::Bond => "Bono",
+            ProductType::Account => "Cuenta",
+        }
+    }
+}
+
+impl From<&str> for ProductType {
+    fn from(item: &str) -> Self {
+        match item {
+            "ETF" | "Fund" | "Fondo" | "ETF/Fondo" => ProductType::Fund,
+            "Bond" | "Bono" => ProductType::Bond,
+            "Account" | "Cuenta" => ProductType::Account,
+            _ => ProductType::Stock,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub enum AcquisitionType {
+    /// Acquired during the year or declared for the first time.
+    #[default]
+    First,
+    /// Already declared in a previous 720.
+    Existing,
+    /// Sold or extinguished during the year.
+    Disposal,
+}
+
+impl AcquisitionType {
+    pub const ALL: [AcquisitionType; 3] = [
+        AcquisitionType::First,
+        AcquisitionType::Existing,
+        AcquisitionType::Disposal,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AcquisitionType::First => "A (alta)",
+            AcquisitionType::Existing => "M (ya declarado)",
+            AcquisitionType::Disposal => "C (extinguido)",
+        }
+    }
+}
+
+impl From<&str> for AcquisitionType {
+    fn from(item: &str) -> Self {
+        match item.chars().next() {
+            Some('M') | Some('m') => AcquisitionType::Existing,
+            Some('C') | Some('c') => AcquisitionType::Disposal,
+            _ => AcquisitionType::First,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct CompanyInfo {
     pub name: String,
     pub isin: String,
 }
 
+impl CompanyInfo {
+    pub fn new(name: String, isin: String) -> Self {
+        if !validate_isin(&isin) {
+            log::warn!("Company {:?} has a not valid ISIN: {:?}", name, isin);
+        }
+
+        Self { name, isin }
+    }
+
+    /// Case-insensitive search by name or ISIN, an empty text matches every company.
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.trim().to_lowercase();
+
+        text.is_empty()
+            || self.name.to_lowercase().contains(&text)
+            || self.isin.to_lowercase().contains(&text)
+    }
+}
+
+/// Officially assigned ISO 3166-1 alpha-2 country codes, sorted.
+const ISO_3166_ALPHA_2_CODES: [&str; 249] = [
+    "AD", "AE", "AF", "AG", "AI", "AL", "AM", "AO", "AQ", "AR", "AS", "AT", "AU", "AW", "AX", "AZ",
+    "BA", "BB", "BD", "BE", "BF", "BG", "BH", "BI", "BJ", "BL", "BM", "BN", "BO", "BQ", "BR", "BS",
+    "BT", "BV", "BW", "BY", "BZ", "CA", "CC", "CD", "CF", "CG", "CH", "CI", "CK", "CL", "CM", "CN",
+    "CO", "CR", "CU", "CV", "CW", "CX", "CY", "CZ", "DE", "DJ", "DK", "DM", "DO", "DZ", "EC", "EE",
+    "EG", "EH", "ER", "ES", "ET", "FI", "FJ", "FK", "FM", "FO", "FR", "GA", "GB", "GD", "GE", "GF",
+    "GG", "GH", "GI", "GL", "GM", "GN", "GP", "GQ", "GR", "GS", "GT", "GU", "GW", "GY", "HK", "HM",
+    "HN", "HR", "HT", "HU", "ID", "IE", "IL", "IM", "IN", "IO", "IQ", "IR", "IS", "IT", "JE", "JM",
+    "JO", "JP", "KE", "KG", "KH", "KI", "KM", "KN", "KP", "KR", "KW", "KY", "KZ", "LA", "LB", "LC",
+    "LI", "LK", "LR", "LS", "LT", "LU", "LV", "LY", "MA", "MC", "MD", "ME", "MF", "MG", "MH", "MK",
+    "ML", "MM", "MN", "MO", "MP", "MQ", "MR", "MS", "MT", "MU", "MV", "MW", "MX", "MY", "MZ", "NA",
+    "NC", "NE", "NF", "NG", "NI", "NL", "NO", "NP", "NR", "NU", "NZ", "OM", "PA", "PE", "PF", "PG",
+    "PH", "PK", "PL", "PM", "PN", "PR", "PS", "PT", "PW", "PY", "QA", "RE", "RO", "RS", "RU", "RW",
+    "SA", "SB", "SC", "SD", "SE", "SG", "SH", "SI", "SJ", "SK", "SL", "SM", "SN", "SO", "SR", "SS",
+    "ST", "SV", "SX", "SY", "SZ", "TC", "TD", "TF", "TG", "TH", "TJ", "TK", "TL", "TM", "TN", "TO",
+    "TR", "TT", "TV", "TW", "TZ", "UA", "UG", "UM", "US", "UY", "UZ", "VA", "VC", "VE", "VG", "VI",
+    "VN", "VU", "WF", "WS", "YE", "YT", "ZA", "ZM", "ZW",
+];
+
+/// Country codes written to the 720 must be ISO 3166-1 alpha-2 ones.
+pub fn validate_country_code(code: &str) -> bool {
+    ISO_3166_ALPHA_2_CODES.binary_search(&code).is_ok()
+}
+
+/// Checks ISIN structure and its Luhn mod-10 check digit.
+pub fn validate_isin(isin: &str) -> bool {
+    let bytes = isin.as_bytes();
+    if bytes.len() != 12
+        || !bytes[0..2].iter().all(u8::is_ascii_uppercase)
+        || !bytes[2..11]
+            .iter()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        || !bytes[11].is_ascii_digit()
+    {
+        return false;
+    }
+
+    // Letters are expanded to two digits (A = 10 ... Z = 35) before applying Luhn.
+    let digits: Vec<u32> = isin
+        .chars()
+        .flat_map(|c| {
+            let value = c.to_digit(36).unwrap_or(0);
+            if value < 10 {
+                vec![value]
+            } else {
+                vec![value / 10, value % 10]
+            }
+        })
+        .collect();
+
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| {
+            if i % 2 == 1 {
+                let doubled = d * 2;
+                doubled / 10 + doubled % 10
+            } else {
+                d
+            }
+        })
+        .sum();
+
+    sum % 10 == 0
+}
+
+/// ISIN of a security identified by its CUSIP, as US brokers report them. The ISIN is the
+/// country code, the CUSIP and a check digit.
+pub fn cusip_to_isin(cusip: &str, country: &str) -> String {
+    let base = format!("{}{}", country, cusip.trim()).to_uppercase();
+
+    (0..10)
+        .map(|digit| format!("{}{}", base, digit))
+        .find(|isin| validate_isin(isin))
+        .unwrap_or(base)
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
 pub struct AccountNote {
     pub date: NaiveDate,
@@ -92,6 +272,9 @@ pub struct BalanceNote {
     pub price: Decimal,
     pub value_in_euro: Decimal,
     pub broker: Arc<BrokerInformation>,
+    pub product_type: ProductType,
+    /// Computed from transactions, its year-end value must be entered by the user.
+    pub derived: bool,
 }
 
 impl BalanceNote {
@@ -112,8 +295,545 @@ impl BalanceNote {
             price,
             value_in_euro,
             broker: Arc::clone(broker),
+            product_type: ProductType::default(),
+            derived: false,
+        }
+    }
+
+    pub fn with_product_type(mut self, product_type: ProductType) -> Self {
+        self.product_type = product_type;
+        self
+    }
+}
+
+/// Year-end cash held in a foreign bank account.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct BankAccountRecord {
+    pub bank: Arc<BrokerInformation>,
+    /// IBAN if the report includes it, empty otherwise.
+    pub account: String,
+    pub balance: Decimal,
+}
+
+impl BankAccountRecord {
+    pub fn new(bank: &Arc<BrokerInformation>, account: String, balance: Decimal) -> Self {
+        Self {
+            bank: Arc::clone(bank),
+            account,
+            balance,
+        }
+    }
+
+    /// Accounts are imported as holdings of the bank keyed by the IBAN.
+    pub fn to_balance_note(&self) -> BalanceNote {
+        BalanceNote::new(
+            CompanyInfo {
+                name: self.bank.name.to_uppercase(),
+                isin: self.account.clone(),
+            },
+            String::from(""),
+            Decimal::ZERO,
+            String::from("EUR"),
+            Decimal::ZERO,
+            self.balance,
+            &self.bank,
+        )
+        .with_product_type(ProductType::Account)
+    }
+}
+
+/// Foreign bank account entered by hand, e.g. N26, declared as an account (type C) in the 720.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ForeignBankAccount {
+    pub iban: String,
+    pub bic: String,
+    pub bank_name: String,
+    pub country_code: String,
+    /// Opening date as yyyymmdd.
+    pub opening_date: usize,
+    /// Balance at 31 December.
+    pub year_end_balance: Decimal,
+    /// Average balance of the last quarter, the 720 declares it along with the year-end one.
+    pub q4_average_balance: Decimal,
+}
+
+impl ForeignBankAccount {
+    pub fn to_aeat720_record(&self, year: usize) -> Aeat720Record {
+        let acquisition_type = if self.opening_date / 10000 == year {
+            AcquisitionType::First
+        } else {
+            AcquisitionType::Existing
+        };
+
+        Aeat720Record {
+            company: CompanyInfo {
+                name: self.bank_name.to_uppercase(),
+                isin: self.iban.clone(),
+            },
+            quantity: Decimal::ZERO,
+            value_in_euro: self.year_end_balance,
+            first_tx_date: self.opening_date,
+            broker: Arc::new(BrokerInformation::new(
+                self.bank_name.clone(),
+                self.country_code.clone(),
+            )),
+            percentage: Decimal::ONE_HUNDRED,
+            year,
+            product_type: ProductType::Account,
+            acquisition_type,
+            extinction_date: 0,
+            derived: false,
+            co_holder_percentage: Decimal::ZERO,
+            acquisition_in_euro: None,
+            account_bic: self.bic.clone(),
+            q4_average_in_euro: Some(self.q4_average_balance),
+            valuation_in_euro: None,
+            original_value: None,
+            issuer_country_code: String::new(),
+        }
+    }
+}
+
+/// Checks IBAN structure and its ISO 7064 mod-97 check digits, spaces are ignored.
+pub fn validate_iban(iban: &str) -> bool {
+    let iban: String = iban.chars().filter(|c| !c.is_whitespace()).collect();
+    let bytes = iban.as_bytes();
+    if !(15..=34).contains(&bytes.len())
+        || !bytes[0..2].iter().all(u8::is_ascii_uppercase)
+        || !bytes[2..4].iter().all(u8::is_ascii_digit)
+        || !bytes[4..]
+            .iter()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+    {
+        return false;
+    }
+
+    // The country and check digits are moved to the end and letters expanded to A = 10 ... Z = 35.
+    iban[4..]
+        .chars()
+        .chain(iban[0..4].chars())
+        .fold(0, |remainder, c| {
+            let value = c.to_digit(36).unwrap_or(0);
+            let shift = if value < 10 { 10 } else { 100 };
+            (remainder * shift + value) % 97
+        })
+        == 1
+}
+
+const NIF_CONTROL_LETTERS: &[u8] = b"TRWAGMYFPDXBNJZSQVHLCKE";
+const CIF_CONTROL_LETTERS: &[u8] = b"JABCDEFGHI";
+
+/// Checks the control character of a Spanish NIF: DNI, NIE or the one of an entity (CIF).
+pub fn validate_nif(nif: &str) -> bool {
+    let bytes = nif.as_bytes();
+    if bytes.len() != 9 || !bytes[1..8].iter().all(u8::is_ascii_digit) {
+        return false;
+    }
+    let number: usize = nif[1..8].parse().unwrap_or(0);
+    let control = bytes[8];
+    let letter_matches = |number: usize| NIF_CONTROL_LETTERS[number % 23] == control;
+
+    match bytes[0] {
+        b'0'..=b'9' => letter_matches(usize::from(bytes[0] - b'0') * 10_000_000 + number),
+        // The leading letter of a NIE stands for a digit, K, L and M are people without DNI.
+        b'X' | b'K' | b'L' | b'M' => letter_matches(number),
+        b'Y' => letter_matches(10_000_000 + number),
+        b'Z' => letter_matches(20_000_000 + number),
+        b'A'..=b'W' => {
+            let sum: u8 = bytes[1..8]
+                .iter()
+                .enumerate()
+                .map(|(i, c)| {
+                    let digit = c - b'0';
+                    if i % 2 == 0 {
+                        (digit * 2) / 10 + (digit * 2) % 10
+                    } else {
+                        digit
+                    }
+                })
+                .sum();
+            let check = (10 - sum % 10) % 10;
+            control == b'0' + check || control == CIF_CONTROL_LETTERS[usize::from(check)]
+        }
+        _ => false,
+    }
+}
+
+/// Identifier of a 720 declaration: the model number, 720, followed by 10 digits.
+pub fn validate_declaration_id(id: &str) -> bool {
+    id.len() == 13 && id.starts_with("720") && id.bytes().all(|c| c.is_ascii_digit())
+}
+
+/// Spanish phone number as the 9 digits the 720 expects, without separators nor +34 prefix.
+pub fn normalize_phone(phone: &str) -> Option<String> {
+    let digits: String = phone.chars().filter(char::is_ascii_digit).collect();
+    let digits = match digits.len() {
+        11 if digits.starts_with("34") => &digits[2..],
+        13 if digits.starts_with("0034") => &digits[4..],
+        _ => &digits,
+    };
+
+    (digits.len() == 9).then(|| digits.to_string())
+}
+
+/// Holdings netting buys minus sells per company, for reports without a positions snapshot.
+/// The year-end value is unknown so it's left at zero and the notes are marked as derived.
+pub fn derive_balances_from_transactions(account_notes: &[AccountNote]) -> BalanceNotes {
+    let mut result: BalanceNotes = vec![];
+
+    for note in account_notes {
+        let quantity = match note.operation {
+            BrokerOperation::Buy => note.quantity,
+            BrokerOperation::Sell => -note.quantity,
+        };
+        match result
+            .iter_mut()
+            .find(|balance| balance.company == note.company)
+        {
+            Some(balance) => {
+                balance.quantity += quantity;
+                balance.price = note.price;
+            }
+            None => {
+                let mut balance = BalanceNote::new(
+                    note.company.clone(),
+                    String::from(""),
+                    quantity,
+                    String::from(""),
+                    note.price,
+                    Decimal::ZERO,
+                    &note.broker,
+                );
+                balance.derived = true;
+                result.push(balance);
+            }
+        }
+    }
+    result.retain(|balance| balance.quantity > Decimal::ZERO);
+
+    result
+}
+
+/// One note per ISIN and broker, summing quantities and values of the positions a broker
+/// lists more than once, e.g. IB currency blocks. The 720 declares each security once per
+/// custodian, so holdings of other brokers, even in the same country, stay apart. Notes
+/// without a valid ISIN aren't the same security and are left as they are. The price of
+/// merged lots is their average, in euros when they were quoted in different currencies.
+pub fn aggregate_by_isin(balance_notes: BalanceNotes) -> BalanceNotes {
+    let mut result: BalanceNotes = vec![];
+
+    for note in balance_notes {
+        if !validate_isin(&note.company.isin) {
+            result.push(note);
+            continue;
+        }
+        match result.iter_mut().find(|balance| {
+            balance.company.isin == note.company.isin
+                && balance.broker.name == note.broker.name
+                && balance.broker.country_code == note.broker.country_code
+        }) {
+            Some(balance) => {
+                log::info!(
+                    "Aggregating repeated position of {:?} ({})",
+                    note.company.name,
+                    note.company.isin
+                );
+                let quantity = balance.quantity + note.quantity;
+                if !quantity.is_zero() {
+                    balance.price = if balance.currency == note.currency {
+                        (balance.price * balance.quantity + note.price * note.quantity) / quantity
+                    } else {
+                        balance.currency = String::from("EUR");
+                        (balance.value_in_euro + note.value_in_euro) / quantity
+                    }
+                    .round_dp(4);
+                }
+                balance.quantity = quantity;
+                balance.value_in_euro += note.value_in_euro;
+                balance.derived |= note.derived;
+            }
+            None => result.push(note),
+        }
+    }
+
+    result
+}
+
+/// Lots of the same holding merged by hand in one record: quantities, values and costs are
+/// summed and the earliest first acquisition date kept. None when the records don't share
+/// ISIN, broker, year and owned percentages, or there's nothing to merge.
+pub fn merge_records(records: &[Aeat720Record]) -> Option<Aeat720Record> {
+    let (first, rest) = records.split_first()?;
+    if rest.is_empty()
+        || rest.iter().any(|record| {
+            record.company.isin != first.company.isin
+                || record.broker != first.broker
+                || record.year != first.year
+                || record.percentage != first.percentage
+                || record.co_holder_percentage != first.co_holder_percentage
+        })
+    {
+        return None;
+    }
+
+    let mut result = first.clone();
+    for record in rest {
+        result.quantity += record.quantity;
+        result.value_in_euro += record.value_in_euro;
+        result.first_tx_date = result.first_tx_date.min(record.first_tx_date);
+        result.derived |= record.derived;
+        result.acquisition_in_euro = result
+            .acquisition_in_euro
+            .zip(record.acquisition_in_euro)
+            .map(|(cost, other)| cost + other);
+        result.valuation_in_euro = result
+            .valuation_in_euro
+            .zip(record.valuation_in_euro)
+            .map(|(valuation, other)| valuation + other);
+        result.original_value = result
+            .original_value
+            .zip(record.original_value.as_ref())
+            .filter(|(value, other)| value.currency == other.currency)
+            .map(|(value, other)| OriginalValue {
+                value: value.value + other.value,
+                ..value
+            });
+    }
+
+    Some(result)
+}
+
+/// ISINs of the securities without repetitions, one per line in the order of the records, to
+/// check them against the broker. Names follow a tab when asked, so they paste as a column.
+pub fn isin_list(records: &[Aeat720Record], with_names: bool) -> String {
+    let mut isins: Vec<&str> = vec![];
+    let mut lines: Vec<String> = vec![];
+    for record in records {
+        let isin = record.company.isin.as_str();
+        if record.product_type == ProductType::Account || isin.is_empty() || isins.contains(&isin) {
+            continue;
+        }
+        isins.push(isin);
+        lines.push(if with_names {
+            format!("{}\t{}", isin, record.company.name)
+        } else {
+            isin.to_string()
+        });
+    }
+
+    lines.join("\n")
+}
+
+/// ISINs in more than one row of the same year, lots to merge or kept apart on purpose.
+pub fn repeated_isins(records: &[Aeat720Record]) -> BTreeSet<(String, usize)> {
+    let mut seen = BTreeSet::new();
+    let mut repeated = BTreeSet::new();
+    for record in records {
+        if record.product_type == ProductType::Account || record.company.isin.is_empty() {
+            continue;
+        }
+        let key = (record.company.isin.clone(), record.year);
+        if !seen.insert(key.clone()) {
+            repeated.insert(key);
+        }
+    }
+
+    repeated
+}
+
+/// Company names held with more than one ISIN, e.g. the ADR and the ordinary shares of the
+/// same company. They're different securities and the 720 declares each of them apart.
+pub fn names_with_several_isins(records: &[Aeat720Record]) -> Vec<(String, Vec<String>)> {
+    let mut names: BTreeMap<String, (String, Vec<String>)> = BTreeMap::new();
+    for record in records {
+        if record.product_type == ProductType::Account || record.company.isin.is_empty() {
+            continue;
+        }
+        let key: String = record
+            .company
+            .name
+            .to_lowercase()
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .collect();
+        let (_, isins) = names
+            .entry(key)
+            .or_insert_with(|| (record.company.name.clone(), vec![]));
+        if !isins.contains(&record.company.isin) {
+            isins.push(record.company.isin.clone());
+        }
+    }
+
+    names
+        .into_values()
+        .filter(|(_, isins)| isins.len() > 1)
+        .collect()
+}
+
+/// Keeps the earliest first acquisition date per ISIN, records without purchases in their
+/// report have the default date and don't count.
+pub fn merge_first_tx_dates(dates: &mut BTreeMap<String, usize>, records: &[Aeat720Record]) {
+    for record in records {
+        if record.company.isin.is_empty() || record.first_tx_date == DEFAULT_FIRST_TX_DATE {
+            continue;
+        }
+        dates
+            .entry(record.company.isin.clone())
+            .and_modify(|date| *date = (*date).min(record.first_tx_date))
+            .or_insert(record.first_tx_date);
+    }
+}
+
+/// Uses the earliest known purchase of each holding, which may come from the transactions
+/// of a previous year.
+pub fn apply_first_tx_dates(records: &mut [Aeat720Record], dates: &BTreeMap<String, usize>) {
+    for record in records {
+        if let Some(date) = dates.get(&record.company.isin) {
+            if record.first_tx_date == DEFAULT_FIRST_TX_DATE || *date < record.first_tx_date {
+                record.first_tx_date = *date;
+            }
+        }
+    }
+}
+
+/// Date of the earliest transaction of the company.
+pub fn first_transaction_date(
+    account_notes: &[AccountNote],
+    company: &CompanyInfo,
+) -> Option<NaiveDate> {
+    account_notes
+        .iter()
+        .filter(|note| note.company == *company)
+        .map(|note| note.date)
+        .min()
+}
+
+/// Holdings first traded during the declared year are new (A), otherwise they're assumed
+/// to come from a previous one (M).
+pub fn infer_acquisition_type(
+    account_notes: &[AccountNote],
+    company: &CompanyInfo,
+    year: usize,
+) -> AcquisitionType {
+    match first_transaction_date(account_notes, company) {
+        Some(date) if date.year() as usize == year => AcquisitionType::First,
+        _ => AcquisitionType::Existing,
+    }
+}
+
+/// Cost of the balance quantity at the average purchase price, in the currency of the
+/// transactions, None without purchases of the company.
+pub fn acquisition_cost(account_notes: &[AccountNote], balance: &BalanceNote) -> Option<Decimal> {
+    let (quantity, value) = account_notes
+        .iter()
+        .filter(|note| note.company == balance.company && note.operation == BrokerOperation::Buy)
+        .fold((Decimal::ZERO, Decimal::ZERO), |(quantity, value), note| {
+            (quantity + note.quantity, value + note.value)
+        });
+
+    if quantity.is_zero() {
+        None
+    } else {
+        Some(value / quantity * balance.quantity)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct DividendNote {
+    pub date: NaiveDate,
+    pub company: CompanyInfo,
+    pub currency: String,
+    pub gross: Decimal,
+    pub withholding: Decimal,
+    pub broker: Arc<BrokerInformation>,
+}
+
+impl DividendNote {
+    pub fn new(
+        date: NaiveDate,
+        company: CompanyInfo,
+        currency: String,
+        gross: Decimal,
+        withholding: Decimal,
+        broker: &Arc<BrokerInformation>,
+    ) -> DividendNote {
+        DividendNote {
+            date,
+            company,
+            currency,
+            gross,
+            withholding,
+            broker: Arc::clone(broker),
         }
     }
+
+    pub fn net(&self) -> Decimal {
+        self.gross - self.withholding
+    }
+}
+
+/// Dividends received from a country in the year, as totalled by the broker in its report.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct CountryDividends {
+    pub country_code: String,
+    pub gross: Decimal,
+    pub withholding: Decimal,
+    pub net: Decimal,
+}
+
+impl CountryDividends {
+    pub fn new(country_code: String, gross: Decimal, withholding: Decimal, net: Decimal) -> Self {
+        CountryDividends {
+            country_code,
+            gross,
+            withholding,
+            net,
+        }
+    }
+}
+
+/// Year-end holding of a cryptoasset in an exchange, declared in the modelo 721
+/// instead of the 720.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct CryptoBalanceNote {
+    pub asset: String,
+    pub quantity: Decimal,
+    pub value_in_euro: Decimal,
+    pub platform: String,
+    pub platform_country: String,
+}
+
+impl CryptoBalanceNote {
+    pub fn new(
+        asset: String,
+        quantity: Decimal,
+        value_in_euro: Decimal,
+        platform: String,
+        platform_country: String,
+    ) -> CryptoBalanceNote {
+        CryptoBalanceNote {
+            asset,
+            quantity,
+            value_in_euro,
+            platform,
+            platform_country,
+        }
+    }
+}
+
+/// A record skipped while importing a broker report.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ParseError {
+    /// Line of the report, 0 when the record isn't tied to a line.
+    pub line: usize,
+    pub reason: String,
+}
+
+impl ParseError {
+    pub fn new(line: usize, reason: String) -> Self {
+        log::warn!("Skipping line {}: {:?}", line, reason);
+        Self { line, reason }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Deserialize, Serialize)]
@@ -129,6 +849,11 @@ impl BrokerInformation {
             country_code: cc,
         }
     }
+
+    /// Spanish custodians aren't declared in the 720, which only covers assets abroad.
+    pub fn is_domestic(&self) -> bool {
+        self.country_code == SPAIN_COUNTRY_CODE
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
@@ -139,6 +864,70 @@ pub struct Aeat720Record {
     pub first_tx_date: usize,
     pub broker: Arc<BrokerInformation>,
     pub percentage: Decimal,
+    pub year: usize,
+    pub product_type: ProductType,
+    pub acquisition_type: AcquisitionType,
+    pub extinction_date: usize,
+    pub derived: bool,
+    /// Owned percentage of the second holder in joint declarations.
+    pub co_holder_percentage: Decimal,
+    /// Cost of the position from the imported purchases, unknown for manual rows.
+    #[serde(default)]
+    pub acquisition_in_euro: Option<Decimal>,
+    /// BIC of the bank holding an account, empty if unknown.
+    #[serde(default)]
+    pub account_bic: String,
+    /// Average balance of an account in the last quarter, the year-end one is used without it.
+    #[serde(default)]
+    pub q4_average_in_euro: Option<Decimal>,
+    /// Second valuation of a security when it has to differ from the first one, not declared
+    /// without it.
+    #[serde(default)]
+    pub valuation_in_euro: Option<Decimal>,
+    /// Value typed in the currency of the statement, `value_in_euro` is converted from it.
+    #[serde(default)]
+    pub original_value: Option<OriginalValue>,
+    /// Country of the issuer of securities whose ISIN prefix isn't one, like the XS of
+    /// Eurobonds or the EU of European Union bonds. Empty to take it from the ISIN.
+    #[serde(default)]
+    pub issuer_country_code: String,
+}
+
+/// Year-end value in a currency other than the euro.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct OriginalValue {
+    pub currency: String,
+    pub value: Decimal,
+}
+
+impl Aeat720Record {
+    /// Assets held by a Spanish custodian or issued in Spain aren't declared in the 720.
+    pub fn is_domestic(&self) -> bool {
+        self.broker.is_domestic() || self.company.isin.starts_with(SPAIN_COUNTRY_CODE)
+    }
+
+    /// Country of the entity declared in the 720: the bank of accounts and the issuer of
+    /// securities, the ISIN prefix unless it isn't a country.
+    pub fn entity_country_code(&self) -> &str {
+        if self.product_type == ProductType::Account {
+            return &self.broker.country_code;
+        }
+        let isin_country_code = self.company.isin.get(0..2).unwrap_or_default();
+        if validate_country_code(isin_country_code) || self.issuer_country_code.is_empty() {
+            isin_country_code
+        } else {
+            &self.issuer_country_code
+        }
+    }
+
+    /// Keeps the broker name but declares it in another country, for accounts held with
+    /// a different entity than the one assumed by the parser.
+    pub fn set_broker_country(&mut self, country_code: &str) {
+        self.broker = Arc::new(BrokerInformation::new(
+            self.broker.name.clone(),
+            country_code.to_string(),
+        ));
+    }
 }
 
 #[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
@@ -148,16 +937,1024 @@ pub struct PersonalInformation {
     pub nif: String,
     pub year: usize,
     pub phone: String,
+    /// Person to contact about the declaration, the declarant when empty.
+    #[serde(default)]
+    pub contact_name: String,
+    /// NIF of the representative filing on behalf of the declarant, e.g. an advisor.
+    #[serde(default)]
+    pub proxy_nif: Option<String>,
+    /// Identifier of the declaration, the default one of the year when empty.
+    #[serde(default)]
+    pub declaration_id: String,
+    #[serde(default)]
+    pub declaration_type: DeclarationType,
+    /// Identifier of the declaration completed or replaced by this one.
+    #[serde(default)]
+    pub previous_declaration_id: String,
+}
+
+impl PersonalInformation {
+    /// The one given by the user or `720`, the year and a sequence number of 6 digits, which
+    /// keeps the declarations of different years apart.
+    pub fn declaration_id(&self) -> String {
+        let id = self.declaration_id.trim();
+        if id.is_empty() {
+            format!("720{:04}{:06}", self.year, 1)
+        } else {
+            id.to_string()
+        }
+    }
+}
+
+/// Complementary declarations add what an earlier one missed, replacement ones substitute
+/// it completely. Both refer to the earlier one by its identifier.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub enum DeclarationType {
+    #[default]
+    Original,
+    Complementary,
+    Replacement,
 }
 
 #[derive(Debug, Eq, Default, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Aeat720Information {
     pub records: Vec<Aeat720Record>,
     pub personal_info: PersonalInformation,
+    pub include_domestic: bool,
+    pub co_holder: Option<PersonalInformation>,
+    /// Earliest known purchase date per ISIN from every imported year.
+    #[serde(default)]
+    pub first_tx_dates: BTreeMap<String, usize>,
 }
 
 impl Aeat720Information {
     pub fn full_name(&self) -> String {
         self.personal_info.surname.clone() + " " + &self.personal_info.name
     }
+
+    pub fn contact_name(&self) -> String {
+        if self.personal_info.contact_name.trim().is_empty() {
+            self.full_name()
+        } else {
+            self.personal_info.contact_name.clone()
+        }
+    }
+
+    /// One declaration per holder, the second one only declares its share of each record.
+    pub fn declarations(&self) -> Vec<Aeat720Information> {
+        let mut result = vec![Aeat720Information {
+            co_holder: None,
+            ..self.clone()
+        }];
+
+        if let Some(co_holder) = &self.co_holder {
+            let phone = if co_holder.phone.is_empty() {
+                self.personal_info.phone.clone()
+            } else {
+                co_holder.phone.clone()
+            };
+            result.push(Aeat720Information {
+                records: self
+                    .records
+                    .iter()
+                    .filter(|record| record.co_holder_percentage > Decimal::ZERO)
+                    .map(|record| Aeat720Record {
+                        percentage: record.co_holder_percentage,
+                        co_holder_percentage: Decimal::ZERO,
+                        ..record.clone()
+                    })
+                    .collect(),
+                personal_info: PersonalInformation {
+                    year: self.personal_info.year,
+                    phone,
+                    proxy_nif: self.personal_info.proxy_nif.clone(),
+                    ..co_holder.clone()
+                },
+                include_domestic: self.include_domestic,
+                co_holder: None,
+                first_tx_dates: self.first_tx_dates.clone(),
+            });
+        }
+
+        result
+    }
+
+    /// Compares holdings by ISIN against the previous year's declaration.
+    pub fn diff(&self, previous: &Aeat720Information) -> Aeat720Diff {
+        let mut result = Aeat720Diff::default();
+        let find = |records: &[Aeat720Record], isin: &str| {
+            records
+                .iter()
+                .find(|record| record.company.isin == isin)
+                .cloned()
+        };
+
+        for record in self.records.iter().filter(|r| !r.company.isin.is_empty()) {
+            match find(&previous.records, &record.company.isin) {
+                None => result.added.push(record.clone()),
+                Some(old) => {
+                    if old.quantity != record.quantity || old.value_in_euro != record.value_in_euro
+                    {
+                        result.changed.push((old, record.clone()));
+                    }
+                }
+            }
+        }
+        for record in previous
+            .records
+            .iter()
+            .filter(|r| !r.company.isin.is_empty())
+        {
+            if find(&self.records, &record.company.isin).is_none() {
+                result.removed.push(record.clone());
+            }
+        }
+
+        result
+    }
+
+    /// Declaration limited to the records matching the predicate, e.g. a single custodian.
+    pub fn filter_records<F>(&self, predicate: F) -> Aeat720Information
+    where
+        F: Fn(&Aeat720Record) -> bool,
+    {
+        Aeat720Information {
+            records: self
+                .records
+                .iter()
+                .filter(|record| predicate(record))
+                .cloned()
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Number of holdings and total value in euros of every broker in `brokers`, in its
+    /// order and even without holdings, followed by the other brokers of the records.
+    pub fn summary_by_broker(&self, brokers: &[String]) -> Vec<(String, usize, Decimal)> {
+        let mut result: Vec<(String, usize, Decimal)> = brokers
+            .iter()
+            .map(|broker| (broker.clone(), 0, Decimal::ZERO))
+            .collect();
+
+        for record in &self.records {
+            match result
+                .iter_mut()
+                .find(|(name, _, _)| *name == record.broker.name)
+            {
+                Some((_, holdings, value)) => {
+                    *holdings += 1;
+                    *value += record.value_in_euro;
+                }
+                None => result.push((record.broker.name.clone(), 1, record.value_in_euro)),
+            }
+        }
+
+        result
+    }
+}
+
+/// Holdings added, removed and changed since the previous year's declaration.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Aeat720Diff {
+    pub added: Vec<Aeat720Record>,
+    pub removed: Vec<Aeat720Record>,
+    /// Previous and current record of holdings whose quantity or value changed.
+    pub changed: Vec<(Aeat720Record, Aeat720Record)>,
+}
+
+impl Aeat720Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Holdings not present last year are new, the rest were already declared.
+    pub fn acquisition_type(&self, isin: &str) -> AcquisitionType {
+        if self.added.iter().any(|record| record.company.isin == isin) {
+            AcquisitionType::First
+        } else {
+            AcquisitionType::Existing
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Apple held in full by the declarant at the default broker, tests override the rest.
+    fn test_record(isin: &str) -> Aeat720Record {
+        Aeat720Record {
+            company: CompanyInfo {
+                name: String::from("Apple"),
+                isin: String::from(isin),
+            },
+            quantity: Decimal::ONE,
+            value_in_euro: Decimal::ONE_HUNDRED,
+            first_tx_date: 20240101,
+            broker: DEFAULT_BROKER.clone(),
+            percentage: Decimal::ONE_HUNDRED,
+            year: DEFAULT_YEAR,
+            product_type: ProductType::Stock,
+            acquisition_type: AcquisitionType::First,
+            extinction_date: 0,
+            derived: false,
+            co_holder_percentage: Decimal::ZERO,
+            acquisition_in_euro: None,
+            account_bic: String::new(),
+            q4_average_in_euro: None,
+            valuation_in_euro: None,
+            original_value: None,
+            issuer_country_code: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_validate_isin() {
+        assert!(validate_isin("US0378331005"));
+        assert!(validate_isin("GG00B4L84979"));
+        assert!(validate_isin("AU000000ANO7"));
+        assert!(validate_isin("KYG8208B1014"));
+        assert!(!validate_isin("47215P106"));
+        assert!(!validate_isin("US0378331006"));
+        assert!(!validate_isin("us0378331005"));
+        assert!(!validate_isin(""));
+    }
+
+    #[test]
+    fn test_validate_country_code() {
+        assert!(validate_country_code("ES"));
+        assert!(validate_country_code("IE"));
+        assert!(validate_country_code("US"));
+        assert!(validate_country_code("GG"));
+        assert!(!validate_country_code("XS"));
+        assert!(!validate_country_code("EU"));
+        assert!(!validate_country_code("es"));
+        assert!(!validate_country_code("E"));
+        assert!(!validate_country_code(""));
+    }
+
+    #[test]
+    fn test_validate_iban() {
+        assert!(validate_iban("DE89370400440532013000"));
+        assert!(validate_iban("GB82 WEST 1234 5698 7654 32"));
+        assert!(validate_iban("ES9121000418450200051332"));
+        assert!(!validate_iban("DE89370400440532013001"));
+        assert!(!validate_iban("de89370400440532013000"));
+        assert!(!validate_iban("DE89"));
+        assert!(!validate_iban(""));
+    }
+
+    #[test]
+    fn test_validate_nif() {
+        assert!(validate_nif("12345678Z"));
+        assert!(validate_nif("00000000T"));
+        assert!(validate_nif("X1234567L"));
+        assert!(validate_nif("Y1234567X"));
+        assert!(validate_nif("B12345674"));
+        assert!(validate_nif("B1234567D"));
+        assert!(!validate_nif("12345678A"));
+        assert!(!validate_nif("B12345675"));
+        assert!(!validate_nif("1234567Z"));
+        assert!(!validate_nif("12345678ZZ"));
+        assert!(!validate_nif("ñ2345678Z"));
+        assert!(!validate_nif(""));
+    }
+
+    #[test]
+    fn test_normalize_phone() {
+        assert_eq!(normalize_phone("600000000").as_deref(), Some("600000000"));
+        assert_eq!(
+            normalize_phone("600 00 00 00").as_deref(),
+            Some("600000000")
+        );
+        assert_eq!(
+            normalize_phone("+34 600-000-000").as_deref(),
+            Some("600000000")
+        );
+        assert_eq!(
+            normalize_phone("0034600000000").as_deref(),
+            Some("600000000")
+        );
+        assert_eq!(normalize_phone("60000000"), None);
+        assert_eq!(normalize_phone("+44 7700 900000"), None);
+        assert_eq!(normalize_phone(""), None);
+    }
+
+    #[test]
+    fn test_foreign_bank_account() {
+        let account = ForeignBankAccount {
+            iban: String::from("DE89370400440532013000"),
+            bic: String::from("NTSBDEB1XXX"),
+            bank_name: String::from("N26 Bank"),
+            country_code: String::from("DE"),
+            opening_date: 20230315,
+            year_end_balance: Decimal::new(52_000_00, 2),
+            q4_average_balance: Decimal::new(49_500_50, 2),
+        };
+
+        let record = account.to_aeat720_record(2023);
+        assert_eq!(record.product_type, ProductType::Account);
+        assert_eq!(record.company.name, "N26 BANK");
+        assert_eq!(record.company.isin, "DE89370400440532013000");
+        assert_eq!(record.broker.country_code, "DE");
+        assert_eq!(record.value_in_euro, Decimal::new(52_000_00, 2));
+        assert_eq!(record.q4_average_in_euro, Some(Decimal::new(49_500_50, 2)));
+        assert_eq!(record.account_bic, "NTSBDEB1XXX");
+        assert_eq!(record.first_tx_date, 20230315);
+        assert_eq!(record.acquisition_type, AcquisitionType::First);
+
+        let record = account.to_aeat720_record(2024);
+        assert_eq!(record.acquisition_type, AcquisitionType::Existing);
+    }
+
+    #[test]
+    fn test_cusip_to_isin() {
+        assert_eq!(cusip_to_isin("037833100", "US"), "US0378331005");
+        assert_eq!(cusip_to_isin("88160R101", "US"), "US88160R1014");
+        assert_eq!(cusip_to_isin("30303m102", "US"), "US30303M1027");
+        assert_eq!(cusip_to_isin("46625H100", "US"), "US46625H1005");
+        assert!(!validate_isin(&cusip_to_isin("0378331", "US")));
+    }
+
+    #[test]
+    fn test_company_matches() {
+        let company = CompanyInfo {
+            name: String::from("Apple Inc"),
+            isin: String::from("US0378331005"),
+        };
+
+        assert!(company.matches(""));
+        assert!(company.matches("  "));
+        assert!(company.matches("apple"));
+        assert!(company.matches("APPLE INC"));
+        assert!(company.matches("us0378"));
+        assert!(company.matches(" 331005 "));
+        assert!(!company.matches("microsoft"));
+    }
+
+    #[test]
+    fn test_summary_by_broker() {
+        let degiro = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let ib = Arc::new(BrokerInformation::new(
+            String::from("Interactive Brokers"),
+            String::from("IE"),
+        ));
+        let record = |broker: &Arc<BrokerInformation>, value: Decimal| Aeat720Record {
+            value_in_euro: value,
+            broker: Arc::clone(broker),
+            ..test_record("US0378331005")
+        };
+        let info = Aeat720Information {
+            records: vec![
+                record(&degiro, Decimal::new(100_50, 2)),
+                record(&ib, Decimal::new(20_00, 2)),
+                record(&degiro, Decimal::new(1_25, 2)),
+            ],
+            personal_info: PersonalInformation::default(),
+            include_domestic: false,
+            co_holder: None,
+            first_tx_dates: Default::default(),
+        };
+
+        assert_eq!(
+            info.summary_by_broker(&[]),
+            vec![
+                (String::from("Degiro"), 2, Decimal::new(101_75, 2)),
+                (
+                    String::from("Interactive Brokers"),
+                    1,
+                    Decimal::new(20_00, 2)
+                ),
+            ]
+        );
+        assert!(Aeat720Information::default()
+            .summary_by_broker(&[])
+            .is_empty());
+        assert_eq!(
+            info.summary_by_broker(&[String::from("XTB"), String::from("Interactive Brokers")]),
+            vec![
+                (String::from("XTB"), 0, Decimal::ZERO),
+                (
+                    String::from("Interactive Brokers"),
+                    1,
+                    Decimal::new(20_00, 2)
+                ),
+                (String::from("Degiro"), 2, Decimal::new(101_75, 2)),
+            ]
+        );
+
+        let degiro_info = info.filter_records(|record| record.broker.name == "Degiro");
+        assert_eq!(degiro_info.records.len(), 2);
+        assert_eq!(degiro_info.personal_info, info.personal_info);
+        assert_eq!(
+            degiro_info.summary_by_broker(&[]),
+            vec![(String::from("Degiro"), 2, Decimal::new(101_75, 2))]
+        );
+    }
+
+    #[test]
+    fn test_set_broker_country() {
+        let mut record = Aeat720Record {
+            broker: Arc::new(BrokerInformation::new(
+                String::from("Interactive Brokers"),
+                String::from("IE"),
+            )),
+            ..test_record("US0378331005")
+        };
+
+        record.set_broker_country("US");
+        assert_eq!(record.broker.name, "Interactive Brokers");
+        assert_eq!(record.broker.country_code, "US");
+        assert!(!record.is_domestic());
+
+        record.set_broker_country(SPAIN_COUNTRY_CODE);
+        assert!(record.is_domestic());
+    }
+
+    #[test]
+    fn test_declarations() {
+        let record = |isin: &str, percentage: i64, co_holder_percentage: i64| Aeat720Record {
+            value_in_euro: Decimal::new(1000_00, 2),
+            percentage: Decimal::new(percentage, 0),
+            co_holder_percentage: Decimal::new(co_holder_percentage, 0),
+            ..test_record(isin)
+        };
+        let mut info = Aeat720Information {
+            records: vec![
+                record("US0378331005", 50, 50),
+                record("US88160R1014", 100, 0),
+            ],
+            personal_info: PersonalInformation {
+                name: String::from("JOHN"),
+                nif: String::from("12345678Z"),
+                year: 2024,
+                phone: String::from("600000000"),
+                ..Default::default()
+            },
+            include_domestic: false,
+            co_holder: None,
+            first_tx_dates: Default::default(),
+        };
+
+        assert_eq!(info.declarations().len(), 1);
+
+        info.co_holder = Some(PersonalInformation {
+            name: String::from("JANE"),
+            nif: String::from("87654321X"),
+            ..Default::default()
+        });
+        let declarations = info.declarations();
+
+        assert_eq!(declarations.len(), 2);
+        assert_eq!(declarations[0].records, info.records);
+        assert_eq!(declarations[0].co_holder, None);
+        assert_eq!(declarations[1].personal_info.nif, "87654321X");
+        assert_eq!(declarations[1].personal_info.year, 2024);
+        assert_eq!(declarations[1].personal_info.phone, "600000000");
+        assert_eq!(declarations[1].records.len(), 1);
+        assert_eq!(declarations[1].records[0].company.isin, "US0378331005");
+        assert_eq!(declarations[1].records[0].percentage, Decimal::new(50, 0));
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_diff() {
+        let record = |isin: &str, quantity: i64| Aeat720Record {
+            quantity: Decimal::new(quantity, 0),
+            value_in_euro: Decimal::new(1000_00, 2),
+            first_tx_date: 20230101,
+            ..test_record(isin)
+        };
+        let previous = Aeat720Information {
+            records: vec![
+                record("US0378331005", 10),
+                record("US88160R1014", 5),
+                record("US5949181045", 3),
+            ],
+            ..Default::default()
+        };
+        let current = Aeat720Information {
+            records: vec![
+                record("US0378331005", 10),
+                record("US88160R1014", 8),
+                record("IE00BFMXXD54", 2),
+            ],
+            ..Default::default()
+        };
+        let diff = current.diff(&previous);
+
+        assert_eq!(diff.added, vec![record("IE00BFMXXD54", 2)]);
+        assert_eq!(diff.removed, vec![record("US5949181045", 3)]);
+        assert_eq!(
+            diff.changed,
+            vec![(record("US88160R1014", 5), record("US88160R1014", 8))]
+        );
+        assert_eq!(
+            diff.acquisition_type("IE00BFMXXD54"),
+            AcquisitionType::First
+        );
+        assert_eq!(
+            diff.acquisition_type("US0378331005"),
+            AcquisitionType::Existing
+        );
+        assert!(current.diff(&current).is_empty());
+    }
+
+    #[test]
+    fn test_derive_balances_from_transactions() {
+        let broker = Arc::new(BrokerInformation::new(
+            String::from("Revolut"),
+            String::from("LT"),
+        ));
+        let apple = CompanyInfo {
+            name: String::from("APPLE INC"),
+            isin: String::from("US0378331005"),
+        };
+        let tesla = CompanyInfo {
+            name: String::from("TESLA INC"),
+            isin: String::from("US88160R1014"),
+        };
+        let note = |company: &CompanyInfo, operation, quantity, price| {
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 1, 2).unwrap(),
+                company.clone(),
+                operation,
+                Decimal::new(quantity, 0),
+                Decimal::new(price, 0),
+                Decimal::new(quantity * price, 0),
+                Decimal::ZERO,
+                &broker,
+            )
+        };
+        let account_notes = vec![
+            note(&apple, BrokerOperation::Buy, 10, 150),
+            note(&tesla, BrokerOperation::Buy, 2, 120),
+            note(&apple, BrokerOperation::Sell, 4, 170),
+            note(&tesla, BrokerOperation::Sell, 2, 250),
+        ];
+
+        let mut expected = BalanceNote::new(
+            apple,
+            String::from(""),
+            Decimal::new(6, 0),
+            String::from(""),
+            Decimal::new(170, 0),
+            Decimal::ZERO,
+            &broker,
+        );
+        expected.derived = true;
+
+        assert_eq!(
+            derive_balances_from_transactions(&account_notes),
+            vec![expected]
+        );
+        assert!(derive_balances_from_transactions(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_aggregate_by_isin() {
+        let ib = Arc::new(BrokerInformation::new(
+            String::from("Interactive Brokers"),
+            String::from("IE"),
+        ));
+        let degiro = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let apple = CompanyInfo {
+            name: String::from("APPLE INC"),
+            isin: String::from("US0378331005"),
+        };
+        let tesla = CompanyInfo {
+            name: String::from("TESLA INC"),
+            isin: String::from("US88160R1014"),
+        };
+        let note = |company: &CompanyInfo, quantity, value, broker: &Arc<BrokerInformation>| {
+            BalanceNote::new(
+                company.clone(),
+                String::from(""),
+                Decimal::new(quantity, 0),
+                String::from("USD"),
+                Decimal::new(150, 0),
+                Decimal::new(value, 0),
+                broker,
+            )
+        };
+        let balance_notes = vec![
+            note(&apple, 10, 1400, &ib),
+            note(&tesla, 2, 400, &ib),
+            note(&apple, 5, 700, &ib),
+            note(&apple, 3, 420, &degiro),
+        ];
+
+        assert_eq!(
+            aggregate_by_isin(balance_notes),
+            vec![
+                note(&apple, 15, 2100, &ib),
+                note(&tesla, 2, 400, &ib),
+                note(&apple, 3, 420, &degiro),
+            ]
+        );
+        assert!(aggregate_by_isin(vec![]).is_empty());
+
+        let unknown = |name: &str| CompanyInfo {
+            name: String::from(name),
+            isin: String::new(),
+        };
+        let balance_notes = vec![
+            note(&unknown("MSFT"), 4, 1200, &ib),
+            note(&unknown("NVDA"), 2, 800, &ib),
+        ];
+        assert_eq!(aggregate_by_isin(balance_notes.clone()), balance_notes);
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_aggregate_by_isin_price() {
+        let ib = Arc::new(BrokerInformation::new(
+            String::from("Interactive Brokers"),
+            String::from("IE"),
+        ));
+        let note = |quantity, currency: &str, price, value| {
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("ASML HOLDING NV"),
+                    isin: String::from("NL0010273215"),
+                },
+                String::from(""),
+                Decimal::new(quantity, 0),
+                String::from(currency),
+                Decimal::new(price, 2),
+                Decimal::new(value, 2),
+                &ib,
+            )
+        };
+
+        // Lots in the same currency keep it, with the average price.
+        let notes = aggregate_by_isin(vec![
+            note(2, "EUR", 600_00, 1200_00),
+            note(1, "EUR", 660_00, 660_00),
+        ]);
+        assert_eq!(notes, vec![note(3, "EUR", 620_00, 1860_00)]);
+
+        // Lots in different currencies are priced with their value in euros.
+        let notes = aggregate_by_isin(vec![
+            note(2, "EUR", 600_00, 1200_00),
+            note(1, "USD", 720_00, 651_00),
+        ]);
+        assert_eq!(notes, vec![note(3, "EUR", 617_00, 1851_00)]);
+    }
+
+    #[test]
+    fn test_aggregate_by_isin_and_country() {
+        let degiro = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let ib_us = Arc::new(BrokerInformation::new(
+            String::from("Interactive Brokers LLC"),
+            String::from("US"),
+        ));
+        let lynx = Arc::new(BrokerInformation::new(
+            String::from("Lynx"),
+            String::from("NL"),
+        ));
+        let apple = CompanyInfo {
+            name: String::from("APPLE INC"),
+            isin: String::from("US0378331005"),
+        };
+        let note = |quantity, value, broker: &Arc<BrokerInformation>| {
+            BalanceNote::new(
+                apple.clone(),
+                String::from(""),
+                Decimal::new(quantity, 0),
+                String::from("USD"),
+                Decimal::new(150, 0),
+                Decimal::new(value, 0),
+                broker,
+            )
+        };
+        let balance_notes = vec![
+            note(10, 1400, &degiro),
+            note(3, 420, &ib_us),
+            note(2, 280, &lynx),
+            note(5, 700, &degiro),
+        ];
+
+        // Brokers of the same country aren't merged either.
+        assert_eq!(
+            aggregate_by_isin(balance_notes),
+            vec![
+                note(15, 2100, &degiro),
+                note(3, 420, &ib_us),
+                note(2, 280, &lynx)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_records() {
+        let record =
+            |isin: &str, quantity: i64, first_tx_date: usize, cost: Option<i64>| Aeat720Record {
+                quantity: Decimal::new(quantity, 0),
+                value_in_euro: Decimal::new(quantity * 150, 0),
+                first_tx_date,
+                broker: Arc::new(BrokerInformation::new(
+                    String::from("Degiro"),
+                    String::from("NL"),
+                )),
+                year: 2023,
+                acquisition_in_euro: cost.map(|cost| Decimal::new(cost, 0)),
+                ..test_record(isin)
+            };
+
+        assert_eq!(
+            merge_records(&[
+                record("US0378331005", 10, 20210610, Some(1200)),
+                record("US0378331005", 5, 20190315, Some(500)),
+            ]),
+            Some(record("US0378331005", 15, 20190315, Some(1700)))
+        );
+        assert_eq!(
+            merge_records(&[
+                record("US0378331005", 10, 20210610, Some(1200)),
+                record("US0378331005", 5, 20220101, None),
+            ]),
+            Some(record("US0378331005", 15, 20210610, None))
+        );
+
+        let mut other_broker = record("US0378331005", 5, 20190315, None);
+        other_broker.broker = Arc::new(BrokerInformation::new(
+            String::from("Interactive Brokers"),
+            String::from("IE"),
+        ));
+        assert_eq!(
+            merge_records(&[record("US0378331005", 10, 20210610, None), other_broker]),
+            None
+        );
+        assert_eq!(
+            merge_records(&[
+                record("US0378331005", 10, 20210610, None),
+                record("US88160R1014", 5, 20190315, None),
+            ]),
+            None
+        );
+        assert_eq!(
+            merge_records(&[record("US0378331005", 10, 20210610, None)]),
+            None
+        );
+        assert_eq!(merge_records(&[]), None);
+
+        let in_currency = |quantity: i64, currency: &str| Aeat720Record {
+            original_value: Some(OriginalValue {
+                currency: String::from(currency),
+                value: Decimal::new(quantity * 165, 0),
+            }),
+            ..record("US0378331005", quantity, 20210610, None)
+        };
+        assert_eq!(
+            merge_records(&[in_currency(10, "USD"), in_currency(5, "USD")]),
+            Some(in_currency(15, "USD"))
+        );
+        assert_eq!(
+            merge_records(&[in_currency(10, "USD"), in_currency(5, "GBP")]),
+            Some(record("US0378331005", 15, 20210610, None))
+        );
+    }
+
+    #[test]
+    fn test_names_with_several_isins() {
+        let record = |name: &str, isin: &str| Aeat720Record {
+            company: CompanyInfo {
+                name: String::from(name),
+                isin: String::from(isin),
+            },
+            quantity: Decimal::new(10, 0),
+            value_in_euro: Decimal::new(300, 0),
+            broker: Arc::new(BrokerInformation::new(
+                String::from("Degiro"),
+                String::from("NL"),
+            )),
+            first_tx_date: 20200110,
+            year: 2020,
+            ..test_record(isin)
+        };
+        let adr = record("JD.COM INC", "US47215P1066");
+        let ordinary = record("JD.com Inc.", "KYG8208B1014");
+
+        assert_eq!(merge_records(&[adr.clone(), ordinary.clone()]), None);
+        assert_eq!(
+            names_with_several_isins(&[
+                adr.clone(),
+                record("APPLE INC", "US0378331005"),
+                ordinary,
+                adr,
+            ]),
+            vec![(
+                String::from("JD.COM INC"),
+                vec![String::from("US47215P1066"), String::from("KYG8208B1014")]
+            )]
+        );
+        assert!(names_with_several_isins(&[record("APPLE INC", "US0378331005")]).is_empty());
+    }
+
+    #[test]
+    fn test_isin_list() {
+        let record = |name: &str, isin: &str, product_type: ProductType| Aeat720Record {
+            company: CompanyInfo {
+                name: String::from(name),
+                isin: String::from(isin),
+            },
+            quantity: Decimal::new(10, 0),
+            value_in_euro: Decimal::new(300, 0),
+            first_tx_date: 20230101,
+            year: 2023,
+            product_type,
+            ..test_record(isin)
+        };
+        let records = [
+            record("APPLE INC", "US0378331005", ProductType::Stock),
+            record("DEGIRO", "NL91ABNA0417164300", ProductType::Account),
+            record("VANGUARD FTSE ALL-WORLD", "IE00BK5BQT80", ProductType::Fund),
+            record("APPLE INC", "US0378331005", ProductType::Stock),
+            record("", "", ProductType::Stock),
+        ];
+
+        assert_eq!(isin_list(&records, false), "US0378331005\nIE00BK5BQT80");
+        assert_eq!(
+            isin_list(&records, true),
+            "US0378331005\tAPPLE INC\nIE00BK5BQT80\tVANGUARD FTSE ALL-WORLD"
+        );
+        assert_eq!(isin_list(&[], true), "");
+    }
+
+    #[test]
+    fn test_repeated_isins() {
+        let record = |isin: &str, year: usize, product_type: ProductType| Aeat720Record {
+            company: CompanyInfo {
+                name: String::from("APPLE INC"),
+                isin: String::from(isin),
+            },
+            quantity: Decimal::new(10, 0),
+            value_in_euro: Decimal::new(300, 0),
+            first_tx_date: 20230101,
+            year,
+            product_type,
+            ..test_record(isin)
+        };
+        let records = [
+            record("US0378331005", 2023, ProductType::Stock),
+            record("US0378331005", 2023, ProductType::Stock),
+            record("US0378331005", 2022, ProductType::Stock),
+            record("IE00BK5BQT80", 2023, ProductType::Fund),
+            record("NL91ABNA0417164300", 2023, ProductType::Account),
+            record("NL91ABNA0417164300", 2023, ProductType::Account),
+            record("", 2023, ProductType::Stock),
+            record("", 2023, ProductType::Stock),
+        ];
+
+        assert_eq!(
+            repeated_isins(&records),
+            BTreeSet::from([(String::from("US0378331005"), 2023)])
+        );
+        assert!(repeated_isins(&records[2..]).is_empty());
+    }
+
+    #[test]
+    fn test_first_tx_dates() {
+        let record = |isin: &str, year: usize, first_tx_date: usize| Aeat720Record {
+            first_tx_date,
+            broker: Arc::new(BrokerInformation::new(
+                String::from("Degiro"),
+                String::from("NL"),
+            )),
+            year,
+            ..test_record(isin)
+        };
+        let mut dates = BTreeMap::new();
+
+        merge_first_tx_dates(
+            &mut dates,
+            &[
+                record("US0378331005", 2019, 20190315),
+                record("US88160R1014", 2019, DEFAULT_FIRST_TX_DATE),
+            ],
+        );
+        merge_first_tx_dates(&mut dates, &[record("US0378331005", 2020, 20200610)]);
+        assert_eq!(
+            dates,
+            BTreeMap::from([(String::from("US0378331005"), 20190315)])
+        );
+
+        let mut records = vec![
+            record("US0378331005", 2021, DEFAULT_FIRST_TX_DATE),
+            record("US88160R1014", 2021, DEFAULT_FIRST_TX_DATE),
+        ];
+        apply_first_tx_dates(&mut records, &dates);
+        assert_eq!(records[0].first_tx_date, 20190315);
+        assert_eq!(records[1].first_tx_date, DEFAULT_FIRST_TX_DATE);
+    }
+
+    #[test]
+    fn test_infer_acquisition_type() {
+        let broker = Arc::new(BrokerInformation::new(
+            String::from("Revolut"),
+            String::from("LT"),
+        ));
+        let apple = CompanyInfo {
+            name: String::from("APPLE INC"),
+            isin: String::from("US0378331005"),
+        };
+        let tesla = CompanyInfo {
+            name: String::from("TESLA INC"),
+            isin: String::from("US88160R1014"),
+        };
+        let note = |company: &CompanyInfo, year, operation| {
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(year, 6, 1).unwrap(),
+                company.clone(),
+                operation,
+                Decimal::ONE,
+                Decimal::ONE,
+                Decimal::ONE,
+                Decimal::ZERO,
+                &broker,
+            )
+        };
+        let account_notes = vec![
+            note(&apple, 2023, BrokerOperation::Buy),
+            note(&tesla, 2023, BrokerOperation::Buy),
+            note(&tesla, 2022, BrokerOperation::Buy),
+        ];
+        let microsoft = CompanyInfo {
+            name: String::from("MICROSOFT CORP"),
+            isin: String::from("US5949181045"),
+        };
+
+        assert_eq!(
+            first_transaction_date(&account_notes, &tesla),
+            NaiveDate::from_ymd_opt(2022, 6, 1)
+        );
+        assert_eq!(
+            infer_acquisition_type(&account_notes, &apple, 2023),
+            AcquisitionType::First
+        );
+        assert_eq!(
+            infer_acquisition_type(&account_notes, &tesla, 2023),
+            AcquisitionType::Existing
+        );
+        assert_eq!(
+            infer_acquisition_type(&account_notes, &microsoft, 2023),
+            AcquisitionType::Existing
+        );
+    }
+
+    #[test]
+    fn test_acquisition_cost() {
+        let broker = Arc::new(BrokerInformation::new(
+            String::from("Revolut"),
+            String::from("LT"),
+        ));
+        let apple = CompanyInfo {
+            name: String::from("APPLE INC"),
+            isin: String::from("US0378331005"),
+        };
+        let note = |operation, quantity, price| {
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 1, 2).unwrap(),
+                apple.clone(),
+                operation,
+                Decimal::new(quantity, 0),
+                Decimal::new(price, 0),
+                Decimal::new(quantity * price, 0),
+                Decimal::ZERO,
+                &broker,
+            )
+        };
+        let account_notes = vec![
+            note(BrokerOperation::Buy, 10, 150),
+            note(BrokerOperation::Sell, 4, 170),
+            note(BrokerOperation::Buy, 10, 130),
+        ];
+        let mut balance = BalanceNote::new(
+            apple.clone(),
+            String::from(""),
+            Decimal::new(16, 0),
+            String::from("USD"),
+            Decimal::new(190, 0),
+            Decimal::new(2750, 0),
+            &broker,
+        );
+
+        assert_eq!(
+            acquisition_cost(&account_notes, &balance),
+            Some(Decimal::new(2240, 0))
+        );
+        balance.company.isin = String::from("US88160R1014");
+        assert_eq!(acquisition_cost(&account_notes, &balance), None);
+    }
 }
@@ -12,10 +12,12 @@ use app::App;
 use wasm_bindgen::prelude::*;
 
 mod app;
+mod bank_accounts;
 mod css;
 mod data;
 mod parsers;
 mod personal_info;
+mod record_template;
 mod reports;
 mod table;
 mod utils;
@@ -26,9 +28,8 @@ pub async fn main_js() -> Result<(), JsValue> {
     #[cfg(debug_assertions)]
     console_error_panic_hook::set_once();
 
-    wasm_logger::init(
-        wasm_logger::Config::new(log::Level::Debug).module_prefix(env!("CARGO_PKG_NAME")),
-    );
+    utils::web::init_logger();
+    utils::web::init_offline_support();
 
     let app = App::new();
 
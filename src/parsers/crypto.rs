@@ -0,0 +1,505 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::Decimal;
+use std::{collections::BTreeMap, str::FromStr};
+
+use crate::{
+    data::{CryptoBalanceNote, CryptoBalanceNotes, ParseError, ParseErrors},
+    utils::{decimal, fx},
+};
+
+/// Year-end balances from the transaction history of a crypto exchange. Histories have no
+/// year-end prices, assets are only valued when traded on 31/12 or pegged to the dollar,
+/// the rest are left for the user to value.
+pub struct CryptoCSVParser {
+    content: String,
+}
+
+#[derive(Default)]
+struct AssetBalance {
+    quantity: Decimal,
+    // Last traded price with its date and currency.
+    price: Option<(NaiveDate, Decimal, String)>,
+}
+
+impl CryptoCSVParser {
+    const BINANCE_PLATFORM: (&'static str, &'static str) = ("Binance", "FR");
+    const COINBASE_PLATFORM: (&'static str, &'static str) = ("Coinbase", "IE");
+    const FIAT_CURRENCIES: [&'static str; 3] = ["EUR", "USD", "GBP"];
+    const STABLECOINS: [&'static str; 3] = ["USDT", "USDC", "BUSD"];
+    const USD_CURRENCY_STR: &'static str = "USD";
+
+    const BINANCE_TIME_HEADER_STR: &'static str = "UTC_Time";
+    const BINANCE_OPERATION_HEADER_STR: &'static str = "Operation";
+    const BINANCE_COIN_HEADER_STR: &'static str = "Coin";
+    const BINANCE_CHANGE_HEADER_STR: &'static str = "Change";
+    const BINANCE_FEE_STR: &'static str = "Fee";
+    const BINANCE_DEPOSIT_STR: &'static str = "Deposit";
+    const BINANCE_WITHDRAW_STR: &'static str = "Withdraw";
+
+    const COINBASE_TIMESTAMP_HEADER_STR: &'static str = "Timestamp";
+    const COINBASE_TYPE_HEADER_STR: &'static str = "Transaction Type";
+    const COINBASE_ASSET_HEADER_STR: &'static str = "Asset";
+    const COINBASE_QUANTITY_HEADER_STR: &'static str = "Quantity Transacted";
+    const COINBASE_PRICE_HEADER_STRS: [&'static str; 2] =
+        ["Spot Price at Transaction", "Price at Transaction"];
+    const COINBASE_CURRENCY_HEADER_STRS: [&'static str; 2] =
+        ["Spot Price Currency", "Price Currency"];
+    const COINBASE_NOTES_HEADER_STR: &'static str = "Notes";
+    const COINBASE_CONVERT_STR: &'static str = "convert";
+    const COINBASE_OUTGOING_STRS: [&'static str; 3] = ["sell", "send", "withdraw"];
+
+    fn lines(content: &[u8]) -> impl Iterator<Item = &[u8]> {
+        content.split(|c| *c == b'\n')
+    }
+
+    fn line_has_fields(line: &[u8], names: &[&str]) -> bool {
+        names.iter().all(|name| {
+            line.split(|c| *c == b',')
+                .any(|field| field.trim_ascii() == name.as_bytes())
+        })
+    }
+
+    pub fn is_binance_csv(content: &[u8]) -> bool {
+        CryptoCSVParser::lines(content).next().is_some_and(|line| {
+            CryptoCSVParser::line_has_fields(
+                line,
+                &[
+                    CryptoCSVParser::BINANCE_TIME_HEADER_STR,
+                    CryptoCSVParser::BINANCE_COIN_HEADER_STR,
+                    CryptoCSVParser::BINANCE_CHANGE_HEADER_STR,
+                ],
+            )
+        })
+    }
+
+    /// Coinbase exports start with a few lines describing the report before the header.
+    pub fn is_coinbase_csv(content: &[u8]) -> bool {
+        CryptoCSVParser::coinbase_header_start(content).is_some()
+    }
+
+    pub fn is_crypto_csv(content: &[u8]) -> bool {
+        CryptoCSVParser::is_binance_csv(content) || CryptoCSVParser::is_coinbase_csv(content)
+    }
+
+    fn coinbase_header_start(content: &[u8]) -> Option<usize> {
+        let mut start = 0;
+        for line in CryptoCSVParser::lines(content).take(10) {
+            if CryptoCSVParser::line_has_fields(
+                line,
+                &[
+                    CryptoCSVParser::COINBASE_TYPE_HEADER_STR,
+                    CryptoCSVParser::COINBASE_QUANTITY_HEADER_STR,
+                ],
+            ) {
+                return Some(start);
+            }
+            start += line.len() + 1;
+        }
+        None
+    }
+
+    fn column(headers: &csv::StringRecord, names: &[&str]) -> Result<usize> {
+        headers
+            .iter()
+            .position(|header| names.contains(&header.trim()))
+            .ok_or_else(|| anyhow!("Column {} not found", names.join(" / ")))
+    }
+
+    fn field<'a>(record: &'a csv::StringRecord, index: usize, name: &str) -> Result<&'a str> {
+        record
+            .get(index)
+            .map(str::trim)
+            .ok_or_else(|| anyhow!("Unable to get {}", name))
+    }
+
+    // Coinbase prefixes prices with the currency symbol.
+    fn decimal(record: &csv::StringRecord, index: usize, name: &str) -> Result<Decimal> {
+        let value = CryptoCSVParser::field(record, index, name)?
+            .trim_start_matches(|c: char| !c.is_ascii_digit() && c != '-');
+
+        Ok(Decimal::from_str(&decimal::normalize_str(value))?)
+    }
+
+    fn date(value: &str) -> Result<NaiveDate> {
+        Ok(NaiveDate::parse_from_str(
+            value.get(..10).unwrap_or(value),
+            "%Y-%m-%d",
+        )?)
+    }
+
+    fn is_fiat(asset: &str) -> bool {
+        CryptoCSVParser::FIAT_CURRENCIES.contains(&asset)
+    }
+
+    fn is_stablecoin(asset: &str) -> bool {
+        CryptoCSVParser::STABLECOINS.contains(&asset)
+    }
+
+    // Dollar stablecoins quote prices like the dollar itself.
+    fn quote_currency(asset: &str) -> Option<&str> {
+        if CryptoCSVParser::is_fiat(asset) {
+            Some(asset)
+        } else if CryptoCSVParser::is_stablecoin(asset) {
+            Some(CryptoCSVParser::USD_CURRENCY_STR)
+        } else {
+            None
+        }
+    }
+
+    // A trade is logged as one change per coin sharing the same time, the price is
+    // only known when a single crypto asset was exchanged for a fiat currency or a
+    // stablecoin.
+    fn binance_trade_price(
+        trade: &[(String, String, Decimal)],
+    ) -> Option<(String, Decimal, String)> {
+        let mut quote = trade
+            .iter()
+            .filter_map(|(_, coin, amount)| Some((CryptoCSVParser::quote_currency(coin)?, amount)));
+        let mut crypto = trade.iter().filter(|(operation, coin, _)| {
+            CryptoCSVParser::quote_currency(coin).is_none()
+                && operation != CryptoCSVParser::BINANCE_FEE_STR
+        });
+
+        match (quote.next(), quote.next(), crypto.next(), crypto.next()) {
+            (Some((currency, amount)), None, Some((_, asset, quantity)), None)
+                if !quantity.is_zero() =>
+            {
+                Some((
+                    asset.clone(),
+                    (*amount / *quantity).abs(),
+                    currency.to_string(),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_binance(&self) -> Result<(BTreeMap<String, AssetBalance>, usize)> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(self.content.as_bytes());
+        let headers = rdr.headers()?.clone();
+        let time = CryptoCSVParser::column(&headers, &[CryptoCSVParser::BINANCE_TIME_HEADER_STR])?;
+        let operation =
+            CryptoCSVParser::column(&headers, &[CryptoCSVParser::BINANCE_OPERATION_HEADER_STR])?;
+        let coin = CryptoCSVParser::column(&headers, &[CryptoCSVParser::BINANCE_COIN_HEADER_STR])?;
+        let change =
+            CryptoCSVParser::column(&headers, &[CryptoCSVParser::BINANCE_CHANGE_HEADER_STR])?;
+        let mut balances: BTreeMap<String, AssetBalance> = BTreeMap::new();
+        let mut year = 0;
+        let mut trade_time = String::new();
+        let mut trade_date = NaiveDate::MIN;
+        let mut trade = vec![];
+
+        for result in rdr.records() {
+            let record = result?;
+            log::debug!("{:?}", record);
+            let time_str = CryptoCSVParser::field(&record, time, "time")?;
+            let operation_str = CryptoCSVParser::field(&record, operation, "operation")?;
+            let coin_str = CryptoCSVParser::field(&record, coin, "coin")?.to_string();
+            let amount = CryptoCSVParser::decimal(&record, change, "change")?;
+            let date = CryptoCSVParser::date(time_str)?;
+            year = year.max(date.year() as usize);
+
+            balances.entry(coin_str.clone()).or_default().quantity += amount;
+
+            if time_str != trade_time {
+                if let Some((asset, price, currency)) = CryptoCSVParser::binance_trade_price(&trade)
+                {
+                    balances.entry(asset).or_default().price = Some((trade_date, price, currency));
+                }
+                trade_time = time_str.to_string();
+                trade_date = date;
+                trade.clear();
+            }
+            if operation_str != CryptoCSVParser::BINANCE_DEPOSIT_STR
+                && operation_str != CryptoCSVParser::BINANCE_WITHDRAW_STR
+            {
+                trade.push((operation_str.to_string(), coin_str, amount));
+            }
+        }
+        if let Some((asset, price, currency)) = CryptoCSVParser::binance_trade_price(&trade) {
+            balances.entry(asset).or_default().price = Some((trade_date, price, currency));
+        }
+
+        Ok((balances, year))
+    }
+
+    // Converted amounts are only in the notes, e.g. "Converted 0.01 BTC to 0.15 ETH".
+    fn coinbase_converted_to(notes: &str) -> Option<(String, Decimal)> {
+        let (_, target) = notes.rsplit_once(" to ")?;
+        let mut words = target.split_whitespace();
+        let quantity = Decimal::from_str(&decimal::normalize_str(words.next()?)).ok()?;
+        let asset = words.next()?.to_string();
+
+        Some((asset, quantity))
+    }
+
+    fn parse_coinbase(&self) -> Result<(BTreeMap<String, AssetBalance>, usize)> {
+        let start = CryptoCSVParser::coinbase_header_start(self.content.as_bytes())
+            .ok_or_else(|| anyhow!("Coinbase header not found"))?;
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(self.content[start..].as_bytes());
+        let headers = rdr.headers()?.clone();
+        let timestamp =
+            CryptoCSVParser::column(&headers, &[CryptoCSVParser::COINBASE_TIMESTAMP_HEADER_STR])?;
+        let kind = CryptoCSVParser::column(&headers, &[CryptoCSVParser::COINBASE_TYPE_HEADER_STR])?;
+        let asset =
+            CryptoCSVParser::column(&headers, &[CryptoCSVParser::COINBASE_ASSET_HEADER_STR])?;
+        let quantity =
+            CryptoCSVParser::column(&headers, &[CryptoCSVParser::COINBASE_QUANTITY_HEADER_STR])?;
+        let price =
+            CryptoCSVParser::column(&headers, &CryptoCSVParser::COINBASE_PRICE_HEADER_STRS)?;
+        let currency =
+            CryptoCSVParser::column(&headers, &CryptoCSVParser::COINBASE_CURRENCY_HEADER_STRS)?;
+        let notes =
+            CryptoCSVParser::column(&headers, &[CryptoCSVParser::COINBASE_NOTES_HEADER_STR]).ok();
+        let mut balances: BTreeMap<String, AssetBalance> = BTreeMap::new();
+        let mut year = 0;
+
+        for result in rdr.records() {
+            let record = result?;
+            log::debug!("{:?}", record);
+            let date =
+                CryptoCSVParser::date(CryptoCSVParser::field(&record, timestamp, "timestamp")?)?;
+            year = year.max(date.year() as usize);
+            let kind_str =
+                CryptoCSVParser::field(&record, kind, "transaction type")?.to_lowercase();
+            let asset_str = CryptoCSVParser::field(&record, asset, "asset")?.to_string();
+            // Older exports don't sign the quantity.
+            let mut amount = CryptoCSVParser::decimal(&record, quantity, "quantity")?.abs();
+            let is_convert = kind_str.contains(CryptoCSVParser::COINBASE_CONVERT_STR);
+            if is_convert
+                || CryptoCSVParser::COINBASE_OUTGOING_STRS
+                    .iter()
+                    .any(|outgoing| kind_str.contains(outgoing))
+            {
+                amount = -amount;
+            }
+
+            let balance = balances.entry(asset_str).or_default();
+            balance.quantity += amount;
+            if let Ok(spot_price) = CryptoCSVParser::decimal(&record, price, "price") {
+                let spot_currency = CryptoCSVParser::field(&record, currency, "currency")?;
+                balance.price = Some((date, spot_price, spot_currency.to_string()));
+            }
+
+            if is_convert {
+                let converted = notes
+                    .and_then(|notes| record.get(notes))
+                    .and_then(CryptoCSVParser::coinbase_converted_to);
+                match converted {
+                    Some((target, target_quantity)) => {
+                        balances.entry(target).or_default().quantity += target_quantity;
+                    }
+                    None => bail!("Unable to get converted amount: {:?}", record),
+                }
+            }
+        }
+
+        Ok((balances, year))
+    }
+
+    // Stablecoins are valued as dollars, other assets only with a price of the last day of
+    // the year.
+    fn year_end_price(
+        asset: &str,
+        balance: &AssetBalance,
+        year: usize,
+    ) -> Option<(Decimal, String)> {
+        if CryptoCSVParser::is_stablecoin(asset) {
+            return Some((Decimal::ONE, CryptoCSVParser::USD_CURRENCY_STR.to_string()));
+        }
+        let year_end = NaiveDate::from_ymd_opt(year as i32, 12, 31)?;
+        balance
+            .price
+            .as_ref()
+            .filter(|(date, _, _)| *date == year_end)
+            .map(|(_, price, currency)| (*price, currency.clone()))
+    }
+
+    /// Assets still held at the end of the last year of the history, fiat balances are
+    /// left out. Assets without a year-end price, or quoted in a currency without exchange
+    /// rate, are valued at zero for the user to value them, the latter with an error.
+    pub fn parse_balance_notes(&self) -> Result<(CryptoBalanceNotes, ParseErrors)> {
+        let ((balances, year), (platform, country)) =
+            if CryptoCSVParser::is_binance_csv(self.content.as_bytes()) {
+                (self.parse_binance()?, CryptoCSVParser::BINANCE_PLATFORM)
+            } else if CryptoCSVParser::is_coinbase_csv(self.content.as_bytes()) {
+                (self.parse_coinbase()?, CryptoCSVParser::COINBASE_PLATFORM)
+            } else {
+                bail!("Unknown crypto exchange CSV");
+            };
+        let mut balance_notes = vec![];
+        let mut errors = vec![];
+
+        for (asset, balance) in balances {
+            if CryptoCSVParser::is_fiat(&asset) || balance.quantity <= Decimal::ZERO {
+                continue;
+            }
+            let mut value_in_euro = Decimal::ZERO;
+            if let Some((price, currency)) = CryptoCSVParser::year_end_price(&asset, &balance, year)
+            {
+                match fx::to_euro(&(price * balance.quantity), &currency, year) {
+                    Ok(value) => value_in_euro = value,
+                    Err(err) => errors.push(ParseError::new(
+                        0,
+                        format!("{}: {}, introduce su valor en euros", asset, err),
+                    )),
+                }
+            }
+
+            balance_notes.push(CryptoBalanceNote::new(
+                asset,
+                balance.quantity,
+                value_in_euro,
+                platform.to_string(),
+                country.to_string(),
+            ));
+        }
+
+        Ok((balance_notes, errors))
+    }
+
+    pub fn new(content: String) -> Self {
+        Self { content }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_binance_balances() {
+        assert!(CryptoCSVParser::is_binance_csv(BINANCE_2023.as_bytes()));
+        assert!(!CryptoCSVParser::is_coinbase_csv(BINANCE_2023.as_bytes()));
+
+        let parser = CryptoCSVParser::new(BINANCE_2023.to_string());
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+        assert!(errors.is_empty());
+        // Last traded in November, there's no price at 31/12.
+        let bal_notes = vec![
+            CryptoBalanceNote::new(
+                String::from("BTC"),
+                Decimal::new(0_01998, 5),
+                Decimal::ZERO,
+                String::from("Binance"),
+                String::from("FR"),
+            ),
+            CryptoBalanceNote::new(
+                String::from("DOT"),
+                Decimal::new(12_5, 1),
+                Decimal::ZERO,
+                String::from("Binance"),
+                String::from("FR"),
+            ),
+        ];
+
+        assert_eq!(bal_notes, balance_notes);
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_coinbase_balances() {
+        assert!(CryptoCSVParser::is_coinbase_csv(COINBASE_2023.as_bytes()));
+        assert!(!CryptoCSVParser::is_binance_csv(COINBASE_2023.as_bytes()));
+
+        let parser = CryptoCSVParser::new(COINBASE_2023.to_string());
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+        assert!(errors.is_empty());
+        let bal_notes = vec![
+            CryptoBalanceNote::new(
+                String::from("BTC"),
+                Decimal::new(0_041, 3),
+                Decimal::ZERO,
+                String::from("Coinbase"),
+                String::from("IE"),
+            ),
+            CryptoBalanceNote::new(
+                String::from("ETH"),
+                Decimal::new(0_25, 2),
+                Decimal::ZERO,
+                String::from("Coinbase"),
+                String::from("IE"),
+            ),
+        ];
+
+        assert_eq!(bal_notes, balance_notes);
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_year_end_prices() {
+        let parser = CryptoCSVParser::new(BINANCE_YEAR_END_2023.to_string());
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+        assert!(errors.is_empty());
+        let bal_notes = vec![
+            CryptoBalanceNote::new(
+                String::from("BTC"),
+                Decimal::new(0_01, 2),
+                Decimal::new(380_00, 2),
+                String::from("Binance"),
+                String::from("FR"),
+            ),
+            CryptoBalanceNote::new(
+                String::from("ETH"),
+                Decimal::new(1, 0),
+                Decimal::new(1900_45, 2),
+                String::from("Binance"),
+                String::from("FR"),
+            ),
+            CryptoBalanceNote::new(
+                String::from("USDC"),
+                Decimal::new(110_50, 2),
+                Decimal::new(100_00, 2),
+                String::from("Binance"),
+                String::from("FR"),
+            ),
+        ];
+        assert_eq!(bal_notes, balance_notes);
+
+        // Quotes in a currency without exchange rate only fail their own asset.
+        let parser = CryptoCSVParser::new(BINANCE_YEAR_END_2023.replace(",EUR,", ",GBP,"));
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+        assert_eq!(balance_notes.len(), 3);
+        assert_eq!(balance_notes[0].value_in_euro, Decimal::ZERO);
+        assert_eq!(errors.len(), 1);
+    }
+
+    const BINANCE_YEAR_END_2023: &str = r#"User_ID,UTC_Time,Account,Operation,Coin,Change,Remark
+123456789,2023-06-01 10:00:00,Spot,Deposit,USDC,2210.50000000,""
+123456789,2023-12-31 10:01:00,Spot,Buy,BTC,0.01000000,""
+123456789,2023-12-31 10:01:00,Spot,Buy,EUR,-380.00000000,""
+123456789,2023-12-31 11:00:00,Spot,Transaction Buy,ETH,1.00000000,""
+123456789,2023-12-31 11:00:00,Spot,Transaction Spend,USDC,-2100.00000000,""
+"#;
+
+    const BINANCE_2023: &str = r#"User_ID,UTC_Time,Account,Operation,Coin,Change,Remark
+123456789,2023-01-05 10:00:00,Spot,Deposit,EUR,1000.00000000,""
+123456789,2023-01-05 10:01:00,Spot,Buy,BTC,0.02000000,""
+123456789,2023-01-05 10:01:00,Spot,Buy,EUR,-400.00000000,""
+123456789,2023-01-05 10:01:00,Spot,Fee,BTC,-0.00002000,""
+123456789,2023-03-10 18:30:00,Spot,Deposit,DOT,12.50000000,""
+123456789,2023-11-20 09:15:00,Spot,Transaction Buy,BTC,0.00500000,""
+123456789,2023-11-20 09:15:00,Spot,Transaction Spend,EUR,-200.00000000,""
+123456789,2023-12-01 12:00:00,Spot,Transaction Sold,BTC,-0.00500000,""
+123456789,2023-12-01 12:00:00,Spot,Transaction Revenue,USDT,210.00000000,""
+123456789,2023-12-01 12:00:00,Spot,Transaction Related,USDT,-210.00000000,""
+"#;
+
+    const COINBASE_2023: &str = r#"You can use this transaction report to inform your likely tax obligations.
+
+Transactions
+User,Jane Doe,0123456789abcdef
+Timestamp,Transaction Type,Asset,Quantity Transacted,Spot Price Currency,Spot Price at Transaction,Subtotal,Total (inclusive of fees and/or spread),Fees and/or Spread,Notes
+2023-02-01T10:00:00Z,Buy,BTC,0.05,EUR,€21000.00,€1050.00,€1065.00,€15.00,Bought 0.05 BTC for €1065.00 EUR
+2023-05-15T09:00:00Z,Convert,BTC,0.01,EUR,€25000.00,€250.00,€252.00,€2.00,Converted 0.01 BTC to 0.15 ETH
+2023-08-01T12:00:00Z,Rewards Income,ETH,0.15,EUR,€1700.00,€255.00,€255.00,€0.00,
+2023-12-20T16:00:00Z,Buy,BTC,0.001,EUR,€38000.00,€38.00,€39.00,€1.00,Bought 0.001 BTC for €39.00 EUR
+2023-12-28T16:00:00Z,Send,ETH,0.05,EUR,€2000.00,€100.00,€100.00,€0.00,Sent 0.05 ETH to 0x1234abcd
+"#;
+}
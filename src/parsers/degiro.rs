@@ -1,14 +1,15 @@
 use std::sync::Arc;
 
 use crate::data::{
-    AccountNote, AccountNotes, BalanceNote, BalanceNotes, BrokerInformation, BrokerOperation,
-    CompanyInfo,
+    validate_isin, AccountNote, AccountNotes, BalanceNote, BalanceNotes, BankAccountRecord,
+    BrokerInformation, BrokerOperation, CompanyInfo, CountryDividends, ParseError, ParseErrors,
+    ProductType,
 };
 
-use crate::utils::decimal;
+use crate::{parsers::util, utils::decimal};
 
-use anyhow::{bail, Context, Result};
-use chrono::NaiveDate;
+use anyhow::{Context, Result};
+use chrono::{Datelike, NaiveDate};
 use nom::character::complete::anychar;
 use nom::multi::many_till;
 use nom::sequence::{preceded, separated_pair};
@@ -16,10 +17,10 @@ use nom::{
     branch::alt,
     bytes::complete::{is_a, take},
     character::complete::none_of,
-    combinator::{map_res, opt, recognize},
+    combinator::{map, map_res, opt, recognize},
     multi::many0,
     multi::many1,
-    sequence::{terminated, tuple},
+    sequence::{delimited, terminated, tuple},
 };
 use nom::{
     bytes::complete::{tag, tag_no_case},
@@ -37,27 +38,69 @@ type Res<T, U> = IResult<T, U, VerboseError<T>>;
 
 pub struct DegiroParser {
     content: String,
+    locale: &'static [&'static str],
     broker: Arc<BrokerInformation>,
 }
 
-pub(crate) const DEGIRO_BALANCE_NOTES_HEADER: &str = r#"Producto ISIN Bolsa Cantidad Moneda Precio Valor (EUR)
-Tipo de
-producto
-"#;
-
-const DEGIRO_BALANCE_HEADER_BEGIN: &str = "CurrencyCASH & CASH FUND (EUR)";
-const DEGIRO_BALANCE_HEADER_END: &str = "Amsterdam, ";
-
-const DEGIRO_NOTES_HEADER_END: &str = "EURTotal\n\nInforme anual de flatex";
-
-pub(crate) const DEGIRO_NOTES_HEADER_BEGIN: &str = r#"
-Fecha Producto Symbol/ISIN Tipo de
-orden Cantidad Precio Valor local Valor en EUR Comisión Tipo de
-cambio Beneficios y
-pérdidas
-"#;
-
 impl DegiroParser {
+    pub(crate) const NOTES_HEADER_BEGIN_STR: usize = 0;
+    const NOTES_HEADER_END_STR: usize = 1;
+    pub(crate) const BALANCE_NOTES_HEADER_STR: usize = 2;
+    const BALANCE_HEADER_BEGIN_STR: usize = 3;
+    const BALANCE_HEADER_END_STR: usize = 4;
+    const FLATEX_ACCOUNT_STR: usize = 5;
+    const FLATEX_BALANCE_STR: usize = 6;
+    const PORTFOLIO_VALUE_STR: usize = 7;
+    const NOTES_TOTAL_STR: usize = 8;
+    const CERTIFICATE_END_STR: usize = 9;
+    const DIVIDENDS_HEADER_STR: usize = 10;
+    const STATEMENT_DATE_STR: usize = 11;
+    // Any of them ends the transactions table, the flatex section is missing in reports of
+    // accounts without a flatex cash account.
+    const NOTES_END_STRS: &'static [usize] = &[
+        DegiroParser::NOTES_HEADER_END_STR,
+        DegiroParser::NOTES_TOTAL_STR,
+        DegiroParser::CERTIFICATE_END_STR,
+    ];
+    const PRODUCT_TYPES: &'static [&'static str] =
+        &["Stock", "ETF", "Bond", "Fund", "Warrant", "Future"];
+    const IBAN_STR: &'static str = "IBAN";
+    const IBAN_MIN_LEN: usize = 15;
+    const IBAN_MAX_LEN: usize = 34;
+
+    const EN_HEADER_CONTENT: &'static str = "Annual Report ";
+    const REPORT_YEAR_STRS: &'static [&'static str] = &["Informe Anual ", "Annual Report "];
+
+    pub(crate) const ES_MSGS: &'static [&'static str] = &[
+        "\nFecha Producto Symbol/ISIN Tipo de\norden Cantidad Precio Valor local Valor en EUR Comisión Tipo de\ncambio Beneficios y\npérdidas\n", // NOTES_HEADER_BEGIN_STR
+        "EURTotal\n\nInforme anual de flatex", // NOTES_HEADER_END_STR
+        "Producto ISIN Bolsa Cantidad Moneda Precio Valor (EUR)\nTipo de\nproducto\n", // BALANCE_NOTES_HEADER_STR
+        "CurrencyCASH & CASH FUND (EUR)", // BALANCE_HEADER_BEGIN_STR
+        "Amsterdam, ", // BALANCE_HEADER_END_STR
+        "Cuenta de Efectivo en flatex", // FLATEX_ACCOUNT_STR
+        "Balance total", // FLATEX_BALANCE_STR
+        "Valor en cartera ", // PORTFOLIO_VALUE_STR
+        "EURTotal", // NOTES_TOTAL_STR
+        "Este certificado", // CERTIFICATE_END_STR
+        "País Producto Ingreso bruto Retenciones a cuenta Ingreso neto\n", // DIVIDENDS_HEADER_STR
+        "Fecha del extracto:", // STATEMENT_DATE_STR
+    ];
+
+    pub(crate) const EN_MSGS: &'static [&'static str] = &[
+        "\nDate Product Symbol/ISIN Order type Quantity Price Local value Value in EUR Fee Exchange\nrate Profit and\nloss\n", // NOTES_HEADER_BEGIN_STR
+        "EURTotal\n\nflatex annual report", // NOTES_HEADER_END_STR
+        "Product ISIN Exchange Quantity Currency Price Value (EUR)\nProduct\ntype\n", // BALANCE_NOTES_HEADER_STR
+        "CurrencyCASH & CASH FUND (EUR)", // BALANCE_HEADER_BEGIN_STR
+        "Amsterdam, ", // BALANCE_HEADER_END_STR
+        "flatex Cash Account", // FLATEX_ACCOUNT_STR
+        "Total balance", // FLATEX_BALANCE_STR
+        "Portfolio value ", // PORTFOLIO_VALUE_STR
+        "EURTotal", // NOTES_TOTAL_STR
+        "This certificate", // CERTIFICATE_END_STR
+        "Country Product Gross amount Withholding tax Net amount\n", // DIVIDENDS_HEADER_STR
+        "Statement date:", // STATEMENT_DATE_STR
+    ];
+
     fn n_to_m_digits<'b>(n: usize, m: usize) -> impl FnMut(&'b str) -> Res<&'b str, String> {
         move |input| {
             many_m_n(n, m, one_of("0123456789"))(input)
@@ -69,7 +112,10 @@ impl DegiroParser {
         context(
             "decimal value",
             map_res(
-                recognize(many1(terminated(one_of("0123456789"), many0(is_a(",."))))),
+                recognize(many1(terminated(
+                    one_of("0123456789"),
+                    many0(is_a(",.\u{00A0}")),
+                ))),
                 |out: &str| Decimal::from_str(&decimal::transform_i18n_es_str(out)),
             ),
         )(input)
@@ -84,16 +130,25 @@ impl DegiroParser {
         )(input)
     }
 
-    fn number_decimal_digits(input: &str, count: usize) -> Res<&str, Decimal> {
+    fn number_decimal_digits(
+        input: &str,
+        count: usize,
+        decimal_separator: char,
+    ) -> Res<&str, Decimal> {
+        let integer_chars = if decimal_separator == '.' {
+            "0123456789,\u{00A0}"
+        } else {
+            "0123456789.\u{00A0}"
+        };
         context(
             "number n decimal digits",
             map_res(
                 recognize(separated_pair(
-                    many1(one_of("0123456789.")),
-                    tag(","),
+                    many1(one_of(integer_chars)),
+                    char(decimal_separator),
                     take(count),
                 )),
-                |out: &str| Decimal::from_str(&decimal::transform_i18n_es_str(out)),
+                |out: &str| Decimal::from_str(&decimal::transform_i18n_str(out, decimal_separator)),
             ),
         )(input)
     }
@@ -141,7 +196,12 @@ impl DegiroParser {
     fn broker_operation(input: &str) -> Res<&str, BrokerOperation> {
         context(
             "broker operation",
-            alt((tag_no_case("C"), tag_no_case("V"))),
+            alt((
+                tag_no_case("C"),
+                tag_no_case("V"),
+                tag_no_case("B"),
+                tag_no_case("S"),
+            )),
         )(input)
         .map(|(next_input, res)| (next_input, res.into()))
     }
@@ -171,13 +231,7 @@ impl DegiroParser {
                 let company_name: String = company_name.into_iter().collect();
                 let company_name = company_name.replace('\n', " ").trim_end().to_string();
 
-                (
-                    next_input,
-                    CompanyInfo {
-                        name: company_name,
-                        isin,
-                    },
-                )
+                (next_input, CompanyInfo::new(company_name, isin))
             },
         )
     }
@@ -242,39 +296,72 @@ impl DegiroParser {
         })
     }
 
+    fn product_type(input: &str) -> Res<&str, &str> {
+        context(
+            "product type",
+            alt((
+                tag("Stock"),
+                tag("ETF"),
+                tag("Bond"),
+                tag("Fund"),
+                tag("Warrant"),
+                tag("Future"),
+            )),
+        )(input)
+    }
+
+    // Derivatives aren't assets of the 720, their positions are left out.
+    fn declared_product_type(product_type: &str, company: &CompanyInfo) -> Option<ProductType> {
+        match product_type {
+            "Warrant" | "Future" => {
+                log::warn!(
+                    "Skipping {} position not declared in 720: {:?} {}",
+                    product_type,
+                    company.name,
+                    company.isin
+                );
+                None
+            }
+            _ => Some(ProductType::from(product_type)),
+        }
+    }
+
     fn balance_note<'a>(
         input: &'a str,
         broker: &Arc<BrokerInformation>,
-    ) -> Res<&'a str, BalanceNote> {
+        separator: char,
+    ) -> Res<&'a str, Option<BalanceNote>> {
         log::trace!("balance note: -{}-", input);
         context(
             "balance note",
             tuple((
                 tag("\n "),
-                |input| DegiroParser::number_decimal_digits(input, 2), // value in euro
-                |input| DegiroParser::number_decimal_digits(input, 4), // price
-                take(3usize),                                          // currency
-                DegiroParser::number_no_decimal_digits,                // quantity
-                take(3usize),                                          // market
-                alt((tag("Stock"), tag("ETF"))),                       // product type: Stock | ETF
-                DegiroParser::company_info,                            // company info
+                |input| DegiroParser::number_decimal_digits(input, 2, separator), // value in euro
+                |input| DegiroParser::number_decimal_digits(input, 4, separator), // price
+                take(3usize),                                                     // currency
+                DegiroParser::number_no_decimal_digits,                           // quantity
+                take(3usize),                                                     // market
+                DegiroParser::product_type,                                       // product type
+                DegiroParser::company_info,                                       // company info
             )),
         )(input)
         .map(|(next_input, res)| {
-            let (_, value_in_euro, price, currency, quantity, market, _product_type, company) = res;
-
-            (
-                next_input,
-                BalanceNote::new(
-                    company,
-                    market.to_string(),
-                    quantity,
-                    currency.to_string(),
-                    price,
-                    value_in_euro,
-                    broker,
-                ),
-            )
+            let (_, value_in_euro, price, currency, quantity, market, product_type, company) = res;
+            let note =
+                DegiroParser::declared_product_type(product_type, &company).map(|product_type| {
+                    BalanceNote::new(
+                        company,
+                        market.to_string(),
+                        quantity,
+                        currency.to_string(),
+                        price,
+                        value_in_euro,
+                        broker,
+                    )
+                    .with_product_type(product_type)
+                });
+
+            (next_input, note)
         })
     }
 
@@ -293,106 +380,500 @@ impl DegiroParser {
     fn balance_notes<'a>(
         input: &'a str,
         broker: &Arc<BrokerInformation>,
+        decimal_separator: char,
     ) -> Res<&'a str, BalanceNotes> {
         context(
             "balance notes",
-            many0(|x| DegiroParser::balance_note(x, broker)),
+            map(
+                many0(|x| DegiroParser::balance_note(x, broker, decimal_separator)),
+                |notes| notes.into_iter().flatten().collect(),
+            ),
         )(input)
     }
 
-    fn parse_account_notes(&self, notes: &str) -> Result<AccountNotes> {
-        log::debug!("account notes:-{}-", notes);
-        let notes = match DegiroParser::account_notes(notes, &self.broker) {
-            Ok((_, notes)) => {
-                log::debug!("Ok parsing {} account notes", notes.len());
-                notes
+    fn token_decimal(token: &str, decimal_separator: char) -> Option<Decimal> {
+        if !token.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        Decimal::from_str(&decimal::transform_i18n_str(token, decimal_separator)).ok()
+    }
+
+    /// Fallback for PDFs whose balance table is extracted in reading order, one row per line
+    /// as `[type] name ISIN market quantity currency price value [type]`, where the company
+    /// name may be wrapped over the previous lines.
+    fn balance_notes_by_lines(
+        input: &str,
+        broker: &Arc<BrokerInformation>,
+        decimal_separator: char,
+    ) -> (BalanceNotes, ParseErrors) {
+        let mut result = vec![];
+        let mut errors = vec![];
+        let mut pending: Vec<&str> = vec![];
+
+        for line in input.lines() {
+            // Non-breaking spaces group thousands, they don't separate columns.
+            let tokens: Vec<&str> = line.split_ascii_whitespace().collect();
+            // Blank lines and cash totals.
+            if tokens
+                .iter()
+                .all(|token| DegiroParser::token_decimal(token, decimal_separator).is_some())
+            {
+                continue;
             }
-            Err(err) => {
-                bail!("Unable to parse account notes: {}", err);
+
+            let Some(isin_pos) = tokens.iter().position(|token| validate_isin(token)) else {
+                pending.extend(tokens);
+                continue;
+            };
+
+            let mut name: Vec<&str> = std::mem::take(&mut pending);
+            name.extend(&tokens[..isin_pos]);
+            let mut columns: Vec<&str> = tokens[isin_pos + 1..].to_vec();
+
+            let is_product_type = |token: &&str| DegiroParser::PRODUCT_TYPES.contains(token);
+            let product_type = if name.first().is_some_and(is_product_type) {
+                name.remove(0)
+            } else if columns.last().is_some_and(is_product_type) {
+                columns.pop().unwrap()
+            } else {
+                "Stock"
+            };
+
+            let numbers: Vec<Decimal> = columns
+                .iter()
+                .filter_map(|token| DegiroParser::token_decimal(token, decimal_separator))
+                .collect();
+            let codes: Vec<&str> = columns
+                .iter()
+                .filter(|token| DegiroParser::token_decimal(token, decimal_separator).is_none())
+                .copied()
+                .collect();
+            if name.is_empty() || numbers.len() != 3 || codes.len() != 2 {
+                errors.push(DegiroParser::unknown_row_error(line));
+                continue;
             }
-        };
 
-        Ok(notes)
+            let company = CompanyInfo::new(name.join(" "), tokens[isin_pos].to_string());
+            let Some(product_type) = DegiroParser::declared_product_type(product_type, &company)
+            else {
+                continue;
+            };
+            result.push(
+                BalanceNote::new(
+                    company,
+                    codes[0].to_string(),
+                    numbers[0],
+                    codes[1].to_string(),
+                    numbers[1],
+                    numbers[2],
+                    broker,
+                )
+                .with_product_type(product_type),
+            );
+        }
+
+        (result, errors)
     }
 
-    fn parse_balance_notes(&self, notes: &str) -> Result<BalanceNotes> {
-        log::debug!("balance notes:-{}-", notes);
-        let notes = match DegiroParser::balance_notes(notes, &self.broker) {
-            Ok((_, notes)) => {
-                log::debug!("Ok parsing {} balance notes", notes.len());
-                notes
-            }
-            Err(err) => {
-                log::debug!("Unable to parse balance notes:-{}-", err);
-                bail!("Unable to parse balance notes: {}", err);
-            }
-        };
+    fn unknown_row_error(row: &str) -> ParseError {
+        ParseError::new(
+            0,
+            format!(
+                "Línea no reconocida en el informe de Degiro: {}",
+                row.trim()
+            ),
+        )
+    }
 
-        Ok(notes)
+    // Position of the line break before the next transaction, rows start with their date.
+    fn next_account_note(input: &str) -> Option<usize> {
+        input.match_indices('\n').map(|(pos, _)| pos).find(|pos| {
+            input[pos + 1..]
+                .split_whitespace()
+                .next()
+                .is_some_and(|token| NaiveDate::parse_from_str(token, "%d/%m/%Y").is_ok())
+        })
     }
 
-    fn parse_pdf_account_notes(&self) -> Result<AccountNotes> {
+    /// Transactions of a page of the report. An unreadable row is reported and skipped, the
+    /// lines after the last row, like the page footer, are ignored.
+    fn parse_account_notes(&self, notes: &str) -> (AccountNotes, ParseErrors) {
+        log::debug!("account notes: {:?}", notes);
         let mut result = vec![];
+        let mut errors = vec![];
+        let mut rest = notes;
+
+        while let Some(start) = DegiroParser::next_account_note(rest) {
+            match DegiroParser::account_notes(&rest[start..], &self.broker) {
+                Ok((next, parsed)) if !parsed.is_empty() => {
+                    log::debug!("Ok parsing {} account notes", parsed.len());
+                    result.extend(parsed);
+                    rest = next;
+                }
+                _ => {
+                    let row = rest[start + 1..].lines().next().unwrap_or_default();
+                    errors.push(DegiroParser::unknown_row_error(row));
+                    rest = &rest[start + 1..];
+                }
+            }
+        }
 
-        let header_begin = self
-            .content
-            .find(DEGIRO_NOTES_HEADER_BEGIN)
-            .context("No account notes section found")?;
+        (result, errors)
+    }
 
-        let header_end = match self.content.rfind(DEGIRO_NOTES_HEADER_END) {
-            Some(end) => end - 1,
-            None => self.content.len(),
-        };
+    // Positions glued in one line each, an unreadable one is reported and skipped.
+    fn balance_notes_glued(
+        input: &str,
+        broker: &Arc<BrokerInformation>,
+        decimal_separator: char,
+    ) -> (BalanceNotes, ParseErrors) {
+        let mut result = vec![];
+        let mut errors = vec![];
+        let mut rest = input;
+
+        while let Ok((next, parsed)) = DegiroParser::balance_notes(rest, broker, decimal_separator)
+        {
+            result.extend(parsed);
+            if next.trim().is_empty() {
+                break;
+            }
+            let first_char = next.chars().next().map_or(0, char::len_utf8);
+            let row_end = next[first_char..]
+                .find("\n ")
+                .map_or(next.len(), |pos| first_char + pos);
+            errors.push(DegiroParser::unknown_row_error(&next[..row_end]));
+            rest = &next[row_end..];
+        }
 
-        let header_end = if let Some(pos) = self.content[..header_end].rfind('\n') {
-            pos
-        } else {
-            header_end
-        };
+        (result, errors)
+    }
 
-        result.extend(self.parse_account_notes(
-            &self.content[header_begin + DEGIRO_NOTES_HEADER_BEGIN.len()..header_end],
-        )?);
+    fn parse_balance_notes(&self, notes: &str) -> (BalanceNotes, ParseErrors) {
+        log::debug!("balance notes: {:?}", notes);
+        // Some reports write the positions certificate with English numbers.
+        let decimal_separator = decimal::detect_decimal_separator(notes);
+        let (balance_notes, errors) =
+            DegiroParser::balance_notes_glued(notes, &self.broker, decimal_separator);
+        if !balance_notes.is_empty() || notes.trim().is_empty() {
+            log::debug!(
+                "Ok parsing {} balance notes with glued columns strategy",
+                balance_notes.len()
+            );
+            return (balance_notes, errors);
+        }
 
-        Ok(result)
+        // Other Degiro PDF generators extract the columns in a different order.
+        let (balance_notes, errors) =
+            DegiroParser::balance_notes_by_lines(notes, &self.broker, decimal_separator);
+        log::info!(
+            "Ok parsing {} balance notes with line based strategy",
+            balance_notes.len()
+        );
+        (balance_notes, errors)
     }
 
-    fn parse_pdf_balance_notes(&self) -> Result<BalanceNotes> {
+    fn parse_pdf_account_notes(&self) -> Result<(AccountNotes, ParseErrors)> {
         let mut result = vec![];
+        let mut errors = vec![];
 
+        let notes_header_begin = self.locale[DegiroParser::NOTES_HEADER_BEGIN_STR];
+        // The table repeats its header on every page it spans.
         let indexes: Vec<_> = self
             .content
-            .match_indices(DEGIRO_BALANCE_HEADER_BEGIN)
+            .match_indices(notes_header_begin)
+            .map(|(pos, _)| pos)
             .collect();
+        let last_header = indexes.last().context("No account notes section found")?;
+        let header_end = self.account_notes_end(last_header + notes_header_begin.len());
+
+        for (i, header_begin) in indexes.iter().enumerate() {
+            let notes_begin = header_begin + notes_header_begin.len();
+            let notes_end = indexes.get(i + 1).copied().unwrap_or(header_end);
+            if notes_begin < notes_end {
+                let (notes, notes_errors) =
+                    self.parse_account_notes(&self.content[notes_begin..notes_end]);
+                result.extend(notes);
+                errors.extend(notes_errors);
+            }
+        }
+
+        Ok((result, errors))
+    }
+
+    // Start of the line with the nearest end marker after the last page of the table.
+    fn account_notes_end(&self, from: usize) -> usize {
+        let section = &self.content[from..];
+        DegiroParser::NOTES_END_STRS
+            .iter()
+            .filter_map(|i| section.find(self.locale[*i]))
+            .min()
+            .map_or(self.content.len(), |end| {
+                from + section[..end].rfind('\n').map_or(0, |pos| pos + 1)
+            })
+    }
+
+    fn parse_pdf_balance_notes(&self) -> (BalanceNotes, ParseErrors) {
+        let mut result = vec![];
+        let mut errors = vec![];
+
+        let balance_header_begin = self.locale[DegiroParser::BALANCE_HEADER_BEGIN_STR];
+        let indexes: Vec<_> = self.content.match_indices(balance_header_begin).collect();
 
         for i in 0..indexes.len() {
-            let header_begin = indexes.get(i).unwrap().0 + DEGIRO_BALANCE_HEADER_BEGIN.len();
+            let header_begin = indexes.get(i).unwrap().0 + balance_header_begin.len();
             let header_end = if i < indexes.len() - 1 {
                 indexes.get(i + 1).unwrap().0
             } else {
-                match self.content.find(DEGIRO_BALANCE_HEADER_END) {
+                match self
+                    .content
+                    .find(self.locale[DegiroParser::BALANCE_HEADER_END_STR])
+                {
                     Some(end) => end - 1,
                     None => self.content.len(),
                 }
             };
-            result.extend(self.parse_balance_notes(&self.content[header_begin..header_end - 1])?);
+            let (notes, notes_errors) =
+                self.parse_balance_notes(&self.content[header_begin..header_end - 1]);
+            result.extend(notes);
+            errors.extend(notes_errors);
         }
 
-        Ok(result)
+        (result, errors)
     }
 
     pub fn new(content: String, broker: &Arc<BrokerInformation>) -> Self {
+        let locale = if content.contains(DegiroParser::EN_HEADER_CONTENT) {
+            DegiroParser::EN_MSGS
+        } else {
+            DegiroParser::ES_MSGS
+        };
+
         Self {
             content,
+            locale,
             broker: Arc::clone(broker),
         }
     }
 
-    pub fn parse_pdf_content(&self) -> Result<(BalanceNotes, AccountNotes)> {
-        let account_notes = self.parse_pdf_account_notes()?;
-        let balance_notes = self.parse_pdf_balance_notes()?;
+    /// Text of a Degiro annual report, e.g. copied from the pdf viewer.
+    pub fn is_degiro_report(content: &str) -> bool {
+        DegiroParser::REPORT_YEAR_STRS
+            .iter()
+            .any(|prefix| content.contains(prefix))
+            && content.to_lowercase().contains("degiro")
+    }
+
+    /// Year of the report title or, without it, of the positions certificate date.
+    pub fn report_year(&self) -> Option<usize> {
+        DegiroParser::REPORT_YEAR_STRS
+            .iter()
+            .find_map(|prefix| util::year_after(&self.content, prefix))
+            .or_else(|| self.statement_date().map(|date| date.year() as usize))
+    }
+
+    /// Date of the positions certificate, printed before or after its label depending on how
+    /// the pdf text was extracted.
+    pub fn statement_date(&self) -> Option<NaiveDate> {
+        let label = self.locale[DegiroParser::STATEMENT_DATE_STR];
+        let pos = self.content.find(label)?;
+        let line_begin = self.content[..pos].rfind('\n').map_or(0, |begin| begin + 1);
+        let line_end = self.content[pos..]
+            .find('\n')
+            .map_or(self.content.len(), |end| pos + end);
+
+        self.content[line_begin..line_end]
+            .replace(label, " ")
+            .split_whitespace()
+            .find_map(|token| NaiveDate::parse_from_str(token, "%d/%m/%Y").ok())
+    }
+
+    // Spaced IBANs are joined, anything not looking like one is discarded.
+    fn flatex_iban(section: &str) -> String {
+        let mut iban = String::new();
+        if let Some(pos) = section.find(DegiroParser::IBAN_STR) {
+            for token in section[pos + DegiroParser::IBAN_STR.len()..].split_whitespace() {
+                let token = token.trim_start_matches(':');
+                if token.is_empty() {
+                    continue;
+                }
+                // Printed IBANs are grouped in blocks of four characters.
+                if !token.chars().all(|c| c.is_ascii_alphanumeric())
+                    || (!iban.is_empty() && token.len() > 4)
+                    || iban.len() + token.len() > DegiroParser::IBAN_MAX_LEN
+                {
+                    break;
+                }
+                iban.push_str(token);
+            }
+        }
+
+        if iban.len() >= DegiroParser::IBAN_MIN_LEN
+            && iban.chars().take(2).all(|c| c.is_ascii_uppercase())
+        {
+            iban
+        } else {
+            String::new()
+        }
+    }
+
+    /// Year-end balance of the flatex cash account linked to the Degiro account, if any.
+    /// The pdf text wraps lines anywhere in this section so whitespace is normalized first.
+    pub fn parse_flatex_account(
+        &self,
+        bank: &Arc<BrokerInformation>,
+    ) -> Result<Option<BankAccountRecord>> {
+        let content = self
+            .content
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let section = match content.find(self.locale[DegiroParser::FLATEX_ACCOUNT_STR]) {
+            Some(pos) => &content[pos..],
+            None => return Ok(None),
+        };
+        let balance_label = self.locale[DegiroParser::FLATEX_BALANCE_STR];
+        let balance_str = section
+            .find(balance_label)
+            .and_then(|pos| {
+                section[pos + balance_label.len()..]
+                    .split_whitespace()
+                    .next()
+            })
+            .context("Unable to find flatex total balance")?;
+        let balance = Decimal::from_str(&decimal::transform_i18n_es_str(
+            balance_str.trim_end_matches(|c: char| !c.is_ascii_digit()),
+        ))
+        .with_context(|| format!("Invalid flatex total balance: {}", balance_str))?;
+
+        Ok(Some(BankAccountRecord::new(
+            bank,
+            DegiroParser::flatex_iban(section),
+            balance,
+        )))
+    }
+
+    /// Year-end value of the positions in the portfolio value summary, written with English
+    /// numbers even in Spanish reports.
+    pub fn parse_portfolio_value(&self) -> Option<Decimal> {
+        let label = self.locale[DegiroParser::PORTFOLIO_VALUE_STR];
+        let pos = self.content.find(label)?;
+        let line = self.content[pos + label.len()..].lines().next()?;
+        let decimal_separator = decimal::detect_decimal_separator(line);
+
+        line.split_ascii_whitespace()
+            .filter_map(|token| DegiroParser::token_decimal(token, decimal_separator))
+            .last()
+    }
+
+    /// Euros kept in the cash fund at Degiro, printed after the label of the first row of the
+    /// positions table when the columns are extracted in order. Reports without an amount,
+    /// or with a zero one, have nothing to declare. The report has no IBAN for it, so the
+    /// account code is left for the user to fill in.
+    pub fn parse_cash_fund(&self) -> Option<BankAccountRecord> {
+        let label = self.locale[DegiroParser::BALANCE_HEADER_BEGIN_STR];
+        let pos = self.content.find(label)?;
+        let line = self.content[pos + label.len()..].lines().next()?;
+        let decimal_separator = decimal::detect_decimal_separator(line);
+
+        line.split_ascii_whitespace()
+            .find_map(|token| DegiroParser::token_decimal(token, decimal_separator))
+            .filter(|balance| !balance.is_zero())
+            .map(|balance| BankAccountRecord::new(&self.broker, String::new(), balance))
+    }
+
+    /// Compares the parsed positions with the portfolio value of the report.
+    pub fn check_portfolio_value(&self, notes: &[BalanceNote]) -> Option<ParseError> {
+        self.parse_portfolio_value()
+            .and_then(|total| util::check_total_in_euro(notes, &total))
+    }
+
+    fn dividend_amount(input: &str, decimal_separator: char) -> Res<&str, Decimal> {
+        context(
+            "dividend amount",
+            map(
+                delimited(
+                    opt(char(' ')),
+                    tuple((opt(char('-')), |input| {
+                        DegiroParser::number_decimal_digits(input, 2, decimal_separator)
+                    })),
+                    tag(" EUR"),
+                ),
+                |(minus, amount)| if minus.is_some() { -amount } else { amount },
+            ),
+        )(input)
+    }
+
+    /// Row of the dividends table as `[country] amount EUR amount EUR amount EUR[product]`.
+    fn dividend_line(
+        input: &str,
+        decimal_separator: char,
+    ) -> Res<&str, (Option<&str>, Decimal, Decimal, Decimal)> {
+        let amount = |input| DegiroParser::dividend_amount(input, decimal_separator);
+        context(
+            "dividend line",
+            tuple((
+                opt(terminated(
+                    recognize(many_m_n(2, 2, one_of("ABCDEFGHIJKLMNOPQRSTUVWXYZ"))),
+                    char(' '),
+                )),
+                amount,
+                amount,
+                amount,
+            )),
+        )(input)
+    }
+
+    // The pdf text doesn't keep the order of the columns in every row, the gross amount is
+    // the one giving the net after taking off the withholding.
+    fn gross_and_withholding(first: Decimal, second: Decimal, net: Decimal) -> (Decimal, Decimal) {
+        if first.abs() - second.abs() == net {
+            (first, second.abs())
+        } else {
+            (second, first.abs())
+        }
+    }
+
+    /// Dividends, coupons and other payments per country. Each country starts with a line with
+    /// its code and totals, followed by the lines of its products.
+    pub fn parse_dividends_by_country(&self) -> Vec<CountryDividends> {
+        let header = self.locale[DegiroParser::DIVIDENDS_HEADER_STR];
+        let pos = match self.content.find(header) {
+            Some(pos) => pos + header.len(),
+            None => return vec![],
+        };
+        let mut lines = self.content[pos..]
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .peekable();
+        let decimal_separator = decimal::detect_decimal_separator(lines.peek().unwrap_or(&""));
+
+        let mut result = vec![];
+        // The table ends at the title of the next section.
+        while let Some(Ok((_, (country_code, first, second, net)))) = lines
+            .next()
+            .map(|line| DegiroParser::dividend_line(line, decimal_separator))
+        {
+            if let Some(country_code) = country_code {
+                let (gross, withholding) = DegiroParser::gross_and_withholding(first, second, net);
+                result.push(CountryDividends::new(
+                    country_code.to_string(),
+                    gross,
+                    withholding,
+                    net,
+                ));
+            }
+        }
+
+        result
+    }
+
+    pub fn parse_pdf_content(&self) -> Result<(BalanceNotes, AccountNotes, ParseErrors)> {
+        let (account_notes, mut errors) = self.parse_pdf_account_notes()?;
+        let (balance_notes, balance_errors) = self.parse_pdf_balance_notes();
+        errors.extend(balance_errors);
 
-        Ok((balance_notes, account_notes))
+        Ok((balance_notes, account_notes, errors))
     }
 }
 
@@ -514,6 +995,10 @@ STOCK WHEN-ISSUED US36262G1013 "#;
             DegiroParser::decimal_value("0,9030 "),
             Ok((" ", Decimal::new(9030, 4)))
         );
+        assert_eq!(
+            DegiroParser::decimal_value("197\u{00A0}152,00 "),
+            Ok((" ", Decimal::new(197_152_00, 2)))
+        );
         assert_eq!(
             DegiroParser::decimal_value("a234,23 "),
             Err(NomErr::Error(VerboseError {
@@ -529,19 +1014,19 @@ STOCK WHEN-ISSUED US36262G1013 "#;
     #[test]
     fn number_decimal_digits_test() {
         assert_eq!(
-            DegiroParser::number_decimal_digits("1.000,03 ", 2),
+            DegiroParser::number_decimal_digits("1.000,03 ", 2, ','),
             Ok((" ", Decimal::new(1_000_03, 2)))
         );
         assert_eq!(
-            DegiroParser::number_decimal_digits("300,00 ", 2),
+            DegiroParser::number_decimal_digits("300,00 ", 2, ','),
             Ok((" ", Decimal::new(300, 0)))
         );
         assert_eq!(
-            DegiroParser::number_decimal_digits("0,90 ", 2),
+            DegiroParser::number_decimal_digits("0,90 ", 2, ','),
             Ok((" ", Decimal::new(90, 2)))
         );
         assert_eq!(
-            DegiroParser::number_decimal_digits("a234,23 ", 2),
+            DegiroParser::number_decimal_digits("a234,23 ", 2, ','),
             Err(NomErr::Error(VerboseError {
                 errors: vec![
                     ("a234,23 ", VerboseErrorKind::Nom(ErrorKind::OneOf)),
@@ -591,10 +1076,10 @@ STOCK WHEN-ISSUED US36262G1013 "#;
  2.247,001.656,0000GBX122LSEStockBURFORD CAP LD GG00B4L84979"#;
 
         assert_eq!(
-            DegiroParser::balance_note(BURFORD_NOTE, &degiro_broker),
+            DegiroParser::balance_note(BURFORD_NOTE, &degiro_broker, ','),
             Ok((
                 "",
-                BalanceNote::new(
+                Some(BalanceNote::new(
                     CompanyInfo {
                         name: String::from("BURFORD CAP LD"),
                         isin: String::from("GG00B4L84979")
@@ -605,9 +1090,116 @@ STOCK WHEN-ISSUED US36262G1013 "#;
                     Decimal::new(1_6560000, 4),
                     Decimal::new(2247_00, 2),
                     &degiro_broker,
+                ))
+            ))
+        );
+
+        const VANGUARD_NOTE: &str = r#"
+ 2.621,50104,8600EUR25XETETFVANGUARD FTSE ALL-WORLD IE00BK5BQT80"#;
+
+        assert_eq!(
+            DegiroParser::balance_note(VANGUARD_NOTE, &degiro_broker, ','),
+            Ok((
+                "",
+                Some(
+                    BalanceNote::new(
+                        CompanyInfo {
+                            name: String::from("VANGUARD FTSE ALL-WORLD"),
+                            isin: String::from("IE00BK5BQT80")
+                        },
+                        String::from("XET"),
+                        Decimal::new(25, 0),
+                        String::from("EUR"),
+                        Decimal::new(104_8600, 4),
+                        Decimal::new(2621_50, 2),
+                        &degiro_broker,
+                    )
+                    .with_product_type(ProductType::Fund)
                 )
             ))
         );
+
+        const WARRANT_NOTE: &str = r#"
+ 312,000,3120EUR1000TDGWarrantSG CALL 25.06.24 DE000SH9VRM6"#;
+
+        assert_eq!(
+            DegiroParser::balance_note(WARRANT_NOTE, &degiro_broker, ','),
+            Ok(("", None))
+        );
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn degiro_columns_parse_balance_notes_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        assert!(DegiroParser::balance_note(
+            "\nStock BURFORD CAP LD GG00B4L84979 LSE 122 GBX 1.656,0000 2.247,00",
+            &degiro_broker,
+            ','
+        )
+        .is_err());
+
+        let parser = DegiroParser::new(INPUT_COLUMNS.to_string(), &degiro_broker);
+        let (balance_notes, errors) = parser.parse_pdf_balance_notes();
+        assert!(errors.is_empty());
+        assert_eq!(parser.report_year(), Some(2021));
+
+        let bal_notes = vec![
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("BURFORD CAP LD"),
+                    isin: String::from("GG00B4L84979"),
+                },
+                String::from("LSE"),
+                Decimal::new(122, 0),
+                String::from("GBX"),
+                Decimal::new(1_656_0000, 4),
+                Decimal::new(2_247_00, 2),
+                &degiro_broker,
+            ),
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("FACEBOOK INC. - CLASS"),
+                    isin: String::from("US30303M1027"),
+                },
+                String::from("NDQ"),
+                Decimal::new(21, 0),
+                String::from("USD"),
+                Decimal::new(131_0900, 4),
+                Decimal::new(2_401_07, 2),
+                &degiro_broker,
+            ),
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("GXO LOGISTICS INC. COMMON STOCK"),
+                    isin: String::from("US36262G1013"),
+                },
+                String::from("NSY"),
+                Decimal::new(41, 0),
+                String::from("USD"),
+                Decimal::new(57_0400, 4),
+                Decimal::new(2_039_76, 2),
+                &degiro_broker,
+            ),
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("ISHARES CORE S&P 500"),
+                    isin: String::from("IE00B5BMR087"),
+                },
+                String::from("XET"),
+                Decimal::new(10, 0),
+                String::from("EUR"),
+                Decimal::new(350_1200, 4),
+                Decimal::new(3_501_20, 2),
+                &degiro_broker,
+            )
+            .with_product_type(ProductType::Fund),
+        ];
+
+        assert_eq!(balance_notes, bal_notes);
     }
 
     #[test]
@@ -709,6 +1301,84 @@ STOCK WHEN-ISSUED US36262G1013 C 69 0,0000 0,00 0,00 0,00 0,8423
         );
     }
 
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn degiro_english_numbers_balance_notes_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let parser = DegiroParser::new(INPUT_2023.to_string(), &degiro_broker);
+        let (notes, errors) = parser.parse_balance_notes(
+            "
+ 2,247.001,656.0000GBX122LSEStockBURFORD CAP LD GG00B4L84979
+ 1,319.371.1940EUR1105MILStockMONDO TV IT0001447785",
+        );
+
+        assert!(errors.is_empty());
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].value_in_euro, Decimal::new(2_247_00, 2));
+        assert_eq!(notes[0].price, Decimal::new(1_656_0000, 4));
+        assert_eq!(notes[0].quantity, Decimal::new(122, 0));
+        assert_eq!(notes[1].value_in_euro, Decimal::new(1_319_37, 2));
+        assert_eq!(notes[1].price, Decimal::new(1_1940, 4));
+        assert_eq!(notes[1].quantity, Decimal::new(1105, 0));
+    }
+
+    #[test]
+    fn degiro_portfolio_value_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let parser = DegiroParser::new(INPUT_2023.to_string(), &degiro_broker);
+        assert_eq!(
+            parser.parse_portfolio_value(),
+            Some(Decimal::new(11_568_35, 2))
+        );
+
+        let (balance_notes, _, _) = parser.parse_pdf_content().unwrap();
+        assert_eq!(parser.check_portfolio_value(&balance_notes), None);
+        assert!(parser
+            .check_portfolio_value(&balance_notes[..balance_notes.len() - 1])
+            .is_some());
+
+        let parser = DegiroParser::new(INPUT_2018_EN_TEXT.to_string(), &degiro_broker);
+        assert_eq!(parser.parse_portfolio_value(), None);
+
+        let parser = DegiroParser::new(
+            INPUT_2023.replace("11,568.35 EUR", "11\u{00A0}568.35 EUR"),
+            &degiro_broker,
+        );
+        assert_eq!(
+            parser.parse_portfolio_value(),
+            Some(Decimal::new(11_568_35, 2))
+        );
+    }
+
+    #[test]
+    fn degiro_cash_fund_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let parser = DegiroParser::new(INPUT_COLUMNS.to_string(), &degiro_broker);
+        let cash = parser.parse_cash_fund().unwrap();
+        assert_eq!(cash.balance, Decimal::new(1_203_45, 2));
+        assert_eq!(cash.account, "");
+        assert_eq!(cash.bank.name, "Degiro");
+        assert_eq!(cash.to_balance_note().product_type, ProductType::Account);
+
+        let parser = DegiroParser::new(
+            INPUT_COLUMNS.replace("(EUR) 1.203,45", "(EUR) 0,00"),
+            &degiro_broker,
+        );
+        assert!(parser.parse_cash_fund().is_none());
+
+        let parser = DegiroParser::new(INPUT_2018_EN_TEXT.to_string(), &degiro_broker);
+        assert!(parser.parse_cash_fund().is_none());
+    }
+
     #[test]
     fn degiro_2023_parse_content_test() {
         let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
@@ -716,7 +1386,9 @@ STOCK WHEN-ISSUED US36262G1013 C 69 0,0000 0,00 0,00 0,00 0,8423
             String::from("NL"),
         ));
         let parser = DegiroParser::new(INPUT_2023.to_string(), &degiro_broker);
-        let (balance_notes, account_notes) = parser.parse_pdf_content().unwrap();
+        let (balance_notes, account_notes, errors) = parser.parse_pdf_content().unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(parser.report_year(), Some(2018));
 
         let bal_notes = vec![
             BalanceNote::new(
@@ -898,6 +1570,374 @@ STOCK WHEN-ISSUED US36262G1013 C 69 0,0000 0,00 0,00 0,00 0,8423
         assert_eq!(acc_notes, account_notes);
     }
 
+    #[test]
+    fn degiro_statement_date_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let parser = DegiroParser::new(INPUT_NO_FLATEX.to_string(), &degiro_broker);
+        assert_eq!(
+            parser.statement_date(),
+            NaiveDate::from_ymd_opt(2019, 12, 31)
+        );
+
+        let parser = DegiroParser::new(INPUT_2018_EN_TEXT.to_string(), &degiro_broker);
+        assert_eq!(
+            parser.statement_date(),
+            NaiveDate::from_ymd_opt(2018, 12, 31)
+        );
+
+        let parser = DegiroParser::new(
+            String::from("País: España\nFecha del extracto: 31/12/2020\n"),
+            &degiro_broker,
+        );
+        assert_eq!(
+            parser.statement_date(),
+            NaiveDate::from_ymd_opt(2020, 12, 31)
+        );
+        assert_eq!(parser.report_year(), Some(2020));
+
+        let parser = DegiroParser::new(String::from("País: España\n"), &degiro_broker);
+        assert_eq!(parser.statement_date(), None);
+        assert_eq!(parser.report_year(), None);
+    }
+
+    #[test]
+    fn degiro_is_report_test() {
+        assert!(DegiroParser::is_degiro_report(INPUT_2018_EN_TEXT));
+        assert!(DegiroParser::is_degiro_report(INPUT_COLUMNS));
+        assert!(!DegiroParser::is_degiro_report(
+            "Symbol,Quantity,Price\nAAPL,10,192.53\n"
+        ));
+    }
+
+    #[test]
+    fn degiro_split_notes_table_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let parser = DegiroParser::new(INPUT_SPLIT_NOTES.to_string(), &degiro_broker);
+        let (account_notes, errors) = parser.parse_pdf_account_notes().unwrap();
+        assert!(errors.is_empty());
+
+        let mondo_tv = CompanyInfo {
+            name: String::from("MONDO TV"),
+            isin: String::from("IT0001447785"),
+        };
+        let acc_notes = vec![
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2020, 11, 23).unwrap(),
+                mondo_tv.clone(),
+                BrokerOperation::Buy,
+                Decimal::new(877, 0),
+                Decimal::new(1_9000, 4),
+                Decimal::new(1_666_30, 2),
+                Decimal::new(4_97, 2),
+                &degiro_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2020, 12, 14).unwrap(),
+                mondo_tv,
+                BrokerOperation::Buy,
+                Decimal::new(228, 0),
+                Decimal::new(1_9000, 4),
+                Decimal::new(433_20, 2),
+                Decimal::new(25, 2),
+                &degiro_broker,
+            ),
+        ];
+
+        assert_eq!(acc_notes, account_notes);
+    }
+
+    #[test]
+    fn degiro_flatex_account_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let flatex_bank: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("flatex Bank"),
+            String::from("DE"),
+        ));
+
+        let parser = DegiroParser::new(INPUT_FLATEX.to_string(), &degiro_broker);
+        assert_eq!(
+            parser.parse_flatex_account(&flatex_bank).unwrap(),
+            Some(BankAccountRecord::new(
+                &flatex_bank,
+                String::from("DE89370400440532013000"),
+                Decimal::new(1_520_35, 2),
+            ))
+        );
+
+        let parser = DegiroParser::new(
+            INPUT_FLATEX.replace("IBAN: DE89 3704 0044 0532 0130 00", ""),
+            &degiro_broker,
+        );
+        assert_eq!(
+            parser.parse_flatex_account(&flatex_bank).unwrap(),
+            Some(BankAccountRecord::new(
+                &flatex_bank,
+                String::from(""),
+                Decimal::new(1_520_35, 2),
+            ))
+        );
+
+        // The intro is there but the balance page didn't make it into the text.
+        let parser = DegiroParser::new(INPUT_2023.to_string(), &degiro_broker);
+        assert!(parser.parse_flatex_account(&flatex_bank).is_err());
+
+        let parser = DegiroParser::new(INPUT_COLUMNS.to_string(), &degiro_broker);
+        assert_eq!(parser.parse_flatex_account(&flatex_bank).unwrap(), None);
+    }
+
+    #[test]
+    fn degiro_dividends_by_country_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let parser = DegiroParser::new(INPUT_2023.to_string(), &degiro_broker);
+        assert_eq!(
+            parser.parse_dividends_by_country(),
+            vec![CountryDividends::new(
+                String::from("GG"),
+                Decimal::new(386, 2),
+                Decimal::ZERO,
+                Decimal::new(386, 2),
+            )]
+        );
+
+        let input = "Dividendos, Cupones y otras remuneraciones\n\
+            País Producto Ingreso bruto Retenciones a cuenta Ingreso neto\n\n\
+            US 1.250,40 EUR-187,56 EUR 1.062,84 EUR\n\
+            -120,00 EUR800,00 EUR 680,00 EURAPPLE INC\n\
+            450,40 EUR-67,56 EUR382,84 EURMICROSOFT CORP\n\
+            DE -26,38 EUR100,00 EUR73,62 EURALLIANZ SE\n\
+             100,00 EUR-26,38 EUR73,62 EUR\n\n\
+            Distribuciones Fondos del Mercado Monetario\n\
+            DE 1,00 EUR0,00 EUR1,00 EUR\n";
+        let parser = DegiroParser::new(input.to_string(), &degiro_broker);
+        assert_eq!(
+            parser.parse_dividends_by_country(),
+            vec![
+                CountryDividends::new(
+                    String::from("US"),
+                    Decimal::new(1250_40, 2),
+                    Decimal::new(187_56, 2),
+                    Decimal::new(1062_84, 2),
+                ),
+                CountryDividends::new(
+                    String::from("DE"),
+                    Decimal::new(100_00, 2),
+                    Decimal::new(26_38, 2),
+                    Decimal::new(73_62, 2),
+                ),
+            ]
+        );
+
+        let parser = DegiroParser::new(INPUT_2018_EN_TEXT.to_string(), &degiro_broker);
+        assert_eq!(parser.parse_dividends_by_country(), vec![]);
+    }
+
+    #[test]
+    fn degiro_no_flatex_account_notes_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let parser = DegiroParser::new(INPUT_NO_FLATEX.to_string(), &degiro_broker);
+        let notes_end = parser.account_notes_end(0);
+        assert!(parser.content[..notes_end].ends_with("www.degiro.es 3 / 3\n\n\n"));
+        assert!(parser.content[notes_end..].starts_with("Este certificado"));
+
+        let (account_notes, errors) = parser.parse_pdf_account_notes().unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(account_notes.len(), 2);
+        assert_eq!(account_notes[0].company.isin, "GG00B4L84979");
+        assert_eq!(account_notes[1].company.isin, "IT0001447785");
+        assert_eq!(account_notes[1].quantity, Decimal::new(200, 0));
+        assert_eq!(parser.parse_flatex_account(&degiro_broker).unwrap(), None);
+    }
+
+    #[test]
+    fn degiro_bond_balance_notes_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let parser = DegiroParser::new(INPUT_BOND.to_string(), &degiro_broker);
+        let (balance_notes, errors) = parser.parse_pdf_balance_notes();
+
+        assert!(errors.is_empty());
+        assert_eq!(balance_notes.len(), 3);
+        assert_eq!(balance_notes[0].company.isin, "GG00B4L84979");
+        assert_eq!(balance_notes[0].product_type, ProductType::Stock);
+        assert_eq!(balance_notes[1].company.isin, "US912810SN90");
+        assert_eq!(balance_notes[1].product_type, ProductType::Bond);
+        assert_eq!(balance_notes[1].quantity, Decimal::new(5000, 0));
+        assert_eq!(balance_notes[1].value_in_euro, Decimal::new(3_093_45, 2));
+        assert_eq!(balance_notes[2].company.isin, "LU0996182563");
+        assert_eq!(balance_notes[2].product_type, ProductType::Fund);
+    }
+
+    #[test]
+    fn degiro_en_text_parse_content_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let parser = DegiroParser::new(INPUT_2018_EN_TEXT.to_string(), &degiro_broker);
+        let (balance_notes, account_notes, errors) = parser.parse_pdf_content().unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(parser.report_year(), Some(2018));
+
+        let bal_notes = vec![
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("BURFORD CAP LD"),
+                    isin: String::from("GG00B4L84979"),
+                },
+                String::from("LSE"),
+                Decimal::new(122, 0),
+                String::from("GBX"),
+                Decimal::new(1_656_0000, 4),
+                Decimal::new(2_247_00, 2),
+                &degiro_broker,
+            ),
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("FACEBOOK INC. - CLASS"),
+                    isin: String::from("US30303M1027"),
+                },
+                String::from("NDQ"),
+                Decimal::new(21, 0),
+                String::from("USD"),
+                Decimal::new(131_0900, 4),
+                Decimal::new(2_401_07, 2),
+                &degiro_broker,
+            ),
+        ];
+        assert_eq!(bal_notes, balance_notes);
+
+        let acc_notes = vec![
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2018, 10, 31).unwrap(),
+                CompanyInfo {
+                    name: String::from("BURFORD CAP LD"),
+                    isin: String::from("GG00B4L84979"),
+                },
+                BrokerOperation::Buy,
+                Decimal::new(122, 0),
+                Decimal::new(1_616_0000, 4),
+                Decimal::new(197_152_00, 2),
+                Decimal::new(5_28, 2),
+                &degiro_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2018, 10, 22).unwrap(),
+                CompanyInfo {
+                    name: String::from("FACEBOOK INC. - CLASS"),
+                    isin: String::from("US30303M1027"),
+                },
+                BrokerOperation::Buy,
+                Decimal::new(26, 0),
+                Decimal::new(154_7600, 4),
+                Decimal::new(4_023_76, 2),
+                Decimal::new(57, 2),
+                &degiro_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2018, 12, 3).unwrap(),
+                CompanyInfo {
+                    name: String::from("FACEBOOK INC. - CLASS"),
+                    isin: String::from("US30303M1027"),
+                },
+                BrokerOperation::Sell,
+                Decimal::new(5, 0),
+                Decimal::new(140_0000, 4),
+                Decimal::new(700_00, 2),
+                Decimal::new(50, 2),
+                &degiro_broker,
+            ),
+        ];
+        assert_eq!(acc_notes, account_notes);
+    }
+
+    #[test]
+    fn degiro_skips_unreadable_rows_test() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let input = INPUT_2018_EN_TEXT
+            .replace("B 26 154,7600", "B 26 n/a")
+            .replace("GBX122LSEStock", "GBX122LSEOption");
+        let parser = DegiroParser::new(input, &degiro_broker);
+        let (balance_notes, account_notes, errors) = parser.parse_pdf_content().unwrap();
+
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].reason.contains("22/10/2018 FACEBOOK INC."));
+        assert!(errors[1].reason.contains("BURFORD CAP LD"));
+        assert_eq!(account_notes.len(), 2);
+        assert_eq!(
+            account_notes[1].date,
+            NaiveDate::from_ymd_opt(2018, 12, 3).unwrap()
+        );
+        assert_eq!(balance_notes.len(), 1);
+        assert_eq!(balance_notes[0].company.isin, "US30303M1027");
+    }
+
+    const INPUT_FLATEX: &str = r#"Informe Anual 2020
+
+Informe anual de flatex
+
+Para ayudarle a realizar su declaración de la renta le proveemos con este informe anual ya que dispone de una Cuenta de
+Efectivo en flatex asociada a su cuenta de DEGIRO.
+
+IBAN: DE89 3704 0044 0532 0130 00
+Depósitos 2.000,00 EUR
+Intereses 0,00 EUR
+Balance total 1.520,35 EUR
+"#;
+
+    // Transactions table continued on a second page, below a repeated header.
+    const INPUT_SPLIT_NOTES: &str = r#"Informe Anual 2020 - www.degiro.es 1 / 2
+
+Beneficios y pérdidas derivadas de la transmisión de elementos patrimoniales
+
+Fecha Producto Symbol/ISIN Tipo de
+orden Cantidad Precio Valor local Valor en EUR Comisión Tipo de
+cambio Beneficios y
+pérdidas
+
+23/11/2020 MONDO TV IT0001447785 C 877 1,9000 1.666,30 1.666,30 4,97 1,0000
+
+Informe Anual 2020 - www.degiro.es 2 / 2
+
+Fecha Producto Symbol/ISIN Tipo de
+orden Cantidad Precio Valor local Valor en EUR Comisión Tipo de
+cambio Beneficios y
+pérdidas
+
+14/12/2020 MONDO TV IT0001447785 C 228 1,9000 433,20 433,20 0,25 1,0000
+
+5,22 EURTotal
+
+Informe anual de flatex
+"#;
+
+    // Extracted text of an English annual report, like the Spanish inputs, not the pdf itself.
+    const INPUT_2018_EN_TEXT: &str = include_str!("testdata/degiro_en_text_test.txt");
+
+    const INPUT_COLUMNS: &str = include_str!("testdata/degiro_columns_test.txt");
+
+    const INPUT_NO_FLATEX: &str = include_str!("testdata/degiro_no_flatex_test.txt");
+    const INPUT_BOND: &str = include_str!("testdata/degiro_bond_test.txt");
+
     const INPUT_2023: &str = r#"
 Sr. John Doe
 neverwhere
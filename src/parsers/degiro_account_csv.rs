@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::{str::FromStr, sync::Arc};
+
+use crate::{
+    data::{BrokerInformation, CompanyInfo, DividendNote, DividendNotes, ParseError, ParseErrors},
+    utils::decimal,
+};
+
+pub struct DegiroAccountCSVParser {
+    content: String,
+    broker: Arc<BrokerInformation>,
+}
+
+impl DegiroAccountCSVParser {
+    const HEADER_STR: &'static str = "Fecha,Hora,Fecha valor,Producto,ISIN,Descripción";
+    const DIVIDEND_STR: &'static str = "Dividendo";
+    const WITHHOLDING_STR: &'static str = "Retención del dividendo";
+    const DATE_FORMAT_STR: &'static str = "%d-%m-%Y";
+
+    const VALUE_DATE_FIELD: usize = 2;
+    const PRODUCT_FIELD: usize = 3;
+    const ISIN_FIELD: usize = 4;
+    const DESCRIPTION_FIELD: usize = 5;
+    const CURRENCY_FIELD: usize = 7;
+    const AMOUNT_FIELD: usize = 8;
+
+    pub fn is_account_csv(content: &[u8]) -> bool {
+        content.starts_with(DegiroAccountCSVParser::HEADER_STR.as_bytes())
+    }
+
+    fn field<'a>(record: &'a csv::StringRecord, index: usize) -> Result<&'a str> {
+        record
+            .get(index)
+            .ok_or_else(|| anyhow!("Unable to get field {} in {:?}", index, record))
+    }
+
+    // Dividend and its withholding are on separate lines sharing value date and ISIN.
+    fn dividend_note<'a>(
+        notes: &'a mut DividendNotes,
+        date: NaiveDate,
+        company: CompanyInfo,
+        currency: &str,
+        broker: &Arc<BrokerInformation>,
+    ) -> &'a mut DividendNote {
+        let position = notes
+            .iter()
+            .position(|x| x.date == date && x.company.isin == company.isin);
+
+        match position {
+            Some(idx) => &mut notes[idx],
+            None => {
+                notes.push(DividendNote::new(
+                    date,
+                    company,
+                    currency.to_string(),
+                    Decimal::ZERO,
+                    Decimal::ZERO,
+                    broker,
+                ));
+                notes.last_mut().unwrap()
+            }
+        }
+    }
+
+    // Adds the dividend or withholding in the record to its note, rows of other
+    // kinds of movement are ignored.
+    fn parse_dividend_row(
+        &self,
+        record: &csv::StringRecord,
+        dividend_notes: &mut DividendNotes,
+    ) -> Result<()> {
+        let description = DegiroAccountCSVParser::field(record, Self::DESCRIPTION_FIELD)?;
+        let is_withholding = description.starts_with(Self::WITHHOLDING_STR);
+        if !is_withholding && !description.starts_with(Self::DIVIDEND_STR) {
+            return Ok(());
+        }
+
+        let isin = DegiroAccountCSVParser::field(record, Self::ISIN_FIELD)?;
+        if isin.is_empty() {
+            return Ok(());
+        }
+        let date = NaiveDate::parse_from_str(
+            DegiroAccountCSVParser::field(record, Self::VALUE_DATE_FIELD)?,
+            Self::DATE_FORMAT_STR,
+        )?;
+        let company = CompanyInfo::new(
+            DegiroAccountCSVParser::field(record, Self::PRODUCT_FIELD)?.to_string(),
+            isin.to_string(),
+        );
+        let currency = DegiroAccountCSVParser::field(record, Self::CURRENCY_FIELD)?;
+        let amount = Decimal::from_str(&decimal::transform_i18n_es_str(
+            DegiroAccountCSVParser::field(record, Self::AMOUNT_FIELD)?,
+        ))?;
+
+        let note = DegiroAccountCSVParser::dividend_note(
+            dividend_notes,
+            date,
+            company,
+            currency,
+            &self.broker,
+        );
+        if is_withholding {
+            note.withholding += amount.abs();
+        } else {
+            note.gross += amount;
+        }
+
+        Ok(())
+    }
+
+    pub fn parse_dividends(&self) -> Result<(DividendNotes, ParseErrors)> {
+        let mut rdr = csv::Reader::from_reader(self.content.as_bytes());
+        let mut dividend_notes = vec![];
+        let mut errors = vec![];
+
+        for result in rdr.records() {
+            let record = result?;
+            log::debug!("{:?}", record);
+            if let Err(err) = self.parse_dividend_row(&record, &mut dividend_notes) {
+                errors.push(ParseError::new(
+                    record.position().map_or(0, |pos| pos.line() as usize),
+                    err.to_string(),
+                ));
+            }
+        }
+
+        dividend_notes.sort_by_key(|x| x.date);
+
+        Ok((dividend_notes, errors))
+    }
+
+    pub fn new(content: String, broker: &Arc<BrokerInformation>) -> Self {
+        Self {
+            content,
+            broker: Arc::clone(broker),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_dividends() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        assert!(DegiroAccountCSVParser::is_account_csv(
+            INPUT_2023.as_bytes()
+        ));
+
+        let parser = DegiroAccountCSVParser::new(INPUT_2023.to_string(), &degiro_broker);
+        let (dividend_notes, errors) = parser.parse_dividends().unwrap();
+        assert!(errors.is_empty());
+
+        let div_notes = vec![
+            DividendNote::new(
+                NaiveDate::from_ymd_opt(2023, 5, 18).unwrap(),
+                CompanyInfo {
+                    name: String::from("APPLE INC"),
+                    isin: String::from("US0378331005"),
+                },
+                String::from("USD"),
+                Decimal::new(2_40, 2),
+                Decimal::new(36, 2),
+                &degiro_broker,
+            ),
+            DividendNote::new(
+                NaiveDate::from_ymd_opt(2023, 6, 1).unwrap(),
+                CompanyInfo {
+                    name: String::from("UNILEVER PLC"),
+                    isin: String::from("GB00B10RZP78"),
+                },
+                String::from("EUR"),
+                Decimal::new(9_21, 2),
+                Decimal::ZERO,
+                &degiro_broker,
+            ),
+            DividendNote::new(
+                NaiveDate::from_ymd_opt(2023, 8, 17).unwrap(),
+                CompanyInfo {
+                    name: String::from("APPLE INC"),
+                    isin: String::from("US0378331005"),
+                },
+                String::from("USD"),
+                Decimal::new(2_40, 2),
+                Decimal::new(36, 2),
+                &degiro_broker,
+            ),
+        ];
+
+        assert_eq!(div_notes, dividend_notes);
+    }
+
+    #[test]
+    fn test_parse_skips_malformed_rows() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let input = INPUT_2023.replace(
+            "01-06-2023,09:02,01-06-2023,UNILEVER",
+            "01-06-2023,09:02,2023/06/01,UNILEVER",
+        );
+        let parser = DegiroAccountCSVParser::new(input, &degiro_broker);
+        let (dividend_notes, errors) = parser.parse_dividends().unwrap();
+        assert_eq!(dividend_notes.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 5);
+    }
+
+    const INPUT_2023: &str = r#"Fecha,Hora,Fecha valor,Producto,ISIN,Descripción,Tipo,Variación,,Saldo,,ID Orden
+17-08-2023,07:41,17-08-2023,APPLE INC,US0378331005,Retención del dividendo,,USD,"-0,36",USD,"2,04",
+17-08-2023,07:41,17-08-2023,APPLE INC,US0378331005,Dividendo,,USD,"2,40",USD,"2,40",
+01-07-2023,10:12,30-06-2023,,,Comisión de conectividad con el mercado 2023,,EUR,"-2,50",EUR,"120,30",
+01-06-2023,09:02,01-06-2023,UNILEVER PLC,GB00B10RZP78,Dividendo,,EUR,"9,21",EUR,"122,80",
+18-05-2023,07:40,18-05-2023,APPLE INC,US0378331005,Dividendo,,USD,"2,40",USD,"2,40",
+18-05-2023,07:40,18-05-2023,APPLE INC,US0378331005,Retención del dividendo,,USD,"-0,36",USD,"0,00",
+15-05-2023,15:30,17-05-2023,APPLE INC,US0378331005,"Compra 2 Apple Inc@170,6 USD (US0378331005)",,USD,"-341,20",USD,"0,00",a1b2c3
+"#;
+}
@@ -3,7 +3,7 @@ use rust_decimal::Decimal;
 use std::{str::FromStr, sync::Arc};
 
 use crate::{
-    data::{BalanceNote, BalanceNotes, BrokerInformation, CompanyInfo},
+    data::{BalanceNote, BalanceNotes, BrokerInformation, CompanyInfo, ParseError, ParseErrors},
     utils::decimal,
 };
 
@@ -12,58 +12,136 @@ pub struct DegiroCSVParser {
     broker: Arc<BrokerInformation>,
 }
 
+// Positions of the fields in the header, older exports write the currency as a prefix of the
+// local value, newer ones in a column of its own.
+struct Columns {
+    name: usize,
+    isin: usize,
+    quantity: usize,
+    price: usize,
+    currency: Option<usize>,
+    local_value: Option<usize>,
+    value_in_euro: usize,
+}
+
 impl DegiroCSVParser {
-    pub fn parse_csv(&self) -> Result<BalanceNotes> {
-        let mut rdr = csv::Reader::from_reader(self.content.as_bytes());
+    const NAME_HEADER_STR: &'static str = "Producto";
+    const ISIN_HEADER_STR: &'static str = "Symbol/ISIN";
+    const QUANTITY_HEADER_STR: &'static str = "Cantidad";
+    const PRICE_HEADER_STR: &'static str = "Precio";
+    const CURRENCY_HEADER_STR: &'static str = "Valor";
+    const LOCAL_VALUE_HEADER_STR: &'static str = "Valor local";
+    const VALUE_IN_EURO_HEADER_STR: &'static str = "Valor en EUR";
+    const CURRENCY_LEN: usize = 3;
+
+    fn column(headers: &csv::StringRecord, name: &str) -> Result<usize> {
+        headers
+            .iter()
+            .position(|header| header.trim() == name)
+            .ok_or_else(|| anyhow!("Column {} not found", name))
+    }
+
+    fn columns(headers: &csv::StringRecord) -> Result<Columns> {
+        Ok(Columns {
+            name: DegiroCSVParser::column(headers, DegiroCSVParser::NAME_HEADER_STR)?,
+            isin: DegiroCSVParser::column(headers, DegiroCSVParser::ISIN_HEADER_STR)?,
+            quantity: DegiroCSVParser::column(headers, DegiroCSVParser::QUANTITY_HEADER_STR)?,
+            // "Precio de" or "Precio de cierre" depending on the export.
+            price: headers
+                .iter()
+                .position(|header| header.trim().starts_with(DegiroCSVParser::PRICE_HEADER_STR))
+                .ok_or_else(|| anyhow!("Column {} not found", DegiroCSVParser::PRICE_HEADER_STR))?,
+            currency: DegiroCSVParser::column(headers, DegiroCSVParser::CURRENCY_HEADER_STR).ok(),
+            local_value: DegiroCSVParser::column(headers, DegiroCSVParser::LOCAL_VALUE_HEADER_STR)
+                .ok(),
+            value_in_euro: DegiroCSVParser::column(
+                headers,
+                DegiroCSVParser::VALUE_IN_EURO_HEADER_STR,
+            )?,
+        })
+    }
+
+    fn is_currency(field: &str) -> bool {
+        field.len() == DegiroCSVParser::CURRENCY_LEN
+            && field.chars().all(|c| c.is_ascii_uppercase())
+    }
+
+    // The "Valor" column in newer exports, otherwise the prefix of the local value, e.g.
+    // `USD 2541.00`.
+    fn currency<'a>(record: &'a csv::StringRecord, columns: &Columns) -> Result<&'a str> {
+        let currency = match columns.currency {
+            Some(index) => record.get(index),
+            None => columns
+                .local_value
+                .and_then(|index| record.get(index))
+                .and_then(|local_value| local_value.split_once(' '))
+                .map(|(prefix, _)| prefix),
+        };
+
+        currency
+            .map(str::trim)
+            .filter(|currency| DegiroCSVParser::is_currency(currency))
+            .ok_or_else(|| anyhow!("Unknown currency"))
+    }
+
+    fn decimal(record: &csv::StringRecord, index: usize, name: &str) -> Result<Decimal> {
+        Ok(Decimal::from_str(&decimal::transform_i18n_es_str(
+            record
+                .get(index)
+                .ok_or_else(|| anyhow!("Unable to get {}", name))?,
+        ))?)
+    }
+
+    fn parse_balance_note(
+        &self,
+        record: &csv::StringRecord,
+        columns: &Columns,
+    ) -> Result<BalanceNote> {
+        Ok(BalanceNote::new(
+            CompanyInfo::new(
+                record
+                    .get(columns.name)
+                    .ok_or_else(|| anyhow!("Unknown company"))?
+                    .to_string(),
+                record
+                    .get(columns.isin)
+                    .ok_or_else(|| anyhow!("Unknown ISIN"))?
+                    .to_string(),
+            ),
+            String::from(""),
+            DegiroCSVParser::decimal(record, columns.quantity, "quantity")?,
+            DegiroCSVParser::currency(record, columns)?.to_string(),
+            DegiroCSVParser::decimal(record, columns.price, "price")?,
+            DegiroCSVParser::decimal(record, columns.value_in_euro, "value in euro")?,
+            &self.broker,
+        ))
+    }
+
+    pub fn parse_csv(&self) -> Result<(BalanceNotes, ParseErrors)> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(self.content.as_bytes());
+        let columns = DegiroCSVParser::columns(rdr.headers()?)?;
         let mut balance_notes = vec![];
+        let mut errors = vec![];
 
         for result in rdr.records() {
             let record = result?;
             log::debug!("{:?}", record);
-            if record.get(1) == Some("") {
+            // Cash has no ISIN.
+            if record.get(columns.isin) == Some("") {
                 continue;
             }
-            let currency_price = record
-                .get(4)
-                .ok_or_else(|| anyhow!("Unknown currency/price"))?;
-            let currency = if let Some(currency_end_index) = currency_price.find(' ') {
-                &currency_price[0..currency_end_index]
-            } else {
-                currency_price
-            };
-            let note = BalanceNote::new(
-                CompanyInfo {
-                    name: record
-                        .get(0)
-                        .ok_or_else(|| anyhow!("Unknown company"))?
-                        .to_string(),
-                    isin: record
-                        .get(1)
-                        .ok_or_else(|| anyhow!("Unknown ISIN"))?
-                        .to_string(),
-                },
-                String::from(""),
-                Decimal::from_str(&decimal::transform_i18n_es_str(
-                    record.get(2).ok_or_else(|| anyhow!("Unknow quantity"))?,
-                ))?,
-                currency.to_string(),
-                Decimal::from_str(&decimal::transform_i18n_es_str(
-                    record
-                        .get(3)
-                        .ok_or_else(|| anyhow!("Unable to get price"))?,
-                ))?,
-                Decimal::from_str(&decimal::transform_i18n_es_str(
-                    record
-                        .get(5)
-                        .ok_or_else(|| anyhow!("Unable to get value in euro"))?,
-                ))?,
-                &self.broker,
-            );
-
-            balance_notes.push(note);
+            match self.parse_balance_note(&record, &columns) {
+                Ok(note) => balance_notes.push(note),
+                Err(err) => errors.push(ParseError::new(
+                    record.position().map_or(0, |pos| pos.line() as usize),
+                    err.to_string(),
+                )),
+            }
         }
 
-        Ok(balance_notes)
+        Ok((balance_notes, errors))
     }
 
     pub fn new(content: String, broker: &Arc<BrokerInformation>) -> Self {
@@ -77,6 +155,7 @@ impl DegiroCSVParser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::decode_text;
 
     fn compare_vectors_by_item<T>(vec1: &[T], vec2: &[T])
     where
@@ -103,7 +182,8 @@ mod tests {
             String::from("NL"),
         ));
         let parser = DegiroCSVParser::new(INPUT_2019.to_string(), &degiro_broker);
-        let balance_notes = parser.parse_csv().unwrap();
+        let (balance_notes, errors) = parser.parse_csv().unwrap();
+        assert!(errors.is_empty());
         let bal_notes = vec![
             BalanceNote::new(
                 CompanyInfo {
@@ -230,6 +310,122 @@ mod tests {
         compare_vectors_by_item(&bal_notes, &balance_notes);
     }
 
+    #[test]
+    fn test_parse_csv_skips_malformed_lines() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let input = INPUT_2019.replace(
+            "BURFORD CAP LD,GG00B4L84979,463,\"712,00\",GBX 329656.00,\"3898,18\"",
+            "BURFORD CAP LD,GG00B4L84979,463",
+        );
+        let parser = DegiroCSVParser::new(input, &degiro_broker);
+        let (balance_notes, errors) = parser.parse_csv().unwrap();
+
+        assert_eq!(balance_notes.len(), 9);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 4);
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_portfolio_csv() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let parser = DegiroCSVParser::new(INPUT_PORTFOLIO_2023.to_string(), &degiro_broker);
+        let (balance_notes, errors) = parser.parse_csv().unwrap();
+        assert!(errors.is_empty());
+        let bal_notes = vec![
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("APPLE INC"),
+                    isin: String::from("US0378331005"),
+                },
+                String::from(""),
+                Decimal::new(10, 0),
+                String::from("USD"),
+                Decimal::new(192_53, 2),
+                Decimal::new(1742_35, 2),
+                &degiro_broker,
+            ),
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("BURFORD CAP LD"),
+                    isin: String::from("GG00B4L84979"),
+                },
+                String::from(""),
+                Decimal::new(463, 0),
+                String::from("GBX"),
+                Decimal::new(1224_00, 2),
+                Decimal::new(6535_17, 2),
+                &degiro_broker,
+            ),
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("MONDO TV"),
+                    isin: String::from("IT0001447785"),
+                },
+                String::from(""),
+                Decimal::new(1105, 0),
+                String::from("EUR"),
+                Decimal::new(0_2390, 4),
+                Decimal::new(264_10, 2),
+                &degiro_broker,
+            ),
+        ];
+
+        assert_eq!(bal_notes, balance_notes);
+    }
+
+    #[test]
+    fn test_parse_portfolio_csv_currency_column() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        // Only the "Valor" column tells the currency, not any other three letter field.
+        let input = INPUT_PORTFOLIO_2023
+            .replace("APPLE INC,US0378331005,USD", "APPLE INC,US0378331005,")
+            .replace("MONDO TV,IT0001447785,EUR", "GBP,IT0001447785,EUR");
+        let parser = DegiroCSVParser::new(input, &degiro_broker);
+        let (balance_notes, errors) = parser.parse_csv().unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+        assert_eq!(balance_notes.len(), 2);
+        assert_eq!(balance_notes[1].currency, "EUR");
+    }
+
+    // Newer Portfolio.csv, the currency has a column of its own.
+    const INPUT_PORTFOLIO_2023: &str = r#"Producto,Symbol/ISIN,Valor,Cantidad,Precio,Valor local,Valor en EUR
+CASH & CASH FUND & FTX CASH (EUR),,EUR,,,"564,19","564,19"
+APPLE INC,US0378331005,USD,10,"192,53","1925,30","1742,35"
+BURFORD CAP LD,GG00B4L84979,GBX,463,"1224,00","566712,00","6535,17"
+MONDO TV,IT0001447785,EUR,1105,"0,2390","264,10","264,10""#;
+
+    #[test]
+    fn test_parse_latin1_csv() {
+        let degiro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let input = INPUT_PORTFOLIO_2023.replace("MONDO TV", "COMPAÑÍA LOGÍSTICA");
+        let (latin1, _, _) = encoding_rs::WINDOWS_1252.encode(&input);
+        assert!(String::from_utf8(latin1.to_vec()).is_err());
+
+        let parser = DegiroCSVParser::new(decode_text(&latin1), &degiro_broker);
+        let (balance_notes, errors) = parser.parse_csv().unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(balance_notes[2].company.name, "COMPAÑÍA LOGÍSTICA");
+
+        let mut with_bom = b"\xEF\xBB\xBF".to_vec();
+        with_bom.extend_from_slice(input.as_bytes());
+        assert!(decode_text(&with_bom).starts_with("Producto,"));
+    }
+
     const INPUT_2019: &str = r#"Producto,Symbol/ISIN,Cantidad,Precio de,Valor local,Valor en EUR
 CASH & CASH FUND & FTX CASH (EUR),,,,EUR 564.19,"564,19"
 ANGI HOMESERVICES INC- A,US00183L1026,300,"8,47",USD 2541.00,"2266,32"
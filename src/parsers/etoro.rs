@@ -0,0 +1,433 @@
+use std::{collections::HashMap, io::Cursor, str::FromStr, sync::Arc};
+
+use anyhow::{anyhow, bail, Result};
+use calamine::{open_workbook_from_rs, Reader, Xlsx};
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+
+use crate::{
+    data::{
+        validate_isin, AccountNote, AccountNotes, BalanceNote, BalanceNotes, BrokerInformation,
+        BrokerOperation, CompanyInfo, ParseError, ParseErrors,
+    },
+    utils::decimal,
+};
+
+type Rows = Vec<Vec<String>>;
+
+pub struct EtoroParser {
+    account_activity: Rows,
+    broker: Arc<BrokerInformation>,
+    companies_info: HashMap<String, CompanyInfo>,
+}
+
+impl EtoroParser {
+    const USD_CURRENCY_STR: &'static str = "USD";
+    const CLOSED_POSITIONS_SHEET_STR: &'static str = "Closed Positions";
+    const ACCOUNT_ACTIVITY_SHEET_STR: &'static str = "Account Activity";
+    const OPEN_POSITION_STR: &'static str = "Open Position";
+    const POSITION_CLOSED_STR: &'static str = "Position closed";
+    const CFD_STR: &'static str = "CFD";
+    const DATE_FORMAT_STR: &'static str = "%d/%m/%Y %H:%M:%S";
+
+    const DATE_COLUMN_STR: &'static str = "Date";
+    const TYPE_COLUMN_STR: &'static str = "Type";
+    const DETAILS_COLUMN_STR: &'static str = "Details";
+    const AMOUNT_COLUMN_STR: &'static str = "Amount";
+    const UNITS_COLUMN_STR: &'static str = "Units";
+    const POSITION_ID_COLUMN_STR: &'static str = "Position ID";
+    const ASSET_TYPE_COLUMN_STR: &'static str = "Asset type";
+    const ACTION_COLUMN_STR: &'static str = "Action";
+    const ISIN_COLUMN_STR: &'static str = "ISIN";
+
+    fn read_sheet(workbook: &mut Xlsx<Cursor<Vec<u8>>>, name: &str) -> Result<Rows> {
+        let range = workbook.worksheet_range(name)?;
+
+        Ok(range
+            .rows()
+            .map(|row| {
+                row.iter()
+                    .map(|cell| cell.to_string().trim().to_string())
+                    .collect()
+            })
+            .collect())
+    }
+
+    fn column(rows: &Rows, name: &str) -> Result<usize> {
+        rows.first()
+            .and_then(|header| header.iter().position(|x| x == name))
+            .ok_or_else(|| anyhow!("Column {} not found in eToro statement", name))
+    }
+
+    fn cell(row: &[String], index: usize) -> &str {
+        row.get(index).map_or("", |x| x.as_str())
+    }
+
+    fn ticker(details: &str) -> &str {
+        details.split('/').next().unwrap_or(details)
+    }
+
+    // Closed positions are the only ones with ISIN, they're linked to the account
+    // activity tickers by position id.
+    fn parse_companies_info(
+        closed_positions: &Rows,
+        account_activity: &Rows,
+    ) -> Result<HashMap<String, CompanyInfo>> {
+        log::debug!("parse companies info");
+        let id_column = EtoroParser::column(closed_positions, EtoroParser::POSITION_ID_COLUMN_STR)?;
+        let action_column = EtoroParser::column(closed_positions, EtoroParser::ACTION_COLUMN_STR)?;
+        let isin_column = EtoroParser::column(closed_positions, EtoroParser::ISIN_COLUMN_STR)?;
+
+        let mut positions: HashMap<&str, CompanyInfo> = HashMap::new();
+        for row in closed_positions.iter().skip(1) {
+            let action = EtoroParser::cell(row, action_column);
+            let name = action
+                .strip_prefix("Buy ")
+                .or_else(|| action.strip_prefix("Sell "))
+                .unwrap_or(action);
+            positions.insert(
+                EtoroParser::cell(row, id_column),
+                CompanyInfo::new(
+                    name.to_string(),
+                    EtoroParser::cell(row, isin_column).to_string(),
+                ),
+            );
+        }
+
+        let id_column = EtoroParser::column(account_activity, EtoroParser::POSITION_ID_COLUMN_STR)?;
+        let details_column =
+            EtoroParser::column(account_activity, EtoroParser::DETAILS_COLUMN_STR)?;
+
+        let mut result = HashMap::new();
+        for row in account_activity.iter().skip(1) {
+            if let Some(company) = positions.get(EtoroParser::cell(row, id_column)) {
+                let ticker = EtoroParser::ticker(EtoroParser::cell(row, details_column));
+                result.insert(ticker.to_string(), company.clone());
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn company_info(&self, ticker: &str) -> CompanyInfo {
+        self.companies_info.get(ticker).cloned().unwrap_or_else(|| {
+            log::error!("Not company info found for {}", ticker);
+            CompanyInfo {
+                name: ticker.to_string(),
+                isin: "".to_string(),
+            }
+        })
+    }
+
+    fn parse_date(date: &str) -> Result<NaiveDate> {
+        Ok(NaiveDateTime::parse_from_str(date, EtoroParser::DATE_FORMAT_STR)?.date())
+    }
+
+    fn price(value: &Decimal, quantity: &Decimal) -> Decimal {
+        if quantity.is_zero() {
+            Decimal::ZERO
+        } else {
+            (*value / *quantity).round_dp(4)
+        }
+    }
+
+    fn decimal(row: &[String], index: usize) -> Result<Decimal> {
+        Ok(Decimal::from_str(&decimal::normalize_str(
+            EtoroParser::cell(row, index),
+        ))?)
+    }
+
+    /// Opened and closed positions of the account activity, rows that can't be read are
+    /// reported with their row in the sheet.
+    pub fn parse_account_notes(&self) -> Result<(AccountNotes, ParseErrors)> {
+        let rows = &self.account_activity;
+        let date_column = EtoroParser::column(rows, EtoroParser::DATE_COLUMN_STR)?;
+        let type_column = EtoroParser::column(rows, EtoroParser::TYPE_COLUMN_STR)?;
+        let details_column = EtoroParser::column(rows, EtoroParser::DETAILS_COLUMN_STR)?;
+        let amount_column = EtoroParser::column(rows, EtoroParser::AMOUNT_COLUMN_STR)?;
+        let units_column = EtoroParser::column(rows, EtoroParser::UNITS_COLUMN_STR)?;
+        let asset_type_column = EtoroParser::column(rows, EtoroParser::ASSET_TYPE_COLUMN_STR)?;
+
+        let mut result = Vec::new();
+        let mut errors = Vec::new();
+
+        for (i, row) in rows.iter().enumerate().skip(1) {
+            let operation = match EtoroParser::cell(row, type_column) {
+                EtoroParser::OPEN_POSITION_STR => BrokerOperation::Buy,
+                EtoroParser::POSITION_CLOSED_STR => BrokerOperation::Sell,
+                _ => continue,
+            };
+            if EtoroParser::cell(row, asset_type_column) == EtoroParser::CFD_STR {
+                continue;
+            }
+            log::debug!("account note fields {:?}", row);
+
+            let fields = (
+                EtoroParser::decimal(row, units_column),
+                EtoroParser::decimal(row, amount_column),
+                EtoroParser::parse_date(EtoroParser::cell(row, date_column)),
+            );
+            let (quantity, value, date) = match fields {
+                (Ok(quantity), Ok(value), Ok(date)) => (quantity, value, date),
+                (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => {
+                    errors.push(ParseError::new(i + 1, err.to_string()));
+                    continue;
+                }
+            };
+            let ticker = EtoroParser::ticker(EtoroParser::cell(row, details_column));
+
+            result.push(AccountNote::new(
+                date,
+                self.company_info(ticker),
+                operation,
+                quantity,
+                EtoroParser::price(&value, &quantity),
+                value,
+                Decimal::ZERO,
+                &self.broker,
+            ));
+        }
+
+        Ok((result, errors))
+    }
+
+    pub fn report_year(&self) -> Option<usize> {
+        let rows = &self.account_activity;
+        let date_column = EtoroParser::column(rows, EtoroParser::DATE_COLUMN_STR).ok()?;
+
+        rows.iter()
+            .skip(1)
+            .filter_map(|row| EtoroParser::parse_date(EtoroParser::cell(row, date_column)).ok())
+            .map(|date| date.year() as usize)
+            .max()
+    }
+
+    /// Open positions at the end of the statement. The statement only has the invested
+    /// amount, not the value at 31/12, so the holdings are left without value for the user to
+    /// type it, and the ones without ISIN are reported instead of imported.
+    pub fn parse_balance_notes(&self) -> Result<(BalanceNotes, ParseErrors)> {
+        let rows = &self.account_activity;
+        let type_column = EtoroParser::column(rows, EtoroParser::TYPE_COLUMN_STR)?;
+        let details_column = EtoroParser::column(rows, EtoroParser::DETAILS_COLUMN_STR)?;
+        let units_column = EtoroParser::column(rows, EtoroParser::UNITS_COLUMN_STR)?;
+        let id_column = EtoroParser::column(rows, EtoroParser::POSITION_ID_COLUMN_STR)?;
+        let asset_type_column = EtoroParser::column(rows, EtoroParser::ASSET_TYPE_COLUMN_STR)?;
+
+        let closed_ids: Vec<&str> = rows
+            .iter()
+            .skip(1)
+            .filter(|row| EtoroParser::cell(row, type_column) == EtoroParser::POSITION_CLOSED_STR)
+            .map(|row| EtoroParser::cell(row, id_column))
+            .collect();
+
+        // Open lots of the same ticker are added up into one holding.
+        let mut holdings: Vec<(&str, Decimal)> = Vec::new();
+        let mut errors = Vec::new();
+        for (i, row) in rows.iter().enumerate().skip(1) {
+            if EtoroParser::cell(row, type_column) != EtoroParser::OPEN_POSITION_STR
+                || EtoroParser::cell(row, asset_type_column) == EtoroParser::CFD_STR
+                || closed_ids.contains(&EtoroParser::cell(row, id_column))
+            {
+                continue;
+            }
+
+            let quantity = match EtoroParser::decimal(row, units_column) {
+                Ok(quantity) => quantity,
+                Err(err) => {
+                    errors.push(ParseError::new(i + 1, err.to_string()));
+                    continue;
+                }
+            };
+            let ticker = EtoroParser::ticker(EtoroParser::cell(row, details_column));
+
+            match holdings.iter_mut().find(|x| x.0 == ticker) {
+                Some(holding) => holding.1 += quantity,
+                None => holdings.push((ticker, quantity)),
+            }
+        }
+
+        let mut result = Vec::new();
+        for (ticker, quantity) in holdings {
+            let company = self.company_info(ticker);
+            if !validate_isin(&company.isin) {
+                errors.push(ParseError::new(
+                    0,
+                    format!(
+                        "Posición abierta de {} sin ISIN en el informe de eToro, añádela a mano",
+                        ticker
+                    ),
+                ));
+                continue;
+            }
+            let mut note = BalanceNote::new(
+                company,
+                String::from(""),
+                quantity,
+                String::from(EtoroParser::USD_CURRENCY_STR),
+                Decimal::ZERO,
+                Decimal::ZERO,
+                &self.broker,
+            );
+            note.derived = true;
+            result.push(note);
+        }
+        if !result.is_empty() {
+            errors.push(ParseError::new(
+                0,
+                String::from(
+                    "El informe de eToro no incluye el valor a 31/12 de las posiciones abiertas, introdúcelo en la tabla",
+                ),
+            ));
+        }
+
+        Ok((result, errors))
+    }
+
+    pub fn new(content: Vec<u8>, broker: &Arc<BrokerInformation>) -> Result<Self> {
+        let mut workbook: Xlsx<_> = open_workbook_from_rs(Cursor::new(content))?;
+
+        let sheet_names = workbook.sheet_names();
+        for sheet in [
+            EtoroParser::CLOSED_POSITIONS_SHEET_STR,
+            EtoroParser::ACCOUNT_ACTIVITY_SHEET_STR,
+        ] {
+            if !sheet_names.iter().any(|x| x == sheet) {
+                bail!("Not found {} sheet in eToro statement", sheet);
+            }
+        }
+
+        let closed_positions =
+            EtoroParser::read_sheet(&mut workbook, EtoroParser::CLOSED_POSITIONS_SHEET_STR)?;
+        let account_activity =
+            EtoroParser::read_sheet(&mut workbook, EtoroParser::ACCOUNT_ACTIVITY_SHEET_STR)?;
+        let companies_info =
+            EtoroParser::parse_companies_info(&closed_positions, &account_activity)?;
+
+        Ok(Self {
+            account_activity,
+            broker: Arc::clone(broker),
+            companies_info,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[ctor::ctor]
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn etoro_parse_account_notes_test() {
+        let etoro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("eToro"),
+            String::from("CY"),
+        ));
+        let parser = EtoroParser::new(INPUT_2023.to_vec(), &etoro_broker).unwrap();
+        let (notes, errors) = parser.parse_account_notes().unwrap();
+        assert!(errors.is_empty());
+
+        let acc_notes = vec![
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 3, 15).unwrap(),
+                CompanyInfo {
+                    name: String::from("Apple"),
+                    isin: String::from("US0378331005"),
+                },
+                BrokerOperation::Buy,
+                Decimal::new(6_5, 1),
+                Decimal::new(153_8462, 4),
+                Decimal::new(1000_00, 2),
+                Decimal::ZERO,
+                &etoro_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 5, 10).unwrap(),
+                CompanyInfo {
+                    name: String::from("MSFT"),
+                    isin: String::from(""),
+                },
+                BrokerOperation::Buy,
+                Decimal::new(4_123456, 6),
+                Decimal::new(291_0180, 4),
+                Decimal::new(1200_00, 2),
+                Decimal::ZERO,
+                &etoro_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 9, 20).unwrap(),
+                CompanyInfo {
+                    name: String::from("Apple"),
+                    isin: String::from("US0378331005"),
+                },
+                BrokerOperation::Sell,
+                Decimal::new(6_5, 1),
+                Decimal::new(172_3846, 4),
+                Decimal::new(1120_50, 2),
+                Decimal::ZERO,
+                &etoro_broker,
+            ),
+        ];
+
+        assert_eq!(acc_notes, notes);
+    }
+
+    #[test]
+    fn etoro_parse_balance_notes_test() {
+        let etoro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("eToro"),
+            String::from("CY"),
+        ));
+        let parser = EtoroParser::new(INPUT_2023.to_vec(), &etoro_broker).unwrap();
+        let (notes, errors) = parser.parse_balance_notes().unwrap();
+        assert_eq!(parser.report_year(), Some(2023));
+
+        // MSFT is the only open position and it has no ISIN.
+        assert!(notes.is_empty());
+        assert_eq!(
+            errors,
+            vec![ParseError {
+                line: 0,
+                reason: String::from(
+                    "Posición abierta de MSFT sin ISIN en el informe de eToro, añádela a mano"
+                ),
+            }]
+        );
+    }
+
+    #[test]
+    fn etoro_balance_notes_without_value_test() {
+        let etoro_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("eToro"),
+            String::from("CY"),
+        ));
+        let mut parser = EtoroParser::new(INPUT_2023.to_vec(), &etoro_broker).unwrap();
+        parser.companies_info.insert(
+            String::from("MSFT"),
+            CompanyInfo::new(String::from("Microsoft"), String::from("US5949181045")),
+        );
+        let (notes, errors) = parser.parse_balance_notes().unwrap();
+
+        let mut expected = BalanceNote::new(
+            CompanyInfo {
+                name: String::from("Microsoft"),
+                isin: String::from("US5949181045"),
+            },
+            String::from(""),
+            Decimal::new(4_123456, 6),
+            String::from("USD"),
+            Decimal::ZERO,
+            Decimal::ZERO,
+            &etoro_broker,
+        );
+        expected.derived = true;
+        assert_eq!(notes, vec![expected]);
+        assert_eq!(errors.len(), 1);
+    }
+
+    const INPUT_2023: &[u8] = include_bytes!("testdata/etoro_test.xlsx");
+}
@@ -7,10 +7,10 @@ use std::{
 use crate::{
     data::{
         AccountNote, AccountNotes, BalanceNote, BalanceNotes, BrokerInformation, BrokerOperation,
-        CompanyInfo,
+        CompanyInfo, ParseError, ParseErrors,
     },
     parsers::util,
-    utils::decimal,
+    utils::{decimal, default_year},
 };
 use anyhow::{anyhow, bail, Result};
 use chrono::NaiveDate;
@@ -34,6 +34,8 @@ static TBODY_TR_SELECTOR: LazyLock<Selector> =
 static TBODY_ACCOUNT_TR_SELECTOR: LazyLock<Selector> =
     LazyLock::new(|| Selector::parse(r#"tbody tr:not(.subtotal):not(.total)"#).unwrap());
 static TR_SELECTOR: LazyLock<Selector> = LazyLock::new(|| Selector::parse(r#"tr"#).unwrap());
+static TH_SELECTOR: LazyLock<Selector> = LazyLock::new(|| Selector::parse(r#"th"#).unwrap());
+static TD_SELECTOR: LazyLock<Selector> = LazyLock::new(|| Selector::parse(r#"td"#).unwrap());
 
 enum NoteState {
     Invalid,
@@ -53,6 +55,13 @@ static STOCKS_STRS: LazyLock<HashSet<Option<&'static str>>> =
 
 impl IBParser {
     const EUR_CURRENCY_STR: &'static str = "EUR";
+    const ROW_SUMMARY_CLASS: &'static str = "row-summary";
+    const ROW_DETAIL_CLASS: &'static str = "row-detail";
+
+    pub fn report_year(&self) -> Option<usize> {
+        let text: String = self.dom.root_element().text().collect();
+        util::period_end_year(&text)
+    }
 
     pub fn new(data: &str, broker: &Arc<BrokerInformation>) -> Result<Self> {
         let dom = Html::parse_document(data);
@@ -65,6 +74,37 @@ impl IBParser {
         })
     }
 
+    /// Combined statements are split in several files, the contract information of any of
+    /// them is used to identify the tickers of the rest.
+    pub fn new_combined(
+        documents: &[String],
+        broker: &Arc<BrokerInformation>,
+    ) -> Result<Vec<Self>> {
+        let mut parsers = documents
+            .iter()
+            .map(|data| IBParser::new(data, broker))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut companies_info = HashMap::new();
+        for parser in &parsers {
+            for (ticker, company) in &parser.companies_info {
+                companies_info
+                    .entry(ticker.clone())
+                    .or_insert_with(|| company.clone());
+            }
+        }
+        for parser in &mut parsers {
+            parser.companies_info = companies_info.clone();
+        }
+
+        Ok(parsers)
+    }
+
+    /// Files of a combined statement without the open positions section.
+    pub fn has_open_positions(&self) -> bool {
+        self.dom.select(&OPEN_POSITIONS_SELECTOR).next().is_some()
+    }
+
     fn parse_account_note(
         &self,
         row: &ElementRef<'_>,
@@ -126,13 +166,39 @@ impl IBParser {
         ))
     }
 
-    pub fn parse_account_notes(&self) -> Result<AccountNotes> {
+    fn row_symbol(row: &ElementRef<'_>, with_account_field: bool) -> Option<String> {
+        let offset = if with_account_field { 1 } else { 0 };
+        row.select(&TD_SELECTOR)
+            .nth(offset)
+            .map(|cell| cell.text().collect::<String>().trim().to_string())
+    }
+
+    // Newer statements don't always mark single execution orders as row-summary, those rows
+    // are trades too when the first cell is a known ticker and every column is present.
+    fn is_trade_row(&self, row: &ElementRef<'_>, columns: usize, with_account_field: bool) -> bool {
+        if row.value().has_class(
+            IBParser::ROW_SUMMARY_CLASS,
+            CaseSensitivity::AsciiCaseInsensitive,
+        ) {
+            return true;
+        }
+
+        row.select(&TD_SELECTOR).count() == columns
+            && IBParser::row_symbol(row, with_account_field)
+                .is_some_and(|symbol| self.companies_info.contains_key(&symbol))
+    }
+
+    pub fn parse_account_notes(&self) -> Result<(AccountNotes, ParseErrors)> {
         let mut result = Vec::new();
+        let mut errors = Vec::new();
         log::debug!("parsing account notes");
 
         if let Some(transactions) = self.dom.select(&TRANSACTIONS_SELECTOR).next() {
             let mut state = NoteState::Invalid;
             let mut with_account_field = false;
+            let mut columns = 0;
+            // Symbol of the last row-summary, its row-detail executions are already included.
+            let mut summary_symbol = None;
 
             for table_row in transactions.select(&THEAD_TH_TR_SELECTOR) {
                 let row_values = table_row.text().filter(|x| *x != "\n").collect::<Vec<_>>();
@@ -140,6 +206,7 @@ impl IBParser {
                 if row_values[0] == "Account" {
                     with_account_field = true;
                 }
+                columns = table_row.select(&TH_SELECTOR).count();
             }
 
             for table_row in transactions.select(&TBODY_ACCOUNT_TR_SELECTOR) {
@@ -195,7 +262,44 @@ impl IBParser {
                             .map(has_currency_class)
                             != Some(true)
                         {
-                            result.push(self.parse_account_note(&table_row, with_account_field)?);
+                            let symbol = IBParser::row_symbol(&table_row, with_account_field);
+                            let element = table_row.value();
+                            if element.has_class(
+                                IBParser::ROW_DETAIL_CLASS,
+                                CaseSensitivity::AsciiCaseInsensitive,
+                            ) && symbol.is_some()
+                                && symbol == summary_symbol
+                            {
+                                log::debug!("Skipping execution of order {:?}", symbol);
+                            } else if self.is_trade_row(&table_row, columns, with_account_field) {
+                                summary_symbol = if element.has_class(
+                                    IBParser::ROW_SUMMARY_CLASS,
+                                    CaseSensitivity::AsciiCaseInsensitive,
+                                ) {
+                                    symbol.clone()
+                                } else {
+                                    None
+                                };
+                                match self.parse_account_note(&table_row, with_account_field) {
+                                    Ok(account_note) => result.push(account_note),
+                                    Err(msg) => {
+                                        log::error!(
+                                            "Error parsing account note: {:?}",
+                                            msg.to_string()
+                                        );
+                                        errors.push(ParseError::new(
+                                            0,
+                                            format!(
+                                                "Operación {} no válida: {}",
+                                                symbol.unwrap_or_default(),
+                                                msg
+                                            ),
+                                        ));
+                                    }
+                                }
+                            } else {
+                                log::warn!("Skipping unknown transaction row {:?}", symbol);
+                            }
                         }
                     }
                     NoteState::Total => {
@@ -205,7 +309,7 @@ impl IBParser {
             }
         }
 
-        Ok(result)
+        Ok((result, errors))
     }
 
     fn parse_companies_info(dom: &Html) -> Result<HashMap<String, CompanyInfo>> {
@@ -243,10 +347,7 @@ impl IBParser {
 
                     result.insert(
                         String::from(*ticker),
-                        CompanyInfo {
-                            name: String::from(*name),
-                            isin: String::from(*isin),
-                        },
+                        CompanyInfo::new(String::from(*name), String::from(*isin)),
                     );
                 }
             }
@@ -294,7 +395,7 @@ impl IBParser {
             })
             .unwrap();
 
-        Ok(BalanceNote::new(
+        Ok(util::normalize_pence(BalanceNote::new(
             company_info,
             String::from(""),
             Decimal::from_str(&decimal::normalize_str(quantity))?
@@ -303,17 +404,59 @@ impl IBParser {
             Decimal::from_str(&decimal::normalize_str(price))?,
             Decimal::from_str(&decimal::normalize_str(value_in_euro))?,
             &self.broker,
-        ))
+        )))
     }
 
-    pub fn parse_balance_notes(&self) -> Result<BalanceNotes> {
+    /// Value in EUR of every stock position as summed by IB, the "Total Stocks in EUR" row.
+    pub fn parse_base_currency_total(&self) -> Option<Decimal> {
+        let table_open_positions = self.dom.select(&OPEN_POSITIONS_SELECTOR).next()?;
+
+        table_open_positions
+            .select(&TBODY_TR_SELECTOR)
+            .filter(|row| {
+                row.value()
+                    .has_class("total", CaseSensitivity::AsciiCaseInsensitive)
+            })
+            .filter_map(|row| {
+                let field_values = row.text().filter(|x| *x != "\n").collect::<Vec<_>>();
+                let label = field_values.first()?.replace('\u{a0}', " ");
+                let is_stocks_total = STOCKS_STRS
+                    .iter()
+                    .flatten()
+                    .any(|stocks| label.contains(stocks));
+                if !is_stocks_total || !label.ends_with(IBParser::EUR_CURRENCY_STR) {
+                    return None;
+                }
+                Decimal::from_str(&decimal::normalize_str(field_values.get(5)?)).ok()
+            })
+            .last()
+    }
+
+    /// Compares the parsed positions with the base currency total of the report.
+    pub fn check_base_currency_total(&self, notes: &[BalanceNote]) -> Option<ParseError> {
+        self.parse_base_currency_total()
+            .and_then(|total| util::check_total_in_euro(notes, &total))
+    }
+
+    fn total_value(row: &ElementRef<'_>) -> Result<Decimal> {
+        let field_values = row.text().filter(|x| *x != "\n").collect::<Vec<_>>();
+        let total = field_values
+            .get(5)
+            .ok_or_else(|| anyhow!("Unable to get total value"))?;
+
+        Ok(Decimal::from_str(&decimal::normalize_str(total))?)
+    }
+
+    pub fn parse_balance_notes(&self) -> Result<(BalanceNotes, ParseErrors)> {
         log::debug!("parsing balance notes");
         let mut result = Vec::new();
+        let mut errors = vec![];
 
         if let Some(table_open_positions) = self.dom.select(&OPEN_POSITIONS_SELECTOR).next() {
             let mut state = NoteState::Invalid;
             let mut current_notes: BalanceNotes = Vec::new();
             let mut currency = None;
+            let mut local_total = None;
 
             for table_row in table_open_positions.select(&TBODY_TR_SELECTOR) {
                 match state {
@@ -356,14 +499,30 @@ impl IBParser {
                                 result.append(&mut current_notes);
                             } else {
                                 state = NoteState::Total;
+                                local_total = IBParser::total_value(&table_row).ok().map(|total| {
+                                    util::normalize_pence_amount(
+                                        total,
+                                        currency.unwrap_or_default(),
+                                    )
+                                });
                             }
                         } else {
                             let balance_note_result = self.parse_balance_note(&table_row, currency);
                             match balance_note_result {
                                 Ok(balance_note) => current_notes.push(balance_note),
                                 Err(msg) => {
-                                    log::error!("Error parsing balance note: {}", msg);
-                                    return Err(msg);
+                                    log::error!(
+                                        "Error parsing balance note: {:?}",
+                                        msg.to_string()
+                                    );
+                                    errors.push(ParseError::new(
+                                        0,
+                                        format!(
+                                            "Posición {} no válida: {}",
+                                            table_row.text().next().unwrap_or_default(),
+                                            msg
+                                        ),
+                                    ));
                                 }
                             }
                         }
@@ -375,17 +534,29 @@ impl IBParser {
                             .has_class("total", CaseSensitivity::AsciiCaseInsensitive)
                         {
                             state = NoteState::Stocks;
-                            let field_values =
-                                table_row.text().filter(|x| *x != "\n").collect::<Vec<_>>();
-                            let total_in_euro_str = field_values
-                                .get(5)
-                                .ok_or_else(|| anyhow!("Unable to get total in euro"))?;
-                            let total_in_euro =
-                                Decimal::from_str(&decimal::normalize_str(total_in_euro_str))?;
-                            log::debug!("total in eur: {:?}", total_in_euro);
-                            util::recalculate_balance_notes(&mut current_notes, &total_in_euro)?;
+                            let recalculated =
+                                IBParser::total_value(&table_row).and_then(|total_in_euro| {
+                                    log::debug!("total in eur: {:?}", total_in_euro);
+                                    util::recalculate_balance_notes(
+                                        &mut current_notes,
+                                        &total_in_euro,
+                                        local_total.as_ref(),
+                                    )
+                                });
+                            if let Err(err) = recalculated {
+                                errors.push(ParseError::new(
+                                    0,
+                                    format!("Total en euros no válido: {}", err),
+                                ));
+                                self.set_values_in_euro(&mut current_notes, &mut errors);
+                            }
                         } else {
                             state = NoteState::Invalid;
+                            errors.push(ParseError::new(
+                                0,
+                                "Posiciones sin total en euros en el informe".to_string(),
+                            ));
+                            self.set_values_in_euro(&mut current_notes, &mut errors);
                         }
                         result.append(&mut current_notes);
                     }
@@ -395,7 +566,17 @@ impl IBParser {
             bail!("Unable to find div with open positions");
         }
 
-        Ok(result)
+        Ok((result, errors))
+    }
+
+    // Without the total in euros of their currency, positions are valued with the year-end
+    // exchange rate.
+    fn set_values_in_euro(&self, notes: &mut BalanceNotes, errors: &mut ParseErrors) {
+        let year = self.report_year().unwrap_or_else(default_year);
+        for note in notes.iter_mut() {
+            let amount = note.value_in_euro;
+            util::set_value_in_euro(note, &amount, year, errors);
+        }
     }
 }
 
@@ -416,7 +597,8 @@ mod tests {
             String::from("IE"),
         ));
         let ibparser = IBParser::new(DEFAULT_HTML_TEST, &ib_broker).unwrap();
-        let notes = ibparser.parse_account_notes().unwrap();
+        let (notes, errors) = ibparser.parse_account_notes().unwrap();
+        assert!(errors.is_empty());
 
         let acc_notes = vec![
             AccountNote::new(
@@ -476,6 +658,49 @@ mod tests {
         assert_eq!(acc_notes, notes);
     }
 
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn ibparser_parse_unclassed_account_notes_test() {
+        let ib_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Interactive Brokers"),
+            String::from("IE"),
+        ));
+        let ibparser = IBParser::new(UNCLASSED_ROWS_HTML_TEST, &ib_broker).unwrap();
+        let (notes, errors) = ibparser.parse_account_notes().unwrap();
+        assert!(errors.is_empty());
+
+        let acc_notes = vec![
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 5, 2).unwrap(),
+                CompanyInfo {
+                    name: String::from("EUROTECH SPA"),
+                    isin: String::from("IT0003895668"),
+                },
+                BrokerOperation::Buy,
+                Decimal::new(500, 0),
+                Decimal::new(3_1200, 4),
+                Decimal::new(1560_00, 2),
+                Decimal::new(3_00, 2),
+                &ib_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 6, 7).unwrap(),
+                CompanyInfo {
+                    name: String::from("PROSUS NV"),
+                    isin: String::from("NL0013654783"),
+                },
+                BrokerOperation::Sell,
+                Decimal::new(20, 0),
+                Decimal::new(65_1000, 4),
+                Decimal::new(1302_00, 2),
+                Decimal::new(3_00, 2),
+                &ib_broker,
+            ),
+        ];
+
+        assert_eq!(acc_notes, notes);
+    }
+
     #[test]
     #[allow(clippy::mistyped_literal_suffixes)]
     fn ibparser_parse_balance_notes_test() {
@@ -484,7 +709,12 @@ mod tests {
             String::from("IE"),
         ));
         let ibparser = IBParser::new(DEFAULT_HTML_TEST, &ib_broker).unwrap();
-        let notes = ibparser.parse_balance_notes().unwrap();
+        let (notes, errors) = ibparser.parse_balance_notes().unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(
+            ibparser.parse_base_currency_total(),
+            Some(Decimal::new(21648_63, 2))
+        );
 
         let bal_notes = vec![
             BalanceNote::new(
@@ -576,5 +806,93 @@ mod tests {
         assert_eq!(bal_notes, notes);
     }
 
+    #[test]
+    fn ibparser_new_combined_test() {
+        let ib_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Interactive Brokers"),
+            String::from("IE"),
+        ));
+        let (trades, contracts) = UNCLASSED_ROWS_HTML_TEST.split_at(
+            UNCLASSED_ROWS_HTML_TEST
+                .find("<div id=\"tblContractInfo")
+                .unwrap(),
+        );
+
+        let parser = IBParser::new(trades, &ib_broker).unwrap();
+        let (notes, _) = parser.parse_account_notes().unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].company.isin, "");
+
+        let documents = [
+            String::from(trades),
+            String::from(contracts),
+            String::from(DEFAULT_HTML_TEST),
+        ];
+        let parsers = IBParser::new_combined(&documents, &ib_broker).unwrap();
+        assert_eq!(parsers.len(), 3);
+        let (notes, _) = parsers[0].parse_account_notes().unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].company.isin, "IT0003895668");
+        assert_eq!(notes[1].company.isin, "NL0013654783");
+        assert!(parsers[1].parse_account_notes().unwrap().0.is_empty());
+        assert!(!parsers[0].has_open_positions());
+        assert_eq!(parsers[0].parse_base_currency_total(), None);
+        assert!(!parsers[1].has_open_positions());
+        assert!(parsers[2].has_open_positions());
+        assert_eq!(parsers[2].parse_balance_notes().unwrap().0.len(), 7);
+    }
+
+    #[test]
+    fn ibparser_parse_account_notes_skips_malformed_rows_test() {
+        let ib_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Interactive Brokers"),
+            String::from("IE"),
+        ));
+        let (expected, _) = IBParser::new(DEFAULT_HTML_TEST, &ib_broker)
+            .unwrap()
+            .parse_account_notes()
+            .unwrap();
+        let html = DEFAULT_HTML_TEST.replace("2019-04-16, 04:19:18", "16/04/2019");
+        let ibparser = IBParser::new(&html, &ib_broker).unwrap();
+        let (notes, errors) = ibparser.parse_account_notes().unwrap();
+        assert_eq!(notes.len(), expected.len() - 1);
+        assert_eq!(errors.len(), 1);
+    }
+
     const DEFAULT_HTML_TEST: &str = include_str!("testdata/ib_test.html");
+
+    // An unclassed single execution order, followed by a summary with its execution detail.
+    const UNCLASSED_ROWS_HTML_TEST: &str = r#"<div id="tblTransactions_XXXXXXXBody"><div>
+<table>
+<thead>
+<tr><th>Symbol</th><th>Date/Time</th><th>Quantity</th><th>T. Price</th><th>C. Price</th><th>Proceeds</th><th>Comm/Fee</th><th>Basis</th><th>Realized P/L</th><th>Realized P/L %</th><th>MTM P/L</th><th>Code</th></tr>
+</thead>
+<tbody>
+<tr><td class="header-asset" colspan="12">Stocks</td></tr>
+</tbody>
+<tbody>
+<tr><td class="header-currency" colspan="12">EUR</td></tr>
+</tbody>
+<tbody>
+<tr>
+<td>E5T</td><td>2023-05-02, 09:31:12</td><td>500</td><td>3.1200</td><td>3.1500</td><td>-1,560.00</td><td>-3.00</td><td>1,563.00</td><td>0.00</td><td>0.00</td><td>15.00</td><td>O</td>
+</tr>
+</tbody>
+<tbody>
+<tr class="row-summary">
+<td>PRX</td><td>2023-06-07, 10:02:45</td><td>-20</td><td>65.1000</td><td>65.3000</td><td>1,302.00</td><td>-3.00</td><td>-1,470.00</td><td>-171.00</td><td>-11.63</td><td>-4.00</td><td>C</td>
+</tr>
+<tr class="row-detail">
+<td>PRX</td><td>2023-06-07, 10:02:45</td><td>-20</td><td>65.1000</td><td>65.3000</td><td>1,302.00</td><td>-3.00</td><td>-1,470.00</td><td>-171.00</td><td>-11.63</td><td>-4.00</td><td>C</td>
+</tr>
+</tbody>
+</table>
+</div></div>
+<div id="tblContractInfoXXXXXXXBody"><div>
+<table>
+<tr><td class="header-asset" colspan="5">Stocks</td></tr>
+<tr><td>E5T</td><td>EUROTECH SPA</td><td>73400808</td><td>IT0003895668</td><td>1</td></tr>
+<tr><td>PRX</td><td>PROSUS NV</td><td>382633310</td><td>NL0013654783</td><td>1</td></tr>
+</table>
+</div></div>"#;
 }
@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use chrono::NaiveDate;
 use rust_decimal::Decimal;
 use std::{collections::HashMap, str::FromStr, sync::Arc};
@@ -6,10 +6,10 @@ use std::{collections::HashMap, str::FromStr, sync::Arc};
 use crate::{
     data::{
         AccountNote, AccountNotes, BalanceNote, BalanceNotes, BrokerInformation, BrokerOperation,
-        CompanyInfo,
+        CompanyInfo, ParseError, ParseErrors,
     },
     parsers::util,
-    utils::decimal,
+    utils::{decimal, default_year},
 };
 
 enum NoteState {
@@ -22,6 +22,8 @@ pub struct IBCSVParser {
     locale: &'static [&'static str],
     broker: Arc<BrokerInformation>,
     companies_info: HashMap<String, CompanyInfo>,
+    // Contract multiplier per symbol, the positions and trades ones may be missing or 1.
+    multipliers: HashMap<String, Decimal>,
 }
 
 impl IBCSVParser {
@@ -39,7 +41,14 @@ impl IBCSVParser {
     const TRADE_STOCK_STR: usize = 9;
     const STOCK_COMPANY_INFO_SECTOR_START_OLD_STR: usize = 10;
 
+    const COMPANY_INFO_MULTIPLIER_FIELD: usize = 9;
+    const COMPANY_INFO_MULTIPLIER_OLD_FIELD: usize = 8;
+
+    const ACCOUNT_NOTE_MIN_FIELDS: usize = 15;
+    const BALANCE_NOTE_MIN_FIELDS: usize = 12;
+
     const ES_HEADER_CONTENT: &str = "Statement,Header,Nombre del campo,Valor del campo";
+    const PERIOD_STR: &str = "Statement,Data,Period,";
 
     const EN_MSGS: &'static [&'static str] = &[
         "Financial Instrument Information,Header,Asset Category,Symbol,Description,Conid,Security ID,Underlying,Listing Exch,Multiplier,Type,Code", // STOCK_COMPANY_INFO_SECTOR_START_STR
@@ -69,16 +78,24 @@ impl IBCSVParser {
         "Información de instrumento financiero,Header,Categoría de activo,Símbolo,Descripción,Conid,Id. de seguridad,Merc. de cotización,Multiplicador,Tipo,Código",  // STOCK_COMPANY_INFO_SECTOR_START_OLD_STR
     ];
 
+    /// Company and contract multiplier of each symbol, older reports lack the underlying
+    /// column so the multiplier is one column before.
     fn parse_companies_info(
         content: &str,
         locale: &[&'static str],
-    ) -> Result<HashMap<String, CompanyInfo>> {
+    ) -> Result<(HashMap<String, CompanyInfo>, HashMap<String, Decimal>)> {
         log::debug!("parse companies info");
         let mut result: HashMap<String, CompanyInfo> = HashMap::new();
+        let mut multipliers: HashMap<String, Decimal> = HashMap::new();
 
-        let start = content
+        let (start, multiplier_field) = content
             .find(locale[IBCSVParser::STOCK_COMPANY_INFO_SECTOR_START_STR])
-            .or_else(|| content.find(locale[IBCSVParser::STOCK_COMPANY_INFO_SECTOR_START_OLD_STR]))
+            .map(|start| (start, IBCSVParser::COMPANY_INFO_MULTIPLIER_FIELD))
+            .or_else(|| {
+                content
+                    .find(locale[IBCSVParser::STOCK_COMPANY_INFO_SECTOR_START_OLD_STR])
+                    .map(|start| (start, IBCSVParser::COMPANY_INFO_MULTIPLIER_OLD_FIELD))
+            })
             .ok_or_else(|| anyhow!("Not found beginning of companies info section"))?;
 
         let end_left = content
@@ -93,29 +110,68 @@ impl IBCSVParser {
 
         for record_result in rdr.records() {
             let record = record_result?;
+            let symbol = String::from(record.get(3).ok_or_else(|| anyhow!("Unknown ticker"))?);
+            if let Some(multiplier) = record
+                .get(multiplier_field)
+                .and_then(|mult| Decimal::from_str(&decimal::normalize_str(mult)).ok())
+                .filter(|mult| *mult > Decimal::ZERO)
+            {
+                multipliers.insert(symbol.clone(), multiplier);
+            }
             result.insert(
-                String::from(record.get(3).ok_or_else(|| anyhow!("Unknown ticker"))?),
-                CompanyInfo {
-                    name: String::from(
+                symbol,
+                CompanyInfo::new(
+                    String::from(
                         record
                             .get(4)
                             .ok_or_else(|| anyhow!("Unknown company name"))?,
                     ),
-                    isin: String::from(record.get(6).ok_or_else(|| anyhow!("Unknown isin"))?),
-                },
+                    String::from(record.get(6).ok_or_else(|| anyhow!("Unknown isin"))?),
+                ),
             );
         }
 
-        Ok(result)
+        Ok((result, multipliers))
+    }
+
+    /// The instrument information one, else the one of the line when there's one.
+    fn multiplier(&self, symbol: &str, line_multiplier: Option<&str>) -> Result<Decimal> {
+        match self.multipliers.get(symbol) {
+            Some(multiplier) => Ok(*multiplier),
+            None => match line_multiplier.filter(|mult| !mult.is_empty()) {
+                Some(mult) => Ok(Decimal::from_str(&decimal::normalize_str(mult))?),
+                None => Ok(Decimal::ONE),
+            },
+        }
     }
 
-    fn parse_account_note(&self, fields: &[&str]) -> Result<AccountNote> {
+    /// Column offset of the trade rows following the given line if it's a
+    /// trades header, as older reports don't include the account column.
+    fn trade_header_offset(&self, line: &str) -> Option<usize> {
+        let line = line.trim_end();
+        if line == self.locale[IBCSVParser::TRADE_BEGIN_STR] {
+            Some(1)
+        } else if line == self.locale[IBCSVParser::TRADE_BEGIN__NO_ACCOUNT_STR] {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    fn parse_account_note(&self, fields: &[&str], offset: usize) -> Result<AccountNote> {
         log::debug!("account note fields {:?}", fields);
-        let offset = if fields.len() == 16 { 0 } else { 1 };
+        if fields.len() < IBCSVParser::ACCOUNT_NOTE_MIN_FIELDS + offset {
+            bail!(
+                "Expected at least {} fields, found {}",
+                IBCSVParser::ACCOUNT_NOTE_MIN_FIELDS + offset,
+                fields.len()
+            );
+        }
         let symbol = fields[5 + offset];
         let date = fields[6 + offset];
         let quantity_str = fields[7 + offset];
-        let quantity = Decimal::from_str(&decimal::normalize_str(quantity_str))?;
+        let quantity = Decimal::from_str(&decimal::normalize_str(quantity_str))?
+            * self.multiplier(symbol, None)?;
         let operation = if quantity.is_sign_negative() {
             BrokerOperation::Sell
         } else {
@@ -147,16 +203,22 @@ impl IBCSVParser {
         ))
     }
 
-    pub fn parse_account_notes(&self) -> Result<AccountNotes> {
+    /// Line number in the report of the given byte offset, starting at 1.
+    fn line_number(&self, offset: usize) -> usize {
+        self.content[..offset].matches('\n').count() + 1
+    }
+
+    pub fn parse_account_notes(&self) -> Result<(AccountNotes, ParseErrors)> {
         let mut result = Vec::new();
-        let start = self
-            .content
-            .find(self.locale[IBCSVParser::TRADE_BEGIN_STR])
-            .or_else(|| {
-                self.content
-                    .find(self.locale[IBCSVParser::TRADE_BEGIN__NO_ACCOUNT_STR])
-            })
-            .ok_or_else(|| anyhow!("Not found beginning of trades section"))?;
+        let mut errors = Vec::new();
+        let start = [
+            IBCSVParser::TRADE_BEGIN_STR,
+            IBCSVParser::TRADE_BEGIN__NO_ACCOUNT_STR,
+        ]
+        .iter()
+        .filter_map(|header| self.content.find(self.locale[*header]))
+        .min()
+        .ok_or_else(|| anyhow!("Not found beginning of trades section"))?;
 
         let end = self
             .content
@@ -165,19 +227,32 @@ impl IBCSVParser {
 
         let lines: Vec<&str> = (self.content[start..end - 1]).split('\n').collect();
 
-        for line in lines.iter() {
-            if line.starts_with(self.locale[IBCSVParser::TRADE_STOCK_STR]) {
+        let first_line = self.line_number(start);
+        let mut offset = 0;
+        for (i, line) in lines.iter().enumerate() {
+            if let Some(header_offset) = self.trade_header_offset(line) {
+                offset = header_offset;
+            } else if line.starts_with(self.locale[IBCSVParser::TRADE_STOCK_STR]) {
                 let replaced_line = util::replace_escaped_fields(line);
                 let fields: Vec<&str> = replaced_line.split(',').collect();
-                let account_note = self.parse_account_note(&fields)?;
-                result.push(account_note);
+                match self.parse_account_note(&fields, offset) {
+                    Ok(account_note) => result.push(account_note),
+                    Err(err) => errors.push(ParseError::new(first_line + i, err.to_string())),
+                }
             }
         }
 
-        Ok(result)
+        Ok((result, errors))
     }
 
     fn parse_balance_note(&self, fields: &[&str], currency: &Option<&str>) -> Result<BalanceNote> {
+        if fields.len() < IBCSVParser::BALANCE_NOTE_MIN_FIELDS {
+            bail!(
+                "Expected at least {} fields, found {}",
+                IBCSVParser::BALANCE_NOTE_MIN_FIELDS,
+                fields.len()
+            );
+        }
         let symbol = fields[5];
         let quantity = fields[6];
         let mult = fields[7];
@@ -198,20 +273,29 @@ impl IBCSVParser {
             })
             .unwrap();
 
-        Ok(BalanceNote::new(
+        Ok(util::normalize_pence(BalanceNote::new(
             company_info,
             String::from(""),
             Decimal::from_str(&decimal::normalize_str(quantity))?
-                * Decimal::from_str(&decimal::normalize_str(mult))?,
+                * self.multiplier(symbol, Some(mult))?,
             String::from(currency.unwrap_or(IBCSVParser::EUR_CURRENCY_STR)),
             Decimal::from_str(&decimal::normalize_str(price))?,
             Decimal::from_str(&decimal::normalize_str(value_in_euro))?,
             &self.broker,
-        ))
+        )))
     }
 
-    pub fn parse_balance_notes(&self) -> Result<BalanceNotes> {
+    fn total_value(fields: &[&str]) -> Result<Decimal> {
+        let total = fields
+            .get(11)
+            .ok_or_else(|| anyhow!("Unknown total value"))?;
+
+        Ok(Decimal::from_str(&decimal::normalize_str(total))?)
+    }
+
+    pub fn parse_balance_notes(&self) -> Result<(BalanceNotes, ParseErrors)> {
         let mut balance_notes = vec![];
+        let mut errors = vec![];
 
         let start = self
             .content
@@ -228,31 +312,46 @@ impl IBCSVParser {
         let mut state = NoteState::Invalid;
         let mut current_notes: BalanceNotes = Vec::new();
         let mut currency = None;
+        let mut local_total = None;
 
-        for line in lines.iter() {
+        let first_line = self.line_number(start);
+        for (i, line) in lines.iter().enumerate() {
             match state {
                 NoteState::Invalid => {
                     log::debug!("Invalid state");
                     if line.starts_with(self.locale[IBCSVParser::OPEN_POSITIONS_STOCK_STR]) {
                         state = NoteState::Stocks;
                         let fields: Vec<&str> = line.split(',').collect();
-                        currency = Some(fields[4]);
-                        let balance_note = self.parse_balance_note(&fields, &currency)?;
-                        current_notes.push(balance_note);
+                        currency = fields.get(4).copied();
+                        match self.parse_balance_note(&fields, &currency) {
+                            Ok(balance_note) => current_notes.push(balance_note),
+                            Err(err) => {
+                                errors.push(ParseError::new(first_line + i, err.to_string()))
+                            }
+                        }
                     }
                 }
                 NoteState::Stocks => {
                     log::debug!("Stocks state");
                     if line.starts_with(self.locale[IBCSVParser::OPEN_POSITIONS_STOCK_STR]) {
                         let fields: Vec<&str> = line.split(',').collect();
-                        currency = Some(fields[4]);
-                        let balance_note = self.parse_balance_note(&fields, &currency)?;
-                        current_notes.push(balance_note);
+                        currency = fields.get(4).copied();
+                        match self.parse_balance_note(&fields, &currency) {
+                            Ok(balance_note) => current_notes.push(balance_note),
+                            Err(err) => {
+                                errors.push(ParseError::new(first_line + i, err.to_string()))
+                            }
+                        }
                     } else if line.starts_with(self.locale[IBCSVParser::OPEN_POSITIONS_TOTAL_STR]) {
                         state = NoteState::Total;
                         if currency == Some(IBCSVParser::EUR_CURRENCY_STR) {
                             state = NoteState::Stocks;
                             balance_notes.append(&mut current_notes);
+                        } else {
+                            let fields: Vec<&str> = line.split(',').collect();
+                            local_total = IBCSVParser::total_value(&fields).ok().map(|total| {
+                                util::normalize_pence_amount(total, currency.unwrap_or_default())
+                            });
                         }
                     }
                 }
@@ -261,17 +360,38 @@ impl IBCSVParser {
 
                     state = NoteState::Stocks;
                     let fields: Vec<&str> = line.split(',').collect();
-                    let total_in_euro_str = fields[11];
-                    let total_in_euro =
-                        Decimal::from_str(&decimal::normalize_str(total_in_euro_str))?;
-                    log::debug!("total in eur: {:?}", total_in_euro);
-                    util::recalculate_balance_notes(&mut current_notes, &total_in_euro)?;
+                    let result = IBCSVParser::total_value(&fields).and_then(|total_in_euro| {
+                        log::debug!("total in eur: {:?}", total_in_euro);
+                        util::recalculate_balance_notes(
+                            &mut current_notes,
+                            &total_in_euro,
+                            local_total.as_ref(),
+                        )
+                    });
+                    if let Err(err) = result {
+                        errors.push(ParseError::new(
+                            first_line + i,
+                            format!("Total en euros no válido: {}", err),
+                        ));
+                        let year = self.report_year().unwrap_or_else(default_year);
+                        for note in current_notes.iter_mut() {
+                            let amount = note.value_in_euro;
+                            util::set_value_in_euro(note, &amount, year, &mut errors);
+                        }
+                    }
                     balance_notes.append(&mut current_notes);
                 }
             }
         }
 
-        Ok(balance_notes)
+        Ok((balance_notes, errors))
+    }
+
+    pub fn report_year(&self) -> Option<usize> {
+        self.content
+            .lines()
+            .find(|line| line.starts_with(IBCSVParser::PERIOD_STR))
+            .and_then(util::period_end_year)
     }
 
     pub fn new(content: String, broker: &Arc<BrokerInformation>) -> Result<Self> {
@@ -280,13 +400,14 @@ impl IBCSVParser {
         } else {
             IBCSVParser::EN_MSGS
         };
-        let companies_info = IBCSVParser::parse_companies_info(&content, locale)?;
+        let (companies_info, multipliers) = IBCSVParser::parse_companies_info(&content, locale)?;
 
         Ok(Self {
             content,
             locale,
             broker: Arc::clone(broker),
             companies_info,
+            multipliers,
         })
     }
 }
@@ -330,6 +451,7 @@ mod tests {
         let companies_info: HashMap<String, CompanyInfo> = companies_info();
 
         assert_eq!(companies_info, parser.companies_info);
+        assert_eq!(parser.report_year(), Some(2021));
     }
 
     #[test]
@@ -344,6 +466,7 @@ mod tests {
         let companies_info: HashMap<String, CompanyInfo> = companies_info();
 
         assert_eq!(companies_info, parser.companies_info);
+        assert_eq!(parser.report_year(), Some(2021));
     }
 
     #[test]
@@ -354,10 +477,11 @@ mod tests {
         ));
 
         let parser = IBCSVParser::new(INPUT_2021.to_string(), &ib_broker).unwrap();
-        let balance_notes = parser.parse_balance_notes().unwrap();
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
         let bal_notes = get_test_balance_notes(&ib_broker);
 
         assert_eq!(&bal_notes, &balance_notes);
+        assert!(errors.is_empty());
     }
 
     #[test]
@@ -368,10 +492,104 @@ mod tests {
         ));
 
         let parser = IBCSVParser::new(INPUT_2021_ES.to_string(), &ib_broker).unwrap();
-        let balance_notes = parser.parse_balance_notes().unwrap();
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
         let bal_notes = get_test_balance_notes(&ib_broker);
 
         assert_eq!(&bal_notes, &balance_notes);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_parse_balance_notes_skips_malformed_lines() {
+        let ib_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("IB"),
+            String::from("IE"),
+        ));
+        let input = INPUT_2021.replace(
+            "Open Positions,Data,Summary,Stocks,AUD,CTT,2500,1,1.2812,3203,3.56,8900,5697,",
+            "Open Positions,Data,Summary,Stocks,AUD,CTT,2500,1,1.2812,3203,n/a,8900,5697,",
+        );
+
+        let parser = IBCSVParser::new(input, &ib_broker).unwrap();
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+
+        assert_eq!(
+            balance_notes.len(),
+            get_test_balance_notes(&ib_broker).len() - 1
+        );
+        assert!(balance_notes
+            .iter()
+            .all(|note| note.company.name != "CETTIRE LTD"));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 182);
+        // The skipped row's share of the AUD total isn't spread over the other AUD positions.
+        for note in get_test_balance_notes(&ib_broker)
+            .iter()
+            .filter(|note| note.currency == "AUD" && note.company.name != "CETTIRE LTD")
+        {
+            assert!(balance_notes.contains(note));
+        }
+    }
+
+    #[test]
+    fn test_parse_balance_notes_with_malformed_total() {
+        let ib_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("IB"),
+            String::from("IE"),
+        ));
+        let input = INPUT_2021.replace(
+            "Open Positions,Total,,Stocks,EUR,,,,,32437.35846912,,31225.26,-1212.09846912,",
+            "Open Positions,Total,,Stocks,EUR,,,,,32437.35846912,,n/a,-1212.09846912,",
+        );
+
+        let parser = IBCSVParser::new(input, &ib_broker).unwrap();
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+
+        assert_eq!(
+            balance_notes.len(),
+            get_test_balance_notes(&ib_broker).len()
+        );
+        assert_eq!(errors[0].line, 185);
+        // Without an AUD rate the positions of that currency are left for the user to value.
+        assert!(balance_notes
+            .iter()
+            .filter(|note| note.currency == "AUD")
+            .all(|note| note.derived && note.value_in_euro == Decimal::ZERO));
+        assert_eq!(errors.len(), 4);
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_balance_notes_in_pence() {
+        let ib_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("IB"),
+            String::from("IE"),
+        ));
+        let hkd_positions = "Open Positions,Data,Summary,Stocks,HKD,9618,";
+        let input = INPUT_2021.replace(
+            hkd_positions,
+            &format!(
+                "{}{}{}{}",
+                "Open Positions,Data,Summary,Stocks,GBX,BUR,463,1,700,324100,712,329656,5556,\n",
+                "Open Positions,Total,,Stocks,GBX,,,,,324100,,329656,5556,\n",
+                "Open Positions,Total,,Stocks,EUR,,,,,3787.29,,3898.18,64.92,\n",
+                hkd_positions
+            ),
+        );
+
+        let parser = IBCSVParser::new(input, &ib_broker).unwrap();
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+        let note = balance_notes
+            .iter()
+            .find(|note| note.company.name == "BUR")
+            .unwrap();
+
+        assert!(errors.is_empty());
+        assert_eq!(note.currency, "GBP");
+        assert_eq!(note.price, Decimal::new(7_12, 2));
+        assert_eq!(note.quantity, Decimal::new(463, 0));
+        // Same value in euros Degiro reports for this holding quoted in pence.
+        assert_eq!(note.value_in_euro, Decimal::new(3898_18, 2));
     }
 
     #[test]
@@ -381,12 +599,13 @@ mod tests {
             String::from("IE"),
         ));
         let ibparser = IBCSVParser::new(INPUT_2021.to_string(), &ib_broker).unwrap();
-        let notes = ibparser.parse_account_notes().unwrap();
+        let (notes, errors) = ibparser.parse_account_notes().unwrap();
 
         let acc_notes = get_test_account_notes(&ib_broker);
 
         compare_vectors_by_item(&acc_notes, &notes);
         assert_eq!(acc_notes, notes);
+        assert!(errors.is_empty());
     }
 
     #[test]
@@ -396,16 +615,84 @@ mod tests {
             String::from("IE"),
         ));
         let ibparser = IBCSVParser::new(INPUT_2021_ES.to_string(), &ib_broker).unwrap();
-        let notes = ibparser.parse_account_notes().unwrap();
+        let (notes, errors) = ibparser.parse_account_notes().unwrap();
 
         let acc_notes = get_test_account_notes(&ib_broker);
 
         compare_vectors_by_item(&acc_notes, &notes);
         assert_eq!(acc_notes, notes);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_parse_notes_with_instrument_multiplier() {
+        let ib_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("IB"),
+            String::from("IE"),
+        ));
+        // Positions and trades of CTT say nothing about a multiplier of 100.
+        let input = INPUT_2021.replace(
+            "CTT,CETTIRE LTD,461249512,AU0000122210,ASX,1,COMMON,",
+            "CTT,CETTIRE LTD,461249512,AU0000122210,ASX,100,COMMON,",
+        );
+
+        let parser = IBCSVParser::new(input, &ib_broker).unwrap();
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+        let note = balance_notes
+            .iter()
+            .find(|note| note.company.name == "CETTIRE LTD")
+            .unwrap();
+
+        assert!(errors.is_empty());
+        assert_eq!(note.quantity, Decimal::new(250_000, 0));
+
+        let (notes, errors) = parser.parse_account_notes().unwrap();
+        let quantities: Vec<Decimal> = notes
+            .iter()
+            .filter(|note| note.company.name == "CETTIRE LTD")
+            .map(|note| note.quantity)
+            .collect();
+
+        assert!(errors.is_empty());
+        assert_eq!(
+            quantities,
+            vec![Decimal::new(500_000, 0), Decimal::new(250_000, 0)]
+        );
+        assert_eq!(parser.multiplier("ANO", Some("1")).unwrap(), Decimal::ONE);
+        assert_eq!(
+            parser.multiplier("UNKNOWN", Some("10")).unwrap(),
+            Decimal::TEN
+        );
+        assert_eq!(parser.multiplier("UNKNOWN", None).unwrap(), Decimal::ONE);
+    }
+
+    #[test]
+    fn test_parse_account_notes_mixed_headers() {
+        let ib_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("Interactive Brokers"),
+            String::from("IE"),
+        ));
+        let ibparser = IBCSVParser::new(INPUT_MIXED.to_string(), &ib_broker).unwrap();
+        let (notes, errors) = ibparser.parse_account_notes().unwrap();
+
+        let acc_notes: Vec<AccountNote> = get_test_account_notes(&ib_broker)
+            .into_iter()
+            .filter(|note| {
+                (note.company.name == "ADVANCE ZINCTEK LTD"
+                    && note.date < NaiveDate::from_ymd_opt(2021, 01, 19).unwrap())
+                    || note.company.name == "CETTIRE LTD"
+            })
+            .collect();
+
+        assert_eq!(acc_notes.len(), 4);
+        compare_vectors_by_item(&acc_notes, &notes);
+        assert_eq!(acc_notes, notes);
+        assert!(errors.is_empty());
     }
 
     const INPUT_2021: &str = include_str!("testdata/ib_test.csv");
     const INPUT_2021_ES: &str = include_str!("testdata/ib_test_es.csv");
+    const INPUT_MIXED: &str = include_str!("testdata/ib_test_mixed.csv");
 
     #[allow(clippy::mistyped_literal_suffixes)]
     fn get_test_balance_notes(broker: &Arc<BrokerInformation>) -> Vec<BalanceNote> {
@@ -1,6 +1,15 @@
+pub mod crypto;
 pub mod degiro;
+pub mod degiro_account_csv;
 pub mod degiro_csv;
+pub mod etoro;
 pub mod ib;
 pub mod ib_csv;
+pub mod myinvestor;
 pub mod pdf;
+pub mod revolut;
+pub mod trading212;
+pub mod tsv;
+pub mod us_broker;
 mod util;
+pub mod xtb;
@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use std::{str::FromStr, sync::Arc};
+
+use crate::{
+    data::{BalanceNote, BalanceNotes, BrokerInformation, CompanyInfo, ParseError, ParseErrors},
+    utils::decimal,
+};
+
+pub struct MyInvestorCSVParser {
+    content: String,
+    broker: Arc<BrokerInformation>,
+}
+
+impl MyInvestorCSVParser {
+    const HEADER_STR: &'static str = "ISIN;Valor;Cantidad;Precio;Divisa;Valor mercado";
+    const DELIMITER: u8 = b';';
+
+    pub fn is_myinvestor_csv(content: &[u8]) -> bool {
+        content.starts_with(MyInvestorCSVParser::HEADER_STR.as_bytes())
+    }
+
+    fn decimal(record: &csv::StringRecord, index: usize, name: &str) -> Result<Decimal> {
+        let value = record
+            .get(index)
+            .ok_or_else(|| anyhow!("Unable to get {}", name))?;
+
+        Ok(Decimal::from_str(&decimal::transform_i18n_es_str(value))?)
+    }
+
+    fn parse_balance_note(&self, record: &csv::StringRecord) -> Result<BalanceNote> {
+        Ok(BalanceNote::new(
+            CompanyInfo::new(
+                record
+                    .get(1)
+                    .ok_or_else(|| anyhow!("Unknown company"))?
+                    .to_string(),
+                record
+                    .get(0)
+                    .ok_or_else(|| anyhow!("Unknown ISIN"))?
+                    .to_string(),
+            ),
+            String::from(""),
+            MyInvestorCSVParser::decimal(record, 2, "quantity")?,
+            record
+                .get(4)
+                .ok_or_else(|| anyhow!("Unknown currency"))?
+                .to_string(),
+            MyInvestorCSVParser::decimal(record, 3, "price")?,
+            MyInvestorCSVParser::decimal(record, 5, "value in euro")?,
+            &self.broker,
+        ))
+    }
+
+    pub fn parse_csv(&self) -> Result<(BalanceNotes, ParseErrors)> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .delimiter(MyInvestorCSVParser::DELIMITER)
+            .flexible(true)
+            .from_reader(self.content.as_bytes());
+        let mut balance_notes = vec![];
+        let mut errors = vec![];
+
+        for result in rdr.records() {
+            let record = result?;
+            log::debug!("{:?}", record);
+            if record.get(0).map_or(true, |isin| isin.is_empty()) {
+                continue;
+            }
+            match self.parse_balance_note(&record) {
+                Ok(note) => balance_notes.push(note),
+                Err(err) => errors.push(ParseError::new(
+                    record.position().map_or(0, |pos| pos.line() as usize),
+                    err.to_string(),
+                )),
+            }
+        }
+
+        Ok((balance_notes, errors))
+    }
+
+    pub fn new(content: String, broker: &Arc<BrokerInformation>) -> Self {
+        Self {
+            content,
+            broker: Arc::clone(broker),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_csv() {
+        let myinvestor_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("MyInvestor"),
+            String::from("ES"),
+        ));
+        assert!(MyInvestorCSVParser::is_myinvestor_csv(
+            INPUT_2023.as_bytes()
+        ));
+
+        let parser = MyInvestorCSVParser::new(INPUT_2023.to_string(), &myinvestor_broker);
+        let (balance_notes, errors) = parser.parse_csv().unwrap();
+        assert!(errors.is_empty());
+        let bal_notes = vec![
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("VANGUARD GLOBAL STOCK INDEX FUND EUR ACC"),
+                    isin: String::from("IE00B03HD191"),
+                },
+                String::from(""),
+                Decimal::new(55_12, 2),
+                String::from("EUR"),
+                Decimal::new(41_3271, 4),
+                Decimal::new(2_278_01, 2),
+                &myinvestor_broker,
+            ),
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("APPLE INC"),
+                    isin: String::from("US0378331005"),
+                },
+                String::from(""),
+                Decimal::new(10, 0),
+                String::from("USD"),
+                Decimal::new(192_53, 2),
+                Decimal::new(1_742_31, 2),
+                &myinvestor_broker,
+            ),
+        ];
+
+        assert_eq!(bal_notes, balance_notes);
+    }
+
+    #[test]
+    fn test_parse_csv_skips_malformed_rows() {
+        let myinvestor_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("MyInvestor"),
+            String::from("ES"),
+        ));
+        let input = INPUT_2023.replace("55,12;41,3271", "n/a;41,3271");
+
+        let parser = MyInvestorCSVParser::new(input, &myinvestor_broker);
+        let (balance_notes, errors) = parser.parse_csv().unwrap();
+
+        assert_eq!(balance_notes.len(), 1);
+        assert_eq!(balance_notes[0].company.isin, "US0378331005");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+    }
+
+    const INPUT_2023: &str = r#"ISIN;Valor;Cantidad;Precio;Divisa;Valor mercado
+IE00B03HD191;VANGUARD GLOBAL STOCK INDEX FUND EUR ACC;55,12;41,3271;EUR;2.278,01
+US0378331005;APPLE INC;10;192,53;USD;1.742,31
+;Total;;;;4.020,32"#;
+}
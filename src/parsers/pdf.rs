@@ -1,6 +1,8 @@
-use pdf_extract::OutputError;
+use std::fmt;
 
-use super::degiro::{DEGIRO_BALANCE_NOTES_HEADER, DEGIRO_NOTES_HEADER_BEGIN};
+use pdf_extract::{encryption::DecryptionError, Error, OutputError};
+
+use super::degiro::DegiroParser;
 
 fn remove_repeated_section(mut input: String, section: &str) -> String {
     if let Some(first_pos) = input.find(section) {
@@ -14,10 +16,41 @@ fn remove_repeated_section(mut input: String, section: &str) -> String {
 
     input
 }
-pub fn read_pdf(data: &[u8]) -> Result<String, OutputError> {
-    let out = pdf_extract::extract_text_from_mem(data)?;
-    let out = remove_repeated_section(out, DEGIRO_NOTES_HEADER_BEGIN);
-    let out = remove_repeated_section(out, DEGIRO_BALANCE_NOTES_HEADER);
+/// Broker pdfs encrypted with a password, Degiro ones usually with the client id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PdfPasswordError {
+    Required,
+    Incorrect,
+}
+
+impl fmt::Display for PdfPasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PdfPasswordError::Required => write!(f, "El PDF está protegido con contraseña"),
+            PdfPasswordError::Incorrect => write!(f, "La contraseña del PDF no es correcta"),
+        }
+    }
+}
+
+impl std::error::Error for PdfPasswordError {}
+
+/// Whether the pdf couldn't be decrypted, without a password it's tried with an empty one.
+pub fn is_password_error(err: &OutputError) -> bool {
+    matches!(
+        err,
+        OutputError::PdfError(Error::Decryption(DecryptionError::IncorrectPassword))
+    )
+}
+
+pub fn read_pdf(data: &[u8], password: Option<&str>) -> Result<String, OutputError> {
+    let mut out = match password {
+        Some(password) => pdf_extract::extract_text_from_mem_encrypted(data, password)?,
+        None => pdf_extract::extract_text_from_mem(data)?,
+    };
+    for locale in [DegiroParser::ES_MSGS, DegiroParser::EN_MSGS] {
+        out = remove_repeated_section(out, locale[DegiroParser::NOTES_HEADER_BEGIN_STR]);
+        out = remove_repeated_section(out, locale[DegiroParser::BALANCE_NOTES_HEADER_STR]);
+    }
     Ok(out)
 }
 
@@ -26,9 +59,19 @@ mod tests {
     #[ignore]
     fn read_pdf_test() {
         let bytes = std::fs::read("tests/data/degiro_2019.pdf").unwrap();
-        let out = super::read_pdf(&bytes).unwrap();
+        let out = super::read_pdf(&bytes, None).unwrap();
         println!("-------------------------------------------------------------");
         print!("{}", out);
         println!("-------------------------------------------------------------");
     }
+
+    #[test]
+    fn is_password_error_test() {
+        use super::*;
+
+        assert!(is_password_error(&OutputError::PdfError(
+            Error::Decryption(DecryptionError::IncorrectPassword)
+        )));
+        assert!(!is_password_error(&OutputError::FormatError(fmt::Error)));
+    }
 }
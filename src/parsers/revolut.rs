@@ -0,0 +1,310 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::{str::FromStr, sync::Arc};
+
+use crate::{
+    data::{
+        AccountNote, AccountNotes, BalanceNote, BalanceNotes, BrokerInformation, BrokerOperation,
+        CompanyInfo, ParseError, ParseErrors,
+    },
+    utils::{decimal, ticker},
+};
+
+pub struct RevolutCSVParser {
+    content: String,
+    broker: Arc<BrokerInformation>,
+}
+
+impl RevolutCSVParser {
+    const STATEMENT_HEADER_STR: &'static str =
+        "Date,Ticker,Type,Quantity,Price per share,Total Amount,Currency";
+    const POSITIONS_HEADER_STR: &'static str =
+        "Ticker,Quantity,Price per share,Total Amount,Currency,FX Rate";
+    const BUY_STR: &'static str = "BUY";
+    const SELL_STR: &'static str = "SELL";
+    const EUR_CURRENCY_STR: &'static str = "EUR";
+
+    pub fn is_revolut_statement(content: &[u8]) -> bool {
+        content.starts_with(RevolutCSVParser::STATEMENT_HEADER_STR.as_bytes())
+    }
+
+    pub fn is_revolut_positions(content: &[u8]) -> bool {
+        content.starts_with(RevolutCSVParser::POSITIONS_HEADER_STR.as_bytes())
+    }
+
+    fn company_info(ticker: &str) -> CompanyInfo {
+        // Revolut only reports tickers.
+        match ticker::embedded_company(ticker) {
+            Some(company) => company,
+            None => {
+                log::warn!("Unknown ISIN for Revolut ticker {}", ticker);
+                CompanyInfo {
+                    name: ticker.to_string(),
+                    isin: "".to_string(),
+                }
+            }
+        }
+    }
+
+    // Amounts may come prefixed by the currency, as in `USD 1,234.56`.
+    fn decimal(record: &csv::StringRecord, index: usize, name: &str) -> Result<Decimal> {
+        let value = record
+            .get(index)
+            .ok_or_else(|| anyhow!("Unable to get {}", name))?;
+        let value: String = value
+            .chars()
+            .filter(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | '-'))
+            .collect();
+
+        Ok(Decimal::from_str(&decimal::normalize_str(&value))?)
+    }
+
+    fn field<'a>(record: &'a csv::StringRecord, index: usize, name: &str) -> Result<&'a str> {
+        record
+            .get(index)
+            .ok_or_else(|| anyhow!("Unable to get {}", name))
+    }
+
+    fn parse_account_note(&self, record: &csv::StringRecord) -> Result<Option<AccountNote>> {
+        let operation_type = RevolutCSVParser::field(record, 2, "type")?;
+        // Cash top-ups, dividends and fees aren't trades.
+        let operation = if operation_type.starts_with(RevolutCSVParser::BUY_STR) {
+            BrokerOperation::Buy
+        } else if operation_type.starts_with(RevolutCSVParser::SELL_STR) {
+            BrokerOperation::Sell
+        } else {
+            return Ok(None);
+        };
+        let date = RevolutCSVParser::field(record, 0, "date")?;
+
+        Ok(Some(AccountNote::new(
+            NaiveDate::parse_from_str(date.get(..10).unwrap_or(date), "%Y-%m-%d")?,
+            RevolutCSVParser::company_info(RevolutCSVParser::field(record, 1, "ticker")?),
+            operation,
+            RevolutCSVParser::decimal(record, 3, "quantity")?.abs(),
+            RevolutCSVParser::decimal(record, 4, "price")?,
+            RevolutCSVParser::decimal(record, 5, "total amount")?.abs(),
+            Decimal::ZERO,
+            &self.broker,
+        )))
+    }
+
+    pub fn parse_account_notes(&self) -> Result<(AccountNotes, ParseErrors)> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(self.content.as_bytes());
+        let mut account_notes = vec![];
+        let mut errors = vec![];
+
+        for result in rdr.records() {
+            let record = result?;
+            log::debug!("{:?}", record);
+            match self.parse_account_note(&record) {
+                Ok(Some(note)) => account_notes.push(note),
+                Ok(None) => {}
+                Err(err) => errors.push(ParseError::new(
+                    record.position().map_or(0, |pos| pos.line() as usize),
+                    err.to_string(),
+                )),
+            }
+        }
+
+        Ok((account_notes, errors))
+    }
+
+    /// The FX rate column is the number of units of the position currency per euro.
+    fn parse_balance_note(&self, record: &csv::StringRecord) -> Result<BalanceNote> {
+        let currency = RevolutCSVParser::field(record, 4, "currency")?;
+        let value = RevolutCSVParser::decimal(record, 3, "total amount")?;
+        let value_in_euro = if currency == RevolutCSVParser::EUR_CURRENCY_STR {
+            value
+        } else {
+            let fx_rate = RevolutCSVParser::decimal(record, 5, "fx rate")?;
+            if fx_rate.is_zero() {
+                bail!("Invalid fx rate for {}", currency);
+            }
+            decimal::round_valuation(&(value / fx_rate))
+        };
+
+        Ok(BalanceNote::new(
+            RevolutCSVParser::company_info(RevolutCSVParser::field(record, 0, "ticker")?),
+            String::from(""),
+            RevolutCSVParser::decimal(record, 1, "quantity")?,
+            currency.to_string(),
+            RevolutCSVParser::decimal(record, 2, "price")?,
+            value_in_euro,
+            &self.broker,
+        ))
+    }
+
+    pub fn parse_balance_notes(&self) -> Result<(BalanceNotes, ParseErrors)> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(self.content.as_bytes());
+        let mut balance_notes = vec![];
+        let mut errors = vec![];
+
+        for result in rdr.records() {
+            let record = result?;
+            log::debug!("{:?}", record);
+            match self.parse_balance_note(&record) {
+                Ok(note) => balance_notes.push(note),
+                Err(err) => errors.push(ParseError::new(
+                    record.position().map_or(0, |pos| pos.line() as usize),
+                    err.to_string(),
+                )),
+            }
+        }
+
+        Ok((balance_notes, errors))
+    }
+
+    pub fn new(content: String, broker: &Arc<BrokerInformation>) -> Self {
+        Self {
+            content,
+            broker: Arc::clone(broker),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn revolut_broker() -> Arc<BrokerInformation> {
+        Arc::new(BrokerInformation::new(
+            String::from("Revolut"),
+            String::from("LT"),
+        ))
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_account_notes() {
+        let revolut_broker = revolut_broker();
+        assert!(RevolutCSVParser::is_revolut_statement(
+            STATEMENT_2023.as_bytes()
+        ));
+        assert!(!RevolutCSVParser::is_revolut_positions(
+            STATEMENT_2023.as_bytes()
+        ));
+
+        let parser = RevolutCSVParser::new(STATEMENT_2023.to_string(), &revolut_broker);
+        let (account_notes, errors) = parser.parse_account_notes().unwrap();
+        assert!(errors.is_empty());
+        let acc_notes = vec![
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 2, 6).unwrap(),
+                CompanyInfo {
+                    name: String::from("APPLE INC"),
+                    isin: String::from("US0378331005"),
+                },
+                BrokerOperation::Buy,
+                Decimal::new(10, 0),
+                Decimal::new(151_73, 2),
+                Decimal::new(1517_30, 2),
+                Decimal::ZERO,
+                &revolut_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 5, 17).unwrap(),
+                CompanyInfo {
+                    name: String::from("XYZW"),
+                    isin: String::from(""),
+                },
+                BrokerOperation::Buy,
+                Decimal::new(25, 1),
+                Decimal::new(40_00, 2),
+                Decimal::new(100_00, 2),
+                Decimal::ZERO,
+                &revolut_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 9, 12).unwrap(),
+                CompanyInfo {
+                    name: String::from("APPLE INC"),
+                    isin: String::from("US0378331005"),
+                },
+                BrokerOperation::Sell,
+                Decimal::new(4, 0),
+                Decimal::new(176_30, 2),
+                Decimal::new(705_20, 2),
+                Decimal::ZERO,
+                &revolut_broker,
+            ),
+        ];
+
+        assert_eq!(acc_notes, account_notes);
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_balance_notes() {
+        let revolut_broker = revolut_broker();
+        assert!(RevolutCSVParser::is_revolut_positions(
+            POSITIONS_2023.as_bytes()
+        ));
+
+        let parser = RevolutCSVParser::new(POSITIONS_2023.to_string(), &revolut_broker);
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+        assert!(errors.is_empty());
+        let bal_notes = vec![
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("APPLE INC"),
+                    isin: String::from("US0378331005"),
+                },
+                String::from(""),
+                Decimal::new(6, 0),
+                String::from("USD"),
+                Decimal::new(192_53, 2),
+                Decimal::new(1045_79, 2),
+                &revolut_broker,
+            ),
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("XYZW"),
+                    isin: String::from(""),
+                },
+                String::from(""),
+                Decimal::new(25, 1),
+                String::from("EUR"),
+                Decimal::new(38_00, 2),
+                Decimal::new(95_00, 2),
+                &revolut_broker,
+            ),
+        ];
+
+        assert_eq!(bal_notes, balance_notes);
+    }
+
+    #[test]
+    fn test_parse_skips_malformed_rows() {
+        let revolut_broker = revolut_broker();
+        let statement = STATEMENT_2023.replace("USD 151.73", "USD n/a");
+        let parser = RevolutCSVParser::new(statement, &revolut_broker);
+        let (account_notes, errors) = parser.parse_account_notes().unwrap();
+        assert_eq!(account_notes.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+
+        let positions = POSITIONS_2023.replace("USD,1.1046", "USD,0");
+        let parser = RevolutCSVParser::new(positions, &revolut_broker);
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+        assert_eq!(balance_notes.len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+    }
+
+    const STATEMENT_2023: &str = r#"Date,Ticker,Type,Quantity,Price per share,Total Amount,Currency,FX Rate
+2023-01-10T09:12:44.120Z,,CASH TOP-UP,,,"USD 2,000.00",USD,1.0734
+2023-02-06T15:31:02.531Z,AAPL,BUY - MARKET,10,USD 151.73,"USD 1,517.30",USD,1.0729
+2023-05-17T14:02:10.004Z,XYZW,BUY - LIMIT,2.5,EUR 40.00,EUR 100.00,EUR,1.0000
+2023-08-17T12:00:00.000Z,AAPL,DIVIDEND,,,USD 2.40,USD,1.0901
+2023-09-12T16:45:33.812Z,AAPL,SELL - MARKET,4,USD 176.30,USD 705.20,USD,1.0752"#;
+
+    const POSITIONS_2023: &str = r#"Ticker,Quantity,Price per share,Total Amount,Currency,FX Rate
+AAPL,6,USD 192.53,"USD 1,155.18",USD,1.1046
+XYZW,2.5,EUR 38.00,EUR 95.00,EUR,1.0000"#;
+}
@@ -0,0 +1,420 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::{str::FromStr, sync::Arc};
+
+use crate::{
+    data::{
+        AccountNote, AccountNotes, BalanceNote, BalanceNotes, BrokerInformation, BrokerOperation,
+        CompanyInfo, ParseError, ParseErrors,
+    },
+    utils::{decimal, fx},
+};
+
+pub struct Trading212CSVParser {
+    content: String,
+    broker: Arc<BrokerInformation>,
+}
+
+// Positions of the fields in the header of each export.
+struct AccountColumns {
+    action: usize,
+    time: usize,
+    isin: usize,
+    name: usize,
+    shares: usize,
+    price: usize,
+    total: usize,
+}
+
+struct BalanceColumns {
+    isin: usize,
+    name: usize,
+    shares: usize,
+    price: usize,
+    currency: usize,
+    total: usize,
+    total_currency: Option<usize>,
+}
+
+impl Trading212CSVParser {
+    const SHARES_HEADER_STR: &'static str = "No. of shares";
+    const ACTION_HEADER_STR: &'static str = "Action";
+    const TIME_HEADER_STR: &'static str = "Time";
+    const ISIN_HEADER_STR: &'static str = "ISIN";
+    const NAME_HEADER_STR: &'static str = "Name";
+    const PRICE_HEADER_STR: &'static str = "Price / share";
+    const CURRENCY_HEADER_STR: &'static str = "Currency (Price / share)";
+    const TOTAL_HEADER_STR: &'static str = "Total";
+    const TOTAL_CURRENCY_HEADER_STR: &'static str = "Currency (Total)";
+    const EUR_CURRENCY_STR: &'static str = "EUR";
+    const BUY_STR: &'static str = "buy";
+    const SELL_STR: &'static str = "sell";
+
+    fn header(content: &[u8]) -> &[u8] {
+        let end = content
+            .iter()
+            .position(|c| *c == b'\n')
+            .unwrap_or(content.len());
+        &content[..end]
+    }
+
+    fn header_contains(content: &[u8], name: &str) -> bool {
+        Trading212CSVParser::header(content)
+            .split(|c| *c == b',')
+            .any(|field| field.trim_ascii() == name.as_bytes())
+    }
+
+    pub fn is_trading212_csv(content: &[u8]) -> bool {
+        Trading212CSVParser::header_contains(content, Trading212CSVParser::SHARES_HEADER_STR)
+    }
+
+    /// Positions exports share the export columns except the action and time ones.
+    pub fn is_trading212_positions(content: &[u8]) -> bool {
+        Trading212CSVParser::is_trading212_csv(content)
+            && !Trading212CSVParser::header_contains(
+                content,
+                Trading212CSVParser::ACTION_HEADER_STR,
+            )
+    }
+
+    fn column(headers: &csv::StringRecord, name: &str) -> Result<usize> {
+        headers
+            .iter()
+            .position(|header| header.trim() == name)
+            .ok_or_else(|| anyhow!("Column {} not found", name))
+    }
+
+    fn field<'a>(record: &'a csv::StringRecord, index: usize, name: &str) -> Result<&'a str> {
+        record
+            .get(index)
+            .ok_or_else(|| anyhow!("Unable to get {}", name))
+    }
+
+    fn decimal(record: &csv::StringRecord, index: usize, name: &str) -> Result<Decimal> {
+        let value = Trading212CSVParser::field(record, index, name)?;
+
+        Ok(Decimal::from_str(&decimal::normalize_str(value))?)
+    }
+
+    fn company_info(record: &csv::StringRecord, isin: usize, name: usize) -> Result<CompanyInfo> {
+        Ok(CompanyInfo::new(
+            Trading212CSVParser::field(record, name, "name")?.to_string(),
+            Trading212CSVParser::field(record, isin, "ISIN")?.to_string(),
+        ))
+    }
+
+    fn parse_account_note(
+        &self,
+        record: &csv::StringRecord,
+        columns: &AccountColumns,
+    ) -> Result<Option<AccountNote>> {
+        let action = Trading212CSVParser::field(record, columns.action, "action")?.to_lowercase();
+        // Deposits, dividends and interests aren't trades.
+        let operation = if action.ends_with(Trading212CSVParser::BUY_STR) {
+            BrokerOperation::Buy
+        } else if action.ends_with(Trading212CSVParser::SELL_STR) {
+            BrokerOperation::Sell
+        } else {
+            return Ok(None);
+        };
+        let date = Trading212CSVParser::field(record, columns.time, "time")?;
+
+        Ok(Some(AccountNote::new(
+            NaiveDate::parse_from_str(date.get(..10).unwrap_or(date), "%Y-%m-%d")?,
+            Trading212CSVParser::company_info(record, columns.isin, columns.name)?,
+            operation,
+            Trading212CSVParser::decimal(record, columns.shares, "number of shares")?,
+            Trading212CSVParser::decimal(record, columns.price, "price")?,
+            Trading212CSVParser::decimal(record, columns.total, "total")?.abs(),
+            Decimal::ZERO,
+            &self.broker,
+        )))
+    }
+
+    pub fn parse_account_notes(&self) -> Result<(AccountNotes, ParseErrors)> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(self.content.as_bytes());
+        let headers = rdr.headers()?.clone();
+        let columns = AccountColumns {
+            action: Trading212CSVParser::column(&headers, Trading212CSVParser::ACTION_HEADER_STR)?,
+            time: Trading212CSVParser::column(&headers, Trading212CSVParser::TIME_HEADER_STR)?,
+            isin: Trading212CSVParser::column(&headers, Trading212CSVParser::ISIN_HEADER_STR)?,
+            name: Trading212CSVParser::column(&headers, Trading212CSVParser::NAME_HEADER_STR)?,
+            shares: Trading212CSVParser::column(&headers, Trading212CSVParser::SHARES_HEADER_STR)?,
+            price: Trading212CSVParser::column(&headers, Trading212CSVParser::PRICE_HEADER_STR)?,
+            total: Trading212CSVParser::column(&headers, Trading212CSVParser::TOTAL_HEADER_STR)?,
+        };
+        let mut account_notes = vec![];
+        let mut errors = vec![];
+
+        for result in rdr.records() {
+            let record = result?;
+            log::debug!("{:?}", record);
+            match self.parse_account_note(&record, &columns) {
+                Ok(Some(note)) => account_notes.push(note),
+                Ok(None) => {}
+                Err(err) => errors.push(ParseError::new(
+                    record.position().map_or(0, |pos| pos.line() as usize),
+                    err.to_string(),
+                )),
+            }
+        }
+
+        Ok((account_notes, errors))
+    }
+
+    // The total is in the account currency, converted with the year-end rate when it isn't
+    // euros. Without a rate the row is left for the user to value.
+    fn parse_balance_note(
+        &self,
+        record: &csv::StringRecord,
+        columns: &BalanceColumns,
+        year: usize,
+        errors: &mut ParseErrors,
+    ) -> Result<BalanceNote> {
+        let total = Trading212CSVParser::decimal(record, columns.total, "total")?;
+        let total_currency = match columns.total_currency {
+            Some(index) => Trading212CSVParser::field(record, index, "total currency")?,
+            None => Trading212CSVParser::EUR_CURRENCY_STR,
+        };
+        let mut note = BalanceNote::new(
+            Trading212CSVParser::company_info(record, columns.isin, columns.name)?,
+            String::from(""),
+            Trading212CSVParser::decimal(record, columns.shares, "number of shares")?,
+            Trading212CSVParser::field(record, columns.currency, "currency")?.to_string(),
+            Trading212CSVParser::decimal(record, columns.price, "price")?,
+            Decimal::ZERO,
+            &self.broker,
+        );
+        match fx::to_euro(&total, total_currency, year) {
+            Ok(value) => note.value_in_euro = value,
+            Err(err) => {
+                errors.push(ParseError::new(
+                    record.position().map_or(0, |pos| pos.line() as usize),
+                    format!(
+                        "{} ({}): {}, introduce su valor en euros",
+                        note.company.name, note.company.isin, err
+                    ),
+                ));
+                note.derived = true;
+            }
+        }
+
+        Ok(note)
+    }
+
+    pub fn parse_balance_notes(&self, year: usize) -> Result<(BalanceNotes, ParseErrors)> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(self.content.as_bytes());
+        let headers = rdr.headers()?.clone();
+        let columns = BalanceColumns {
+            isin: Trading212CSVParser::column(&headers, Trading212CSVParser::ISIN_HEADER_STR)?,
+            name: Trading212CSVParser::column(&headers, Trading212CSVParser::NAME_HEADER_STR)?,
+            shares: Trading212CSVParser::column(&headers, Trading212CSVParser::SHARES_HEADER_STR)?,
+            price: Trading212CSVParser::column(&headers, Trading212CSVParser::PRICE_HEADER_STR)?,
+            currency: Trading212CSVParser::column(
+                &headers,
+                Trading212CSVParser::CURRENCY_HEADER_STR,
+            )?,
+            total: Trading212CSVParser::column(&headers, Trading212CSVParser::TOTAL_HEADER_STR)?,
+            total_currency: Trading212CSVParser::column(
+                &headers,
+                Trading212CSVParser::TOTAL_CURRENCY_HEADER_STR,
+            )
+            .ok(),
+        };
+        let mut balance_notes = vec![];
+        let mut errors = vec![];
+        if columns.total_currency.is_none() {
+            errors.push(ParseError::new(
+                1,
+                String::from(
+                    "El fichero de Trading 212 no indica la moneda del total, se ha tomado en euros; compruébalo",
+                ),
+            ));
+        }
+
+        for result in rdr.records() {
+            let record = result?;
+            log::debug!("{:?}", record);
+            match self.parse_balance_note(&record, &columns, year, &mut errors) {
+                Ok(note) => balance_notes.push(note),
+                Err(err) => errors.push(ParseError::new(
+                    record.position().map_or(0, |pos| pos.line() as usize),
+                    err.to_string(),
+                )),
+            }
+        }
+
+        Ok((balance_notes, errors))
+    }
+
+    pub fn new(content: String, broker: &Arc<BrokerInformation>) -> Self {
+        Self {
+            content,
+            broker: Arc::clone(broker),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trading212_broker() -> Arc<BrokerInformation> {
+        Arc::new(BrokerInformation::new(
+            String::from("Trading 212"),
+            String::from("CY"),
+        ))
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_account_notes() {
+        let trading212_broker = trading212_broker();
+        assert!(Trading212CSVParser::is_trading212_csv(
+            EXPORT_2023.as_bytes()
+        ));
+        assert!(!Trading212CSVParser::is_trading212_positions(
+            EXPORT_2023.as_bytes()
+        ));
+
+        let parser = Trading212CSVParser::new(EXPORT_2023.to_string(), &trading212_broker);
+        let (account_notes, errors) = parser.parse_account_notes().unwrap();
+        assert!(errors.is_empty());
+        let acc_notes = vec![
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 3, 1).unwrap(),
+                CompanyInfo {
+                    name: String::from("Apple"),
+                    isin: String::from("US0378331005"),
+                },
+                BrokerOperation::Buy,
+                Decimal::new(5, 0),
+                Decimal::new(145_31, 2),
+                Decimal::new(685_42, 2),
+                Decimal::ZERO,
+                &trading212_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 6, 20).unwrap(),
+                CompanyInfo {
+                    name: String::from("Vanguard S&P 500 (Acc)"),
+                    isin: String::from("IE00BFMXXD54"),
+                },
+                BrokerOperation::Buy,
+                Decimal::new(12_5, 1),
+                Decimal::new(80_12, 2),
+                Decimal::new(1001_50, 2),
+                Decimal::ZERO,
+                &trading212_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 10, 4).unwrap(),
+                CompanyInfo {
+                    name: String::from("Apple"),
+                    isin: String::from("US0378331005"),
+                },
+                BrokerOperation::Sell,
+                Decimal::new(2, 0),
+                Decimal::new(173_66, 2),
+                Decimal::new(331_06, 2),
+                Decimal::ZERO,
+                &trading212_broker,
+            ),
+        ];
+
+        assert_eq!(acc_notes, account_notes);
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_balance_notes() {
+        let trading212_broker = trading212_broker();
+        assert!(Trading212CSVParser::is_trading212_positions(
+            POSITIONS_2023.as_bytes()
+        ));
+
+        let parser = Trading212CSVParser::new(POSITIONS_2023.to_string(), &trading212_broker);
+        let (balance_notes, errors) = parser.parse_balance_notes(2023).unwrap();
+        assert!(errors.is_empty());
+        let bal_notes = vec![
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("Apple"),
+                    isin: String::from("US0378331005"),
+                },
+                String::from(""),
+                Decimal::new(3, 0),
+                String::from("USD"),
+                Decimal::new(192_53, 2),
+                Decimal::new(522_70, 2),
+                &trading212_broker,
+            ),
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("Vanguard S&P 500 (Acc)"),
+                    isin: String::from("IE00BFMXXD54"),
+                },
+                String::from(""),
+                Decimal::new(12_5, 1),
+                String::from("EUR"),
+                Decimal::new(86_70, 2),
+                Decimal::new(1083_75, 2),
+                &trading212_broker,
+            ),
+        ];
+
+        assert_eq!(bal_notes, balance_notes);
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_balance_notes_total_currency() {
+        let trading212_broker = trading212_broker();
+        // An account in dollars.
+        let positions = POSITIONS_2023.replace("1.105,522.70,EUR", "1,577.58,USD");
+        let parser = Trading212CSVParser::new(positions, &trading212_broker);
+        let (balance_notes, errors) = parser.parse_balance_notes(2023).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(balance_notes[0].value_in_euro, Decimal::new(522_70, 2));
+
+        let (balance_notes, errors) = parser.parse_balance_notes(2000).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+        assert!(balance_notes[0].derived);
+        assert!(balance_notes[0].value_in_euro.is_zero());
+    }
+
+    #[test]
+    fn test_parse_skips_malformed_rows() {
+        let trading212_broker = trading212_broker();
+        let export = EXPORT_2023.replace("2023-06-20 09:15:44", "20/06/2023");
+        let parser = Trading212CSVParser::new(export, &trading212_broker);
+        let (account_notes, errors) = parser.parse_account_notes().unwrap();
+        assert_eq!(account_notes.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 4);
+
+        let positions = POSITIONS_2023.replace("12.5,86.70", "n/a,86.70");
+        let parser = Trading212CSVParser::new(positions, &trading212_broker);
+        let (balance_notes, errors) = parser.parse_balance_notes(2023).unwrap();
+        assert_eq!(balance_notes.len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+    }
+
+    const EXPORT_2023: &str = r#"Action,Time,ISIN,Ticker,Name,No. of shares,Price / share,Currency (Price / share),Exchange rate,Total,Currency (Total)
+Deposit,2023-02-27 10:01:12,,,,,,,,2000.00,EUR
+Market buy,2023-03-01 15:32:05,US0378331005,AAPL,Apple,5,145.31,USD,1.06,685.42,EUR
+Limit buy,2023-06-20 09:15:44,IE00BFMXXD54,VUAA,Vanguard S&P 500 (Acc),12.5,80.12,EUR,1.00,1001.50,EUR
+Dividend (Ordinary),2023-08-17 12:00:00,US0378331005,AAPL,Apple,5,0.24,USD,1.09,0.94,EUR
+Market sell,2023-10-04 16:01:20,US0378331005,AAPL,Apple,2,173.66,USD,1.0491,331.06,EUR"#;
+
+    const POSITIONS_2023: &str = r#"ISIN,Ticker,Name,No. of shares,Price / share,Currency (Price / share),Exchange rate,Total,Currency (Total)
+US0378331005,AAPL,Apple,3,192.53,USD,1.105,522.70,EUR
+IE00BFMXXD54,VUAA,Vanguard S&P 500 (Acc),12.5,86.70,EUR,1.00,1083.75,EUR"#;
+}
@@ -0,0 +1,238 @@
+use anyhow::{anyhow, bail, Result};
+use rust_decimal::Decimal;
+use std::{str::FromStr, sync::Arc};
+
+use crate::{
+    data::{
+        validate_isin, BalanceNote, BalanceNotes, BrokerInformation, CompanyInfo, ParseError,
+        ParseErrors,
+    },
+    utils::decimal,
+};
+
+/// Positions table copied from a broker web page or a spreadsheet, which keep the cells
+/// separated by tabs. Columns are found by their header, in Spanish or English.
+pub struct TsvParser {
+    content: String,
+    broker: Arc<BrokerInformation>,
+}
+
+struct TsvColumns {
+    isin: usize,
+    name: Option<usize>,
+    quantity: usize,
+    value_in_euro: usize,
+    price: Option<usize>,
+    currency: Option<usize>,
+}
+
+impl TsvParser {
+    const DELIMITER: char = '\t';
+    const ISIN_HEADERS: &'static [&'static str] = &["isin"];
+    const NAME_HEADERS: &'static [&'static str] = &[
+        "producto",
+        "product",
+        "nombre",
+        "name",
+        "instrumento",
+        "instrument",
+        "descripción",
+        "description",
+    ];
+    const QUANTITY_HEADERS: &'static [&'static str] = &[
+        "cantidad",
+        "quantity",
+        "acciones",
+        "shares",
+        "títulos",
+        "participaciones",
+        "units",
+    ];
+    // Ordered by preference, the euro value goes before the one in the local currency.
+    const VALUE_HEADERS: &'static [&'static str] = &[
+        "valor en eur",
+        "value in eur",
+        "valor (eur)",
+        "value (eur)",
+        "valor de mercado",
+        "market value",
+        "valor",
+        "value",
+        "importe",
+        "amount",
+    ];
+    const PRICE_HEADERS: &'static [&'static str] = &["precio", "price"];
+    const CURRENCY_HEADERS: &'static [&'static str] = &["moneda", "divisa", "currency"];
+
+    fn split(line: &str) -> Vec<&str> {
+        line.split(TsvParser::DELIMITER).map(str::trim).collect()
+    }
+
+    fn is_header(cells: &[&str]) -> bool {
+        cells.len() > 1 && TsvParser::find_column(cells, TsvParser::ISIN_HEADERS).is_some()
+    }
+
+    pub fn is_tsv_table(content: &str) -> bool {
+        content
+            .lines()
+            .any(|line| TsvParser::is_header(&TsvParser::split(line)))
+    }
+
+    fn find_column(headers: &[&str], names: &[&str]) -> Option<usize> {
+        names.iter().find_map(|name| {
+            headers
+                .iter()
+                .position(|header| header.to_lowercase().contains(name))
+        })
+    }
+
+    fn columns(headers: &[&str]) -> Result<TsvColumns> {
+        let column = |names: &[&str], label: &str| {
+            TsvParser::find_column(headers, names)
+                .ok_or_else(|| anyhow!("Falta la columna {} en la tabla pegada", label))
+        };
+
+        Ok(TsvColumns {
+            isin: column(TsvParser::ISIN_HEADERS, "ISIN")?,
+            name: TsvParser::find_column(headers, TsvParser::NAME_HEADERS),
+            quantity: column(TsvParser::QUANTITY_HEADERS, "cantidad")?,
+            value_in_euro: column(TsvParser::VALUE_HEADERS, "valor")?,
+            price: TsvParser::find_column(headers, TsvParser::PRICE_HEADERS),
+            currency: TsvParser::find_column(headers, TsvParser::CURRENCY_HEADERS),
+        })
+    }
+
+    // Web pages print amounts with their currency, e.g. `1.234,56 €` or `$1,234.56`.
+    fn decimal(cell: &str, decimal_separator: char) -> Result<Decimal> {
+        let number = cell
+            .trim_start_matches(|c: char| !c.is_ascii_digit() && c != '-')
+            .trim_end_matches(|c: char| !c.is_ascii_digit());
+        Decimal::from_str(&decimal::transform_i18n_str(number, decimal_separator))
+            .map_err(|err| anyhow!("{}: {}", cell, err))
+    }
+
+    fn parse_balance_note(
+        &self,
+        cells: &[&str],
+        columns: &TsvColumns,
+        decimal_separator: char,
+    ) -> Result<BalanceNote> {
+        let cell = |index: usize| cells.get(index).copied().unwrap_or("");
+        let isin = cell(columns.isin).to_uppercase();
+        if !validate_isin(&isin) {
+            bail!("ISIN no válido {}", isin);
+        }
+        let name = match columns.name.map(cell) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => isin.clone(),
+        };
+        let price = match columns.price.map(cell) {
+            Some(price) if !price.is_empty() => TsvParser::decimal(price, decimal_separator)?,
+            _ => Decimal::ZERO,
+        };
+        let currency = match columns.currency.map(cell) {
+            Some(currency) if !currency.is_empty() => currency.to_uppercase(),
+            _ => String::from("EUR"),
+        };
+
+        Ok(BalanceNote::new(
+            CompanyInfo::new(name, isin),
+            String::from(""),
+            TsvParser::decimal(cell(columns.quantity), decimal_separator)?,
+            currency,
+            price,
+            TsvParser::decimal(cell(columns.value_in_euro), decimal_separator)?,
+            &self.broker,
+        ))
+    }
+
+    pub fn parse_balance_notes(&self) -> Result<(BalanceNotes, ParseErrors)> {
+        let mut lines = self.content.lines().enumerate();
+        let columns = match lines.find(|(_, line)| TsvParser::is_header(&TsvParser::split(line))) {
+            Some((_, header)) => TsvParser::columns(&TsvParser::split(header))?,
+            None => bail!("No se reconoce la tabla pegada, falta la columna ISIN"),
+        };
+        let decimal_separator = decimal::detect_decimal_separator(&self.content);
+        let mut balance_notes = vec![];
+        let mut errors = vec![];
+
+        for (i, line) in lines {
+            let cells = TsvParser::split(line);
+            log::debug!("table row {:?}", cells);
+            // Totals and section titles have no ISIN.
+            if cells.get(columns.isin).map_or(true, |isin| isin.is_empty()) {
+                continue;
+            }
+            match self.parse_balance_note(&cells, &columns, decimal_separator) {
+                Ok(note) => balance_notes.push(note),
+                Err(err) => errors.push(ParseError::new(
+                    i + 1,
+                    format!("Fila no reconocida de la tabla pegada: {} ({})", line, err),
+                )),
+            }
+        }
+
+        Ok((balance_notes, errors))
+    }
+
+    pub fn new(content: String, broker: &Arc<BrokerInformation>) -> Self {
+        Self {
+            content,
+            broker: Arc::clone(broker),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT_ES: &str = "Cartera\n\
+        Producto\tISIN\tCantidad\tPrecio\tValor local\tValor en EUR\n\
+        APPLE INC\tUS0378331005\t10\t192,53 $\t1.925,30 $\t1.742,35 €\n\
+        ISHARES CORE S&P 500\tIE00B5BMR087\t5\t\t\t2.265,00 €\n\
+        Total\t\t\t\t\t4.007,35 €\n\
+        MONDO TV\tIT0001447785\tn/a\t\t\t0,00 €\n";
+
+    #[test]
+    fn test_parse_balance_notes() {
+        let broker: Arc<BrokerInformation> =
+            Arc::new(BrokerInformation::new(String::from(""), String::from("")));
+        assert!(TsvParser::is_tsv_table(INPUT_ES));
+        assert!(!TsvParser::is_tsv_table("ISIN,Cantidad\nUS0378331005,10"));
+
+        let parser = TsvParser::new(INPUT_ES.to_string(), &broker);
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+        assert_eq!(
+            balance_notes,
+            vec![
+                BalanceNote::new(
+                    CompanyInfo::new(String::from("APPLE INC"), String::from("US0378331005")),
+                    String::from(""),
+                    Decimal::new(10, 0),
+                    String::from("EUR"),
+                    Decimal::new(192_53, 2),
+                    Decimal::new(1_742_35, 2),
+                    &broker,
+                ),
+                BalanceNote::new(
+                    CompanyInfo::new(
+                        String::from("ISHARES CORE S&P 500"),
+                        String::from("IE00B5BMR087"),
+                    ),
+                    String::from(""),
+                    Decimal::new(5, 0),
+                    String::from("EUR"),
+                    Decimal::ZERO,
+                    Decimal::new(2_265_00, 2),
+                    &broker,
+                ),
+            ]
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 6);
+
+        let parser = TsvParser::new(String::from("ISIN\tNombre\nUS0378331005\tAPPLE"), &broker);
+        assert!(parser.parse_balance_notes().is_err());
+    }
+}
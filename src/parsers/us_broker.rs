@@ -0,0 +1,456 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::{str::FromStr, sync::Arc};
+
+use crate::{
+    data::{
+        cusip_to_isin, AccountNote, AccountNotes, BalanceNote, BalanceNotes, BrokerInformation,
+        BrokerOperation, CompanyInfo, ParseError, ParseErrors,
+    },
+    parsers::util,
+    utils::decimal,
+};
+
+struct AccountColumns {
+    date: usize,
+    action: usize,
+    cusip: usize,
+    description: usize,
+    quantity: usize,
+    price: usize,
+    amount: usize,
+    fees: Option<usize>,
+}
+
+struct BalanceColumns {
+    cusip: usize,
+    description: usize,
+    quantity: usize,
+    price: usize,
+    market_value: usize,
+}
+
+/// CSV exports of US brokers like Schwab, where securities are identified by CUSIP and
+/// amounts are in dollars.
+pub struct UsBrokerCSVParser {
+    content: String,
+    broker: Arc<BrokerInformation>,
+}
+
+impl UsBrokerCSVParser {
+    const CUSIP_HEADER_STR: &'static str = "CUSIP";
+    const ACTION_HEADER_STR: &'static str = "Action";
+    const DATE_HEADER_STR: &'static str = "Date";
+    const DESCRIPTION_HEADER_STR: &'static str = "Description";
+    const QUANTITY_HEADER_STR: &'static str = "Quantity";
+    const PRICE_HEADER_STR: &'static str = "Price";
+    const AMOUNT_HEADER_STR: &'static str = "Amount";
+    const FEES_HEADER_STR: &'static str = "Fees & Comm";
+    const MARKET_VALUE_HEADER_STR: &'static str = "Market Value";
+    const AS_OF_STR: &'static str = "as of ";
+    const BUY_STR: &'static str = "buy";
+    const SELL_STR: &'static str = "sell";
+    const USD_CURRENCY_STR: &'static str = "USD";
+    const US_COUNTRY_CODE: &'static str = "US";
+    // Schwab writes a title line, and sometimes a blank one, before the header.
+    const MAX_PREAMBLE_LINES: usize = 3;
+
+    fn header_fields(line: &str) -> impl Iterator<Item = &str> {
+        line.split(',').map(|field| field.trim().trim_matches('"'))
+    }
+
+    // Byte offset of the header line, the first one with a CUSIP column.
+    fn header_begin(content: &str) -> Option<usize> {
+        let mut offset = 0;
+        for line in content
+            .split_inclusive('\n')
+            .take(UsBrokerCSVParser::MAX_PREAMBLE_LINES + 1)
+        {
+            if UsBrokerCSVParser::header_fields(line)
+                .any(|field| field == UsBrokerCSVParser::CUSIP_HEADER_STR)
+            {
+                return Some(offset);
+            }
+            offset += line.len();
+        }
+
+        None
+    }
+
+    pub fn is_us_broker_csv(content: &[u8]) -> bool {
+        std::str::from_utf8(content)
+            .ok()
+            .and_then(UsBrokerCSVParser::header_begin)
+            .is_some()
+    }
+
+    /// Positions exports have no action column.
+    pub fn is_us_broker_positions(content: &[u8]) -> bool {
+        std::str::from_utf8(content).is_ok_and(|content| {
+            UsBrokerCSVParser::header_begin(content).is_some_and(|begin| {
+                let header = content[begin..].lines().next().unwrap_or("");
+                !UsBrokerCSVParser::header_fields(header)
+                    .any(|field| field == UsBrokerCSVParser::ACTION_HEADER_STR)
+            })
+        })
+    }
+
+    /// Year of the positions snapshot, from a title like `... as of 04:00 PM ET, 12/31/2023`.
+    pub fn report_year(&self) -> Option<usize> {
+        let preamble = &self.content[..UsBrokerCSVParser::header_begin(&self.content)?];
+        let date = preamble
+            .split(UsBrokerCSVParser::AS_OF_STR)
+            .nth(1)?
+            .split(['"', '\n'])
+            .next()?;
+        let year = date.trim().rsplit('/').next()?;
+
+        if year.len() == 4 {
+            year.parse::<usize>().ok()
+        } else {
+            None
+        }
+    }
+
+    fn reader(&self) -> Result<csv::Reader<&[u8]>> {
+        let begin = UsBrokerCSVParser::header_begin(&self.content)
+            .ok_or_else(|| anyhow!("Column {} not found", UsBrokerCSVParser::CUSIP_HEADER_STR))?;
+
+        Ok(csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(self.content[begin..].as_bytes()))
+    }
+
+    fn column(headers: &csv::StringRecord, name: &str) -> Result<usize> {
+        headers
+            .iter()
+            .position(|header| header.trim() == name)
+            .ok_or_else(|| anyhow!("Column {} not found", name))
+    }
+
+    fn field<'a>(record: &'a csv::StringRecord, index: usize, name: &str) -> Result<&'a str> {
+        record
+            .get(index)
+            .map(str::trim)
+            .ok_or_else(|| anyhow!("Unable to get {}", name))
+    }
+
+    // Amounts come as `$1,234.56` or `-$1,234.56`, empty ones are zero.
+    fn decimal(record: &csv::StringRecord, index: usize, name: &str) -> Result<Decimal> {
+        let value: String = UsBrokerCSVParser::field(record, index, name)?
+            .chars()
+            .filter(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | '-'))
+            .collect();
+        if value.is_empty() {
+            return Ok(Decimal::ZERO);
+        }
+
+        Ok(Decimal::from_str(&decimal::normalize_str(&value))?)
+    }
+
+    fn company_info(
+        record: &csv::StringRecord,
+        cusip: usize,
+        description: usize,
+    ) -> Result<CompanyInfo> {
+        Ok(CompanyInfo::new(
+            UsBrokerCSVParser::field(record, description, "description")?.to_string(),
+            cusip_to_isin(
+                UsBrokerCSVParser::field(record, cusip, "CUSIP")?,
+                UsBrokerCSVParser::US_COUNTRY_CODE,
+            ),
+        ))
+    }
+
+    // Line of the record in the file, the reader starts at the header.
+    fn record_line(&self, record: &csv::StringRecord) -> usize {
+        let preamble = UsBrokerCSVParser::header_begin(&self.content)
+            .map_or(0, |begin| self.content[..begin].matches('\n').count());
+
+        record
+            .position()
+            .map_or(0, |pos| pos.line() as usize + preamble)
+    }
+
+    fn parse_account_note(
+        &self,
+        record: &csv::StringRecord,
+        columns: &AccountColumns,
+    ) -> Result<Option<AccountNote>> {
+        let action_str = UsBrokerCSVParser::field(record, columns.action, "action")?.to_lowercase();
+        // Dividends, interests and transfers aren't trades.
+        let operation = if action_str == UsBrokerCSVParser::BUY_STR {
+            BrokerOperation::Buy
+        } else if action_str == UsBrokerCSVParser::SELL_STR {
+            BrokerOperation::Sell
+        } else {
+            return Ok(None);
+        };
+        // Dates may come as `12/29/2023 as of 12/28/2023`.
+        let date_str = UsBrokerCSVParser::field(record, columns.date, "date")?;
+        let commission = match columns.fees {
+            Some(fees) => UsBrokerCSVParser::decimal(record, fees, "fees")?.abs(),
+            None => Decimal::ZERO,
+        };
+
+        Ok(Some(AccountNote::new(
+            NaiveDate::parse_from_str(date_str.get(..10).unwrap_or(date_str), "%m/%d/%Y")?,
+            UsBrokerCSVParser::company_info(record, columns.cusip, columns.description)?,
+            operation,
+            UsBrokerCSVParser::decimal(record, columns.quantity, "quantity")?.abs(),
+            UsBrokerCSVParser::decimal(record, columns.price, "price")?,
+            UsBrokerCSVParser::decimal(record, columns.amount, "amount")?.abs(),
+            commission,
+            &self.broker,
+        )))
+    }
+
+    pub fn parse_account_notes(&self) -> Result<(AccountNotes, ParseErrors)> {
+        let mut rdr = self.reader()?;
+        let headers = rdr.headers()?.clone();
+        let columns = AccountColumns {
+            date: UsBrokerCSVParser::column(&headers, UsBrokerCSVParser::DATE_HEADER_STR)?,
+            action: UsBrokerCSVParser::column(&headers, UsBrokerCSVParser::ACTION_HEADER_STR)?,
+            cusip: UsBrokerCSVParser::column(&headers, UsBrokerCSVParser::CUSIP_HEADER_STR)?,
+            description: UsBrokerCSVParser::column(
+                &headers,
+                UsBrokerCSVParser::DESCRIPTION_HEADER_STR,
+            )?,
+            quantity: UsBrokerCSVParser::column(&headers, UsBrokerCSVParser::QUANTITY_HEADER_STR)?,
+            price: UsBrokerCSVParser::column(&headers, UsBrokerCSVParser::PRICE_HEADER_STR)?,
+            amount: UsBrokerCSVParser::column(&headers, UsBrokerCSVParser::AMOUNT_HEADER_STR)?,
+            fees: UsBrokerCSVParser::column(&headers, UsBrokerCSVParser::FEES_HEADER_STR).ok(),
+        };
+        let mut account_notes = vec![];
+        let mut errors = vec![];
+
+        for result in rdr.records() {
+            let record = result?;
+            log::debug!("{:?}", record);
+            match self.parse_account_note(&record, &columns) {
+                Ok(Some(note)) => account_notes.push(note),
+                Ok(None) => {}
+                Err(err) => {
+                    errors.push(ParseError::new(self.record_line(&record), err.to_string()))
+                }
+            }
+        }
+
+        Ok((account_notes, errors))
+    }
+
+    fn parse_balance_note(
+        &self,
+        record: &csv::StringRecord,
+        columns: &BalanceColumns,
+        year: usize,
+        errors: &mut ParseErrors,
+    ) -> Result<Option<BalanceNote>> {
+        // Cash and account total rows have no CUSIP.
+        if UsBrokerCSVParser::field(record, columns.cusip, "CUSIP")?.is_empty() {
+            return Ok(None);
+        }
+        let value = UsBrokerCSVParser::decimal(record, columns.market_value, "market value")?;
+
+        let mut note = BalanceNote::new(
+            UsBrokerCSVParser::company_info(record, columns.cusip, columns.description)?,
+            String::from(""),
+            UsBrokerCSVParser::decimal(record, columns.quantity, "quantity")?,
+            String::from(UsBrokerCSVParser::USD_CURRENCY_STR),
+            UsBrokerCSVParser::decimal(record, columns.price, "price")?,
+            Decimal::ZERO,
+            &self.broker,
+        );
+        util::set_value_in_euro(&mut note, &value, year, errors);
+
+        Ok(Some(note))
+    }
+
+    /// Market values are converted from dollars with the year-end rate, positions of a year
+    /// without rate are left for the user to value.
+    pub fn parse_balance_notes(&self, year: usize) -> Result<(BalanceNotes, ParseErrors)> {
+        let mut rdr = self.reader()?;
+        let headers = rdr.headers()?.clone();
+        let columns = BalanceColumns {
+            cusip: UsBrokerCSVParser::column(&headers, UsBrokerCSVParser::CUSIP_HEADER_STR)?,
+            description: UsBrokerCSVParser::column(
+                &headers,
+                UsBrokerCSVParser::DESCRIPTION_HEADER_STR,
+            )?,
+            quantity: UsBrokerCSVParser::column(&headers, UsBrokerCSVParser::QUANTITY_HEADER_STR)?,
+            price: UsBrokerCSVParser::column(&headers, UsBrokerCSVParser::PRICE_HEADER_STR)?,
+            market_value: UsBrokerCSVParser::column(
+                &headers,
+                UsBrokerCSVParser::MARKET_VALUE_HEADER_STR,
+            )?,
+        };
+        let mut balance_notes = vec![];
+        let mut errors = vec![];
+
+        for result in rdr.records() {
+            let record = result?;
+            log::debug!("{:?}", record);
+            match self.parse_balance_note(&record, &columns, year, &mut errors) {
+                Ok(Some(note)) => balance_notes.push(note),
+                Ok(None) => {}
+                Err(err) => {
+                    errors.push(ParseError::new(self.record_line(&record), err.to_string()))
+                }
+            }
+        }
+
+        Ok((balance_notes, errors))
+    }
+
+    pub fn new(content: String, broker: &Arc<BrokerInformation>) -> Self {
+        Self {
+            content,
+            broker: Arc::clone(broker),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn us_broker() -> Arc<BrokerInformation> {
+        Arc::new(BrokerInformation::new(
+            String::from("Charles Schwab"),
+            String::from("US"),
+        ))
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_account_notes() {
+        let us_broker = us_broker();
+        assert!(UsBrokerCSVParser::is_us_broker_csv(
+            TRANSACTIONS_2023.as_bytes()
+        ));
+        assert!(!UsBrokerCSVParser::is_us_broker_positions(
+            TRANSACTIONS_2023.as_bytes()
+        ));
+
+        let parser = UsBrokerCSVParser::new(TRANSACTIONS_2023.to_string(), &us_broker);
+        let (account_notes, errors) = parser.parse_account_notes().unwrap();
+        assert!(errors.is_empty());
+        let acc_notes = vec![
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 3, 1).unwrap(),
+                CompanyInfo {
+                    name: String::from("APPLE INC"),
+                    isin: String::from("US0378331005"),
+                },
+                BrokerOperation::Buy,
+                Decimal::new(10, 0),
+                Decimal::new(145_31, 2),
+                Decimal::new(1453_10, 2),
+                Decimal::ZERO,
+                &us_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 12, 29).unwrap(),
+                CompanyInfo {
+                    name: String::from("TESLA INC"),
+                    isin: String::from("US88160R1014"),
+                },
+                BrokerOperation::Sell,
+                Decimal::new(4, 0),
+                Decimal::new(253_18, 2),
+                Decimal::new(1012_67, 2),
+                Decimal::new(5, 2),
+                &us_broker,
+            ),
+        ];
+
+        assert_eq!(acc_notes, account_notes);
+    }
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_parse_balance_notes() {
+        let us_broker = us_broker();
+        assert!(UsBrokerCSVParser::is_us_broker_csv(
+            POSITIONS_2023.as_bytes()
+        ));
+        assert!(UsBrokerCSVParser::is_us_broker_positions(
+            POSITIONS_2023.as_bytes()
+        ));
+
+        let parser = UsBrokerCSVParser::new(POSITIONS_2023.to_string(), &us_broker);
+        assert_eq!(parser.report_year(), Some(2023));
+        let (balance_notes, errors) = parser.parse_balance_notes(2023).unwrap();
+        assert!(errors.is_empty());
+        let bal_notes = vec![
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("APPLE INC"),
+                    isin: String::from("US0378331005"),
+                },
+                String::from(""),
+                Decimal::new(6, 0),
+                String::from("USD"),
+                Decimal::new(192_53, 2),
+                Decimal::new(1045_41, 2),
+                &us_broker,
+            ),
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("VANGUARD TOTAL STOCK MARKET ETF"),
+                    isin: String::from("US9229087690"),
+                },
+                String::from(""),
+                Decimal::new(12_5, 1),
+                String::from("USD"),
+                Decimal::new(237_22, 2),
+                Decimal::new(2683_48, 2),
+                &us_broker,
+            ),
+        ];
+
+        assert_eq!(bal_notes, balance_notes);
+
+        // Without exchange rate for the year the positions are kept, waiting for a value.
+        let (balance_notes, errors) = parser.parse_balance_notes(1990).unwrap();
+        assert_eq!(balance_notes.len(), 2);
+        assert!(balance_notes
+            .iter()
+            .all(|note| note.derived && note.value_in_euro.is_zero()));
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_skips_malformed_rows() {
+        let us_broker = us_broker();
+        let transactions = TRANSACTIONS_2023.replace("\"03/01/2023\"", "\"2023-03-01\"");
+        let parser = UsBrokerCSVParser::new(transactions, &us_broker);
+        let (account_notes, errors) = parser.parse_account_notes().unwrap();
+        assert_eq!(account_notes.len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+
+        let positions =
+            POSITIONS_2023.replace("\"$192.53\",\"$1,155.18\",\"Equity\"", "\"$192.53\"");
+        let parser = UsBrokerCSVParser::new(positions, &us_broker);
+        let (balance_notes, errors) = parser.parse_balance_notes(2023).unwrap();
+        assert_eq!(balance_notes.len(), 1);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 4);
+    }
+
+    const TRANSACTIONS_2023: &str = r#""Date","Action","Symbol","CUSIP","Description","Quantity","Price","Fees & Comm","Amount"
+"03/01/2023","Buy","AAPL","037833100","APPLE INC","10","$145.31","","-$1,453.10"
+"08/17/2023","Cash Dividend","AAPL","037833100","APPLE INC","","","","$2.40"
+"12/29/2023 as of 12/28/2023","Sell","TSLA","88160R101","TESLA INC","4","$253.18","$0.05","$1,012.67"
+"12/31/2023","Credit Interest","","","SCHWAB1 INT","","","","$0.31""#;
+
+    const POSITIONS_2023: &str = r#""Positions for account Individual ...123 as of 04:00 PM ET, 12/31/2023"
+
+"Symbol","CUSIP","Description","Quantity","Price","Market Value","Security Type"
+"AAPL","037833100","APPLE INC","6","$192.53","$1,155.18","Equity"
+"VTI","922908769","VANGUARD TOTAL STOCK MARKET ETF","12.5","$237.22","$2,965.25","ETFs & Closed End Funds"
+"Cash & Cash Investments","","","","","$120.55","Cash and Money Market"
+"Account Total","","","","","$4,240.98","""#;
+}
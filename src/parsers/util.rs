@@ -1,21 +1,116 @@
-use crate::data::BalanceNotes;
-use anyhow::Result;
+use crate::{
+    data::{BalanceNote, BalanceNotes, ParseError, ParseErrors},
+    utils::{decimal::round_valuation, fx},
+};
+use anyhow::{bail, Result};
 use rust_decimal::Decimal;
 
+/// Spreads the total in euros of a currency over its positions. `local_total` is the total of
+/// the report in that currency; when some of its rows were skipped, their share is left out of
+/// the total in euros so it isn't added to the remaining positions.
 pub(crate) fn recalculate_balance_notes(
     notes: &mut BalanceNotes,
     total_in_euro: &Decimal,
+    local_total: Option<&Decimal>,
 ) -> Result<()> {
+    if notes.is_empty() {
+        return Ok(());
+    }
     let total = notes
         .iter()
         .fold(Decimal::new(0, 2), |acc, x| acc + x.price * x.quantity);
+    if total.is_zero() {
+        bail!("Unable to spread the total in euros over positions without value");
+    }
+    let parsed_total = notes
+        .iter()
+        .fold(Decimal::ZERO, |acc, x| acc + x.value_in_euro);
+    let total_in_euro = match local_total {
+        Some(local_total) if !local_total.is_zero() && *local_total != parsed_total => {
+            *total_in_euro * parsed_total / *local_total
+        }
+        _ => *total_in_euro,
+    };
     for note in notes {
-        note.value_in_euro = ((note.value_in_euro * total_in_euro) / total).round_dp(2);
+        note.value_in_euro = round_valuation(&((note.value_in_euro * total_in_euro) / total));
     }
 
     Ok(())
 }
 
+/// Warns when the positions don't add up to the total in euros of the report, a sign of
+/// a layout change spreading the currency totals wrongly. Up to 1 € or 0.1% is rounding.
+pub(crate) fn check_total_in_euro(
+    notes: &[BalanceNote],
+    total_in_euro: &Decimal,
+) -> Option<ParseError> {
+    let sum = notes
+        .iter()
+        .fold(Decimal::ZERO, |acc, note| acc + note.value_in_euro);
+    let tolerance = Decimal::ONE.max(total_in_euro.abs() * Decimal::new(1, 3));
+
+    ((sum - total_in_euro).abs() > tolerance).then(|| {
+        ParseError::new(
+            0,
+            format!(
+                "La suma de las posiciones ({} €) no coincide con el total en euros del informe ({} €), revisa los valores importados",
+                sum.round_dp(2),
+                total_in_euro.round_dp(2)
+            ),
+        )
+    })
+}
+
+/// Sets the year-end value in euros of a position. Without an exchange rate for the year the
+/// value is left for the user to type it, like in derived holdings, with an error for that
+/// row only.
+pub(crate) fn set_value_in_euro(
+    note: &mut BalanceNote,
+    amount: &Decimal,
+    year: usize,
+    errors: &mut ParseErrors,
+) {
+    match fx::to_euro(amount, &note.currency, year) {
+        Ok(value) => note.value_in_euro = value,
+        Err(err) => {
+            errors.push(ParseError::new(
+                0,
+                format!(
+                    "{} ({}): {}, introduce su valor en euros",
+                    note.company.name, note.company.isin, err
+                ),
+            ));
+            note.value_in_euro = Decimal::ZERO;
+            note.derived = true;
+        }
+    }
+}
+
+const PENCE_CURRENCY_STR: &str = "GBX";
+const POUND_CURRENCY_STR: &str = "GBP";
+const PENCE_PER_POUND: i64 = 100;
+
+/// London quotes in pence are moved to pounds, so price and local value stay in the same unit
+/// when the value is later converted to euros.
+pub(crate) fn normalize_pence(mut note: BalanceNote) -> BalanceNote {
+    if note.currency == PENCE_CURRENCY_STR {
+        note.currency = POUND_CURRENCY_STR.to_string();
+        note.price /= Decimal::from(PENCE_PER_POUND);
+        note.value_in_euro /= Decimal::from(PENCE_PER_POUND);
+    }
+
+    note
+}
+
+/// Amount of a report total in the unit its positions are normalized to.
+pub(crate) fn normalize_pence_amount(amount: Decimal, currency: &str) -> Decimal {
+    if currency == PENCE_CURRENCY_STR {
+        amount / Decimal::from(PENCE_PER_POUND)
+    } else {
+        amount
+    }
+}
+
 pub(crate) fn replace_escaped_fields(original_str: &str) -> String {
     let mut fields_str = String::new();
     let mut in_quoted_field = false;
@@ -35,3 +130,166 @@ pub(crate) fn replace_escaped_fields(original_str: &str) -> String {
 
     fields_str
 }
+
+fn parse_year(input: &str) -> Option<usize> {
+    if input.len() == 4 && input.bytes().all(|b| b.is_ascii_digit()) {
+        input.parse::<usize>().ok()
+    } else {
+        None
+    }
+}
+
+/// Finds the first four digit year right after any occurrence of `prefix`.
+pub(crate) fn year_after(content: &str, prefix: &str) -> Option<usize> {
+    content
+        .match_indices(prefix)
+        .find_map(|(idx, _)| parse_year(content.get(idx + prefix.len()..idx + prefix.len() + 4)?))
+}
+
+/// Finds the end year of a statement period like "January 1, 2021 - December 31, 2021".
+pub(crate) fn period_end_year(content: &str) -> Option<usize> {
+    content.match_indices(" - ").find_map(|(idx, separator)| {
+        let period_end = content[idx + separator.len()..]
+            .split(['"', '<', '\n'])
+            .next()?;
+        let (_, year) = period_end.trim().rsplit_once(", ")?;
+        parse_year(year)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{BrokerInformation, CompanyInfo};
+    use std::sync::Arc;
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn test_normalize_pence() {
+        let broker = Arc::new(BrokerInformation::new(
+            String::from("IB"),
+            String::from("IE"),
+        ));
+        let note = |currency: &str, price, value| {
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("BURFORD CAP LD"),
+                    isin: String::from("GG00B4L84979"),
+                },
+                String::from(""),
+                Decimal::new(463, 0),
+                String::from(currency),
+                price,
+                value,
+                &broker,
+            )
+        };
+
+        assert_eq!(
+            normalize_pence(note("GBX", Decimal::new(712, 0), Decimal::new(329656, 0))),
+            note("GBP", Decimal::new(7_12, 2), Decimal::new(3296_56, 2))
+        );
+        assert_eq!(
+            normalize_pence(note("GBP", Decimal::new(7_12, 2), Decimal::new(3296_56, 2))),
+            note("GBP", Decimal::new(7_12, 2), Decimal::new(3296_56, 2))
+        );
+    }
+
+    #[test]
+    fn test_check_total_in_euro() {
+        let broker = Arc::new(BrokerInformation::new(
+            String::from("IB"),
+            String::from("IE"),
+        ));
+        let note = |value| {
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("APPLE INC"),
+                    isin: String::from("US0378331005"),
+                },
+                String::from(""),
+                Decimal::ONE,
+                String::from("EUR"),
+                value,
+                value,
+                &broker,
+            )
+        };
+        let notes = [
+            note(Decimal::new(8675_85, 2)),
+            note(Decimal::new(12972_78, 2)),
+        ];
+
+        assert_eq!(
+            check_total_in_euro(&notes, &Decimal::new(21648_63, 2)),
+            None
+        );
+        assert_eq!(
+            check_total_in_euro(&notes, &Decimal::new(21660_00, 2)),
+            None
+        );
+        assert_eq!(
+            check_total_in_euro(&notes, &Decimal::new(25264_89, 2)),
+            Some(ParseError {
+                line: 0,
+                reason: String::from("La suma de las posiciones (21648.63 €) no coincide con el total en euros del informe (25264.89 €), revisa los valores importados"),
+            })
+        );
+        assert_eq!(check_total_in_euro(&[], &Decimal::new(50, 2)), None);
+    }
+
+    #[test]
+    fn test_set_value_in_euro() {
+        let broker = Arc::new(BrokerInformation::new(
+            String::from("eToro"),
+            String::from("CY"),
+        ));
+        let mut note = BalanceNote::new(
+            CompanyInfo {
+                name: String::from("APPLE INC"),
+                isin: String::from("US0378331005"),
+            },
+            String::from(""),
+            Decimal::ONE,
+            String::from("USD"),
+            Decimal::ZERO,
+            Decimal::ZERO,
+            &broker,
+        );
+        let mut errors = vec![];
+
+        set_value_in_euro(&mut note, &Decimal::new(1200_00, 2), 2023, &mut errors);
+        assert_eq!(note.value_in_euro, Decimal::new(1085_97, 2));
+        assert!(!note.derived);
+        assert!(errors.is_empty());
+
+        set_value_in_euro(&mut note, &Decimal::new(1200_00, 2), 1990, &mut errors);
+        assert_eq!(note.value_in_euro, Decimal::ZERO);
+        assert!(note.derived);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_year_after() {
+        let content = "Annual Report for the year 2018\nAnnual Report 2018 - www.degiro.ie";
+        assert_eq!(year_after(content, "Annual Report "), Some(2018));
+        assert_eq!(
+            year_after("Informe Anual de flatex", "Informe Anual "),
+            None
+        );
+        assert_eq!(year_after("Informe Anual 20", "Informe Anual "), None);
+    }
+
+    #[test]
+    fn test_period_end_year() {
+        assert_eq!(
+            period_end_year(r#"Statement,Data,Period,"January 1, 2021 - December 31, 2021""#),
+            Some(2021)
+        );
+        assert_eq!(
+            period_end_year("<div>Enero 1, 2022 - Diciembre 31, 2022</div>"),
+            Some(2022)
+        );
+        assert_eq!(period_end_year("GRAVITY CO. LTD. - AM,US38911N2062"), None);
+    }
+}
@@ -0,0 +1,391 @@
+use std::{str::FromStr, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use crate::{
+    data::{
+        AccountNote, AccountNotes, BalanceNote, BalanceNotes, BrokerInformation, BrokerOperation,
+        CompanyInfo, ParseError, ParseErrors, ProductType,
+    },
+    parsers::util,
+    utils::decimal,
+};
+
+pub struct XtbParser {
+    content: String,
+    broker: Arc<BrokerInformation>,
+}
+
+impl XtbParser {
+    const ISSUER_STRS: &'static [&'static str] = &["X-Trade Brokers", "XTB"];
+    const REPORT_YEAR_STR: &'static str = "Informe anual ";
+
+    const OPEN_POSITIONS_HEADER_STR: &'static str =
+        "Instrumento ISIN Tipo Volumen Precio apertura Precio actual Valor (EUR)\n";
+    const CLOSED_POSITIONS_HEADER_STR: &'static str =
+        "Instrumento ISIN Tipo Fecha apertura Fecha cierre Volumen Precio apertura Precio cierre Beneficio (EUR)\n";
+    const SECTION_END_STR: &'static str = "\nTotal";
+
+    const CFD_TYPE_STR: &'static str = "CFD";
+    const DATE_FORMAT_STR: &'static str = "%d/%m/%Y";
+
+    const OPEN_POSITION_FIELDS: usize = 7;
+    const CLOSED_POSITION_FIELDS: usize = 9;
+
+    // Currency of the instruments by the market suffix of their symbol, e.g. `AAPL.US`.
+    const MARKET_CURRENCIES: &'static [(&'static str, &'static str)] = &[
+        ("US", "USD"),
+        ("UK", "GBX"),
+        ("CH", "CHF"),
+        ("PL", "PLN"),
+        ("CZ", "CZK"),
+        ("DK", "DKK"),
+        ("SE", "SEK"),
+        ("NO", "NOK"),
+        ("DE", "EUR"),
+        ("ES", "EUR"),
+        ("FR", "EUR"),
+        ("IT", "EUR"),
+        ("NL", "EUR"),
+        ("BE", "EUR"),
+        ("PT", "EUR"),
+        ("FI", "EUR"),
+        ("IE", "EUR"),
+    ];
+
+    // Other reports may mention XTB, only the ones with its positions tables are parsed.
+    pub fn is_xtb_report(content: &str) -> bool {
+        XtbParser::ISSUER_STRS.iter().any(|x| content.contains(x))
+            && [
+                XtbParser::OPEN_POSITIONS_HEADER_STR,
+                XtbParser::CLOSED_POSITIONS_HEADER_STR,
+            ]
+            .iter()
+            .any(|header| content.contains(header))
+    }
+
+    // Instrument names may have spaces, e.g. `EURUSD - CFD`, the other columns don't so
+    // they're taken from the end of the row.
+    fn columns(row: &str, count: usize) -> Vec<&str> {
+        let mut columns: Vec<&str> = row.rsplitn(count, ' ').collect();
+        columns.reverse();
+        columns
+    }
+
+    fn currency(symbol: &str) -> String {
+        symbol
+            .rsplit_once('.')
+            .and_then(|(_, market)| {
+                XtbParser::MARKET_CURRENCIES
+                    .iter()
+                    .find(|(code, _)| *code == market)
+            })
+            .map_or(String::from(""), |(_, currency)| currency.to_string())
+    }
+
+    fn section<'a>(&'a self, header: &str) -> Result<&'a str> {
+        let begin = self
+            .content
+            .find(header)
+            .with_context(|| format!("Not found section beginning: {}", header.trim_end()))?
+            + header.len();
+        let end = self.content[begin..]
+            .find(XtbParser::SECTION_END_STR)
+            .map_or(self.content.len(), |x| begin + x);
+
+        Ok(&self.content[begin..end])
+    }
+
+    fn decimal(input: &str) -> Result<Decimal> {
+        Ok(Decimal::from_str(&decimal::transform_i18n_es_str(input))?)
+    }
+
+    fn date(input: &str) -> Result<NaiveDate> {
+        Ok(NaiveDate::parse_from_str(
+            input,
+            XtbParser::DATE_FORMAT_STR,
+        )?)
+    }
+
+    // XTB only reports the instrument symbol, it's used as company name.
+    fn company_info(symbol: &str, isin: &str) -> CompanyInfo {
+        CompanyInfo::new(symbol.to_string(), isin.to_string())
+    }
+
+    fn parse_balance_note(&self, fields: &[&str]) -> Result<BalanceNote> {
+        log::debug!("balance note fields {:?}", fields);
+        if fields.len() != XtbParser::OPEN_POSITION_FIELDS {
+            bail!("Unexpected open position fields: {:?}", fields);
+        }
+
+        Ok(BalanceNote::new(
+            XtbParser::company_info(fields[0], fields[1]),
+            String::from(""),
+            XtbParser::decimal(fields[3])?,
+            XtbParser::currency(fields[0]),
+            XtbParser::decimal(fields[5])?,
+            XtbParser::decimal(fields[6])?,
+            &self.broker,
+        )
+        .with_product_type(ProductType::from(fields[2])))
+    }
+
+    fn parse_account_note(&self, fields: &[&str]) -> Result<(AccountNote, AccountNote)> {
+        log::debug!("account note fields {:?}", fields);
+        if fields.len() != XtbParser::CLOSED_POSITION_FIELDS {
+            bail!("Unexpected closed position fields: {:?}", fields);
+        }
+
+        let company = XtbParser::company_info(fields[0], fields[1]);
+        let quantity = XtbParser::decimal(fields[5])?;
+        let open_price = XtbParser::decimal(fields[6])?;
+        let close_price = XtbParser::decimal(fields[7])?;
+
+        Ok((
+            AccountNote::new(
+                XtbParser::date(fields[3])?,
+                company.clone(),
+                BrokerOperation::Buy,
+                quantity,
+                open_price,
+                (quantity * open_price).round_dp(2),
+                Decimal::ZERO,
+                &self.broker,
+            ),
+            AccountNote::new(
+                XtbParser::date(fields[4])?,
+                company,
+                BrokerOperation::Sell,
+                quantity,
+                close_price,
+                (quantity * close_price).round_dp(2),
+                Decimal::ZERO,
+                &self.broker,
+            ),
+        ))
+    }
+
+    // CFDs aren't declarable in the 720 form.
+    fn is_cfd(fields: &[&str]) -> bool {
+        fields.get(2) == Some(&XtbParser::CFD_TYPE_STR)
+    }
+
+    fn row_error(line: &str, err: anyhow::Error) -> ParseError {
+        ParseError::new(
+            0,
+            format!(
+                "Línea no reconocida en el informe de XTB: {} ({})",
+                line, err
+            ),
+        )
+    }
+
+    pub fn parse_balance_notes(&self) -> Result<(BalanceNotes, ParseErrors)> {
+        let mut result = vec![];
+        let mut errors = vec![];
+
+        for line in self.section(XtbParser::OPEN_POSITIONS_HEADER_STR)?.lines() {
+            let row = line.split_whitespace().collect::<Vec<_>>().join(" ");
+            let fields = XtbParser::columns(&row, XtbParser::OPEN_POSITION_FIELDS);
+            if row.is_empty() || XtbParser::is_cfd(&fields) {
+                continue;
+            }
+            match self.parse_balance_note(&fields) {
+                Ok(note) => result.push(note),
+                Err(err) => errors.push(XtbParser::row_error(line, err)),
+            }
+        }
+
+        Ok((result, errors))
+    }
+
+    pub fn parse_account_notes(&self) -> Result<(AccountNotes, ParseErrors)> {
+        let mut result = vec![];
+        let mut errors = vec![];
+
+        for line in self
+            .section(XtbParser::CLOSED_POSITIONS_HEADER_STR)?
+            .lines()
+        {
+            let row = line.split_whitespace().collect::<Vec<_>>().join(" ");
+            let fields = XtbParser::columns(&row, XtbParser::CLOSED_POSITION_FIELDS);
+            if row.is_empty() || XtbParser::is_cfd(&fields) {
+                continue;
+            }
+            match self.parse_account_note(&fields) {
+                Ok((buy, sell)) => {
+                    result.push(buy);
+                    result.push(sell);
+                }
+                Err(err) => errors.push(XtbParser::row_error(line, err)),
+            }
+        }
+
+        result.sort_by_key(|x| x.date);
+
+        Ok((result, errors))
+    }
+
+    pub fn new(content: String, broker: &Arc<BrokerInformation>) -> Self {
+        Self {
+            content,
+            broker: Arc::clone(broker),
+        }
+    }
+
+    pub fn report_year(&self) -> Option<usize> {
+        util::year_after(&self.content, XtbParser::REPORT_YEAR_STR)
+    }
+
+    pub fn parse_pdf_content(&self) -> Result<(BalanceNotes, AccountNotes, ParseErrors)> {
+        let (account_notes, mut errors) = self.parse_account_notes()?;
+        let (balance_notes, balance_errors) = self.parse_balance_notes()?;
+        errors.extend(balance_errors);
+
+        Ok((balance_notes, account_notes, errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::mistyped_literal_suffixes)]
+    fn xtb_parse_content_test() {
+        let xtb_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("XTB"),
+            String::from("PL"),
+        ));
+        assert!(XtbParser::is_xtb_report(INPUT_2023));
+        assert!(!XtbParser::is_xtb_report(
+            "Informe Anual 2023 - www.degiro.es\nTraspaso de XTB\n"
+        ));
+
+        let parser = XtbParser::new(INPUT_2023.to_string(), &xtb_broker);
+        let (balance_notes, account_notes, errors) = parser.parse_pdf_content().unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(parser.report_year(), Some(2023));
+
+        let bal_notes = vec![
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("AAPL.US"),
+                    isin: String::from("US0378331005"),
+                },
+                String::from(""),
+                Decimal::new(10, 0),
+                String::from("USD"),
+                Decimal::new(192_53, 2),
+                Decimal::new(1_742_31, 2),
+                &xtb_broker,
+            ),
+            BalanceNote::new(
+                CompanyInfo {
+                    name: String::from("VWCE.DE"),
+                    isin: String::from("IE00BK5BQT80"),
+                },
+                String::from(""),
+                Decimal::new(25, 0),
+                String::from("EUR"),
+                Decimal::new(104_86, 2),
+                Decimal::new(2_621_50, 2),
+                &xtb_broker,
+            )
+            .with_product_type(ProductType::Fund),
+        ];
+        assert_eq!(bal_notes, balance_notes);
+
+        let acc_notes = vec![
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 2, 1).unwrap(),
+                CompanyInfo {
+                    name: String::from("SAN.ES"),
+                    isin: String::from("ES0113900J37"),
+                },
+                BrokerOperation::Buy,
+                Decimal::new(100, 0),
+                Decimal::new(3_12, 2),
+                Decimal::new(312_00, 2),
+                Decimal::ZERO,
+                &xtb_broker,
+            ),
+            AccountNote::new(
+                NaiveDate::from_ymd_opt(2023, 6, 15).unwrap(),
+                CompanyInfo {
+                    name: String::from("SAN.ES"),
+                    isin: String::from("ES0113900J37"),
+                },
+                BrokerOperation::Sell,
+                Decimal::new(100, 0),
+                Decimal::new(3_58, 2),
+                Decimal::new(358_00, 2),
+                Decimal::ZERO,
+                &xtb_broker,
+            ),
+        ];
+        assert_eq!(acc_notes, account_notes);
+    }
+
+    #[test]
+    fn xtb_skips_unreadable_rows_test() {
+        let xtb_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("XTB"),
+            String::from("PL"),
+        ));
+        let input = INPUT_2023
+            .replace("STC 10 150,25", "STC diez 150,25")
+            .replace("01/02/2023 15/06/2023", "01/02/2023 n/a");
+        let parser = XtbParser::new(input, &xtb_broker);
+        let (balance_notes, account_notes, errors) = parser.parse_pdf_content().unwrap();
+
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].reason.contains("SAN.ES"));
+        assert!(errors[1].reason.contains("AAPL.US"));
+        assert!(account_notes.is_empty());
+        assert_eq!(balance_notes.len(), 1);
+        assert_eq!(balance_notes[0].company.isin, "IE00BK5BQT80");
+    }
+
+    #[test]
+    fn xtb_instrument_names_with_spaces_test() {
+        let xtb_broker: Arc<BrokerInformation> = Arc::new(BrokerInformation::new(
+            String::from("XTB"),
+            String::from("PL"),
+        ));
+        let input = INPUT_2023
+            .replace("AAPL.US US0378331005", "APPLE INC.US US0378331005")
+            .replace("EURUSD - CFD", "EUR USD - CFD");
+        let parser = XtbParser::new(input, &xtb_broker);
+        let (balance_notes, errors) = parser.parse_balance_notes().unwrap();
+
+        assert!(errors.is_empty());
+        assert_eq!(balance_notes.len(), 2);
+        assert_eq!(balance_notes[0].company.name, "APPLE INC.US");
+        assert_eq!(balance_notes[0].company.isin, "US0378331005");
+        assert_eq!(balance_notes[0].currency, "USD");
+        assert_eq!(balance_notes[0].quantity, Decimal::new(10, 0));
+    }
+
+    const INPUT_2023: &str = r#"
+X-Trade Brokers Dom Maklerski S.A., Sucursal en España
+Informe anual 2023
+
+Posiciones abiertas a 31/12/2023
+
+Instrumento ISIN Tipo Volumen Precio apertura Precio actual Valor (EUR)
+AAPL.US US0378331005 STC 10 150,25 192,53 1.742,31
+EURUSD - CFD 1,5 1,0850 1,1040 -285,00
+VWCE.DE IE00BK5BQT80 ETF 25 98,10 104,86 2.621,50
+Total 4.078,81
+
+Transacciones cerradas
+
+Instrumento ISIN Tipo Fecha apertura Fecha cierre Volumen Precio apertura Precio cierre Beneficio (EUR)
+SAN.ES ES0113900J37 STC 01/02/2023 15/06/2023 100 3,12 3,58 46,00
+US500 - CFD 03/03/2023 04/03/2023 2 3.950,10 3.990,40 74,40
+Total 120,40
+"#;
+}
@@ -1,21 +1,247 @@
 use std::sync::Arc;
 
 use dominator::{clone, events, html, with_node, Dom};
-use futures_signals::signal::Mutable;
-use web_sys::HtmlInputElement;
+use futures_signals::signal::{always, Mutable, Signal, SignalExt};
+use web_sys::{HtmlInputElement, HtmlSelectElement};
 
 use crate::{
     css::{FLEX_CONTAINER_CLASS, FLEX_CONTAINER_ITEM_20_CLASS},
-    data::{PersonalInformation, DEFAULT_YEAR},
+    data::{
+        normalize_phone, validate_declaration_id, validate_nif, DeclarationType,
+        PersonalInformation,
+    },
+    utils::default_year,
 };
 
+const PHONE_NOT_VALID_ERR_MSG: &str = "Teléfono no válido, 9 dígitos";
+const PROXY_NIF_NOT_VALID_ERR_MSG: &str = "NIF no válido";
+const DECLARATION_ID_NOT_VALID_ERR_MSG: &str =
+    "Identificador no válido, 13 dígitos empezando por 720";
+
 pub struct PersonalInfoViewer {
     personal_info: Mutable<PersonalInformation>,
+    co_holder: Mutable<Option<PersonalInformation>>,
+    phone_err: Mutable<bool>,
+    proxy_nif_err: Mutable<bool>,
+    declaration_id_err: Mutable<bool>,
+    previous_declaration_id_err: Mutable<bool>,
+    // Shown in the year input, only changed when the year is set from outside it.
+    year_value: Mutable<String>,
 }
 
 impl PersonalInfoViewer {
-    pub fn new(personal_info: Mutable<PersonalInformation>) -> Arc<Self> {
-        Arc::new(PersonalInfoViewer { personal_info })
+    pub fn new(
+        personal_info: Mutable<PersonalInformation>,
+        co_holder: Mutable<Option<PersonalInformation>>,
+    ) -> Arc<Self> {
+        Arc::new(PersonalInfoViewer {
+            personal_info,
+            co_holder,
+            phone_err: Mutable::new(false),
+            proxy_nif_err: Mutable::new(false),
+            declaration_id_err: Mutable::new(false),
+            previous_declaration_id_err: Mutable::new(false),
+            year_value: Mutable::new(personal_info.lock_ref().year.to_string()),
+        })
+    }
+
+    /// Year detected in the imported reports and confirmed by the user.
+    pub fn set_year(&self, year: usize) {
+        self.personal_info.lock_mut().year = year;
+        self.year_value.set(year.to_string());
+    }
+
+    fn render_co_holder_input(
+        this: &Arc<Self>,
+        id: &str,
+        placeholder: &str,
+        update: fn(&mut PersonalInformation, String),
+    ) -> Dom {
+        html!("span", {
+            .class(&*FLEX_CONTAINER_ITEM_20_CLASS)
+            .children(&mut [
+                html!("input" => HtmlInputElement, {
+                    .attr("id", id)
+                    .attr("alt", placeholder)
+                    .attr("type", "text")
+                    .attr("placeholder", placeholder)
+                    .style("height", "24px")
+                    .with_node!(element => {
+                        .event(clone!(this => move |_: events::Input| {
+                            if let Some(co_holder) = this.co_holder.lock_mut().as_mut() {
+                                update(co_holder, element.value().to_uppercase());
+                            }
+                        }))
+                    })
+                }),
+            ])
+        })
+    }
+
+    pub fn render_co_holder(this: &Arc<Self>) -> Dom {
+        html!("section", {
+            .child(html!("input" => HtmlInputElement, {
+                .attr("id", "co_holder")
+                .attr("type", "checkbox")
+                .with_node!(element => {
+                    .event(clone!(this => move |_: events::Change| {
+                        this.co_holder.set(element.checked().then(PersonalInformation::default));
+                    }))
+                })
+            }))
+            .child(html!("label", {
+                .attr("for", "co_holder")
+                .text("Declaración conjunta con un segundo titular")
+            }))
+            .child(html!("div", {
+                .class(&*FLEX_CONTAINER_CLASS)
+                .visible_signal(this.co_holder.signal_ref(|x| x.is_some()))
+                .child(Self::render_co_holder_input(this, "co_holder_name", "Nombre 2º titular", |info, value| info.name = value))
+                .child(Self::render_co_holder_input(this, "co_holder_surname", "Apellidos 2º titular", |info, value| info.surname = value))
+                .child(Self::render_co_holder_input(this, "co_holder_nif", "DNI 2º titular", |info, value| info.nif = value))
+            }))
+        })
+    }
+
+    pub fn render_proxy(this: &Arc<Self>) -> Dom {
+        html!("section", {
+            .child(html!("input" => HtmlInputElement, {
+                .attr("id", "proxy")
+                .attr("type", "checkbox")
+                .with_node!(element => {
+                    .event(clone!(this => move |_: events::Change| {
+                        this.proxy_nif_err.set_neq(false);
+                        this.personal_info.lock_mut().proxy_nif = element.checked().then(String::new);
+                    }))
+                })
+            }))
+            .child(html!("label", {
+                .attr("for", "proxy")
+                .text("Presento la declaración como representante")
+            }))
+            .child(html!("div", {
+                .class(&*FLEX_CONTAINER_CLASS)
+                .visible_signal(this.personal_info.signal_ref(|info| info.proxy_nif.is_some()))
+                .child(html!("span", {
+                    .class(&*FLEX_CONTAINER_ITEM_20_CLASS)
+                    .children(&mut [
+                        html!("input" => HtmlInputElement, {
+                            .attr("id", "proxy_nif")
+                            .attr("alt", "NIF del representante")
+                            .attr("type", "text")
+                            .attr("maxlength", "9")
+                            .attr("placeholder", "NIF del representante")
+                            .style("height", "24px")
+                            .with_node!(element => {
+                                .event(clone!(this => move |_: events::Input| {
+                                    let nif = element.value().trim().to_uppercase();
+                                    this.proxy_nif_err.set_neq(!nif.is_empty() && !validate_nif(&nif));
+                                    if let Some(proxy_nif) = this.personal_info.lock_mut().proxy_nif.as_mut() {
+                                        *proxy_nif = nif;
+                                    }
+                                }))
+                            })
+                        }),
+                        html!("span", {
+                            .style("color", "var(--error-color)")
+                            .style("font-size", "small")
+                            .visible_signal(this.proxy_nif_err.signal())
+                            .text(PROXY_NIF_NOT_VALID_ERR_MSG)
+                        }),
+                    ])
+                }))
+            }))
+        })
+    }
+
+    fn render_declaration_id_input(
+        id: &str,
+        alt: &str,
+        placeholder: impl Signal<Item = String> + 'static,
+        err: &Mutable<bool>,
+        update: impl Fn(String) + 'static,
+    ) -> Dom {
+        html!("span", {
+            .class(&*FLEX_CONTAINER_ITEM_20_CLASS)
+            .children(&mut [
+                html!("input" => HtmlInputElement, {
+                    .attr("id", id)
+                    .attr("alt", alt)
+                    .attr("type", "text")
+                    .attr("maxlength", "13")
+                    .attr_signal("placeholder", placeholder)
+                    .style("height", "24px")
+                    .with_node!(element => {
+                        .event(clone!(err => move |_: events::Input| {
+                            let id = element.value().trim().to_string();
+                            err.set_neq(!id.is_empty() && !validate_declaration_id(&id));
+                            update(id);
+                        }))
+                    })
+                }),
+                html!("span", {
+                    .style("color", "var(--error-color)")
+                    .style("font-size", "small")
+                    .visible_signal(err.signal())
+                    .text(DECLARATION_ID_NOT_VALID_ERR_MSG)
+                }),
+            ])
+        })
+    }
+
+    // The identifier is generated from the year unless given, complementary and replacement
+    // declarations also need the one of the declaration they amend.
+    pub fn render_declaration(this: &Arc<Self>) -> Dom {
+        html!("section", {
+            .class(&*FLEX_CONTAINER_CLASS)
+            .child(html!("span", {
+                .class(&*FLEX_CONTAINER_ITEM_20_CLASS)
+                .child(html!("select" => HtmlSelectElement, {
+                    .attr("id", "declaration_type")
+                    .style("height", "24px")
+                    .children([
+                        ("original", "Declaración ordinaria"),
+                        ("complementary", "Declaración complementaria"),
+                        ("replacement", "Declaración sustitutiva"),
+                    ].iter().map(|(value, label)| {
+                        html!("option", {
+                            .attr("value", value)
+                            .text(label)
+                        })
+                    }))
+                    .with_node!(element => {
+                        .event(clone!(this => move |_: events::Change| {
+                            this.personal_info.lock_mut().declaration_type = match element.value().as_str() {
+                                "complementary" => DeclarationType::Complementary,
+                                "replacement" => DeclarationType::Replacement,
+                                _ => DeclarationType::Original,
+                            };
+                        }))
+                    })
+                }))
+            }))
+            .child(Self::render_declaration_id_input(
+                "declaration_id",
+                "Identificador",
+                this.personal_info.signal_ref(|info| format!("Identificador ({})", info.declaration_id())),
+                &this.declaration_id_err,
+                clone!(this => move |id| {
+                    this.personal_info.lock_mut().declaration_id = id;
+                }),
+            ))
+            .child(html!("span", {
+                .visible_signal(this.personal_info.signal_ref(|info| info.declaration_type != DeclarationType::Original))
+                .child(Self::render_declaration_id_input(
+                    "previous_declaration_id",
+                    "Identificador anterior",
+                    always(String::from("Identificador anterior")),
+                    &this.previous_declaration_id_err,
+                    clone!(this => move |id| {
+                        this.personal_info.lock_mut().previous_declaration_id = id;
+                    }),
+                ))
+            }))
+        })
     }
 
     pub fn render(this: &Arc<Self>) -> Dom {
@@ -84,11 +310,11 @@ impl PersonalInfoViewer {
                         .attr("type", "text")
                         .attr("maxlength", "4")
                         .attr("placeholder", "Año")
-                        .attr("value", &DEFAULT_YEAR.to_string())
+                        .prop_signal("value", this.year_value.signal_cloned())
                         .style("height", "24px")
                         .with_node!(element => {
                             .event(clone!(this => move |_: events::Input| {
-                                this.personal_info.lock_mut().year = element.value().parse::<usize>().unwrap_or(DEFAULT_YEAR);
+                                this.personal_info.lock_mut().year = element.value().parse::<usize>().unwrap_or_else(|_| default_year());
                             }))
                         })
                     }),
@@ -102,12 +328,39 @@ impl PersonalInfoViewer {
                         .attr("alt", "Teléfono")
                         .attr("type", "text")
                         .attr("autocomplete", "tel")
-                        .attr("maxlength", "9")
+                        .attr("maxlength", "16")
                         .attr("placeholder", "Teléfono")
                         .style("height", "24px")
                         .with_node!(element => {
                             .event(clone!(this => move |_: events::Input| {
-                                this.personal_info.lock_mut().phone = element.value().to_uppercase();
+                                let value = element.value().trim().to_string();
+                                let phone = normalize_phone(&value);
+                                this.phone_err.set(phone.is_none() && !value.is_empty());
+                                this.personal_info.lock_mut().phone = phone.unwrap_or(value);
+                            }))
+                        })
+                    }),
+                    html!("span", {
+                        .style("color", "var(--error-color)")
+                        .style("font-size", "small")
+                        .visible_signal(this.phone_err.signal())
+                        .text(PHONE_NOT_VALID_ERR_MSG)
+                    }),
+                ])
+            }))
+            .child(html!("span", {
+                .class(&*FLEX_CONTAINER_ITEM_20_CLASS)
+                .children(&mut [
+                    html!("input" => HtmlInputElement, {
+                        .attr("id", "contact_name")
+                        .attr("alt", "Persona de contacto")
+                        .attr("type", "text")
+                        .attr("placeholder", "Persona de contacto (opcional)")
+                        .attr("title", "Por defecto el declarante")
+                        .style("height", "24px")
+                        .with_node!(element => {
+                            .event(clone!(this => move |_: events::Input| {
+                                this.personal_info.lock_mut().contact_name = element.value().to_uppercase();
                             }))
                         })
                     }),
@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use dominator::{clone, events, html, with_node, Dom};
+use futures_signals::signal::{Mutable, SignalExt};
+use num_format::Locale;
+use rust_decimal::Decimal;
+use web_sys::HtmlInputElement;
+
+use crate::{
+    css::{FLEX_CONTAINER_CLASS, FLEX_CONTAINER_ITEM_20_CLASS},
+    data::{
+        validate_country_code, AcquisitionType, Aeat720Record, BrokerInformation, CompanyInfo,
+        ProductType, DEFAULT_BROKER, DEFAULT_NUMBER_OF_DECIMALS,
+    },
+    utils::{
+        decimal::{decimal_to_str_locale, str_locale_to_decimal, valid_str_number_with_decimals},
+        default_year, web,
+    },
+};
+
+const TEMPLATE_SETTING_KEY: &str = "record_template";
+const INPUT_NOT_VALID_ERR_MSG: &str = "No válido";
+
+type UpdateTemplate = fn(&mut Aeat720Record, &str, &Locale) -> bool;
+
+/// Values of the rows added by hand, saved between visits for users entering many of them.
+pub struct RecordTemplateViewer {
+    template: Mutable<Aeat720Record>,
+    locale: Mutable<Locale>,
+}
+
+impl RecordTemplateViewer {
+    pub fn new(template: Mutable<Aeat720Record>, locale: Mutable<Locale>) -> Arc<Self> {
+        Arc::new(RecordTemplateViewer { template, locale })
+    }
+
+    pub fn default_template() -> Aeat720Record {
+        Aeat720Record {
+            company: CompanyInfo {
+                name: "Nueva compañía".to_string(),
+                isin: "".to_string(),
+            },
+            quantity: Decimal::ONE_HUNDRED,
+            value_in_euro: Decimal::ZERO,
+            first_tx_date: 0,
+            broker: DEFAULT_BROKER.clone(),
+            percentage: Decimal::ONE_HUNDRED,
+            year: default_year(),
+            product_type: ProductType::Stock,
+            acquisition_type: AcquisitionType::First,
+            extinction_date: 0,
+            derived: false,
+            co_holder_percentage: Decimal::ZERO,
+            acquisition_in_euro: None,
+            account_bic: String::new(),
+            q4_average_in_euro: None,
+            valuation_in_euro: None,
+            original_value: None,
+            issuer_country_code: String::new(),
+        }
+    }
+
+    /// Template saved in a previous visit or the default one.
+    pub fn load_template() -> Aeat720Record {
+        web::load_setting(TEMPLATE_SETTING_KEY)
+            .and_then(|json| match serde_json::from_str(&json) {
+                Ok(template) => Some(template),
+                Err(err) => {
+                    log::warn!("Discarding saved record template: {}", err);
+                    None
+                }
+            })
+            .unwrap_or_else(Self::default_template)
+    }
+
+    fn save_template(&self) {
+        match serde_json::to_string(&*self.template.lock_ref()) {
+            Ok(json) => web::save_setting(TEMPLATE_SETTING_KEY, &json),
+            Err(err) => log::error!("Unable to serialize record template: {}", err),
+        }
+    }
+
+    fn parse_percentage(value: &str, locale: &Locale) -> Option<Decimal> {
+        if !valid_str_number_with_decimals(value, DEFAULT_NUMBER_OF_DECIMALS, locale) {
+            return None;
+        }
+        str_locale_to_decimal(value, locale)
+            .ok()
+            .filter(|percentage| *percentage <= Decimal::ONE_HUNDRED)
+    }
+
+    fn render_input(
+        this: &Arc<Self>,
+        id: &str,
+        placeholder: &str,
+        value: String,
+        update: UpdateTemplate,
+    ) -> Dom {
+        let invalid = Mutable::new(false);
+        html!("span", {
+            .class(&*FLEX_CONTAINER_ITEM_20_CLASS)
+            .child(html!("label", {
+                .attr("for", id)
+                .text(placeholder)
+            }))
+            .child(html!("input" => HtmlInputElement, {
+                .attr("id", id)
+                .attr("alt", placeholder)
+                .attr("type", "text")
+                .attr("value", &value)
+                .style("height", "24px")
+                .with_node!(element => {
+                    .event(clone!(this, invalid => move |_: events::Input| {
+                        let locale = this.locale.get();
+                        let valid = update(&mut this.template.lock_mut(), element.value().trim(), &locale);
+                        invalid.set_neq(!valid);
+                        if valid {
+                            this.save_template();
+                        }
+                    }))
+                })
+            }))
+            .child(html!("span", {
+                .style("color", "var(--error-color)")
+                .style("font-size", "small")
+                .visible_signal(invalid.signal())
+                .text(INPUT_NOT_VALID_ERR_MSG)
+            }))
+        })
+    }
+
+    pub fn render(this: &Arc<Self>) -> Dom {
+        let template = this.template.get_cloned();
+        let locale = this.locale.get();
+        html!("details", {
+            .child(html!("summary", {
+                .text("Valores por defecto de las filas añadidas a mano")
+            }))
+            .child(html!("div", {
+                .class(&*FLEX_CONTAINER_CLASS)
+                .child(Self::render_input(this, "template_name", "Nombre compañía", template.company.name.clone(), |template, value, _| {
+                    template.company.name = value.to_string();
+                    !value.is_empty()
+                }))
+                .child(Self::render_input(this, "template_broker", "Broker", template.broker.name.clone(), |template, value, _| {
+                    template.broker = Arc::new(BrokerInformation::new(
+                        value.to_string(),
+                        template.broker.country_code.clone(),
+                    ));
+                    !value.is_empty()
+                }))
+                .child(Self::render_input(this, "template_country", "Cód. país", template.broker.country_code.clone(), |template, value, _| {
+                    let country_code = value.to_uppercase();
+                    let valid = validate_country_code(&country_code);
+                    if valid {
+                        template.set_broker_country(&country_code);
+                    }
+                    valid
+                }))
+                .child(Self::render_input(this, "template_percentage", "Porcentaje", decimal_to_str_locale(&template.percentage, &locale), |template, value, locale| {
+                    match Self::parse_percentage(value, locale) {
+                        Some(percentage) if !percentage.is_zero() => {
+                            template.percentage = percentage;
+                            true
+                        }
+                        _ => false,
+                    }
+                }))
+                .child(Self::render_input(this, "template_co_holder_percentage", "% 2º titular", decimal_to_str_locale(&template.co_holder_percentage, &locale), |template, value, locale| {
+                    match Self::parse_percentage(value, locale) {
+                        Some(percentage) => {
+                            template.co_holder_percentage = percentage;
+                            true
+                        }
+                        None => false,
+                    }
+                }))
+            }))
+        })
+    }
+}
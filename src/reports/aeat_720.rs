@@ -1,8 +1,15 @@
-use crate::data::{Aeat720Information, Aeat720Record};
-use anyhow::{bail, Result};
+use crate::{
+    data::{
+        normalize_phone, validate_country_code, validate_declaration_id, validate_iban,
+        validate_nif, AcquisitionType, Aeat720Information, Aeat720Record, BrokerInformation,
+        CompanyInfo, DeclarationType, ProductType,
+    },
+    utils::{decimal::round_valuation, usize_to_date},
+};
+use anyhow::{anyhow, bail, Result};
 use encoding_rs::ISO_8859_15;
 use rust_decimal::{prelude::ToPrimitive, Decimal};
-use std::io::Write;
+use std::{io::Write, sync::Arc};
 
 /*
    aeat 720 model specification.
@@ -18,10 +25,10 @@ use std::io::Write;
     transmission: StringField,            // Pos 58 value: 'T'
     telephone: NumericField,              // Pos 59-67
     contactName: AlphaNumericField,       // Pos 68-107
-    id: NumericField,                     // Pos 108-120
+    id: NumericField,                     // Pos 108-120 '720' and 10 digits chosen by the declarant
     complementary: StringField,           // Pos 121 value: 'C' if complementary declaration
     replacement: StringField,             // Pos 122 value: 'S' if replacement declaration
-    previousDeclarationId: NumericField,  // Pos 123-135
+    previousDeclarationId: NumericField,  // Pos 123-135 id of the completed or replaced declaration
     totalDetailRegisters: NumericField,   // Pos 136-144
     acquisitionSummarySign: StringField,  // Pos 145 value: 'N' if negative
     acquisitionSummaryInt: NumericField,  // Pos 146-160
@@ -74,10 +81,74 @@ use std::io::Write;
 const AEAT_720_REGISTER_SIZE_BYTES: usize = 500;
 const AEAT_720_DOCUMENT_ID: usize = 720;
 const AEAT_720_NEGATIVE_SIGN: &str = "N";
+const AEAT_720_SUMMARY_PREFIX: &[u8] = b"1720";
+const AEAT_720_DETAIL_PREFIX: &[u8] = b"2720";
+// Entities in the file are the issuers, the broker isn't declared.
+const AEAT_720_IMPORTED_BROKER_NAME: &str = "Modelo 720";
 
 type AeatRegisterArray = [u8; AEAT_720_REGISTER_SIZE_BYTES];
 
-#[derive(Debug, PartialEq)]
+// Splits the absolute value rounded to two decimals (banker's rounding) into its
+// integer part and its hundredths, as AEAT expects amounts, quantities and percentages.
+fn to_hundredths(value: &Decimal) -> (usize, usize) {
+    let rounded = round_valuation(value).abs();
+    let fraction = rounded.fract() * Decimal::ONE_HUNDRED;
+
+    (
+        rounded.trunc().to_usize().unwrap_or(0),
+        fraction.trunc().to_usize().unwrap_or(0),
+    )
+}
+
+// The file is encoded in ISO-8859-15, dashes and quotes outside it are replaced by their ASCII
+// version and any other character, e.g. cyrillic, is dropped.
+fn to_iso_8859_15(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut buffer = [0; 4];
+
+    for c in value.chars() {
+        if !ISO_8859_15.encode(c.encode_utf8(&mut buffer)).2 {
+            result.push(c);
+            continue;
+        }
+        match c {
+            '\u{2010}'..='\u{2015}' | '\u{2212}' => result.push('-'),
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{2032}' => result.push('\''),
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{2033}' => result.push('"'),
+            '\u{2026}' => result.push_str("..."),
+            _ => {}
+        }
+    }
+    if result != value {
+        log::warn!(
+            "Replaced characters not representable in ISO-8859-15: {:?} -> {:?}",
+            value,
+            result
+        );
+    }
+
+    result
+}
+
+/// Text cut to the size of its field when written to the 720 file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TruncatedField {
+    pub field: &'static str,
+    pub size: usize,
+    pub original: String,
+    pub truncated: String,
+}
+
+impl TruncatedField {
+    pub fn message(&self) -> String {
+        format!(
+            "{} recortado a {} caracteres: {} -> {}",
+            self.field, self.size, self.original, self.truncated
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum Aeat720Field {
     AlphaNumeric(usize, usize),
     Numeric(usize, usize),
@@ -85,6 +156,38 @@ enum Aeat720Field {
 }
 
 impl Aeat720Field {
+    fn size(&self) -> usize {
+        match self {
+            Aeat720Field::AlphaNumeric(begin, end)
+            | Aeat720Field::Numeric(begin, end)
+            | Aeat720Field::String(begin, end) => (end - begin) + 1,
+        }
+    }
+
+    // Like `write_field` but reports values longer than the field, named `name`.
+    fn write_text_field(
+        fields: &mut AeatRegisterArray,
+        field: Aeat720Field,
+        name: &'static str,
+        value: &str,
+    ) -> Result<Option<TruncatedField>> {
+        let size = field.size();
+        let encoded_len = ISO_8859_15.encode(&to_iso_8859_15(value)).0.len();
+        Self::write_field(fields, field, value)?;
+        if encoded_len <= size {
+            return Ok(None);
+        }
+
+        let truncated = TruncatedField {
+            field: name,
+            size,
+            original: value.to_string(),
+            truncated: Self::read_field(fields, field),
+        };
+        log::warn!("{:?}", truncated.message());
+        Ok(Some(truncated))
+    }
+
     fn write_field(fields: &mut AeatRegisterArray, field: Aeat720Field, value: &str) -> Result<()> {
         match field {
             Aeat720Field::Numeric(_, _) => {
@@ -95,7 +198,7 @@ impl Aeat720Field {
                 let size = (end - begin) + 1;
                 let mut slice = &mut fields[begin - 1..end];
 
-                let result = ISO_8859_15.encode(value);
+                let result = ISO_8859_15.encode(&to_iso_8859_15(value));
                 if result.2 {
                     bail!("Unable to encode to ISO-8859-15")
                 } else if result.0.len() > size {
@@ -129,6 +232,79 @@ impl Aeat720Field {
 
         Ok(())
     }
+
+    // Field as it would be read back from the file, without padding.
+    fn read_field(fields: &AeatRegisterArray, field: Aeat720Field) -> String {
+        let (begin, end) = match field {
+            Aeat720Field::AlphaNumeric(begin, end)
+            | Aeat720Field::Numeric(begin, end)
+            | Aeat720Field::String(begin, end) => (begin, end),
+        };
+
+        ISO_8859_15
+            .decode(&fields[begin - 1..end])
+            .0
+            .trim()
+            .to_string()
+    }
+
+    // Integer and fraction fields joined with a decimal comma, zero padding removed.
+    fn read_number_fields(
+        fields: &AeatRegisterArray,
+        int_field: Aeat720Field,
+        fraction_field: Aeat720Field,
+    ) -> String {
+        let int_part = Self::read_field(fields, int_field);
+        let int_part = int_part.trim_start_matches('0');
+
+        format!(
+            "{},{}",
+            if int_part.is_empty() { "0" } else { int_part },
+            Self::read_field(fields, fraction_field)
+        )
+    }
+
+    fn read_amount_fields(
+        fields: &AeatRegisterArray,
+        sign_field: Aeat720Field,
+        int_field: Aeat720Field,
+        fraction_field: Aeat720Field,
+    ) -> String {
+        let sign = if Self::read_field(fields, sign_field) == AEAT_720_NEGATIVE_SIGN {
+            "-"
+        } else {
+            ""
+        };
+
+        sign.to_string() + &Self::read_number_fields(fields, int_field, fraction_field)
+    }
+
+    fn read_decimal_fields(
+        fields: &AeatRegisterArray,
+        int_field: Aeat720Field,
+        fraction_field: Aeat720Field,
+    ) -> Result<Decimal> {
+        let int_part = Self::read_field(fields, int_field).parse::<i64>()?;
+        let fraction = Self::read_field(fields, fraction_field).parse::<i64>()?;
+
+        Ok(Decimal::new(int_part * 100 + fraction, 2))
+    }
+
+    fn write_amount_fields(
+        fields: &mut AeatRegisterArray,
+        sign_field: Aeat720Field,
+        int_field: Aeat720Field,
+        fraction_field: Aeat720Field,
+        amount: &Decimal,
+    ) -> Result<()> {
+        let (int_part, cents) = to_hundredths(amount);
+        if amount.is_sign_negative() && (int_part, cents) != (0, 0) {
+            Self::write_field(fields, sign_field, AEAT_720_NEGATIVE_SIGN)?;
+        }
+
+        Self::write_numeric_field(fields, int_field, int_part)?;
+        Self::write_numeric_field(fields, fraction_field, cents)
+    }
 }
 
 #[derive(Debug)]
@@ -140,6 +316,8 @@ impl SummaryRegister {
     // Field values
     const AEAT_720_SUMMARY_REGISTER_TYPE: usize = 1;
     const AEAT_720_TRANSMISSION_ASSET: &'static str = "T";
+    const AEAT_720_COMPLEMENTARY_DECLARATION: &'static str = "C";
+    const AEAT_720_REPLACEMENT_DECLARATION: &'static str = "S";
 
     // Field definitions
     const REGISTER_TYPE_FIELD: Aeat720Field = Aeat720Field::Numeric(1, 1);
@@ -228,11 +406,12 @@ impl Default for SummaryRegister {
 
 impl SummaryRegister {
     fn new(
-        records: &[Aeat720Record],
+        details: &[DetailRegister],
         year: usize,
         nif: &str,
         name: &str,
         phone: &str,
+        contact_name: &str,
     ) -> Result<Self> {
         let mut fields = Self::default().fields;
 
@@ -246,49 +425,88 @@ impl SummaryRegister {
             Aeat720Field::write_field(&mut fields, Self::TELEPHONE_FIELD, phone)?;
         }
 
-        Aeat720Field::write_field(&mut fields, Self::CONTACT_NAME_FIELD, name)?;
+        Aeat720Field::write_field(&mut fields, Self::CONTACT_NAME_FIELD, contact_name)?;
 
         Aeat720Field::write_numeric_field(
             &mut fields,
             Self::TOTAL_DETAIL_REGISTERS_FIELD,
-            records.len(),
+            details.len(),
         )?;
 
         let mut total_acquisition = Decimal::new(0, 2);
+        let mut total_valuation = Decimal::new(0, 2);
 
-        for note in records {
-            total_acquisition += note.value_in_euro;
+        for detail in details {
+            total_acquisition += detail.acquisition;
+            total_valuation += detail.valuation;
         }
 
-        if total_acquisition.is_sign_negative() {
-            Aeat720Field::write_field(
-                &mut fields,
-                Self::ACQUISITON_SIGN_FIELD,
-                AEAT_720_NEGATIVE_SIGN,
-            )?;
-        }
-
-        Aeat720Field::write_numeric_field(
+        Aeat720Field::write_amount_fields(
             &mut fields,
+            Self::ACQUISITON_SIGN_FIELD,
             Self::ACQUISITION_INT_FIELD,
-            total_acquisition.trunc().abs().to_usize().unwrap_or(0),
+            Self::ACQUISITION_FRACTION_FIELD,
+            &total_acquisition,
         )?;
 
-        let mut remainder = total_acquisition.fract();
-        remainder.set_scale(0)?;
-        Aeat720Field::write_numeric_field(
+        Aeat720Field::write_amount_fields(
             &mut fields,
-            Self::ACQUISITION_FRACTION_FIELD,
-            remainder.to_usize().unwrap_or(0),
+            Self::VALUATION_SIGN_FIELD,
+            Self::VALUATION_INT_FIELD,
+            Self::VALUATION_FRACTION_FIELD,
+            &total_valuation,
         )?;
 
         Ok(Self { fields })
     }
+
+    // Ids are 13 digits starting by the model number, only the last 10 are written as the
+    // model already fills the first positions of the field.
+    fn set_declaration(
+        &mut self,
+        id: &str,
+        declaration_type: DeclarationType,
+        previous_id: &str,
+    ) -> Result<()> {
+        if !validate_declaration_id(id) {
+            bail!("Declaration id isn't valid: {}", id);
+        }
+        Aeat720Field::write_field(&mut self.fields, Self::ID_FIELD, &id[3..])?;
+
+        let type_field = match declaration_type {
+            DeclarationType::Original => return Ok(()),
+            DeclarationType::Complementary => Self::COMPLEMENTARY_FIELD,
+            DeclarationType::Replacement => Self::REPLACEMENT_FIELD,
+        };
+        if !validate_declaration_id(previous_id) {
+            bail!("Previous declaration id isn't valid: {}", previous_id);
+        }
+        if previous_id == id {
+            bail!("Declaration id must differ from the previous one: {}", id);
+        }
+        let value = if declaration_type == DeclarationType::Complementary {
+            Self::AEAT_720_COMPLEMENTARY_DECLARATION
+        } else {
+            Self::AEAT_720_REPLACEMENT_DECLARATION
+        };
+        Aeat720Field::write_field(&mut self.fields, type_field, value)?;
+        Aeat720Field::write_field(
+            &mut self.fields,
+            Self::PREVIOUS_DECLARARION_ID_FIELD,
+            previous_id,
+        )
+    }
 }
 
 #[derive(Debug)]
 struct DetailRegister {
     fields: AeatRegisterArray,
+    // Amounts written to the acquisition (valoración 1) and valuation (valoración 2) fields,
+    // kept so the summary register totals match the details.
+    acquisition: Decimal,
+    valuation: Decimal,
+    // Text values cut to fit their field.
+    truncations: Vec<TruncatedField>,
 }
 
 impl DetailRegister {
@@ -296,11 +514,20 @@ impl DetailRegister {
     const AEAT_720_DETAIL_REGISTER_TYPE: usize = 2;
     const AEAT_720_OWNER_TYPE: usize = 1;
     const AEAT_720_ASSET_TYPE: &'static str = "V";
+    const AEAT_720_FUND_ASSET_TYPE: &'static str = "I";
+    const AEAT_720_STOCK_ASSET_SUBTYPE: usize = 1;
+    const AEAT_720_BOND_ASSET_SUBTYPE: usize = 2;
+    const AEAT_720_FUND_ASSET_SUBTYPE: usize = 0;
+    const AEAT_720_ACCOUNT_ASSET_TYPE: &'static str = "C";
+    const AEAT_720_CURRENT_ACCOUNT_ASSET_SUBTYPE: usize = 1;
+    const AEAT_720_ACCOUNT_IBAN_ID_TYPE: &'static str = "I";
+    const AEAT_720_ACCOUNT_OTHER_ID_TYPE: &'static str = "O";
     const AEAT_720_STOCK_ID_TYPE: usize = 1;
     const AEAT_720_ASSET_FIRST_ACQUISITION: &'static str = "A";
-    // const AEAT_720_ASSET_INCREMENTAL_ACQUISITION: &'static str = "M";
-    // const AEAT_720_ASSET_DISPOSAL: &'static str = "C";
+    const AEAT_720_ASSET_INCREMENTAL_ACQUISITION: &'static str = "M";
+    const AEAT_720_ASSET_DISPOSAL: &'static str = "C";
     const AEAT_720_ASSET_REPRESENTATON: &'static str = "A";
+    const AEAT_720_NO_REPRESENTATION: &'static str = "";
 
     // Field definitions
     const REGISTER_TYPE_FIELD: Aeat720Field = Aeat720Field::Numeric(1, 1);
@@ -387,7 +614,7 @@ impl Default for DetailRegister {
         Aeat720Field::write_numeric_field(
             &mut fields,
             Self::ASSET_SUBTYPE_FIELD,
-            Self::AEAT_720_OWNER_TYPE,
+            Self::AEAT_720_STOCK_ASSET_SUBTYPE,
         );
 
         Aeat720Field::write_field(&mut fields, Self::REAL_STATE_ASSET_TYPE_FIELD, "");
@@ -456,142 +683,668 @@ impl Default for DetailRegister {
 
         Aeat720Field::write_field(&mut fields, Self::REMAINDER_BLANK_FIELD, "");
 
-        Self { fields }
+        Self {
+            fields,
+            acquisition: Decimal::ZERO,
+            valuation: Decimal::ZERO,
+            truncations: Vec::new(),
+        }
     }
 }
 
 impl DetailRegister {
+    // Funds and ETFs are collective investment institutions (I), shares and bonds are securities (V).
+    fn asset_type(product_type: ProductType) -> (&'static str, usize) {
+        match product_type {
+            ProductType::Stock => (
+                Self::AEAT_720_ASSET_TYPE,
+                Self::AEAT_720_STOCK_ASSET_SUBTYPE,
+            ),
+            ProductType::Bond => (Self::AEAT_720_ASSET_TYPE, Self::AEAT_720_BOND_ASSET_SUBTYPE),
+            ProductType::Fund => (
+                Self::AEAT_720_FUND_ASSET_TYPE,
+                Self::AEAT_720_FUND_ASSET_SUBTYPE,
+            ),
+            ProductType::Account => (
+                Self::AEAT_720_ACCOUNT_ASSET_TYPE,
+                Self::AEAT_720_CURRENT_ACCOUNT_ASSET_SUBTYPE,
+            ),
+        }
+    }
+
+    // Securities and fund units held at a broker are book entries (A), valued at their
+    // year-end market or net asset value. Accounts leave the key blank, they're valued by
+    // their balance.
+    fn valuation_method(product_type: ProductType) -> &'static str {
+        match product_type {
+            ProductType::Stock | ProductType::Bond | ProductType::Fund => {
+                Self::AEAT_720_ASSET_REPRESENTATON
+            }
+            ProductType::Account => Self::AEAT_720_NO_REPRESENTATION,
+        }
+    }
+
+    // Filled in when a representative files the declaration on behalf of the declarant.
+    fn set_proxy_nif(&mut self, proxy_nif: &str) -> Result<()> {
+        Aeat720Field::write_field(&mut self.fields, Self::PROXY_NIF_FIELD, proxy_nif)
+    }
+
+    fn acquisition_type(acquisition_type: AcquisitionType) -> &'static str {
+        match acquisition_type {
+            AcquisitionType::First => Self::AEAT_720_ASSET_FIRST_ACQUISITION,
+            AcquisitionType::Existing => Self::AEAT_720_ASSET_INCREMENTAL_ACQUISITION,
+            AcquisitionType::Disposal => Self::AEAT_720_ASSET_DISPOSAL,
+        }
+    }
+
     fn new(record: &Aeat720Record, year: usize, nif: &str, name: &str) -> Result<Self> {
         let mut fields = Self::default().fields;
+        let mut truncations = Vec::new();
 
         Aeat720Field::write_numeric_field(&mut fields, Self::YEAR_FIELD, year)?;
         Aeat720Field::write_field(&mut fields, Self::NIF_FIELD, nif)?;
         Aeat720Field::write_field(&mut fields, Self::DECLARED_NIF_FIELD, nif)?;
         Aeat720Field::write_field(&mut fields, Self::NAME_FIELD, name)?;
+        let (asset_type, asset_subtype) = Self::asset_type(record.product_type);
+        Aeat720Field::write_field(&mut fields, Self::ASSET_TYPE_FIELD, asset_type)?;
+        Aeat720Field::write_numeric_field(&mut fields, Self::ASSET_SUBTYPE_FIELD, asset_subtype)?;
+        if !validate_country_code(&record.broker.country_code) {
+            bail!(
+                "Country code of {} isn't valid: {}",
+                record.broker.name,
+                record.broker.country_code
+            );
+        }
         Aeat720Field::write_field(
             &mut fields,
             Self::COUNTRY_CODE_FIELD,
             &record.broker.country_code,
         )?;
-        Aeat720Field::write_field(&mut fields, Self::STOCK_ID_FIELD, &record.company.isin)?;
-        Aeat720Field::write_field(
+        let is_account = record.product_type == ProductType::Account;
+        if is_account {
+            if record.company.isin.is_empty() {
+                bail!("Account at {} without account code", record.broker.name);
+            }
+            // Accounts are identified by IBAN, held by the bank in its own country.
+            Aeat720Field::write_numeric_field(&mut fields, Self::STOCK_ID_TYPE_FIELD, 0)?;
+            let account_id_type = if validate_iban(&record.company.isin) {
+                Self::AEAT_720_ACCOUNT_IBAN_ID_TYPE
+            } else {
+                Self::AEAT_720_ACCOUNT_OTHER_ID_TYPE
+            };
+            Aeat720Field::write_field(&mut fields, Self::ACCOUNT_ID_TYPE_FIELD, account_id_type)?;
+            truncations.extend(Aeat720Field::write_text_field(
+                &mut fields,
+                Self::ACCOUNT_ID_FIELD,
+                "BIC",
+                &record.account_bic,
+            )?);
+            truncations.extend(Aeat720Field::write_text_field(
+                &mut fields,
+                Self::ACCOUNT_CODE_FIELD,
+                "Código de cuenta",
+                &record.company.isin,
+            )?);
+        } else {
+            truncations.extend(Aeat720Field::write_text_field(
+                &mut fields,
+                Self::STOCK_ID_FIELD,
+                "ISIN",
+                &record.company.isin,
+            )?);
+        }
+        truncations.extend(Aeat720Field::write_text_field(
             &mut fields,
             Self::ENTITY_NAME_FIELD,
+            "Nombre de la entidad",
             &record.company.name.to_uppercase(),
-        )?;
+        )?);
+        let entity_country_code = record.entity_country_code();
+        if !validate_country_code(entity_country_code) {
+            bail!(
+                "Issuer country code of {} isn't valid: {}",
+                record.company.isin,
+                entity_country_code
+            );
+        }
         Aeat720Field::write_field(
             &mut fields,
             Self::ENTITY_COUNTRY_CODE_FIELD,
-            &record.company.isin[0..2],
+            entity_country_code,
         )?;
         Aeat720Field::write_numeric_field(
             &mut fields,
             Self::FIRST_ACQUISITION_DATE_FIELD,
             record.first_tx_date,
         )?;
-
-        if record.value_in_euro.is_sign_negative() {
-            Aeat720Field::write_field(
+        Aeat720Field::write_field(
+            &mut fields,
+            Self::ACQUISITION_TYPE_FIELD,
+            Self::acquisition_type(record.acquisition_type),
+        )?;
+        if record.acquisition_type == AcquisitionType::Disposal {
+            if usize_to_date(record.extinction_date).is_none() {
+                bail!(
+                    "Disposal of {} without a valid extinction date: {}",
+                    record.company.isin,
+                    record.extinction_date
+                );
+            }
+            Aeat720Field::write_numeric_field(
                 &mut fields,
-                Self::ACQUISITON_SIGN_FIELD,
-                AEAT_720_NEGATIVE_SIGN,
+                Self::EXTINCTION_DATE_FIELD,
+                record.extinction_date,
             )?;
         }
-        Aeat720Field::write_numeric_field(
+
+        if record.quantity < Decimal::ZERO {
+            bail!(
+                "Short position of {} can't be declared as securities: {}",
+                record.company.isin,
+                record.quantity
+            );
+        }
+        if record.percentage <= Decimal::ZERO || record.percentage > Decimal::ONE_HUNDRED {
+            bail!(
+                "Owned percentage of {} must be between 0 and 100: {}",
+                record.company.isin,
+                record.percentage
+            );
+        }
+        if record.percentage + record.co_holder_percentage > Decimal::ONE_HUNDRED {
+            bail!(
+                "Owned percentages of {} add up to more than 100: {} and {}",
+                record.company.isin,
+                record.percentage,
+                record.co_holder_percentage
+            );
+        }
+        // Joint holdings only declare the owned share of the value.
+        let acquisition = record.value_in_euro * record.percentage / Decimal::ONE_HUNDRED;
+        Aeat720Field::write_amount_fields(
             &mut fields,
+            Self::ACQUISITON_SIGN_FIELD,
             Self::ACQUISITION_INT_FIELD,
-            record.value_in_euro.trunc().abs().to_usize().unwrap_or(0),
+            Self::ACQUISITION_FRACTION_FIELD,
+            &acquisition,
         )?;
-        let mut remainder = record.value_in_euro.fract();
-        remainder.set_scale(0)?;
-        Aeat720Field::write_numeric_field(
+
+        // Securities only fill the second valuation when the user enters one, accounts always
+        // do with the average balance of the last quarter. Reports only have the year-end
+        // balance so it's used as the estimate when the average isn't known.
+        let valuation = if is_account {
+            record.q4_average_in_euro.map_or(acquisition, |average| {
+                average * record.percentage / Decimal::ONE_HUNDRED
+            })
+        } else {
+            record.valuation_in_euro.map_or(Decimal::ZERO, |valuation| {
+                valuation * record.percentage / Decimal::ONE_HUNDRED
+            })
+        };
+        Aeat720Field::write_amount_fields(
             &mut fields,
-            Self::ACQUISITION_FRACTION_FIELD,
-            remainder.to_usize().unwrap_or(0),
+            Self::VALUATION_SIGN_FIELD,
+            Self::VALUATION_INT_FIELD,
+            Self::VALUATION_FRACTION_FIELD,
+            &valuation,
         )?;
 
+        Aeat720Field::write_field(
+            &mut fields,
+            Self::STOCK_REPRESENTATION_FIELD,
+            Self::valuation_method(record.product_type),
+        )?;
+
+        let (quantity_int, quantity_fraction) = to_hundredths(&record.quantity);
         Aeat720Field::write_numeric_field(
             &mut fields,
             Self::STOCK_QUANTITY_INT_FIELD,
-            record.quantity.trunc().abs().to_usize().unwrap_or(0),
+            quantity_int,
         )?;
-
-        let mut remainder = record.quantity.fract();
-        remainder.set_scale(2)?;
         Aeat720Field::write_numeric_field(
             &mut fields,
             Self::STOCK_QUANTITY_FRACTION_FIELD,
-            remainder.trunc().to_usize().unwrap_or(0),
+            quantity_fraction,
         )?;
 
+        let (percentage_int, percentage_fraction) = to_hundredths(&record.percentage);
         Aeat720Field::write_numeric_field(
             &mut fields,
             Self::OWNED_PERCENTAGE_INT_FIELD,
-            record.percentage.abs().to_usize().unwrap_or(0),
+            percentage_int,
         )?;
-        let mut remainder = record.percentage.fract();
-        remainder.set_scale(2)?;
-
         Aeat720Field::write_numeric_field(
             &mut fields,
             Self::OWNED_PERCENTAGE_FRACTION_FIELD,
-            remainder.trunc().to_usize().unwrap_or(0),
+            percentage_fraction,
         )?;
 
-        Ok(Self { fields })
+        Ok(Self {
+            fields,
+            acquisition,
+            valuation,
+            truncations,
+        })
     }
 }
+impl DetailRegister {
+    fn read_product_type(asset_type: &str, asset_subtype: &str) -> ProductType {
+        match (asset_type, asset_subtype.parse::<usize>()) {
+            (Self::AEAT_720_FUND_ASSET_TYPE, _) => ProductType::Fund,
+            (Self::AEAT_720_ACCOUNT_ASSET_TYPE, _) => ProductType::Account,
+            (_, Ok(Self::AEAT_720_BOND_ASSET_SUBTYPE)) => ProductType::Bond,
+            _ => ProductType::Stock,
+        }
+    }
+
+    fn read_acquisition_type(acquisition_type: &str) -> Result<AcquisitionType> {
+        match acquisition_type {
+            Self::AEAT_720_ASSET_FIRST_ACQUISITION => Ok(AcquisitionType::First),
+            Self::AEAT_720_ASSET_INCREMENTAL_ACQUISITION => Ok(AcquisitionType::Existing),
+            Self::AEAT_720_ASSET_DISPOSAL => Ok(AcquisitionType::Disposal),
+            _ => bail!("Unknown acquisition type: {}", acquisition_type),
+        }
+    }
+
+    // Inverse of `DetailRegister::new`, the declared value is scaled back to the full holding.
+    fn to_record(fields: &AeatRegisterArray) -> Result<Aeat720Record> {
+        let read = Aeat720Field::read_field;
+        let product_type = Self::read_product_type(
+            &read(fields, Self::ASSET_TYPE_FIELD),
+            &read(fields, Self::ASSET_SUBTYPE_FIELD),
+        );
+        let isin = if product_type == ProductType::Account {
+            read(fields, Self::ACCOUNT_CODE_FIELD)
+        } else {
+            read(fields, Self::STOCK_ID_FIELD)
+        };
+        let mut acquisition = Aeat720Field::read_decimal_fields(
+            fields,
+            Self::ACQUISITION_INT_FIELD,
+            Self::ACQUISITION_FRACTION_FIELD,
+        )?;
+        if read(fields, Self::ACQUISITON_SIGN_FIELD) == AEAT_720_NEGATIVE_SIGN {
+            acquisition.set_sign_negative(true);
+        }
+        let percentage = Aeat720Field::read_decimal_fields(
+            fields,
+            Self::OWNED_PERCENTAGE_INT_FIELD,
+            Self::OWNED_PERCENTAGE_FRACTION_FIELD,
+        )?;
+        if percentage.is_zero() {
+            bail!("Owned percentage of {} is zero", isin);
+        }
+        let mut valuation = Aeat720Field::read_decimal_fields(
+            fields,
+            Self::VALUATION_INT_FIELD,
+            Self::VALUATION_FRACTION_FIELD,
+        )?;
+        if read(fields, Self::VALUATION_SIGN_FIELD) == AEAT_720_NEGATIVE_SIGN {
+            valuation.set_sign_negative(true);
+        }
+        let valuation = round_valuation(&(valuation * Decimal::ONE_HUNDRED / percentage));
+        let entity_country_code = read(fields, Self::ENTITY_COUNTRY_CODE_FIELD);
+        let issuer_country_code = if product_type == ProductType::Account
+            || isin.get(0..2) == Some(entity_country_code.as_str())
+        {
+            String::new()
+        } else {
+            entity_country_code
+        };
+        let (account_bic, q4_average_in_euro, valuation_in_euro) =
+            if product_type == ProductType::Account {
+                (read(fields, Self::ACCOUNT_ID_FIELD), Some(valuation), None)
+            } else {
+                (
+                    String::new(),
+                    None,
+                    (!valuation.is_zero()).then_some(valuation),
+                )
+            };
+
+        Ok(Aeat720Record {
+            company: CompanyInfo::new(read(fields, Self::ENTITY_NAME_FIELD), isin),
+            quantity: Aeat720Field::read_decimal_fields(
+                fields,
+                Self::STOCK_QUANTITY_INT_FIELD,
+                Self::STOCK_QUANTITY_FRACTION_FIELD,
+            )?,
+            value_in_euro: round_valuation(&(acquisition * Decimal::ONE_HUNDRED / percentage)),
+            first_tx_date: read(fields, Self::FIRST_ACQUISITION_DATE_FIELD).parse::<usize>()?,
+            broker: Arc::new(BrokerInformation::new(
+                String::from(AEAT_720_IMPORTED_BROKER_NAME),
+                read(fields, Self::COUNTRY_CODE_FIELD),
+            )),
+            percentage,
+            year: read(fields, Self::YEAR_FIELD).parse::<usize>()?,
+            product_type,
+            acquisition_type: Self::read_acquisition_type(&read(
+                fields,
+                Self::ACQUISITION_TYPE_FIELD,
+            ))?,
+            extinction_date: read(fields, Self::EXTINCTION_DATE_FIELD)
+                .parse::<usize>()
+                .unwrap_or(0),
+            derived: false,
+            co_holder_percentage: Decimal::ZERO,
+            acquisition_in_euro: None,
+            account_bic,
+            q4_average_in_euro,
+            valuation_in_euro,
+            original_value: None,
+            issuer_country_code,
+        })
+    }
+}
+
+/// A 720 file as generated by [`Aeat720Report::generate`] or the AEAT forms.
+pub fn is_720_file(content: &[u8]) -> bool {
+    content.starts_with(AEAT_720_SUMMARY_PREFIX)
+}
+
+/// Records declared in a 720 file, e.g. last year's one, to compare with or edit them.
+pub fn parse_720_file(content: &[u8]) -> Result<Vec<Aeat720Record>> {
+    if !is_720_file(content) {
+        bail!("No es un fichero del modelo 720");
+    }
+    let mut records = vec![];
+
+    for (i, line) in content.split(|c| *c == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if !line.starts_with(AEAT_720_DETAIL_PREFIX) {
+            continue;
+        }
+        let fields: &AeatRegisterArray = line
+            .try_into()
+            .map_err(|_| anyhow!("Line {} isn't a 720 register", i + 1))?;
+        records.push(DetailRegister::to_record(fields)?);
+    }
+
+    Ok(records)
+}
+
+/// Separator written after each register, some upload tools on Windows expect CRLF.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+        }
+    }
+}
+
 pub struct Aeat720Report {
     summary: SummaryRegister,
     details: Vec<DetailRegister>,
+    warnings: Vec<String>,
 }
 
 impl Aeat720Report {
+    // Real gains or losses rarely reach this factor, a value this far from the cost is more
+    // likely a parsing error like the price and value columns swapped.
+    const MAX_VALUATION_RATIO: Decimal = Decimal::TEN;
+
+    fn valuation_warning(record: &Aeat720Record) -> Option<String> {
+        let cost = record
+            .acquisition_in_euro
+            .filter(|cost| *cost > Decimal::ZERO)?;
+
+        if record.value_in_euro > cost * Self::MAX_VALUATION_RATIO
+            || record.value_in_euro * Self::MAX_VALUATION_RATIO < cost
+        {
+            Some(format!(
+                "{} ({}): valoración de {} € frente a un coste de adquisición de {} €",
+                record.company.name,
+                record.company.isin,
+                record.value_in_euro.round_dp(2),
+                cost.round_dp(2)
+            ))
+        } else {
+            None
+        }
+    }
+
     pub fn new(info: &Aeat720Information) -> Result<Aeat720Report> {
         let mut details = Vec::new();
+        let mut warnings = Vec::new();
         let full_name = info.full_name();
+        let phone = if info.personal_info.phone.is_empty() {
+            String::new()
+        } else {
+            normalize_phone(&info.personal_info.phone)
+                .ok_or_else(|| anyhow!("Phone isn't valid: {}", info.personal_info.phone))?
+        };
+        let proxy_nif = info.personal_info.proxy_nif.as_deref().unwrap_or_default();
+        if !proxy_nif.is_empty() && !validate_nif(proxy_nif) {
+            bail!("Proxy NIF isn't valid: {}", proxy_nif);
+        }
 
         for record in &info.records {
-            let detail = DetailRegister::new(
+            if !info.include_domestic && record.is_domestic() {
+                log::info!(
+                    "Excluding spanish asset from 720: {:?} {} ({})",
+                    record.company.name,
+                    record.company.isin,
+                    record.broker.name
+                );
+                continue;
+            }
+            // Closed positions are still listed by some brokers, there's nothing to declare.
+            if record.quantity.is_zero() && record.value_in_euro.is_zero() {
+                log::info!(
+                    "Excluding closed position from 720: {:?} {} ({})",
+                    record.company.name,
+                    record.company.isin,
+                    record.broker.name
+                );
+                continue;
+            }
+            let mut detail = DetailRegister::new(
                 record,
                 info.personal_info.year,
                 &info.personal_info.nif,
                 &full_name,
             )?;
+            detail.set_proxy_nif(proxy_nif)?;
+            warnings.extend(detail.truncations.iter().map(TruncatedField::message));
             details.push(detail);
+            if let Some(warning) = Self::valuation_warning(record) {
+                log::warn!("Valuation far from acquisition cost: {:?}", warning);
+                warnings.push(warning);
+            }
         }
 
+        let mut summary = SummaryRegister::new(
+            &details,
+            info.personal_info.year,
+            &info.personal_info.nif,
+            &full_name,
+            &phone,
+            &info.contact_name().to_uppercase(),
+        )?;
+        summary.set_declaration(
+            &info.personal_info.declaration_id(),
+            info.personal_info.declaration_type,
+            info.personal_info.previous_declaration_id.trim(),
+        )?;
+
         Ok(Aeat720Report {
-            summary: SummaryRegister::new(
-                &info.records,
-                info.personal_info.year,
-                &info.personal_info.nif,
-                &full_name,
-                &info.personal_info.phone,
-            )?,
+            summary,
             details,
+            warnings,
         })
     }
 
-    pub fn generate(self) -> Result<Vec<u8>> {
+    /// Records whose year-end value is suspiciously far from their acquisition cost or
+    /// with text cut to fit the 720 fields.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn generate(self, line_ending: LineEnding) -> Result<Vec<u8>> {
+        let line_ending = line_ending.as_bytes();
         let mut result = Vec::with_capacity(
-            AEAT_720_REGISTER_SIZE_BYTES * (self.details.len() + 1) + (self.details.len() + 1),
+            (AEAT_720_REGISTER_SIZE_BYTES + line_ending.len()) * (self.details.len() + 1),
         );
 
         result.write_all(&self.summary.fields)?;
-        result.write_all(b"\n")?;
+        result.write_all(line_ending)?;
         for detail in self.details {
             result.write_all(&detail.fields)?;
-            result.write_all(b"\n")?;
+            result.write_all(line_ending)?;
         }
 
         Ok(result)
     }
 }
 
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html_row(cell: &str, values: &[String]) -> String {
+    let cells: String = values
+        .iter()
+        .map(|value| format!("<{cell}>{}</{cell}>", escape_html(value)))
+        .collect();
+
+    format!("<tr>{}</tr>\n", cells)
+}
+
+/// Readable version of the registers, read back from the fixed-width fields, to proofread
+/// the file before submitting it.
+pub fn preview_html(report: &Aeat720Report) -> String {
+    let summary = &report.summary.fields;
+    let read = Aeat720Field::read_field;
+    let mut result = String::from(
+        "<!DOCTYPE html>\n<html lang=\"es\">\n<head><meta charset=\"utf-8\"><title>Modelo 720</title></head>\n<body>\n",
+    );
+
+    result.push_str(&format!(
+        "<h1>Modelo 720 - Ejercicio {}</h1>\n",
+        read(summary, SummaryRegister::YEAR_FIELD)
+    ));
+    result.push_str("<table border=\"1\">\n");
+    for (label, value) in [
+        ("NIF", read(summary, SummaryRegister::NIF_FIELD)),
+        ("Nombre", read(summary, SummaryRegister::NAME_FIELD)),
+        ("Teléfono", read(summary, SummaryRegister::TELEPHONE_FIELD)),
+        (
+            "Nº registros",
+            read(summary, SummaryRegister::TOTAL_DETAIL_REGISTERS_FIELD)
+                .parse::<usize>()
+                .unwrap_or(0)
+                .to_string(),
+        ),
+        (
+            "Suma valoración 1 (€)",
+            Aeat720Field::read_amount_fields(
+                summary,
+                SummaryRegister::ACQUISITON_SIGN_FIELD,
+                SummaryRegister::ACQUISITION_INT_FIELD,
+                SummaryRegister::ACQUISITION_FRACTION_FIELD,
+            ),
+        ),
+        (
+            "Suma valoración 2 (€)",
+            Aeat720Field::read_amount_fields(
+                summary,
+                SummaryRegister::VALUATION_SIGN_FIELD,
+                SummaryRegister::VALUATION_INT_FIELD,
+                SummaryRegister::VALUATION_FRACTION_FIELD,
+            ),
+        ),
+    ] {
+        result.push_str(&html_row("td", &[label.to_string(), value]));
+    }
+    result.push_str("</table>\n");
+
+    result.push_str("<table border=\"1\">\n");
+    result.push_str(&html_row(
+        "th",
+        &[
+            "NIF",
+            "Nombre",
+            "Clave",
+            "ISIN",
+            "Cuenta",
+            "País",
+            "Entidad",
+            "Origen",
+            "Fecha 1ª adquisición",
+            "Fecha extinción",
+            "Valoración 1 (€)",
+            "Valoración 2 (€)",
+            "Nº acciones",
+            "Porcentaje",
+        ]
+        .map(String::from),
+    ));
+    for detail in &report.details {
+        let fields = &detail.fields;
+        result.push_str(&html_row(
+            "td",
+            &[
+                read(fields, DetailRegister::NIF_FIELD),
+                read(fields, DetailRegister::NAME_FIELD),
+                read(fields, DetailRegister::ASSET_TYPE_FIELD)
+                    + &read(fields, DetailRegister::ASSET_SUBTYPE_FIELD),
+                read(fields, DetailRegister::STOCK_ID_FIELD),
+                read(fields, DetailRegister::ACCOUNT_CODE_FIELD),
+                read(fields, DetailRegister::COUNTRY_CODE_FIELD),
+                read(fields, DetailRegister::ENTITY_NAME_FIELD),
+                read(fields, DetailRegister::ACQUISITION_TYPE_FIELD),
+                read(fields, DetailRegister::FIRST_ACQUISITION_DATE_FIELD),
+                read(fields, DetailRegister::EXTINCTION_DATE_FIELD),
+                Aeat720Field::read_amount_fields(
+                    fields,
+                    DetailRegister::ACQUISITON_SIGN_FIELD,
+                    DetailRegister::ACQUISITION_INT_FIELD,
+                    DetailRegister::ACQUISITION_FRACTION_FIELD,
+                ),
+                Aeat720Field::read_amount_fields(
+                    fields,
+                    DetailRegister::VALUATION_SIGN_FIELD,
+                    DetailRegister::VALUATION_INT_FIELD,
+                    DetailRegister::VALUATION_FRACTION_FIELD,
+                ),
+                Aeat720Field::read_number_fields(
+                    fields,
+                    DetailRegister::STOCK_QUANTITY_INT_FIELD,
+                    DetailRegister::STOCK_QUANTITY_FRACTION_FIELD,
+                ),
+                Aeat720Field::read_number_fields(
+                    fields,
+                    DetailRegister::OWNED_PERCENTAGE_INT_FIELD,
+                    DetailRegister::OWNED_PERCENTAGE_FRACTION_FIELD,
+                ),
+            ],
+        ));
+    }
+    result.push_str("</table>\n</body>\n</html>\n");
+
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::data::{
+        AcquisitionType, BrokerInformation, CompanyInfo, ForeignBankAccount, PersonalInformation,
+        ProductType, DEFAULT_BROKER,
+    };
+    use std::sync::Arc;
 
     #[test]
     fn test_write_numeric_field() {
@@ -815,8 +1568,826 @@ mod tests {
         assert_eq!(DetailRegister::default().fields, DEFAULT_FIELDS);
     }
 
+    fn test_record(value_in_euro: Decimal) -> Aeat720Record {
+        Aeat720Record {
+            company: CompanyInfo {
+                name: String::from("BURFORD CAP LD"),
+                isin: String::from("GG00B4L84979"),
+            },
+            quantity: Decimal::new(122, 0),
+            value_in_euro,
+            first_tx_date: 20181031,
+            broker: DEFAULT_BROKER.clone(),
+            percentage: Decimal::ONE_HUNDRED,
+            year: 2019,
+            product_type: ProductType::Stock,
+            acquisition_type: AcquisitionType::First,
+            extinction_date: 0,
+            derived: false,
+            co_holder_percentage: Decimal::ZERO,
+            acquisition_in_euro: None,
+            account_bic: String::new(),
+            q4_average_in_euro: None,
+            valuation_in_euro: None,
+            original_value: None,
+            issuer_country_code: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_summary_register_amounts() {
+        let details = [
+            DetailRegister::new(&test_record(Decimal::new(2247_00, 2)), 2019, "", "").unwrap(),
+            DetailRegister::new(&test_record(Decimal::new(-3000_25, 2)), 2019, "", "").unwrap(),
+        ];
+        let summary = SummaryRegister::new(&details, 2019, "", "", "", "").unwrap();
+
+        assert_eq!(summary.fields[144..162], *b"N00000000000075325");
+        assert_eq!(summary.fields[162..180], *b" 00000000000000000");
+
+        let mut details = details;
+        details[0].valuation = Decimal::new(1500_10, 2);
+        details[1].valuation = Decimal::new(-1600_20, 2);
+        let summary = SummaryRegister::new(&details, 2019, "", "", "", "").unwrap();
+
+        assert_eq!(summary.fields[162..180], *b"N00000000000010010");
+    }
+
+    #[test]
+    fn test_summary_register_contact() {
+        let mut info = Aeat720Information {
+            records: vec![test_record(Decimal::new(2247_00, 2))],
+            personal_info: PersonalInformation {
+                name: String::from("JOHN"),
+                surname: String::from("DOE"),
+                nif: String::from("12345678Z"),
+                year: 2019,
+                phone: String::from("+34 600 11 22 33"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = Aeat720Report::new(&info).unwrap();
+
+        assert_eq!(report.summary.fields[58..67], *b"600112233");
+        assert_eq!(report.summary.fields[67..75], *b"DOE JOHN");
+
+        info.personal_info.contact_name = String::from("Asesoria Fiscal");
+        let report = Aeat720Report::new(&info).unwrap();
+
+        assert_eq!(report.summary.fields[67..82], *b"ASESORIA FISCAL");
+
+        info.personal_info.phone = String::from("600 11 22");
+        assert!(Aeat720Report::new(&info).is_err());
+    }
+
+    #[test]
+    fn test_summary_register_declaration_id() {
+        let mut info = Aeat720Information {
+            records: vec![test_record(Decimal::new(2247_00, 2))],
+            personal_info: PersonalInformation {
+                nif: String::from("12345678Z"),
+                year: 2023,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = Aeat720Report::new(&info).unwrap();
+        assert_eq!(
+            report.summary.fields[107..135],
+            *b"7202023000001  0000000000000"
+        );
+
+        info.personal_info.declaration_id = String::from("7202023000002");
+        info.personal_info.declaration_type = DeclarationType::Complementary;
+        info.personal_info.previous_declaration_id = String::from("7202023000001");
+        let report = Aeat720Report::new(&info).unwrap();
+        assert_eq!(
+            report.summary.fields[107..135],
+            *b"7202023000002C 7202023000001"
+        );
+
+        info.personal_info.declaration_type = DeclarationType::Replacement;
+        let report = Aeat720Report::new(&info).unwrap();
+        assert_eq!(
+            report.summary.fields[107..135],
+            *b"7202023000002 S7202023000001"
+        );
+
+        info.personal_info.previous_declaration_id = String::from("7202023000002");
+        assert!(Aeat720Report::new(&info).is_err());
+
+        info.personal_info.previous_declaration_id = String::new();
+        assert!(Aeat720Report::new(&info).is_err());
+
+        info.personal_info.declaration_type = DeclarationType::Original;
+        info.personal_info.declaration_id = String::from("1002023000002");
+        assert!(Aeat720Report::new(&info).is_err());
+    }
+
+    #[test]
+    fn test_detail_register_proxy_nif() {
+        let mut info = Aeat720Information {
+            records: vec![test_record(Decimal::new(2247_00, 2)); 2],
+            personal_info: PersonalInformation {
+                nif: String::from("12345678Z"),
+                year: 2019,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = Aeat720Report::new(&info).unwrap();
+        assert_eq!(report.details[0].fields[17..35], *b"12345678Z         ");
+
+        info.personal_info.proxy_nif = Some(String::from("B12345674"));
+        let report = Aeat720Report::new(&info).unwrap();
+        for detail in &report.details {
+            assert_eq!(detail.fields[17..35], *b"12345678ZB12345674");
+        }
+
+        info.personal_info.proxy_nif = Some(String::from("B12345675"));
+        assert!(Aeat720Report::new(&info).is_err());
+    }
+
+    // Fixed-width file written by hand from the specification, any change in the offsets or
+    // padding of a field shows up as a different register.
+    const GOLDEN_720: &[u8] = include_bytes!("testdata/aeat_720_golden.txt");
+
+    #[test]
+    fn test_report_golden_file() {
+        let record = |name: &str, isin: &str, broker: &Arc<BrokerInformation>| Aeat720Record {
+            company: CompanyInfo {
+                name: String::from(name),
+                isin: String::from(isin),
+            },
+            broker: broker.clone(),
+            year: 2023,
+            ..test_record(Decimal::ZERO)
+        };
+        let degiro = Arc::new(BrokerInformation::new(
+            String::from("Degiro"),
+            String::from("NL"),
+        ));
+        let ib = Arc::new(BrokerInformation::new(
+            String::from("Interactive Brokers"),
+            String::from("IE"),
+        ));
+        let info = Aeat720Information {
+            records: vec![
+                Aeat720Record {
+                    quantity: Decimal::new(12_5, 1),
+                    value_in_euro: Decimal::new(312_77, 2),
+                    first_tx_date: 20230315,
+                    ..record("Société Générale", "FR0000130809", &degiro)
+                },
+                Aeat720Record {
+                    quantity: Decimal::new(30, 0),
+                    value_in_euro: Decimal::new(2500_505, 3),
+                    first_tx_date: 20200110,
+                    percentage: Decimal::new(50, 0),
+                    product_type: ProductType::Fund,
+                    acquisition_type: AcquisitionType::Existing,
+                    valuation_in_euro: Some(Decimal::new(2600, 0)),
+                    ..record("iShares Core MSCI World", "IE00B4L5Y983", &degiro)
+                },
+                Aeat720Record {
+                    quantity: Decimal::ZERO,
+                    value_in_euro: Decimal::new(-150_75, 2),
+                    first_tx_date: 20210601,
+                    product_type: ProductType::Account,
+                    acquisition_type: AcquisitionType::Existing,
+                    account_bic: String::from("COBADEFFXXX"),
+                    ..record("Interactive Brokers Ireland", "DE89370400440532013000", &ib)
+                },
+                Aeat720Record {
+                    quantity: Decimal::new(10, 0),
+                    value_in_euro: Decimal::new(980_40, 2),
+                    first_tx_date: 20190405,
+                    acquisition_type: AcquisitionType::Disposal,
+                    extinction_date: 20231120,
+                    ..record("Nestlé SA", "CH0038863350", &ib)
+                },
+            ],
+            personal_info: PersonalInformation {
+                name: String::from("JOSÉ"),
+                surname: String::from("PEÑA NÚÑEZ"),
+                nif: String::from("12345678Z"),
+                year: 2023,
+                phone: String::from("600112233"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let generated = Aeat720Report::new(&info)
+            .unwrap()
+            .generate(LineEnding::Lf)
+            .unwrap();
+
+        let registers = generated.split(|c| *c == b'\n');
+        let golden_registers = GOLDEN_720.split(|c| *c == b'\n');
+        for (i, (register, golden)) in registers.zip(golden_registers).enumerate() {
+            assert_eq!(
+                ISO_8859_15.decode(register).0,
+                ISO_8859_15.decode(golden).0,
+                "register {}",
+                i
+            );
+        }
+        assert_eq!(generated, GOLDEN_720);
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let info = Aeat720Information {
+            records: vec![test_record(Decimal::new(2247_00, 2)); 2],
+            personal_info: PersonalInformation {
+                year: 2019,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let lf = Aeat720Report::new(&info)
+            .unwrap()
+            .generate(LineEnding::Lf)
+            .unwrap();
+        let crlf = Aeat720Report::new(&info)
+            .unwrap()
+            .generate(LineEnding::CrLf)
+            .unwrap();
+
+        assert_eq!(lf.len(), 3 * (AEAT_720_REGISTER_SIZE_BYTES + 1));
+        assert_eq!(crlf.len(), 3 * (AEAT_720_REGISTER_SIZE_BYTES + 2));
+        assert!(!lf.contains(&b'\r'));
+        assert!(crlf.ends_with(b"\r\n"));
+        let lines: Vec<&[u8]> = crlf.split(|c| *c == b'\n').collect();
+        assert!(lines[..3].iter().all(|line| line.ends_with(b"\r")));
+        assert_eq!(
+            crlf.iter()
+                .filter(|c| **c != b'\r')
+                .copied()
+                .collect::<Vec<u8>>(),
+            lf
+        );
+        assert_eq!(parse_720_file(&crlf).unwrap(), parse_720_file(&lf).unwrap());
+    }
+
+    #[test]
+    fn test_registers_by_record_count() {
+        for count in [1, 2, 4, 7, 10] {
+            let info = Aeat720Information {
+                records: vec![test_record(Decimal::new(2247_00, 2)); count],
+                personal_info: PersonalInformation {
+                    year: 2019,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let content = Aeat720Report::new(&info)
+                .unwrap()
+                .generate(LineEnding::Lf)
+                .unwrap();
+            let registers: Vec<&[u8]> = content
+                .split(|c| *c == b'\n')
+                .filter(|line| !line.is_empty())
+                .collect();
+
+            assert_eq!(registers.len(), count + 1);
+            assert!(registers
+                .iter()
+                .all(|register| register.len() == AEAT_720_REGISTER_SIZE_BYTES));
+            assert_eq!(registers[0][0], b'1');
+            assert_eq!(registers[0][135..144], *format!("{:09}", count).as_bytes());
+            assert_eq!(
+                registers[0][144..162],
+                *format!(" {:015}00", 2247 * count).as_bytes()
+            );
+            for detail in &registers[1..] {
+                assert_eq!(detail[0], b'2');
+                assert_eq!(detail[131..143], *b"GG00B4L84979");
+            }
+        }
+    }
+
+    #[test]
+    fn test_preview_html() {
+        let mut record = test_record(Decimal::new(-3000_25, 2));
+        record.company.name = String::from("AT&T <INC>");
+        record.quantity = Decimal::new(12_5, 1);
+        let info = Aeat720Information {
+            records: vec![record],
+            personal_info: PersonalInformation {
+                name: String::from("JOHN"),
+                surname: String::from("DOE"),
+                nif: String::from("12345678Z"),
+                year: 2019,
+                phone: String::from("600000000"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let preview = preview_html(&Aeat720Report::new(&info).unwrap());
+
+        assert!(preview.contains("<h1>Modelo 720 - Ejercicio 2019</h1>"));
+        assert!(preview.contains("<tr><td>NIF</td><td>12345678Z</td></tr>"));
+        assert!(preview.contains("<tr><td>Nº registros</td><td>1</td></tr>"));
+        assert!(preview.contains("<tr><td>Suma valoración 1 (€)</td><td>-3000,25</td></tr>"));
+        assert!(preview.contains(
+            "<tr><td>12345678Z</td><td>DOE JOHN</td><td>V1</td><td>GG00B4L84979</td><td></td>\
+             <td>IE</td><td>AT&amp;T &lt;INC&gt;</td><td>A</td><td>20181031</td><td>00000000</td>\
+             <td>-3000,25</td><td>0,00</td><td>12,50</td><td>100,00</td></tr>"
+        ));
+    }
+
+    #[test]
+    fn test_domestic_records_are_skipped() {
+        let mut domestic_broker = test_record(Decimal::new(1000_00, 2));
+        domestic_broker.broker = Arc::new(BrokerInformation::new(
+            String::from("MyInvestor"),
+            String::from("ES"),
+        ));
+        let mut domestic_isin = test_record(Decimal::new(500_00, 2));
+        domestic_isin.company.isin = String::from("ES0113900J37");
+        let mut info = Aeat720Information {
+            records: vec![
+                test_record(Decimal::new(2247_00, 2)),
+                domestic_broker,
+                domestic_isin,
+            ],
+            personal_info: PersonalInformation {
+                year: 2019,
+                ..Default::default()
+            },
+            include_domestic: false,
+            co_holder: None,
+            first_tx_dates: Default::default(),
+        };
+        let report = Aeat720Report::new(&info).unwrap();
+
+        assert_eq!(report.details.len(), 1);
+        assert_eq!(report.summary.fields[144..162], *b" 00000000000224700");
+
+        info.include_domestic = true;
+        let report = Aeat720Report::new(&info).unwrap();
+
+        assert_eq!(report.details.len(), 3);
+        assert_eq!(report.summary.fields[144..162], *b" 00000000000374700");
+    }
+
+    #[test]
+    fn test_closed_positions_are_skipped() {
+        let mut closed = test_record(Decimal::ZERO);
+        closed.quantity = Decimal::ZERO;
+        let mut sold = test_record(Decimal::new(10_00, 2));
+        sold.quantity = Decimal::ZERO;
+        let info = Aeat720Information {
+            records: vec![test_record(Decimal::new(2247_00, 2)), closed, sold],
+            personal_info: PersonalInformation {
+                year: 2019,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = Aeat720Report::new(&info).unwrap();
+
+        assert_eq!(report.details.len(), 2);
+        assert_eq!(report.summary.fields[144..162], *b" 00000000000225700");
+    }
+
+    #[test]
+    fn test_valuation_warnings() {
+        let with_cost = |value: i64, cost: i64| Aeat720Record {
+            acquisition_in_euro: Some(Decimal::new(cost, 0)),
+            ..test_record(Decimal::new(value, 0))
+        };
+        let info = Aeat720Information {
+            records: vec![
+                test_record(Decimal::new(2247, 0)),
+                with_cost(2247, 1800),
+                with_cost(2247, 150),
+                with_cost(0, 1800),
+            ],
+            personal_info: PersonalInformation {
+                year: 2019,
+                ..Default::default()
+            },
+            include_domestic: false,
+            co_holder: None,
+            first_tx_dates: Default::default(),
+        };
+        let report = Aeat720Report::new(&info).unwrap();
+
+        assert_eq!(
+            report.warnings(),
+            [
+                "BURFORD CAP LD (GG00B4L84979): valoración de 2247 € frente a un coste de adquisición de 150 €",
+                "BURFORD CAP LD (GG00B4L84979): valoración de 0 € frente a un coste de adquisición de 1800 €",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_truncation_warnings() {
+        let name = "BURFORD CAPITAL LIMITED ORDINARY SHARES NPV GUERNSEY REGISTR";
+        assert_eq!(name.len(), 60);
+        let record = Aeat720Record {
+            company: CompanyInfo {
+                name: String::from(name),
+                isin: String::from("GG00B4L84979"),
+            },
+            ..test_record(Decimal::new(2247, 0))
+        };
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+
+        assert_eq!(
+            detail.truncations,
+            [TruncatedField {
+                field: "Nombre de la entidad",
+                size: 41,
+                original: String::from(name),
+                truncated: String::from("BURFORD CAPITAL LIMITED ORDINARY SHARES N"),
+            }]
+        );
+        assert_eq!(detail.fields[189..230], name.as_bytes()[..41]);
+
+        let info = Aeat720Information {
+            records: vec![record, test_record(Decimal::new(2247, 0))],
+            personal_info: PersonalInformation {
+                year: 2019,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = Aeat720Report::new(&info).unwrap();
+
+        assert_eq!(
+            report.warnings(),
+            [format!(
+                "Nombre de la entidad recortado a 41 caracteres: {} -> BURFORD CAPITAL LIMITED ORDINARY SHARES N",
+                name
+            )]
+        );
+    }
+
+    #[test]
+    fn test_detail_register_asset_type() {
+        let mut record = test_record(Decimal::new(2247_00, 2));
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[101..103], *b"V1");
+        assert_eq!(detail.fields[461..462], *b"A");
+
+        record.product_type = ProductType::Bond;
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[101..103], *b"V2");
+        assert_eq!(detail.fields[461..462], *b"A");
+
+        record.product_type = ProductType::Fund;
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[101..103], *b"I0");
+        assert_eq!(detail.fields[461..462], *b"A");
+    }
+
+    #[test]
+    fn test_detail_register_account() {
+        let mut record = test_record(Decimal::new(1520_35, 2));
+        record.product_type = ProductType::Account;
+        record.company = CompanyInfo {
+            name: String::from("flatex Bank"),
+            isin: String::from("DE89370400440532013000"),
+        };
+        record.broker = Arc::new(BrokerInformation::new(
+            String::from("flatex Bank"),
+            String::from("DE"),
+        ));
+        let detail = DetailRegister::new(&record, 2020, "", "").unwrap();
+        assert_eq!(detail.fields[101..103], *b"C1");
+        assert_eq!(detail.fields[128..130], *b"DE");
+        assert_eq!(detail.fields[130..143], *b"0            ");
+        assert_eq!(detail.fields[143..144], *b"I");
+        assert_eq!(&detail.fields[155..177], b"DE89370400440532013000");
+        assert_eq!(detail.fields[412..414], *b"DE");
+        assert_eq!(detail.valuation, Decimal::new(1520_35, 2));
+        assert_eq!(detail.fields[446..461], *b" 00000000152035");
+        assert_eq!(detail.fields[461..462], *b" ");
+
+        record.account_bic = String::from("NTSBDEB1XXX");
+        record.q4_average_in_euro = Some(Decimal::new(1310_50, 2));
+        let detail = DetailRegister::new(&record, 2020, "", "").unwrap();
+        assert_eq!(&detail.fields[144..155], b"NTSBDEB1XXX");
+        assert_eq!(detail.acquisition, Decimal::new(1520_35, 2));
+        assert_eq!(detail.valuation, Decimal::new(1310_50, 2));
+        let imported = DetailRegister::to_record(&detail.fields).unwrap();
+        assert_eq!(imported.account_bic, "NTSBDEB1XXX");
+        assert_eq!(imported.q4_average_in_euro, Some(Decimal::new(1310_50, 2)));
+
+        record.company.isin = String::from("0123456789");
+        let detail = DetailRegister::new(&record, 2020, "", "").unwrap();
+        assert_eq!(detail.fields[143..144], *b"O");
+
+        record.company.isin = String::from("");
+        assert!(DetailRegister::new(&record, 2020, "", "").is_err());
+    }
+
+    #[test]
+    fn test_detail_register_account_balances() {
+        let account = ForeignBankAccount {
+            iban: String::from("DE89370400440532013000"),
+            bic: String::from("NTSBDEB1XXX"),
+            bank_name: String::from("N26 Bank"),
+            country_code: String::from("DE"),
+            opening_date: 20210401,
+            year_end_balance: Decimal::new(52_000_00, 2),
+            q4_average_balance: Decimal::new(49_500_55, 2),
+        };
+        let mut record = account.to_aeat720_record(2023);
+
+        // Balance at 31 December first, average balance of the last quarter second.
+        let detail = DetailRegister::new(&record, 2023, "", "").unwrap();
+        assert_eq!(detail.fields[431..446], *b" 00000005200000");
+        assert_eq!(detail.fields[446..461], *b" 00000004950055");
+
+        record.percentage = Decimal::new(50, 0);
+        record.q4_average_in_euro = Some(Decimal::new(-120_10, 2));
+        let detail = DetailRegister::new(&record, 2023, "", "").unwrap();
+        assert_eq!(detail.fields[431..446], *b" 00000002600000");
+        assert_eq!(detail.fields[446..461], *b"N00000000006005");
+        let imported = DetailRegister::to_record(&detail.fields).unwrap();
+        assert_eq!(imported.value_in_euro, Decimal::new(52_000_00, 2));
+        assert_eq!(imported.q4_average_in_euro, Some(Decimal::new(-120_10, 2)));
+    }
+
+    #[test]
+    fn test_detail_register_security_valuation() {
+        let mut record = test_record(Decimal::new(2247_00, 2));
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[431..446], *b" 00000000224700");
+        assert_eq!(detail.fields[446..461], *b" 00000000000000");
+        assert_eq!(
+            DetailRegister::to_record(&detail.fields)
+                .unwrap()
+                .valuation_in_euro,
+            None
+        );
+
+        record.percentage = Decimal::new(50, 0);
+        record.valuation_in_euro = Some(Decimal::new(2100_00, 2));
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[431..446], *b" 00000000112350");
+        assert_eq!(detail.fields[446..461], *b" 00000000105000");
+        assert_eq!(detail.valuation, Decimal::new(1050_00, 2));
+        let imported = DetailRegister::to_record(&detail.fields).unwrap();
+        assert_eq!(imported.value_in_euro, Decimal::new(2247_00, 2));
+        assert_eq!(imported.valuation_in_euro, Some(Decimal::new(2100_00, 2)));
+        assert_eq!(imported.q4_average_in_euro, None);
+    }
+
+    #[test]
+    fn test_detail_register_acquisition_type() {
+        let mut record = test_record(Decimal::new(2247_00, 2));
+        record.extinction_date = 20190615;
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[422..431], *b"A00000000");
+
+        record.acquisition_type = AcquisitionType::Existing;
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[422..431], *b"M00000000");
+
+        record.acquisition_type = AcquisitionType::Disposal;
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[422..431], *b"C20190615");
+
+        record.extinction_date = 0;
+        assert!(DetailRegister::new(&record, 2019, "", "").is_err());
+        record.extinction_date = 20191315;
+        assert!(DetailRegister::new(&record, 2019, "", "").is_err());
+    }
+
+    #[test]
+    fn test_detail_register_owned_percentage() {
+        let mut record = test_record(Decimal::new(2247_00, 2));
+        record.percentage = Decimal::new(50, 0);
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.acquisition, Decimal::new(1123_50, 2));
+        assert_eq!(detail.fields[431..446], *b" 00000000112350");
+        assert_eq!(detail.fields[475..480], *b"05000");
+
+        record.percentage = Decimal::new(33_33, 2);
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[431..446], *b" 00000000074893");
+        assert_eq!(detail.fields[475..480], *b"03333");
+
+        record.percentage = Decimal::ZERO;
+        assert!(DetailRegister::new(&record, 2019, "", "").is_err());
+        record.percentage = Decimal::new(100_01, 2);
+        assert!(DetailRegister::new(&record, 2019, "", "").is_err());
+    }
+
+    #[test]
+    fn test_parse_720_file() {
+        let mut account = test_record(Decimal::new(1520_35, 2));
+        account.company = CompanyInfo::new(
+            String::from("FLATEX BANK"),
+            String::from("DE89370400440532013000"),
+        );
+        account.product_type = ProductType::Account;
+        account.broker = Arc::new(BrokerInformation::new(
+            String::from("flatex Bank"),
+            String::from("DE"),
+        ));
+        let info = Aeat720Information {
+            records: vec![
+                Aeat720Record {
+                    percentage: Decimal::new(50, 0),
+                    acquisition_type: AcquisitionType::Existing,
+                    ..test_record(Decimal::new(2247_00, 2))
+                },
+                Aeat720Record {
+                    product_type: ProductType::Fund,
+                    acquisition_type: AcquisitionType::Disposal,
+                    extinction_date: 20190614,
+                    ..test_record(Decimal::new(-30_25, 2))
+                },
+                account,
+            ],
+            personal_info: PersonalInformation {
+                year: 2019,
+                ..Default::default()
+            },
+            include_domestic: false,
+            co_holder: None,
+            first_tx_dates: Default::default(),
+        };
+        let content = Aeat720Report::new(&info)
+            .unwrap()
+            .generate(LineEnding::Lf)
+            .unwrap();
+        let records = parse_720_file(&content).unwrap();
+
+        assert_eq!(records.len(), info.records.len());
+        for (parsed, record) in records.iter().zip(&info.records) {
+            assert_eq!(parsed.company, record.company);
+            assert_eq!(parsed.quantity, record.quantity);
+            assert_eq!(parsed.value_in_euro, record.value_in_euro);
+            assert_eq!(parsed.first_tx_date, record.first_tx_date);
+            assert_eq!(parsed.broker.country_code, record.broker.country_code);
+            assert_eq!(parsed.percentage, record.percentage);
+            assert_eq!(parsed.year, 2019);
+            assert_eq!(parsed.product_type, record.product_type);
+            assert_eq!(parsed.acquisition_type, record.acquisition_type);
+            assert_eq!(parsed.extinction_date, record.extinction_date);
+        }
+        assert!(parse_720_file(b"Producto,Symbol/ISIN").is_err());
+    }
+
+    #[test]
+    fn test_to_hundredths() {
+        assert_eq!(to_hundredths(&Decimal::new(66_1549, 4)), (66, 15));
+        assert_eq!(to_hundredths(&Decimal::new(10_656, 3)), (10, 66));
+        assert_eq!(to_hundredths(&Decimal::new(3_999, 3)), (4, 0));
+        assert_eq!(to_hundredths(&Decimal::new(2_5, 1)), (2, 50));
+        assert_eq!(to_hundredths(&Decimal::new(-7_126, 3)), (7, 13));
+        assert_eq!(to_hundredths(&Decimal::new(300, 0)), (300, 0));
+        assert_eq!(to_hundredths(&Decimal::new(2392_161184, 6)), (2392, 16));
+        assert_eq!(to_hundredths(&Decimal::new(4906_059999, 6)), (4906, 6));
+        assert_eq!(to_hundredths(&Decimal::new(0_125, 3)), (0, 12));
+        assert_eq!(to_hundredths(&Decimal::new(0_135, 3)), (0, 14));
+        assert_eq!(to_hundredths(&Decimal::new(-3000_255, 3)), (3000, 26));
+    }
+
+    #[test]
+    fn test_write_amount_fields() {
+        let mut fields: AeatRegisterArray = [b' '; AEAT_720_REGISTER_SIZE_BYTES];
+
+        Aeat720Field::write_amount_fields(
+            &mut fields,
+            DetailRegister::ACQUISITON_SIGN_FIELD,
+            DetailRegister::ACQUISITION_INT_FIELD,
+            DetailRegister::ACQUISITION_FRACTION_FIELD,
+            &Decimal::new(2392_161184, 6),
+        )
+        .unwrap();
+        assert_eq!(fields[431..446], *b" 00000000239216");
+
+        let mut fields: AeatRegisterArray = [b' '; AEAT_720_REGISTER_SIZE_BYTES];
+        Aeat720Field::write_amount_fields(
+            &mut fields,
+            DetailRegister::ACQUISITON_SIGN_FIELD,
+            DetailRegister::ACQUISITION_INT_FIELD,
+            DetailRegister::ACQUISITION_FRACTION_FIELD,
+            &Decimal::new(-15_005, 3),
+        )
+        .unwrap();
+        assert_eq!(fields[431..446], *b"N00000000001500");
+
+        let mut fields: AeatRegisterArray = [b' '; AEAT_720_REGISTER_SIZE_BYTES];
+        Aeat720Field::write_amount_fields(
+            &mut fields,
+            DetailRegister::ACQUISITON_SIGN_FIELD,
+            DetailRegister::ACQUISITION_INT_FIELD,
+            DetailRegister::ACQUISITION_FRACTION_FIELD,
+            &Decimal::new(-4, 3),
+        )
+        .unwrap();
+        assert_eq!(fields[431..446], *b" 00000000000000");
+    }
+
+    #[test]
+    fn test_detail_register_country_codes() {
+        let mut record = test_record(Decimal::new(1000_00, 2));
+        record.broker = Arc::new(BrokerInformation::new(
+            String::from("Interactive Brokers"),
+            String::from("XX"),
+        ));
+        assert!(DetailRegister::new(&record, 2019, "", "").is_err());
+
+        let mut record = test_record(Decimal::new(1000_00, 2));
+        record.company.isin = String::from("XS1234567890");
+        assert!(DetailRegister::new(&record, 2019, "", "").is_err());
+        record.issuer_country_code = String::from("DE");
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[412..414], *b"DE");
+        assert_eq!(
+            DetailRegister::to_record(&detail.fields)
+                .unwrap()
+                .issuer_country_code,
+            "DE"
+        );
+
+        let mut record = test_record(Decimal::new(1000_00, 2));
+        record.company.isin = String::from("EU000A1G0DH2");
+        assert!(DetailRegister::new(&record, 2019, "", "").is_err());
+        record.issuer_country_code = String::from("LU");
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[412..414], *b"LU");
+
+        // The ISIN country wins over a stale issuer country.
+        let mut record = test_record(Decimal::new(1000_00, 2));
+        record.issuer_country_code = String::from("DE");
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[412..414], *b"GG");
+
+        let record = test_record(Decimal::new(1000_00, 2));
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[128..130], *b"IE");
+        assert_eq!(detail.fields[412..414], *b"GG");
+    }
+
+    #[test]
+    fn test_detail_register_co_holder_percentage() {
+        let mut record = test_record(Decimal::new(1000_00, 2));
+        record.percentage = Decimal::new(50, 0);
+        record.co_holder_percentage = Decimal::new(50, 0);
+        assert!(DetailRegister::new(&record, 2019, "", "").is_ok());
+
+        record.co_holder_percentage = Decimal::new(60, 0);
+        assert!(DetailRegister::new(&record, 2019, "", "").is_err());
+    }
+
+    #[test]
+    fn test_detail_register_short_position() {
+        let record = Aeat720Record {
+            quantity: Decimal::new(-122, 0),
+            ..test_record(Decimal::new(-2247_00, 2))
+        };
+
+        assert!(DetailRegister::new(&record, 2019, "", "").is_err());
+    }
+
+    #[test]
+    fn test_detail_register_fractional_quantity() {
+        let mut record = test_record(Decimal::new(2247_00, 2));
+
+        record.quantity = Decimal::new(66_1549, 4);
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[462..474], *b"000000006615");
+
+        record.quantity = Decimal::new(10_656, 3);
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[462..474], *b"000000001066");
+
+        record.quantity = Decimal::new(9_9951, 4);
+        let detail = DetailRegister::new(&record, 2019, "", "").unwrap();
+        assert_eq!(detail.fields[462..474], *b"000000001000");
+    }
+
     #[test]
     fn test_iso_8859_15_encoding() {
         assert_eq!(ISO_8859_15.encode("Ñ").0.to_vec(), vec![209]);
     }
+
+    #[test]
+    fn test_write_field_unrepresentable_characters() {
+        let mut fields: AeatRegisterArray = [b' '; AEAT_720_REGISTER_SIZE_BYTES];
+
+        assert!(Aeat720Field::write_field(
+            &mut fields,
+            DetailRegister::ENTITY_NAME_FIELD,
+            "SOCIÉTÉ GÉNÉRALE — ÑANDÚ “ПАО” €"
+        )
+        .is_ok());
+        assert_eq!(
+            Aeat720Field::read_field(&fields, DetailRegister::ENTITY_NAME_FIELD),
+            "SOCIÉTÉ GÉNÉRALE - ÑANDÚ \"\" €"
+        );
+        assert_eq!(fields[189..196], *b"SOCI\xC9T\xC9");
+    }
 }
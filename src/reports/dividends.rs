@@ -0,0 +1,135 @@
+use crate::data::CountryDividends;
+use rust_decimal::Decimal;
+
+/*
+   Foreign dividends for the deduction for international double taxation of the modelo 100.
+
+   The deduction is the lower of the tax withheld abroad and the one the tax treaty lets the
+   source country withhold, 15% of the gross amount for most treaties. Anything withheld over
+   it has to be claimed back in the source country.
+*/
+
+/// Dividends of the year per country, merging the reports of several brokers.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DividendsReport {
+    countries: Vec<CountryDividends>,
+}
+
+impl DividendsReport {
+    const TREATY_WITHHOLDING_RATE: Decimal = Decimal::from_parts(15, 0, 0, false, 2);
+    const DOMESTIC_COUNTRY_CODE: &'static str = "ES";
+
+    /// Spanish dividends are left out, they don't give a foreign tax deduction.
+    pub fn new(dividends: &[CountryDividends]) -> Self {
+        let mut countries: Vec<CountryDividends> = vec![];
+        for dividend in dividends {
+            if dividend.country_code == Self::DOMESTIC_COUNTRY_CODE {
+                continue;
+            }
+            match countries
+                .iter_mut()
+                .find(|country| country.country_code == dividend.country_code)
+            {
+                Some(country) => {
+                    country.gross += dividend.gross;
+                    country.withholding += dividend.withholding;
+                    country.net += dividend.net;
+                }
+                None => countries.push(dividend.clone()),
+            }
+        }
+        countries.sort_by(|a, b| a.country_code.cmp(&b.country_code));
+
+        DividendsReport { countries }
+    }
+
+    pub fn countries(&self) -> &[CountryDividends] {
+        &self.countries
+    }
+
+    /// Sum of every country, with an empty country code.
+    pub fn total(&self) -> CountryDividends {
+        self.countries
+            .iter()
+            .fold(CountryDividends::default(), |mut total, country| {
+                total.gross += country.gross;
+                total.withholding += country.withholding;
+                total.net += country.net;
+                total
+            })
+    }
+
+    /// Withholding that can be deducted in Spain, see the limit above.
+    pub fn deductible(dividends: &CountryDividends) -> Decimal {
+        let limit = (dividends.gross * Self::TREATY_WITHHOLDING_RATE).round_dp(2);
+
+        dividends.withholding.min(limit).max(Decimal::ZERO)
+    }
+
+    pub fn total_deductible(&self) -> Decimal {
+        self.countries.iter().map(Self::deductible).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dividends(country_code: &str, gross: i64, withholding: i64) -> CountryDividends {
+        CountryDividends::new(
+            String::from(country_code),
+            Decimal::new(gross, 2),
+            Decimal::new(withholding, 2),
+            Decimal::new(gross - withholding, 2),
+        )
+    }
+
+    #[test]
+    fn test_dividends_report_by_country() {
+        let report = DividendsReport::new(&[
+            dividends("US", 1000_00, 150_00),
+            dividends("ES", 500_00, 95_00),
+            dividends("DE", 200_00, 52_75),
+            dividends("US", 250_00, 37_50),
+        ]);
+
+        assert_eq!(
+            report.countries(),
+            [
+                dividends("DE", 200_00, 52_75),
+                dividends("US", 1250_00, 187_50)
+            ]
+        );
+        assert_eq!(
+            report.total(),
+            CountryDividends::new(
+                String::new(),
+                Decimal::new(1450_00, 2),
+                Decimal::new(240_25, 2),
+                Decimal::new(1209_75, 2),
+            )
+        );
+    }
+
+    #[test]
+    fn test_dividends_report_deductible() {
+        assert_eq!(
+            DividendsReport::deductible(&dividends("US", 1000_00, 150_00)),
+            Decimal::new(150_00, 2)
+        );
+        assert_eq!(
+            DividendsReport::deductible(&dividends("US", 1000_00, 300_00)),
+            Decimal::new(150_00, 2)
+        );
+        assert_eq!(
+            DividendsReport::deductible(&dividends("GG", 386, 0)),
+            Decimal::ZERO
+        );
+
+        let report = DividendsReport::new(&[
+            dividends("DE", 200_00, 52_75),
+            dividends("US", 1000_00, 100_00),
+        ]);
+        assert_eq!(report.total_deductible(), Decimal::new(130_00, 2));
+    }
+}
@@ -1 +1,2 @@
 pub mod aeat_720;
+pub mod dividends;
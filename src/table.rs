@@ -1,34 +1,146 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
 
-use chrono::NaiveDate;
+use chrono::{Datelike, NaiveDate};
 use dominator::{clone, events, html, with_node, Dom};
 use futures_signals::{
     map_ref,
     signal::{Mutable, Signal, SignalExt},
     signal_vec::{MutableVec, SignalVecExt},
 };
+use num_format::Locale;
 use rust_decimal::Decimal;
-use web_sys::{HtmlElement, HtmlInputElement};
+use web_sys::{HtmlInputElement, HtmlSelectElement};
 
 use crate::{
-    css::{TABLE_CAPTION, TABLE_HEADER, TABLE_ROW, TABLE_STYLE},
+    css::{
+        ERROR_PARAGRAPH_CLASS, ICON_BUTTON, TABLE_CAPTION, TABLE_HEADER, TABLE_ROW,
+        TABLE_ROW_REPEATED, TABLE_STYLE,
+    },
     data::{
-        Aeat720Record, BrokerInformation, CompanyInfo, DEFAULT_BROKER, DEFAULT_LOCALE,
-        DEFAULT_NUMBER_OF_DECIMALS, DEFAULT_YEAR,
+        apply_first_tx_dates, merge_first_tx_dates, merge_records, names_with_several_isins,
+        repeated_isins, validate_country_code, validate_isin, AcquisitionType, Aeat720Diff,
+        Aeat720Record, BrokerInformation, CompanyInfo, OriginalValue, ParseError, ParseErrors,
+        ProductType, DEFAULT_NUMBER_OF_DECIMALS,
     },
     utils::{
         date_to_usize,
-        decimal::{decimal_to_str_locale, valid_str_number_with_decimals},
+        decimal::{
+            decimal_to_str_locale, round_valuation, str_locale_to_decimal,
+            valid_str_number_with_decimals, valuation_to_str_locale,
+        },
+        default_year, fx,
+        history::History,
         icons::{render_svg_plus_icon, render_svg_trash_icon},
-        usize_to_date,
+        today, usize_to_date, web,
     },
 };
 
 const NAME_NOT_VALID_ERR_MSG: &str = "Nombre no válido";
 const ISIN_NOT_VALID_ERR_MSG: &str = "ISIN no válido";
+const ACCOUNT_CODE_MISSING_ERR_MSG: &str = "Indica el código de la cuenta";
 const VALUE_NOT_VALID_ERR_MSG: &str = "Valor (€) no válido";
+const VALUE_MISSING_ERR_MSG: &str = "Introduce el valor a 31/12";
+const FX_RATE_MISSING_ERR_MSG: &str = "Sin tipo de cambio para la moneda y el año";
 const QUANTITY_NOT_VALID_ERR_MSG: &str = "Nº acciones no válido";
 const PERCENT_NOT_VALID_ERR_MSG: &str = "Porcentaje no válido";
+const PERCENT_SUM_NOT_VALID_ERR_MSG: &str = "Los dos titulares suman más del 100%";
+const DATE_NOT_VALID_ERR_MSG: &str = "Fecha no válida";
+const EXTINCTION_DATE_MISSING_ERR_MSG: &str = "Indica la fecha de extinción";
+const COUNTRY_NOT_VALID_ERR_MSG: &str = "País no válido";
+const ISIN_COUNTRY_NOT_VALID_ERR_MSG: &str = "Indica el país del emisor";
+const ISSUER_COUNTRY_TITLE: &str = "País del emisor, el prefijo del ISIN no es un país";
+const PASTE_COLUMNS_NOT_VALID_ERR_MSG: &str = "Se esperan 4 o 5 columnas separadas por tabuladores";
+const MERGE_NOT_VALID_ERR_MSG: &str =
+    "Selecciona al menos dos filas con el mismo ISIN, broker, año y porcentajes para fusionarlas";
+
+const MIN_FIRST_TX_YEAR: i32 = 1900;
+
+const MAX_HISTORY_STATES: usize = 50;
+
+const COLUMNS_SETTING_KEY: &str = "table_columns";
+
+/// Columns of the table that can be hidden, the records keep every field anyway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Column {
+    Name,
+    Isin,
+    ProductType,
+    Broker,
+    CountryCode,
+    Year,
+    FirstTxDate,
+    AcquisitionType,
+    ExtinctionDate,
+    Value,
+    Valuation,
+    Quantity,
+    Percentage,
+    CoHolderPercentage,
+}
+
+impl Column {
+    pub const ALL: [Column; 14] = [
+        Column::Name,
+        Column::Isin,
+        Column::ProductType,
+        Column::Broker,
+        Column::CountryCode,
+        Column::Year,
+        Column::FirstTxDate,
+        Column::AcquisitionType,
+        Column::ExtinctionDate,
+        Column::Value,
+        Column::Valuation,
+        Column::Quantity,
+        Column::Percentage,
+        Column::CoHolderPercentage,
+    ];
+
+    pub fn header(&self) -> &'static str {
+        match self {
+            Column::Name => "Nombre compañía",
+            Column::Isin => "ISIN",
+            Column::ProductType => "Tipo",
+            Column::Broker => "Broker",
+            Column::CountryCode => "Cód. país",
+            Column::Year => "Año",
+            Column::FirstTxDate => "Fecha 1ª adquisición",
+            Column::AcquisitionType => "Origen",
+            Column::ExtinctionDate => "Fecha extinción",
+            Column::Value => "Valor",
+            Column::Valuation => "Valoración 2 (€)",
+            Column::Quantity => "Nº acciones",
+            Column::Percentage => "Porcentaje",
+            Column::CoHolderPercentage => "% 2º titular",
+        }
+    }
+
+    fn key(&self) -> &'static str {
+        match self {
+            Column::Name => "name",
+            Column::Isin => "isin",
+            Column::ProductType => "product_type",
+            Column::Broker => "broker",
+            Column::CountryCode => "country_code",
+            Column::Year => "year",
+            Column::FirstTxDate => "first_tx_date",
+            Column::AcquisitionType => "acquisition_type",
+            Column::ExtinctionDate => "extinction_date",
+            Column::Value => "value",
+            Column::Valuation => "valuation",
+            Column::Quantity => "quantity",
+            Column::Percentage => "percentage",
+            Column::CoHolderPercentage => "co_holder_percentage",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Column> {
+        Column::ALL.into_iter().find(|column| column.key() == key)
+    }
+}
 
 #[derive(Debug, Clone)]
 struct Aeat720RecordInfo {
@@ -38,76 +150,463 @@ struct Aeat720RecordInfo {
     value_err_msg: Mutable<Option<&'static str>>,
     quantity_err_msg: Mutable<Option<&'static str>>,
     percent_err_msg: Mutable<Option<&'static str>>,
+    co_holder_percent_err_msg: Mutable<Option<&'static str>>,
+    date_err_msg: Mutable<Option<&'static str>>,
+    extinction_date_err_msg: Mutable<Option<&'static str>>,
+    country_err_msg: Mutable<Option<&'static str>>,
+    selected: Mutable<bool>,
 }
 pub struct Table {
-    headers: Vec<&'static str>,
+    columns: Mutable<HashSet<Column>>,
     data: MutableVec<Mutable<Aeat720RecordInfo>>,
+    history: Mutable<History<Vec<Aeat720Record>>>,
+    year_filter: Mutable<Option<usize>>,
+    text_filter: Mutable<String>,
+    first_tx_dates: Mutable<BTreeMap<String, usize>>,
+    locale: Mutable<Locale>,
+    template: Mutable<Aeat720Record>,
 }
 
 impl Table {
-    pub fn new() -> Arc<Self> {
+    pub fn new(locale: Mutable<Locale>, template: Mutable<Aeat720Record>) -> Arc<Self> {
         Arc::new(Self {
-            headers: vec![
-                "Nombre compañía",
-                "ISIN",
-                "Cód. país",
-                "Fecha 1ª adquisición",
-                "Valor (€)",
-                "Nº acciones",
-                "Porcentaje",
-            ],
+            columns: Mutable::new(Self::load_columns()),
             data: MutableVec::new(),
+            history: Mutable::new(History::new(MAX_HISTORY_STATES)),
+            year_filter: Mutable::new(None),
+            text_filter: Mutable::new(String::new()),
+            first_tx_dates: Mutable::new(BTreeMap::new()),
+            locale,
+            template,
         })
     }
 
+    /// Visible columns saved in a previous visit, all of them the first time.
+    fn load_columns() -> HashSet<Column> {
+        match web::load_setting(COLUMNS_SETTING_KEY) {
+            Some(keys) => keys.split(',').filter_map(Column::from_key).collect(),
+            None => Column::ALL.into_iter().collect(),
+        }
+    }
+
+    pub fn set_column_visible(&self, column: Column, visible: bool) {
+        let mut columns = self.columns.lock_mut();
+        if visible {
+            columns.insert(column);
+        } else {
+            columns.remove(&column);
+        }
+        let keys: Vec<&str> = Column::ALL
+            .iter()
+            .filter(|column| columns.contains(column))
+            .map(Column::key)
+            .collect();
+        web::save_setting(COLUMNS_SETTING_KEY, &keys.join(","));
+    }
+
     pub fn table_rows_not_empty(&self) -> impl Signal<Item = bool> {
         self.data
             .signal_vec_cloned()
             .to_signal_map(|x| !x.is_empty())
     }
 
+    fn row_errors_signal(
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Vec<&'static str>> {
+        let info = record.lock_ref();
+        map_ref! {
+            let name = info.name_err_msg.signal(),
+            let isin = info.isin_err_msg.signal(),
+            let value = info.value_err_msg.signal(),
+            let quantity = info.quantity_err_msg.signal(),
+            let percent = info.percent_err_msg.signal(),
+            let co_holder_percent = info.co_holder_percent_err_msg.signal(),
+            let date = info.date_err_msg.signal(),
+            let extinction_date = info.extinction_date_err_msg.signal(),
+            let country = info.country_err_msg.signal() =>
+            [
+                *name,
+                *isin,
+                *value,
+                *quantity,
+                *percent,
+                *co_holder_percent,
+                *date,
+                *extinction_date,
+                *country,
+            ]
+                .into_iter()
+                .flatten()
+                .collect()
+        }
+    }
+
+    /// Index and error messages of every row with a field not valid.
+    pub fn invalid_rows_signal(&self) -> impl Signal<Item = Vec<(usize, Vec<&'static str>)>> {
+        self.data
+            .signal_vec_cloned()
+            .map_signal(|record| Self::row_errors_signal(&record))
+            .to_signal_map(|rows| {
+                rows.iter()
+                    .enumerate()
+                    .filter(|(_, errors)| !errors.is_empty())
+                    .map(|(index, errors)| (index, errors.clone()))
+                    .collect()
+            })
+    }
+
+    pub fn rows_valid_signal(&self) -> impl Signal<Item = bool> {
+        self.invalid_rows_signal().map(|rows| rows.is_empty())
+    }
+
+    fn row_id(index: usize) -> String {
+        format!("row-{}", index + 1)
+    }
+
+    fn scroll_to_row(&self, index: usize) {
+        if let Some(row) = gloo_utils::document().get_element_by_id(&Self::row_id(index)) {
+            row.scroll_into_view();
+        }
+    }
+
+    pub fn records_signal(&self) -> impl Signal<Item = Vec<Aeat720Record>> {
+        self.data
+            .signal_vec_cloned()
+            .map_signal(|x| x.signal_ref(|r| r.record.clone()))
+            .to_signal_cloned()
+    }
+
+    /// Whether the ISIN of the row is in other rows of the same year.
+    fn is_repeated_signal(&self, record: &Mutable<Aeat720RecordInfo>) -> impl Signal<Item = bool> {
+        map_ref! {
+            let repeated = self.records_signal().map(|records| repeated_isins(&records)),
+            let key = record.signal_ref(|r| (r.record.company.isin.clone(), r.record.year)) =>
+            repeated.contains(key)
+        }
+    }
+
+    fn create_record_info(record: Aeat720Record) -> Mutable<Aeat720RecordInfo> {
+        let isin_err_msg = Self::isin_err_msg(record.product_type, &record.company.isin);
+        // Holdings derived from transactions have no year-end price.
+        let value_err_msg = if record.derived && record.value_in_euro.is_zero() {
+            Some(VALUE_MISSING_ERR_MSG)
+        } else {
+            None
+        };
+        let country_err_msg = Self::country_err_msg(&record);
+        let co_holder_percent_err_msg = Self::percent_sum_err_msg(&record);
+        let extinction_date_err_msg = Self::extinction_date_err_msg(&record);
+        Mutable::new(Aeat720RecordInfo {
+            record,
+            name_err_msg: Mutable::new(None),
+            isin_err_msg: Mutable::new(isin_err_msg),
+            value_err_msg: Mutable::new(value_err_msg),
+            quantity_err_msg: Mutable::new(None),
+            percent_err_msg: Mutable::new(None),
+            co_holder_percent_err_msg: Mutable::new(co_holder_percent_err_msg),
+            date_err_msg: Mutable::new(None),
+            extinction_date_err_msg: Mutable::new(extinction_date_err_msg),
+            country_err_msg: Mutable::new(country_err_msg),
+            selected: Mutable::new(false),
+        })
+    }
+
+    // Accounts carry an IBAN, or another code of the bank, instead of an ISIN.
+    fn isin_err_msg(product_type: ProductType, isin: &str) -> Option<&'static str> {
+        if product_type == ProductType::Account {
+            isin.is_empty().then_some(ACCOUNT_CODE_MISSING_ERR_MSG)
+        } else {
+            (!validate_isin(isin)).then_some(ISIN_NOT_VALID_ERR_MSG)
+        }
+    }
+
+    // Both the broker country and the issuer one go to the 720.
+    fn country_err_msg(record: &Aeat720Record) -> Option<&'static str> {
+        if !validate_country_code(&record.broker.country_code) {
+            Some(COUNTRY_NOT_VALID_ERR_MSG)
+        } else if record.product_type != ProductType::Account
+            && validate_isin(&record.company.isin)
+            && !validate_country_code(record.entity_country_code())
+        {
+            Some(ISIN_COUNTRY_NOT_VALID_ERR_MSG)
+        } else {
+            None
+        }
+    }
+
+    // The declarant and the second holder can't own more than the whole asset.
+    fn percent_sum_err_msg(record: &Aeat720Record) -> Option<&'static str> {
+        (record.percentage + record.co_holder_percentage > Decimal::ONE_HUNDRED)
+            .then_some(PERCENT_SUM_NOT_VALID_ERR_MSG)
+    }
+
+    // Assets disposed during the year must say when.
+    fn extinction_date_err_msg(record: &Aeat720Record) -> Option<&'static str> {
+        (record.acquisition_type == AcquisitionType::Disposal
+            && usize_to_date(record.extinction_date).is_none())
+        .then_some(EXTINCTION_DATE_MISSING_ERR_MSG)
+    }
+
+    // Shown in the second holder cell, unless its value isn't valid on its own.
+    fn update_percent_sum_err_msg(record: &Aeat720RecordInfo) {
+        let mut err_msg = record.co_holder_percent_err_msg.lock_mut();
+        if err_msg.is_none() || *err_msg == Some(PERCENT_SUM_NOT_VALID_ERR_MSG) {
+            *err_msg = Self::percent_sum_err_msg(&record.record);
+        }
+    }
+
+    // Securities like Eurobonds have an ISIN prefix that isn't a country, their issuer one is
+    // asked for.
+    fn needs_issuer_country(record: &Aeat720Record) -> bool {
+        record.product_type != ProductType::Account
+            && validate_isin(&record.company.isin)
+            && !validate_country_code(&record.company.isin[0..2])
+    }
+
     pub fn extend_rows(&self, records: Vec<Aeat720Record>) {
+        if records.is_empty() {
+            return;
+        }
+        self.save_history();
+        merge_first_tx_dates(&mut self.first_tx_dates.lock_mut(), &records);
         for record in records.into_iter() {
             self.data
                 .lock_mut()
-                .push_cloned(Mutable::new(Aeat720RecordInfo {
-                    record,
-                    name_err_msg: Mutable::new(None),
-                    isin_err_msg: Mutable::new(None),
-                    value_err_msg: Mutable::new(None),
-                    quantity_err_msg: Mutable::new(None),
-                    percent_err_msg: Mutable::new(None),
-                }));
+                .push_cloned(Self::create_record_info(record));
         }
+        self.update_first_tx_dates();
     }
 
-    fn create_default_record() -> Aeat720RecordInfo {
-        let record = Aeat720Record {
+    fn parse_pasted_decimal(value: &str, locale: &Locale) -> Option<Decimal> {
+        let value = value.trim();
+        if !valid_str_number_with_decimals(value, DEFAULT_NUMBER_OF_DECIMALS, locale) {
+            return None;
+        }
+        str_locale_to_decimal(value, locale).ok()
+    }
+
+    // Columns: name, ISIN, value (€), quantity and optionally percentage, the rest come from
+    // the template of rows added by hand.
+    fn parse_pasted_row(
+        fields: &[&str],
+        template: &Aeat720Record,
+        year: usize,
+        locale: &Locale,
+    ) -> Result<Aeat720Record, &'static str> {
+        if !(4..=5).contains(&fields.len()) {
+            return Err(PASTE_COLUMNS_NOT_VALID_ERR_MSG);
+        }
+        let name = fields[0].trim();
+        if name.is_empty() {
+            return Err(NAME_NOT_VALID_ERR_MSG);
+        }
+        let isin = fields[1].trim().to_uppercase();
+        if !validate_isin(&isin) {
+            return Err(ISIN_NOT_VALID_ERR_MSG);
+        }
+        let value_in_euro = Self::parse_pasted_decimal(fields[2], locale)
+            .filter(|value| *value > Decimal::ZERO)
+            .ok_or(VALUE_NOT_VALID_ERR_MSG)?;
+        let quantity = Self::parse_pasted_decimal(fields[3], locale)
+            .filter(|quantity| *quantity > Decimal::ZERO)
+            .ok_or(QUANTITY_NOT_VALID_ERR_MSG)?;
+        let percentage = match fields.get(4).map(|field| field.trim()) {
+            None | Some("") => template.percentage,
+            Some(field) => Self::parse_pasted_decimal(field, locale)
+                .filter(|p| *p > Decimal::ZERO && *p <= Decimal::ONE_HUNDRED)
+                .ok_or(PERCENT_NOT_VALID_ERR_MSG)?,
+        };
+        Ok(Aeat720Record {
             company: CompanyInfo {
-                name: "Nueva compañía".to_string(),
-                isin: "".to_string(),
+                name: name.to_string(),
+                isin,
             },
-            quantity: Decimal::ONE_HUNDRED,
-            value_in_euro: Decimal::ZERO,
-            first_tx_date: date_to_usize(DEFAULT_YEAR as i32, 1, 1),
-            broker: DEFAULT_BROKER.clone(),
-            percentage: Decimal::ONE_HUNDRED,
+            value_in_euro,
+            quantity,
+            percentage,
+            first_tx_date: date_to_usize(year as i32, 1, 1),
+            year,
+            original_value: None,
+            ..template.clone()
+        })
+    }
+
+    /// Rows pasted from a spreadsheet, one per line with tab separated columns.
+    pub fn paste_rows(&self, text: &str) -> ParseErrors {
+        let template = self.template.get_cloned();
+        let year = self.default_year();
+        let locale = self.locale.get();
+        let mut records = vec![];
+        let mut errors = vec![];
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            // Header row copied along with the data.
+            if index == 0 && fields.get(1).map(|f| f.trim()) == Some("ISIN") {
+                continue;
+            }
+            match Self::parse_pasted_row(&fields, &template, year, &locale) {
+                Ok(record) => records.push(record),
+                Err(reason) => errors.push(ParseError::new(index + 1, reason.to_string())),
+            }
+        }
+        self.extend_rows(records);
+        errors
+    }
+
+    /// Completes the rows imported before the transactions of their report were parsed, see
+    /// `utils::ib_html_importer`. Records without a row, like positions derived from the
+    /// transactions, are added.
+    pub fn complete_rows(&self, records: Vec<Aeat720Record>) {
+        let mut new_records = vec![];
+        for record in &records {
+            let data = self.data.lock_ref();
+            let row = data.iter().find(|row| {
+                let row = row.lock_ref();
+                row.record.company.isin == record.company.isin
+                    && row.record.broker == record.broker
+                    && row.record.year == record.year
+                    && row.record.quantity == record.quantity
+            });
+            match row {
+                Some(row) => {
+                    let mut row = row.lock_mut();
+                    row.record.acquisition_type = record.acquisition_type;
+                    row.record.acquisition_in_euro = record.acquisition_in_euro;
+                }
+                None => new_records.push(record.clone()),
+            }
+        }
+        self.add_first_tx_dates(&BTreeMap::new(), &records);
+        self.extend_rows(new_records);
+    }
+
+    /// Purchases imported from other years, e.g. the session of the previous declaration.
+    pub fn add_first_tx_dates(&self, dates: &BTreeMap<String, usize>, records: &[Aeat720Record]) {
+        {
+            let mut first_tx_dates = self.first_tx_dates.lock_mut();
+            for (isin, date) in dates {
+                first_tx_dates
+                    .entry(isin.clone())
+                    .and_modify(|current| *current = (*current).min(*date))
+                    .or_insert(*date);
+            }
+            merge_first_tx_dates(&mut first_tx_dates, records);
+        }
+        self.update_first_tx_dates();
+    }
+
+    pub fn first_tx_dates(&self) -> BTreeMap<String, usize> {
+        self.first_tx_dates.get_cloned()
+    }
+
+    // Rows bought in an earlier year than their report take the earliest known date.
+    fn update_first_tx_dates(&self) {
+        let dates = self.first_tx_dates.lock_ref();
+        for row in self.data.lock_ref().iter() {
+            apply_first_tx_dates(std::slice::from_mut(&mut row.lock_mut().record), &dates);
+        }
+    }
+
+    fn replace_rows(&self, records: Vec<Aeat720Record>) {
+        self.data
+            .lock_mut()
+            .replace_cloned(records.into_iter().map(Self::create_record_info).collect());
+    }
+
+    fn save_history(&self) {
+        let records = self.get_records();
+        self.history.lock_mut().save(records);
+    }
+
+    pub fn undo(&self) {
+        let previous = self.history.lock_mut().undo(self.get_records());
+        if let Some(records) = previous {
+            self.replace_rows(records);
+        }
+    }
+
+    pub fn redo(&self) {
+        let next = self.history.lock_mut().redo(self.get_records());
+        if let Some(records) = next {
+            self.replace_rows(records);
+        }
+    }
+
+    pub fn can_undo_signal(&self) -> impl Signal<Item = bool> {
+        self.history.signal_ref(|h| h.can_undo())
+    }
+
+    pub fn can_redo_signal(&self) -> impl Signal<Item = bool> {
+        self.history.signal_ref(|h| h.can_redo())
+    }
+
+    // Rows added by hand start from the user's template, with their own error state.
+    fn create_default_record(template: &Aeat720Record, year: usize) -> Aeat720RecordInfo {
+        let record = Aeat720Record {
+            first_tx_date: date_to_usize(year as i32, 1, 1),
+            year,
+            ..template.clone()
         };
+        let isin_err_msg = Self::isin_err_msg(record.product_type, &record.company.isin);
+        let value_err_msg = record
+            .value_in_euro
+            .is_zero()
+            .then_some(VALUE_NOT_VALID_ERR_MSG);
+        let country_err_msg = Self::country_err_msg(&record);
         Aeat720RecordInfo {
             record,
             name_err_msg: Mutable::new(None),
-            isin_err_msg: Mutable::new(Some(ISIN_NOT_VALID_ERR_MSG)),
-            value_err_msg: Mutable::new(Some(VALUE_NOT_VALID_ERR_MSG)),
+            isin_err_msg: Mutable::new(isin_err_msg),
+            value_err_msg: Mutable::new(value_err_msg),
             quantity_err_msg: Mutable::new(None),
             percent_err_msg: Mutable::new(None),
+            co_holder_percent_err_msg: Mutable::new(None),
+            date_err_msg: Mutable::new(None),
+            extinction_date_err_msg: Mutable::new(None),
+            country_err_msg: Mutable::new(country_err_msg),
+            selected: Mutable::new(false),
         }
     }
 
     pub fn add_default(&self) {
-        let record = Self::create_default_record();
+        self.save_history();
+        let record = Self::create_default_record(&self.template.lock_ref(), self.default_year());
         self.data.lock_mut().insert_cloned(0, Mutable::new(record));
     }
 
+    pub fn years_signal(&self) -> impl Signal<Item = Vec<usize>> {
+        self.records_signal().map(|records| {
+            let mut years: Vec<usize> = records.iter().map(|r| r.year).collect();
+            years.sort_unstable();
+            years.dedup();
+            years
+        })
+    }
+
+    pub fn set_year_filter(&self, year: Option<usize>) {
+        self.year_filter.set_neq(year);
+    }
+
+    /// Only hides rows, generating the report and deleting rows work on every record.
+    pub fn set_text_filter(&self, text: String) {
+        self.text_filter.set_neq(text);
+    }
+
+    fn default_year(&self) -> usize {
+        self.year_filter.get().unwrap_or_else(default_year)
+    }
+
+    pub fn get_selected_records(&self) -> Vec<Aeat720Record> {
+        let year_filter = self.year_filter.get();
+        self.get_records()
+            .into_iter()
+            .filter(|r| year_filter.map_or(true, |year| r.year == year))
+            .collect()
+    }
+
     pub fn get_records(&self) -> Vec<Aeat720Record> {
         let mut result = vec![];
         for record in self.data.lock_ref().iter() {
@@ -116,27 +615,68 @@ impl Table {
         result
     }
 
+    /// Picks the acquisition type of each row from the previous year's holdings,
+    /// rows already marked as extinguished are left untouched.
+    pub fn set_acquisition_types(&self, diff: &Aeat720Diff) {
+        self.save_history();
+        for record in self.data.lock_ref().iter() {
+            let mut record = record.lock_mut();
+            if record.record.company.isin.is_empty()
+                || record.record.acquisition_type == AcquisitionType::Disposal
+            {
+                continue;
+            }
+            record.record.acquisition_type = diff.acquisition_type(&record.record.company.isin);
+        }
+    }
+
+    /// Replaces the selected rows by their merge, placed where the first of them was.
+    pub fn merge_selected(&self) -> Result<(), &'static str> {
+        let selected: Vec<(usize, Aeat720Record)> = self
+            .data
+            .lock_ref()
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| record.lock_ref().selected.get())
+            .map(|(index, record)| (index, record.lock_ref().record.clone()))
+            .collect();
+        let records: Vec<Aeat720Record> =
+            selected.iter().map(|(_, record)| record.clone()).collect();
+        let merged = merge_records(&records).ok_or(MERGE_NOT_VALID_ERR_MSG)?;
+
+        self.save_history();
+        let mut data = self.data.lock_mut();
+        for (index, _) in selected.iter().rev() {
+            data.remove(*index);
+        }
+        data.insert_cloned(selected[0].0, Self::create_record_info(merged));
+        Ok(())
+    }
+
     pub fn clear(&self) {
+        self.save_history();
         self.data.lock_mut().clear();
+        self.first_tx_dates.lock_mut().clear();
     }
 
-    fn render_header_cells(this: &Arc<Self>) -> Vec<Dom> {
-        this.headers
+    fn render_header_cells(columns: &HashSet<Column>) -> Vec<Dom> {
+        Column::ALL
             .iter()
-            .map(|header_cell| {
+            .filter(|column| columns.contains(column))
+            .map(|column| {
                 html!("th", {
                   .attr("scope", "col")
                   .attr("role", "columnheader")
                   .style("vertical-align", "bottom")
                   .style("font-weight", "bold")
-                  .style("background-color", "#ddd")
-                  .text(header_cell)
+                  .style("background-color", "var(--header-background-color)")
+                  .text(column.header())
                 })
             })
             .collect()
     }
 
-    fn render_header(this: &Arc<Self>) -> Dom {
+    fn render_header(this: &Arc<Self>, columns: &HashSet<Column>) -> Dom {
         html!("thead", {
           .class(&*TABLE_HEADER)
           .child(
@@ -147,26 +687,28 @@ impl Table {
                   .attr("role", "columnheader")
                   .style("vertical-align", "bottom")
                   .style("font-weight", "bold")
-                  .style("background-color", "#ddd")
+                  .style("background-color", "var(--header-background-color)")
                   .text("#")
                 })
               )
-              .children(Self::render_header_cells(this))
+              .children(Self::render_header_cells(columns))
               .child(
                 html!("th", {
                   .attr("scope", "col")
                   .attr("role", "columnheader")
                   .style("vertical-align", "bottom")
                   .style("font-weight", "bold")
-                  .style("background-color", "#ddd")
-                  .child(html!("span" => HtmlElement, {
+                  .style("background-color", "var(--header-background-color)")
+                  // Buttons are reachable with Tab and fire click on Enter and Space.
+                  .child(html!("button", {
+                    .class(&*ICON_BUTTON)
+                    .attr("type", "button")
+                    .attr("aria-label", "Añadir fila")
+                    .attr("title", "Añadir fila")
                     .child(render_svg_plus_icon("red", "24"))
-                    .with_node!(_element => {
-                      .event(clone!(this => move |_: events::Click| {
-                        let record_info = Mutable::new(Self::create_default_record());
-                        this.data.lock_mut().insert_cloned(0, record_info);
-                      }))
-                    })
+                    .event(clone!(this => move |_: events::Click| {
+                      this.add_default();
+                    }))
                   }))
                 })
               )
@@ -175,8 +717,11 @@ impl Table {
         })
     }
 
-    fn company_name_cell(record: &Mutable<Aeat720RecordInfo>) -> impl Signal<Item = Option<Dom>> {
-        record.signal_ref(clone!(record => move |r| {
+    fn company_name_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        record.signal_ref(clone!(this, record => move |r| {
             Some(
               html!("td", {
                 .child(
@@ -196,22 +741,24 @@ impl Table {
                       }))
                     })
                     .with_node!(element => {
-                     .event(clone!(record => move |_: events::Change| {
+                     .event(clone!(this, record => move |_: events::Change| {
                         let name = element.value();
-                        if !name.is_empty() {
-                          *record.lock_mut().name_err_msg.lock_mut() = None;
-                        } else {
+                        if name.is_empty() {
                           *record.lock_mut().name_err_msg.lock_mut() = Some(NAME_NOT_VALID_ERR_MSG);
                           let _ = element.focus();
+                          return;
                         }
-                        record.lock_mut().record.company.name = name;
+                        this.save_history();
+                        let mut info = record.lock_mut();
+                        *info.name_err_msg.lock_mut() = None;
+                        info.record.company.name = name;
                       }))
                     })
                   })
                 )
                 .child(
                   html!("span", {
-                    .style("color", "red")
+                    .style("color", "var(--error-color)")
                     .style("font-size", "small")
                     .text_signal(record.lock_ref().name_err_msg.signal_ref(|t| t.unwrap_or("")))
                   })
@@ -221,42 +768,46 @@ impl Table {
         }))
     }
 
-    fn company_isin_cell(record: &Mutable<Aeat720RecordInfo>) -> impl Signal<Item = Option<Dom>> {
-        record.signal_ref(clone!(record => move |r| {
+    fn company_isin_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        record.signal_ref(clone!(this, record => move |r| {
             Some(
               html!("td", {
                 .child(html!("input" => HtmlInputElement, {
                     .style("display", "block")
                     .attr("type", "text")
                     .attr("size", "12")
-                    .attr("maxlength", "12")
+                    .attr("maxlength", if r.record.product_type == ProductType::Account { "34" } else { "12" })
                     .attr("value", &r.record.company.isin)
                     .with_node!(element => {
                       .event(clone!(record => move |_: events::Input| {
-                        let isin = element.value();
-                        if isin::parse(&isin).is_ok() {
-                          *record.lock_mut().isin_err_msg.lock_mut() = None;
-                        } else {
-                          *record.lock_mut().isin_err_msg.lock_mut() = Some(ISIN_NOT_VALID_ERR_MSG);
+                        let product_type = record.lock_ref().record.product_type;
+                        let err_msg = Self::isin_err_msg(product_type, &element.value());
+                        if err_msg.is_some() {
                           let _ = element.focus();
                         }
+                        *record.lock_mut().isin_err_msg.lock_mut() = err_msg;
                       }))
                     })
                     .with_node!(element => {
-                      .event(clone!(record => move |_: events::Change| {
+                      .event(clone!(this, record => move |_: events::Change| {
                         let isin = element.value();
-                        if isin::parse(&isin).is_ok() {
-                          *record.lock_mut().isin_err_msg.lock_mut() = None;
-                        } else {
-                          *record.lock_mut().isin_err_msg.lock_mut() = Some(ISIN_NOT_VALID_ERR_MSG);
+                        let product_type = record.lock_ref().record.product_type;
+                        let err_msg = Self::isin_err_msg(product_type, &isin);
+                        *record.lock_mut().isin_err_msg.lock_mut() = err_msg;
+                        if err_msg.is_some() {
                           let _ = element.focus();
+                          return;
                         }
+                        this.save_history();
                         record.lock_mut().record.company.isin = isin;
                       }))
                     })
                 }))
                 .child(html!("span", {
-                    .style("color", "red")
+                    .style("color", "var(--error-color)")
                     .style("font-size", "small")
                     .text_signal(record.lock_ref().isin_err_msg.signal_ref(|t| t.unwrap_or("")))
                 }))
@@ -265,10 +816,72 @@ impl Table {
         }))
     }
 
+    fn product_type_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        record.signal_ref(clone!(this, record => move |r| {
+            let current = r.record.product_type;
+            Some(
+              html!("td", {
+                .child(
+                  html!("select" => HtmlSelectElement, {
+                    .children(ProductType::ALL.iter().map(|product_type| {
+                      html!("option", {
+                        .attr("value", product_type.label())
+                        .prop("selected", *product_type == current)
+                        .text(product_type.label())
+                      })
+                    }))
+                    .with_node!(element => {
+                      .event(clone!(this, record => move |_: events::Change| {
+                        this.save_history();
+                        let product_type = ProductType::from(element.value().as_str());
+                        let mut info = record.lock_mut();
+                        info.isin_err_msg.set(Self::isin_err_msg(product_type, &info.record.company.isin));
+                        info.record.product_type = product_type;
+                      }))
+                    })
+                  })
+                )
+              })
+            )
+        }))
+    }
+
+    fn broker_name_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        record.signal_ref(clone!(this, record => move |r| {
+            Some(
+              html!("td", {
+                .child(
+                  html!("input" => HtmlInputElement, {
+                    .attr("type", "text")
+                    .attr("size", "15")
+                    .attr("maxlength", "40")
+                    .attr("value", &r.record.broker.name)
+                    .with_node!(element => {
+                      .event(clone!(this, record => move |_: events::Change| {
+                        this.save_history();
+                        let country_code = record.lock_ref().record.broker.country_code.clone();
+                        record.lock_mut().record.broker =
+                          Arc::new(BrokerInformation::new(element.value(), country_code));
+                      }))
+                    })
+                  })
+                )
+              })
+            )
+        }))
+    }
+
     fn broker_country_code_cell(
+        this: &Arc<Self>,
         record: &Mutable<Aeat720RecordInfo>,
     ) -> impl Signal<Item = Option<Dom>> {
-        record.signal_ref(clone!(record => move |r| {
+        record.signal_ref(clone!(this, record => move |r| {
             Some(
               html!("td", {
                 .child(
@@ -278,23 +891,64 @@ impl Table {
                     .attr("maxlength", "2")
                     .attr("value", &r.record.broker.country_code)
                     .with_node!(element => {
-                      .event(clone!(record => move |_: events::Change| {
-                        let broker = Arc::new(BrokerInformation{
-                          name: "new unknown".to_string(),
-                          country_code: element.value(),
-                        });
-                        record.lock_mut().record.broker = broker;
+                      .event(clone!(this, record => move |_: events::Change| {
+                        this.save_history();
+                        let name = record.lock_ref().record.broker.name.clone();
+                        let mut record = record.lock_mut();
+                        record.record.broker =
+                          Arc::new(BrokerInformation::new(name, element.value().to_uppercase()));
+                        *record.country_err_msg.lock_mut() = Self::country_err_msg(&record.record);
                       }))
                     })
                   })
                 )
+                .apply_if(Self::needs_issuer_country(&r.record), |dom| {
+                  dom.child(html!("input" => HtmlInputElement, {
+                    .attr("type", "text")
+                    .attr("size", "2")
+                    .attr("maxlength", "2")
+                    .attr("placeholder", "Emisor")
+                    .attr("title", ISSUER_COUNTRY_TITLE)
+                    .attr("value", &r.record.issuer_country_code)
+                    .with_node!(element => {
+                      .event(clone!(this, record => move |_: events::Change| {
+                        this.save_history();
+                        let mut record = record.lock_mut();
+                        record.record.issuer_country_code = element.value().trim().to_uppercase();
+                        *record.country_err_msg.lock_mut() = Self::country_err_msg(&record.record);
+                      }))
+                    })
+                  }))
+                })
+                .child(html!("span", {
+                    .style("color", "var(--error-color)")
+                    .style("font-size", "small")
+                    .text_signal(record.lock_ref().country_err_msg.signal_ref(|t| t.unwrap_or("")))
+                }))
               })
             )
         }))
     }
 
-    fn date_cell(record: &Mutable<Aeat720RecordInfo>) -> impl Signal<Item = Option<Dom>> {
-        record.signal_ref(clone!(record => move |r| {
+    fn year_cell(record: &Mutable<Aeat720RecordInfo>) -> impl Signal<Item = Option<Dom>> {
+        record.signal_ref(|r| {
+            Some(html!("td", {
+              .text(&r.record.year.to_string())
+            }))
+        })
+    }
+
+    /// First acquisition dates can't be empty, in the future or before 1900.
+    fn parse_first_tx_date(value: &str) -> Option<NaiveDate> {
+        let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+        (date.year() >= MIN_FIRST_TX_YEAR && date <= today()).then_some(date)
+    }
+
+    fn date_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        record.signal_ref(clone!(this, record => move |r| {
           let first_tx_date = r.record.first_tx_date;
           let date = usize_to_date(first_tx_date)
               .map_or("".to_string(), |d| d.format("%Y-%m-%d").to_string());
@@ -304,30 +958,163 @@ impl Table {
                   .attr("type", "date")
                   .attr("value", &date)
                   .with_node!(element => {
-                      .event(clone!(record => move |_: events::Change| {
-                        let parsed_date = NaiveDate::parse_from_str(&element.value(), "%Y-%m-%d").unwrap();
-                        record.lock_mut().record.first_tx_date =
-                          parsed_date.format("%Y%m%d").to_string().parse::<usize>().unwrap_or(first_tx_date);
+                      .event(clone!(this, record => move |_: events::Change| {
+                        match Self::parse_first_tx_date(&element.value()) {
+                          Some(d) => {
+                            this.save_history();
+                            *record.lock_mut().date_err_msg.lock_mut() = None;
+                            record.lock_mut().record.first_tx_date = date_to_usize(d.year(), d.month(), d.day());
+                          }
+                          None => {
+                            element.set_value(&date);
+                            *record.lock_mut().date_err_msg.lock_mut() = Some(DATE_NOT_VALID_ERR_MSG);
+                          }
+                        }
                       }))
                     })
                 }))
+                .child(html!("span", {
+                  .style("color", "var(--error-color)")
+                  .style("font-size", "small")
+                  .text_signal(record.lock_ref().date_err_msg.signal_ref(|t| t.unwrap_or("")))
+                }))
+              })
+            )
+        }))
+    }
+
+    fn acquisition_type_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        record.signal_ref(clone!(this, record => move |r| {
+            let current = r.record.acquisition_type;
+            Some(
+              html!("td", {
+                .child(
+                  html!("select" => HtmlSelectElement, {
+                    .children(AcquisitionType::ALL.iter().map(|acquisition_type| {
+                      html!("option", {
+                        .attr("value", acquisition_type.label())
+                        .prop("selected", *acquisition_type == current)
+                        .text(acquisition_type.label())
+                      })
+                    }))
+                    .with_node!(element => {
+                      .event(clone!(this, record => move |_: events::Change| {
+                        this.save_history();
+                        let mut record = record.lock_mut();
+                        record.record.acquisition_type = AcquisitionType::from(element.value().as_str());
+                        *record.extinction_date_err_msg.lock_mut() = Self::extinction_date_err_msg(&record.record);
+                      }))
+                    })
+                  })
+                )
               })
             )
         }))
     }
 
-    fn value_cell(record: &Mutable<Aeat720RecordInfo>) -> impl Signal<Item = Option<Dom>> {
-        record.signal_ref(clone!(record => move |r| {
+    fn extinction_date_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        record.signal_ref(clone!(this, record => move |r| {
+          let date = usize_to_date(r.record.extinction_date)
+              .map_or("".to_string(), |d| d.format("%Y-%m-%d").to_string());
+            Some(
+              html!("td", {
+                .child(html!("input" => HtmlInputElement, {
+                  .attr("type", "date")
+                  .attr("value", &date)
+                  .prop("disabled", r.record.acquisition_type != AcquisitionType::Disposal)
+                  .with_node!(element => {
+                      .event(clone!(this, record => move |_: events::Change| {
+                        match NaiveDate::parse_from_str(&element.value(), "%Y-%m-%d") {
+                          Ok(d) => {
+                            this.save_history();
+                            *record.lock_mut().extinction_date_err_msg.lock_mut() = None;
+                            record.lock_mut().record.extinction_date = date_to_usize(d.year(), d.month(), d.day());
+                          }
+                          Err(_) => {
+                            element.set_value(&date);
+                            *record.lock_mut().extinction_date_err_msg.lock_mut() = Some(EXTINCTION_DATE_MISSING_ERR_MSG);
+                          }
+                        }
+                      }))
+                    })
+                }))
+                .child(html!("span", {
+                  .style("color", "var(--error-color)")
+                  .style("font-size", "small")
+                  .text_signal(record.lock_ref().extinction_date_err_msg.signal_ref(|t| t.unwrap_or("")))
+                }))
+              })
+            )
+        }))
+    }
+
+    // Values in another currency are converted with the year-end rate of the row's year, the
+    // original one is kept to show it again.
+    fn set_value(
+        record: &mut Aeat720Record,
+        currency: &str,
+        value: Decimal,
+    ) -> Result<(), &'static str> {
+        if currency == fx::EUR_CURRENCY_STR {
+            record.original_value = None;
+            record.value_in_euro = round_valuation(&value);
+            return Ok(());
+        }
+
+        record.value_in_euro = fx::to_euro(&value, currency, record.year).map_err(|err| {
+            log::warn!("Unable to convert {} {}: {}", value, currency, err);
+            FX_RATE_MISSING_ERR_MSG
+        })?;
+        record.original_value = Some(OriginalValue {
+            currency: currency.to_string(),
+            value,
+        });
+        Ok(())
+    }
+
+    // The history only keeps values that were accepted.
+    fn update_value(&self, record: &Mutable<Aeat720RecordInfo>, currency: &str, value: Decimal) {
+        let mut updated = record.lock_ref().record.clone();
+        match Self::set_value(&mut updated, currency, value) {
+            Ok(()) => {
+                self.save_history();
+                let mut info = record.lock_mut();
+                *info.value_err_msg.lock_mut() = None;
+                info.record = updated;
+            }
+            Err(err) => *record.lock_ref().value_err_msg.lock_mut() = Some(err),
+        }
+    }
+
+    fn value_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        map_ref! {
+            let r = record.signal_cloned(),
+            let locale = this.locale.signal() => (r.clone(), *locale)
+        }
+        .map(clone!(this, record => move |(r, locale)| {
+            let (currency, value) = r.record.original_value.as_ref().map_or(
+                (fx::EUR_CURRENCY_STR.to_string(), r.record.value_in_euro),
+                |original| (original.currency.clone(), original.value),
+            );
             Some(html!("td", {
               .child(html!("input" => HtmlInputElement, {
                 .style("text-align", "right")
                 .attr("type", "text")
                 .attr("size", "9")
                 .attr("maxlength", "15")
-                .attr("value", &decimal_to_str_locale(&r.record.value_in_euro, DEFAULT_LOCALE))
+                .attr("value", &valuation_to_str_locale(&value, &locale))
                 .with_node!(element => {
                   .event(clone!(record => move |_: events::Input| {
-                    if valid_str_number_with_decimals(&element.value(), DEFAULT_NUMBER_OF_DECIMALS, DEFAULT_LOCALE) {
+                    if valid_str_number_with_decimals(&element.value(), DEFAULT_NUMBER_OF_DECIMALS, &locale) {
                         *record.lock_mut().value_err_msg.lock_mut() = None;
                     } else {
                         *record.lock_mut().value_err_msg.lock_mut() = Some(VALUE_NOT_VALID_ERR_MSG);
@@ -335,23 +1122,42 @@ impl Table {
                   }))
                 })
                 .with_node!(element => {
-                  .event(clone!(record => move |_: events::Change| {
+                  .event(clone!(this, record, currency => move |_: events::Change| {
                     let money_str = element.value();
-                    if valid_str_number_with_decimals(&money_str, DEFAULT_NUMBER_OF_DECIMALS, DEFAULT_LOCALE) {
-                      if let Ok(money) = money_str.parse::<Decimal>() {
-                        *record.lock_mut().value_err_msg.lock_mut() = None;
-                        record.lock_mut().record.value_in_euro = money;
+                    if valid_str_number_with_decimals(&money_str, DEFAULT_NUMBER_OF_DECIMALS, &locale) {
+                      if let Ok(money) = str_locale_to_decimal(&money_str, &locale) {
+                        this.update_value(&record, &currency, money);
                         return
                       }
                     }
                     *record.lock_mut().value_err_msg.lock_mut() = Some(VALUE_NOT_VALID_ERR_MSG);
-                    record.lock_mut().record.value_in_euro = Decimal::ZERO;
                     let _ = element.focus();
                   }))
                 })
               }))
+              // The typed amount is kept and read in the new currency.
+              .child(html!("select" => HtmlSelectElement, {
+                .attr("aria-label", "Moneda")
+                .children(fx::CURRENCIES.iter().map(|option| {
+                  html!("option", {
+                    .attr("value", option)
+                    .prop("selected", *option == currency)
+                    .text(option)
+                  })
+                }))
+                .with_node!(element => {
+                  .event(clone!(this, record => move |_: events::Change| {
+                    this.update_value(&record, &element.value(), value);
+                  }))
+                })
+              }))
+              .child(html!("span", {
+                .style("font-size", "small")
+                .visible(r.record.original_value.is_some())
+                .text(&format!(" {} €", valuation_to_str_locale(&r.record.value_in_euro, &locale)))
+              }))
               .child(html!("span", {
-                .style("color", "red")
+                .style("color", "var(--error-color)")
                 .style("font-size", "small")
                 .text_signal(record.lock_ref().value_err_msg.signal_ref(|t| t.unwrap_or("")))
               }))
@@ -359,18 +1165,71 @@ impl Table {
         }))
     }
 
-    fn quantity_cell(record: &Mutable<Aeat720RecordInfo>) -> impl Signal<Item = Option<Dom>> {
-        record.signal_ref(clone!(record => move |r| {
+    // Optional, empty leaves the second valuation of securities undeclared. Accounts use the
+    // average balance of the last quarter instead.
+    fn valuation_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        map_ref! {
+            let r = record.signal_cloned(),
+            let locale = this.locale.signal() => (r.clone(), *locale)
+        }
+        .map(clone!(this, record => move |(r, locale)| {
+            let valuation = r.record.valuation_in_euro
+                .map_or(String::new(), |valuation| valuation_to_str_locale(&valuation, &locale));
+            Some(html!("td", {
+              .child(html!("input" => HtmlInputElement, {
+                .style("text-align", "right")
+                .attr("type", "text")
+                .attr("size", "9")
+                .attr("maxlength", "15")
+                .attr("value", &valuation)
+                .prop("disabled", r.record.product_type == ProductType::Account)
+                .with_node!(element => {
+                  .event(clone!(this, record => move |_: events::Change| {
+                    let money_str = element.value();
+                    let money_str = money_str.trim();
+                    if money_str.is_empty() {
+                      this.save_history();
+                      record.lock_mut().record.valuation_in_euro = None;
+                      return
+                    }
+                    if valid_str_number_with_decimals(money_str, DEFAULT_NUMBER_OF_DECIMALS, &locale) {
+                      if let Ok(money) = str_locale_to_decimal(money_str, &locale) {
+                        this.save_history();
+                        record.lock_mut().record.valuation_in_euro = Some(money);
+                        return
+                      }
+                    }
+                    element.set_value(&valuation);
+                    let _ = element.focus();
+                  }))
+                })
+              }))
+            }))
+        }))
+    }
+
+    fn quantity_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        map_ref! {
+            let r = record.signal_cloned(),
+            let locale = this.locale.signal() => (r.clone(), *locale)
+        }
+        .map(clone!(this, record => move |(r, locale)| {
             Some(html!("td", {
               .child(html!("input" => HtmlInputElement, {
                 .style("text-align", "right")
                 .attr("type", "text")
                 .attr("size", "6")
                 .attr("maxlength", "15")
-                .attr("value", &decimal_to_str_locale(&r.record.quantity, DEFAULT_LOCALE))
+                .attr("value", &decimal_to_str_locale(&r.record.quantity, &locale))
                 .with_node!(element => {
                   .event(clone!(record => move |_: events::Input| {
-                    if valid_str_number_with_decimals(&element.value(), DEFAULT_NUMBER_OF_DECIMALS, DEFAULT_LOCALE) {
+                    if valid_str_number_with_decimals(&element.value(), DEFAULT_NUMBER_OF_DECIMALS, &locale) {
                         *record.lock_mut().quantity_err_msg.lock_mut() = None;
                     } else {
                         *record.lock_mut().quantity_err_msg.lock_mut() = Some(QUANTITY_NOT_VALID_ERR_MSG);
@@ -378,23 +1237,23 @@ impl Table {
                   }))
                 })
                 .with_node!(element => {
-                  .event(clone!(record => move |_: events::Change| {
+                  .event(clone!(this, record => move |_: events::Change| {
                     let quantity_str = element.value();
-                    if valid_str_number_with_decimals(&quantity_str, DEFAULT_NUMBER_OF_DECIMALS, DEFAULT_LOCALE) {
-                      if let Ok(quantity) = quantity_str.parse::<Decimal>() {
+                    if valid_str_number_with_decimals(&quantity_str, DEFAULT_NUMBER_OF_DECIMALS, &locale) {
+                      if let Ok(quantity) = str_locale_to_decimal(&quantity_str, &locale) {
+                        this.save_history();
                         *record.lock_mut().quantity_err_msg.lock_mut() = None;
                         record.lock_mut().record.quantity = quantity;
                         return
                       }
                     }
                     *record.lock_mut().quantity_err_msg.lock_mut() = Some(QUANTITY_NOT_VALID_ERR_MSG);
-                    record.lock_mut().record.quantity = Decimal::ONE_HUNDRED;
                     let _ = element.focus();
                   }))
                 })
               }))
               .child(html!("span", {
-                .style("color", "red")
+                .style("color", "var(--error-color)")
                 .style("font-size", "small")
                 .text_signal(record.lock_ref().quantity_err_msg.signal_ref(|t| t.unwrap_or("")))
               }))
@@ -402,18 +1261,25 @@ impl Table {
         }))
     }
 
-    fn percentage_cell(record: &Mutable<Aeat720RecordInfo>) -> impl Signal<Item = Option<Dom>> {
-        record.signal_ref(clone!(record => move |r| {
+    fn percentage_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        map_ref! {
+            let r = record.signal_cloned(),
+            let locale = this.locale.signal() => (r.clone(), *locale)
+        }
+        .map(clone!(this, record => move |(r, locale)| {
             Some(html!("td", {
               .child(html!("input" => HtmlInputElement, {
                 .style("text-align", "right")
                 .attr("type", "text")
                 .attr("size", "4")
                 .attr("maxlength", "6")
-                .attr("value", &r.record.percentage.to_string())
+                .attr("value", &decimal_to_str_locale(&r.record.percentage, &locale))
                 .with_node!(element => {
                   .event(clone!(record => move |_: events::Input| {
-                    if valid_str_number_with_decimals(&element.value(), DEFAULT_NUMBER_OF_DECIMALS, DEFAULT_LOCALE) {
+                    if valid_str_number_with_decimals(&element.value(), DEFAULT_NUMBER_OF_DECIMALS, &locale) {
                         *record.lock_mut().percent_err_msg.lock_mut() = None;
                     } else {
                         *record.lock_mut().percent_err_msg.lock_mut() = Some(PERCENT_NOT_VALID_ERR_MSG);
@@ -421,26 +1287,27 @@ impl Table {
                   }))
                 })
                 .with_node!(element => {
-                  .event(clone!(record => move |_: events::Change| {
-                    let percentage_str = element.value().replace(DEFAULT_LOCALE.decimal(), ".");
-                    if valid_str_number_with_decimals(&percentage_str, DEFAULT_NUMBER_OF_DECIMALS, DEFAULT_LOCALE) {
-                      if let Ok(percentage) = percentage_str.parse::<Decimal>() {
+                  .event(clone!(this, record => move |_: events::Change| {
+                    let percentage_str = element.value();
+                    if valid_str_number_with_decimals(&percentage_str, DEFAULT_NUMBER_OF_DECIMALS, &locale) {
+                      if let Ok(percentage) = str_locale_to_decimal(&percentage_str, &locale) {
                         if percentage.gt(&Decimal::ZERO) && percentage.le(&Decimal::ONE_HUNDRED) {
+                          this.save_history();
                           *record.lock_mut().percent_err_msg.lock_mut() = None;
                           record.lock_mut().record.percentage = percentage;
+                          Self::update_percent_sum_err_msg(&record.lock_ref());
                           return;
                         }
                       }
                     }
                     *record.lock_mut().percent_err_msg.lock_mut() = Some(PERCENT_NOT_VALID_ERR_MSG);
-                    record.lock_mut().record.percentage = Decimal::ONE_HUNDRED;
                     let _ = element.focus();
                   }))
                 })
               }))
               .text(" % ")
               .child(html!("span", {
-                .style("color", "red")
+                .style("color", "var(--error-color)")
                 .style("font-size", "small")
                 .text_signal(record.lock_ref().percent_err_msg.signal_ref(|t| t.unwrap_or("")))
               }))
@@ -448,62 +1315,223 @@ impl Table {
         }))
     }
 
+    fn co_holder_percentage_cell(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = Option<Dom>> {
+        map_ref! {
+            let r = record.signal_cloned(),
+            let locale = this.locale.signal() => (r.clone(), *locale)
+        }
+        .map(clone!(this, record => move |(r, locale)| {
+            Some(html!("td", {
+              .child(html!("input" => HtmlInputElement, {
+                .style("text-align", "right")
+                .attr("type", "text")
+                .attr("size", "4")
+                .attr("maxlength", "6")
+                .attr("value", &decimal_to_str_locale(&r.record.co_holder_percentage, &locale))
+                .with_node!(element => {
+                  .event(clone!(record => move |_: events::Input| {
+                    if valid_str_number_with_decimals(&element.value(), DEFAULT_NUMBER_OF_DECIMALS, &locale) {
+                        *record.lock_mut().co_holder_percent_err_msg.lock_mut() = None;
+                    } else {
+                        *record.lock_mut().co_holder_percent_err_msg.lock_mut() = Some(PERCENT_NOT_VALID_ERR_MSG);
+                    }
+                  }))
+                })
+                .with_node!(element => {
+                  .event(clone!(this, record => move |_: events::Change| {
+                    let percentage_str = element.value();
+                    if valid_str_number_with_decimals(&percentage_str, DEFAULT_NUMBER_OF_DECIMALS, &locale) {
+                      if let Ok(percentage) = str_locale_to_decimal(&percentage_str, &locale) {
+                        // Zero means the second holder doesn't own this asset.
+                        if percentage.ge(&Decimal::ZERO) && percentage.le(&Decimal::ONE_HUNDRED) {
+                          this.save_history();
+                          *record.lock_mut().co_holder_percent_err_msg.lock_mut() = None;
+                          record.lock_mut().record.co_holder_percentage = percentage;
+                          Self::update_percent_sum_err_msg(&record.lock_ref());
+                          return;
+                        }
+                      }
+                    }
+                    *record.lock_mut().co_holder_percent_err_msg.lock_mut() = Some(PERCENT_NOT_VALID_ERR_MSG);
+                    let _ = element.focus();
+                  }))
+                })
+              }))
+              .text(" % ")
+              .child(html!("span", {
+                .style("color", "var(--error-color)")
+                .style("font-size", "small")
+                .text_signal(record.lock_ref().co_holder_percent_err_msg.signal_ref(|t| t.unwrap_or("")))
+              }))
+            }))
+        }))
+    }
+
     fn actions_cell(
         this: &Arc<Self>,
         index: usize,
         record: &Mutable<Aeat720RecordInfo>,
     ) -> impl Signal<Item = Option<Dom>> {
         record.signal_ref(clone!(this => move |_r| {
-         let delete_span = html!("span" => HtmlElement, {
-           .child(render_svg_trash_icon("red", "24"))
-          .with_node!(_element => {
-            .event(clone!(this => move |_: events::Click| {
-              this.data.lock_mut().remove(index);
-            }))
-          })
+         let label = format!("Eliminar fila {}", index + 1);
+         let delete_button = html!("button", {
+          .class(&*ICON_BUTTON)
+          .attr("type", "button")
+          .attr("aria-label", &label)
+          .attr("title", &label)
+          .child(render_svg_trash_icon("red", "24"))
+          .event(clone!(this => move |_: events::Click| {
+            this.save_history();
+            this.data.lock_mut().remove(index);
+          }))
          });
 
             Some(
               html!("td", {
-                .child(delete_span)
+                .child(delete_button)
               })
             )
         }))
     }
 
-    fn render_row(this: &Arc<Self>, index: usize, record: &Mutable<Aeat720RecordInfo>) -> Dom {
+    fn render_row(
+        this: &Arc<Self>,
+        index: usize,
+        record: &Mutable<Aeat720RecordInfo>,
+        columns: &HashSet<Column>,
+    ) -> Dom {
         html!("tr", {
           .class(&*TABLE_ROW)
+          .class_signal(&*TABLE_ROW_REPEATED, this.is_repeated_signal(record))
+          .attr_signal("title", this.is_repeated_signal(record).map(|repeated| {
+            repeated.then_some("ISIN repetido en otras filas del mismo año")
+          }))
+          .attr("id", &Self::row_id(index))
+          .visible_signal(this.year_filter.signal_ref(clone!(record => move |year_filter| {
+            year_filter.map_or(true, |year| record.lock_ref().record.year == year)
+          })))
           .child(
             html!("td", {
+              .child(html!("input" => HtmlInputElement, {
+                .attr("type", "checkbox")
+                .attr("aria-label", &format!("Seleccionar fila {}", index + 1))
+                .prop("checked", record.lock_ref().selected.get())
+                .with_node!(element => {
+                  .event(clone!(record => move |_: events::Change| {
+                    record.lock_ref().selected.set(element.checked());
+                  }))
+                })
+              }))
               .text(&format!("{}", index + 1))
             })
           )
-          .child_signal(Self::company_name_cell(record))
-          .child_signal(Self::company_isin_cell(record))
-          .child_signal(Self::broker_country_code_cell(record))
-          .child_signal(Self::date_cell(record))
-          .child_signal(Self::value_cell(record))
-          .child_signal(Self::quantity_cell(record))
-          .child_signal(Self::percentage_cell(record))
+          .apply_if(columns.contains(&Column::Name), |dom| dom.child_signal(Self::company_name_cell(this, record)))
+          .apply_if(columns.contains(&Column::Isin), |dom| dom.child_signal(Self::company_isin_cell(this, record)))
+          .apply_if(columns.contains(&Column::ProductType), |dom| dom.child_signal(Self::product_type_cell(this, record)))
+          .apply_if(columns.contains(&Column::Broker), |dom| dom.child_signal(Self::broker_name_cell(this, record)))
+          .apply_if(columns.contains(&Column::CountryCode), |dom| dom.child_signal(Self::broker_country_code_cell(this, record)))
+          .apply_if(columns.contains(&Column::Year), |dom| dom.child_signal(Self::year_cell(record)))
+          .apply_if(columns.contains(&Column::FirstTxDate), |dom| dom.child_signal(Self::date_cell(this, record)))
+          .apply_if(columns.contains(&Column::AcquisitionType), |dom| dom.child_signal(Self::acquisition_type_cell(this, record)))
+          .apply_if(columns.contains(&Column::ExtinctionDate), |dom| dom.child_signal(Self::extinction_date_cell(this, record)))
+          .apply_if(columns.contains(&Column::Value), |dom| dom.child_signal(Self::value_cell(this, record)))
+          .apply_if(columns.contains(&Column::Valuation), |dom| dom.child_signal(Self::valuation_cell(this, record)))
+          .apply_if(columns.contains(&Column::Quantity), |dom| dom.child_signal(Self::quantity_cell(this, record)))
+          .apply_if(columns.contains(&Column::Percentage), |dom| dom.child_signal(Self::percentage_cell(this, record)))
+          .apply_if(columns.contains(&Column::CoHolderPercentage), |dom| dom.child_signal(Self::co_holder_percentage_cell(this, record)))
           .child_signal(Self::actions_cell(this, index, record))
         })
     }
 
+    fn matches_text_filter(
+        this: &Arc<Self>,
+        record: &Mutable<Aeat720RecordInfo>,
+    ) -> impl Signal<Item = bool> {
+        map_ref! {
+            let text = this.text_filter.signal_cloned(),
+            let company = record.signal_ref(|info| info.record.company.clone()) =>
+            company.matches(text)
+        }
+    }
+
+    // Rows are enumerated before filtering so deletions use the index in the data.
     fn render_body(this: &Arc<Self>) -> Dom {
+        let columns = this.columns.get_cloned();
         html!("tbody", {
           .children_signal_vec(this.data.signal_vec_cloned()
-            .enumerate().map(clone!(this => move |(index, record)| {
+            .enumerate()
+            .filter_signal_cloned(clone!(this => move |(_, record)| {
+              Table::matches_text_filter(&this, record)
+            }))
+            .map(clone!(this => move |(index, record)| {
               let i = index.get().unwrap_or(usize::MAX);
-              Table::render_row(&this, i, &record)
+              Table::render_row(&this, i, &record, &columns)
            }))
           )
         })
     }
 
+    /// Records of the rows shown, the ones of the selected year matching the text filter.
+    fn shown_records_signal(this: &Arc<Self>) -> impl Signal<Item = Vec<Aeat720Record>> {
+        map_ref! {
+            let records = this.records_signal(),
+            let year_filter = this.year_filter.signal(),
+            let text = this.text_filter.signal_cloned() =>
+            records
+                .iter()
+                .filter(|record| year_filter.map_or(true, |year| record.year == year))
+                .filter(|record| record.company.matches(text))
+                .cloned()
+                .collect()
+        }
+    }
+
+    // Values are added in euros, whatever the currency they're typed in. The row number and
+    // actions columns are always shown, so the spans are never empty.
+    fn render_footer(this: &Arc<Self>) -> Dom {
+        html!("tfoot", {
+          .class(&*TABLE_HEADER)
+          .child_signal(map_ref! {
+            let records = Self::shown_records_signal(this),
+            let columns = this.columns.signal_cloned(),
+            let locale = this.locale.signal() => (records.clone(), columns.clone(), *locale)
+          }.map(|(records, columns, locale)| {
+            let value: Decimal = records.iter().map(|record| record.value_in_euro).sum();
+            let quantity: Decimal = records.iter().map(|record| record.quantity).sum();
+            let span = |range: &[Column]| {
+              (1 + range.iter().filter(|column| columns.contains(column)).count()).to_string()
+            };
+            Some(html!("tr", {
+              .style("font-weight", "bold")
+              .style("background-color", "var(--header-background-color)")
+              .child(html!("td", {
+                .attr("colspan", &span(&Column::ALL[..9]))
+                .text(&format!("Total: {} filas", records.len()))
+              }))
+              .apply_if(columns.contains(&Column::Value), |dom| dom.child(html!("td", {
+                .style("text-align", "right")
+                .text(&format!("{} €", valuation_to_str_locale(&value, &locale)))
+              })))
+              .apply_if(columns.contains(&Column::Valuation), |dom| dom.child(html!("td")))
+              .apply_if(columns.contains(&Column::Quantity), |dom| dom.child(html!("td", {
+                .style("text-align", "right")
+                .text(&decimal_to_str_locale(&quantity, &locale))
+              })))
+              .child(html!("td", {
+                .attr("colspan", &span(&Column::ALL[12..]))
+              }))
+            }))
+          }))
+        })
+    }
+
     fn is_needed_to_rerender_rows(this: &Arc<Self>) -> impl Signal<Item = bool> {
         map_ref! {
             // let _editable_changed = this.editable.signal(),
+            let _columns_changed = this.columns.signal_ref(|_| ()),
             let records_len = this.data.signal_vec_cloned().to_signal_map(|x| x.len()) => {
               log::debug!("Rerendering rows, new rows: {}", records_len);
               true
@@ -511,6 +1539,77 @@ impl Table {
         }
     }
 
+    /// List of the rows with errors, the report can't be generated until they're fixed.
+    pub fn render_validation_summary(this: &Arc<Self>) -> Dom {
+        html!("section", {
+          .visible_signal(this.rows_valid_signal().map(|valid| !valid))
+          .child(html!("p", {
+            .class(&*ERROR_PARAGRAPH_CLASS)
+            .text("Corrige estas filas antes de generar el informe:")
+          }))
+          .child(html!("ul", {
+            .children_signal_vec(this.invalid_rows_signal().map(clone!(this => move |rows| {
+              rows.into_iter().map(|(index, errors)| {
+                html!("li", {
+                  .child(html!("button", {
+                    .attr("type", "button")
+                    .text(&format!("Fila {}", index + 1))
+                    .event(clone!(this => move |_: events::Click| {
+                      this.scroll_to_row(index);
+                    }))
+                  }))
+                  .text(&format!(" {}", errors.join(", ")))
+                })
+              }).collect()
+            })).to_signal_vec())
+          }))
+        })
+    }
+
+    /// Rows of one company with different ISINs, like an ADR and its ordinary shares, are kept
+    /// apart but listed in case one of them was entered by mistake.
+    pub fn render_same_name_notice(this: &Arc<Self>) -> Dom {
+        html!("section", {
+          .visible_signal(this.records_signal().map(|records| !names_with_several_isins(&records).is_empty()))
+          .child(html!("p", {
+            .text("Estas compañías aparecen con varios ISIN y se declaran como valores distintos:")
+          }))
+          .child(html!("ul", {
+            .children_signal_vec(this.records_signal().map(|records| {
+              names_with_several_isins(&records).into_iter().map(|(name, isins)| {
+                html!("li", {
+                  .text(&format!("{}: {}", name, isins.join(", ")))
+                })
+              }).collect()
+            }).to_signal_vec())
+          }))
+        })
+    }
+
+    /// Hiding columns only affects the table, the report is generated with every field.
+    pub fn render_column_selector(this: &Arc<Self>) -> Dom {
+        html!("details", {
+          .child(html!("summary", {
+            .text("Columnas visibles")
+          }))
+          .children(Column::ALL.into_iter().map(|column| {
+            html!("label", {
+              .style("margin-right", "10px")
+              .child(html!("input" => HtmlInputElement, {
+                .attr("type", "checkbox")
+                .prop("checked", this.columns.lock_ref().contains(&column))
+                .with_node!(element => {
+                  .event(clone!(this => move |_: events::Change| {
+                    this.set_column_visible(column, element.checked());
+                  }))
+                })
+              }))
+              .text(column.header())
+            })
+          }))
+        })
+    }
+
     pub fn render(this: &Arc<Self>) -> Dom {
         html!("table", {
          .class(&*TABLE_STYLE)
@@ -521,12 +1620,15 @@ impl Table {
             })
 
           )
-          .child(Self::render_header(this))
+          .child_signal(this.columns.signal_cloned().map(clone!(this => move |columns| {
+            Some(Self::render_header(&this, &columns))
+          })))
           .child_signal(Self::is_needed_to_rerender_rows(this).map(
             clone!(this => move |_x| {
               Some(Self::render_body(&this))
             }))
           )
+          .child(Self::render_footer(this))
         })
     }
 }
@@ -0,0 +1,218 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::{Aeat720Information, PersonalInformation},
+    reports::aeat_720::{Aeat720Report, LineEnding},
+    utils::{
+        file_importer,
+        zip::{read_zip_entries, write_zip},
+    },
+};
+
+/// Name of the client list inside a batch archive.
+pub const BATCH_MANIFEST_FILE_NAME: &str = "clientes.json";
+const BATCH_ERRORS_FILE_NAME: &str = "errores.txt";
+
+/// Client of an advisor as listed in the manifest, e.g.
+/// `[{"file": "cliente1.zip", "name": "JUAN", "surname": "ESPAÑOL", "nif": "12345678Z",
+/// "year": 2023, "phone": "600000000"}]`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ClientInfo {
+    /// Name of the client's broker report inside the batch archive.
+    pub file: String,
+    #[serde(flatten)]
+    pub personal_info: PersonalInformation,
+    #[serde(default)]
+    pub include_domestic: bool,
+}
+
+pub fn parse_batch_manifest(content: &[u8]) -> Result<Vec<ClientInfo>> {
+    serde_json::from_slice(content).context("Manifest of the batch isn't valid")
+}
+
+/// Pairs every client of the manifest with its file from an archive holding both, a client
+/// whose file is missing gets an error instead of failing the whole batch.
+pub fn read_batch(data: Vec<u8>) -> Result<Vec<(ClientInfo, Result<Vec<u8>>)>> {
+    let mut entries = read_zip_entries(data)?;
+    let manifest = match entries
+        .iter()
+        .position(|(name, _)| name == BATCH_MANIFEST_FILE_NAME)
+    {
+        Some(index) => entries.swap_remove(index).1,
+        None => bail!("Batch without {}", BATCH_MANIFEST_FILE_NAME),
+    };
+
+    Ok(parse_batch_manifest(&manifest)?
+        .into_iter()
+        .map(|client| {
+            let content = entries
+                .iter()
+                .find(|(name, _)| *name == client.file)
+                .map(|(_, content)| content.clone())
+                .ok_or_else(|| anyhow!("File {} not found in the batch", client.file));
+            (client, content)
+        })
+        .collect())
+}
+
+fn process_client(client: &ClientInfo, content: Vec<u8>) -> Result<Vec<u8>> {
+    let (records, errors) = file_importer(content)?;
+    for error in &errors {
+        log::warn!("Skipped line of {:?}: {:?}", client.file, error);
+    }
+
+    let info = Aeat720Information {
+        records,
+        personal_info: client.personal_info.clone(),
+        include_domestic: client.include_domestic,
+        ..Default::default()
+    };
+    Aeat720Report::new(&info)?.generate(LineEnding::default())
+}
+
+/// One 720 form per client, an error in a client's file doesn't stop the rest.
+pub fn process_batch(inputs: Vec<(ClientInfo, Result<Vec<u8>>)>) -> Vec<Result<Vec<u8>>> {
+    inputs
+        .into_iter()
+        .map(|(client, content)| {
+            content
+                .and_then(|content| process_client(&client, content))
+                .with_context(|| format!("{} ({})", client.file, client.personal_info.nif))
+        })
+        .collect()
+}
+
+/// Archive with the 720 form of every client and the list of the failed ones.
+pub fn batch_archive(data: Vec<u8>) -> Result<Vec<u8>> {
+    let inputs = read_batch(data)?;
+    let clients: Vec<ClientInfo> = inputs.iter().map(|(client, _)| client.clone()).collect();
+    let mut files = vec![];
+    let mut errors = String::new();
+
+    for (client, result) in clients.iter().zip(process_batch(inputs)) {
+        match result {
+            Ok(form) => files.push((
+                format!(
+                    "fichero-720-{}-{}.txt",
+                    client.personal_info.nif, client.personal_info.year
+                ),
+                form,
+            )),
+            Err(err) => {
+                log::error!(
+                    "Unable to generate 720 of {:?}: {:?}",
+                    client.file,
+                    format!("{:#}", err)
+                );
+                errors.push_str(&format!("{:#}\n", err));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        files.push((String::from(BATCH_ERRORS_FILE_NAME), errors.into_bytes()));
+    }
+    let files: Vec<(&str, Vec<u8>)> = files
+        .iter()
+        .map(|(name, content)| (name.as_str(), content.clone()))
+        .collect();
+
+    write_zip(&files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reports::aeat_720::is_720_file;
+
+    const DEGIRO_PORTFOLIO: &str = r#"Producto,Symbol/ISIN,Valor,Cantidad,Precio,Valor local,Valor en EUR
+APPLE INC,US0378331005,USD,10,"192,53","1925,30","1742,35"
+BURFORD CAP LD,GG00B4L84979,GBX,463,"1224,00","566712,00","6535,17""#;
+
+    const MANIFEST: &str = r#"[
+        {"file": "juan.csv", "name": "JUAN", "surname": "ESPAÑOL", "nif": "12345678Z", "year": 2023, "phone": "600000000"},
+        {"file": "ana.pdf", "name": "ANA", "surname": "GARCIA", "nif": "87654321X", "year": 2023, "phone": "", "include_domestic": true}
+    ]"#;
+
+    #[test]
+    fn test_parse_batch_manifest() {
+        let clients = parse_batch_manifest(MANIFEST.as_bytes()).unwrap();
+
+        assert_eq!(
+            clients[0],
+            ClientInfo {
+                file: String::from("juan.csv"),
+                personal_info: PersonalInformation {
+                    name: String::from("JUAN"),
+                    surname: String::from("ESPAÑOL"),
+                    nif: String::from("12345678Z"),
+                    year: 2023,
+                    phone: String::from("600000000"),
+                    ..Default::default()
+                },
+                include_domestic: false,
+            }
+        );
+        assert!(clients[1].include_domestic);
+        assert!(parse_batch_manifest(b"{}").is_err());
+    }
+
+    #[test]
+    fn test_process_batch() {
+        let clients = parse_batch_manifest(MANIFEST.as_bytes()).unwrap();
+        let results = process_batch(vec![
+            (clients[0].clone(), Ok(DEGIRO_PORTFOLIO.as_bytes().to_vec())),
+            (clients[1].clone(), Ok(b"not a broker report".to_vec())),
+            (
+                clients[1].clone(),
+                Err(anyhow!("File ana.pdf not found in the batch")),
+            ),
+        ]);
+
+        assert_eq!(results.len(), 3);
+        let form = results[0].as_ref().unwrap();
+        assert!(is_720_file(form));
+        assert_eq!(
+            form.split(|c| *c == b'\n')
+                .filter(|l| !l.is_empty())
+                .count(),
+            3
+        );
+        let err = results[1].as_ref().unwrap_err();
+        assert!(format!("{:#}", err).starts_with("ana.pdf (87654321X)"));
+        let err = results[2].as_ref().unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "ana.pdf (87654321X): File ana.pdf not found in the batch"
+        );
+    }
+
+    #[test]
+    fn test_read_batch() {
+        let data = write_zip(&[
+            (BATCH_MANIFEST_FILE_NAME, MANIFEST.as_bytes().to_vec()),
+            ("juan.csv", DEGIRO_PORTFOLIO.as_bytes().to_vec()),
+            ("ana.pdf", b"%PDF".to_vec()),
+        ])
+        .unwrap();
+        let inputs = read_batch(data).unwrap();
+
+        assert_eq!(inputs.len(), 2);
+        assert_eq!(inputs[0].0.personal_info.nif, "12345678Z");
+        assert_eq!(inputs[0].1.as_ref().unwrap(), DEGIRO_PORTFOLIO.as_bytes());
+        assert_eq!(inputs[1].1.as_ref().unwrap(), b"%PDF");
+
+        let data = write_zip(&[
+            (BATCH_MANIFEST_FILE_NAME, MANIFEST.as_bytes().to_vec()),
+            ("juan.csv", DEGIRO_PORTFOLIO.as_bytes().to_vec()),
+        ])
+        .unwrap();
+        let inputs = read_batch(data).unwrap();
+        assert_eq!(inputs.len(), 2);
+        assert!(inputs[0].1.is_ok());
+        assert!(inputs[1].1.is_err());
+
+        let data = write_zip(&[("juan.csv", DEGIRO_PORTFOLIO.as_bytes().to_vec())]).unwrap();
+        assert!(read_batch(data).is_err());
+    }
+}
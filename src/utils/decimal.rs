@@ -1,12 +1,92 @@
 use num_format::Locale;
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::str::FromStr;
+
+const THOUSANDS_GROUP_DIGITS: usize = 3;
+// Some PDF extractors write the thousands separator as a space, usually a non-breaking one.
+const SPACE_SEPARATORS: [char; 3] = [' ', '\u{00A0}', '\u{202F}'];
+const VALUATION_DECIMALS: u32 = 2;
+
+// The last separator is the decimal one when both are present, a repeated separator
+// is always a thousands one and a single separator followed by other than three digits
+// is a decimal one, otherwise it's ambiguous and the default convention is used.
+fn normalize_with_default(input: &str, default_decimal: char) -> String {
+    let last_comma = input.rfind(',');
+    let last_dot = input.rfind('.');
+
+    let decimal_separator = match (last_comma, last_dot) {
+        (Some(comma), Some(dot)) => Some(if comma > dot { ',' } else { '.' }),
+        (Some(pos), None) | (None, Some(pos)) => {
+            let separator = input[pos..].chars().next().unwrap();
+            let digits_after = input[pos + 1..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .count();
+            if input.matches(separator).count() > 1 {
+                None
+            } else if digits_after != THOUSANDS_GROUP_DIGITS || separator == default_decimal {
+                Some(separator)
+            } else {
+                None
+            }
+        }
+        (None, None) => None,
+    };
+
+    input
+        .chars()
+        .filter_map(|c| match c {
+            ',' | '.' if Some(c) == decimal_separator => Some('.'),
+            ',' | '.' => None,
+            c if SPACE_SEPARATORS.contains(&c) => None,
+            _ => Some(c),
+        })
+        .collect()
+}
 
 pub fn transform_i18n_es_str(input: &str) -> String {
-    str::replace(&str::replace(input, ".", ""), ",", ".")
+    normalize_with_default(input, ',')
+}
+
+/// Like `transform_i18n_es_str` but with the decimal separator of the text it comes from.
+pub fn transform_i18n_str(input: &str, decimal_separator: char) -> String {
+    normalize_with_default(input, decimal_separator)
+}
+
+/// Decimal separator used by the numbers of a text, reports mixing Spanish and English
+/// formatted sections are parsed one section at a time. Only separators followed by other
+/// than three digits count, the comma wins ties.
+pub fn detect_decimal_separator(text: &str) -> char {
+    let bytes = text.as_bytes();
+    let (mut commas, mut dots) = (0, 0);
+
+    for (i, c) in bytes.iter().enumerate() {
+        if !matches!(c, b',' | b'.') || i == 0 || !bytes[i - 1].is_ascii_digit() {
+            continue;
+        }
+        let digits_after = bytes[i + 1..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if digits_after == 0 || digits_after == THOUSANDS_GROUP_DIGITS {
+            continue;
+        }
+        if *c == b',' {
+            commas += 1;
+        } else {
+            dots += 1;
+        }
+    }
+
+    if dots > commas {
+        '.'
+    } else {
+        ','
+    }
 }
 
 pub fn normalize_str(input: &str) -> String {
-    str::replace(input, ",", "")
+    normalize_with_default(input, '.')
 }
 
 pub fn decimal_to_str_locale(number: &Decimal, locale: &Locale) -> String {
@@ -18,6 +98,25 @@ pub fn decimal_to_str_locale(number: &Decimal, locale: &Locale) -> String {
     result
 }
 
+/// Holdings are valued in euro cents whatever their currency or the decimals of their price,
+/// rounding half to even like the amounts written to the 720 file.
+pub fn round_valuation(value: &Decimal) -> Decimal {
+    value.round_dp_with_strategy(VALUATION_DECIMALS, RoundingStrategy::MidpointNearestEven)
+}
+
+/// Valuation as shown to the user, rounded the same way it's declared.
+pub fn valuation_to_str_locale(value: &Decimal, locale: &Locale) -> String {
+    decimal_to_str_locale(&round_valuation(value), locale)
+}
+
+// Like `valid_str_number_with_decimals` the dot is accepted as decimal separator in any locale.
+pub fn str_locale_to_decimal(
+    number: &str,
+    locale: &Locale,
+) -> Result<Decimal, rust_decimal::Error> {
+    Decimal::from_str(&number.replace(locale.decimal(), "."))
+}
+
 pub fn valid_str_number_with_decimals(number: &str, decimal_number: u16, locale: &Locale) -> bool {
     let mut state = 0; // 0 integer part, 1 decimal part
     let mut decimals = 0;
@@ -45,12 +144,89 @@ pub fn valid_str_number_with_decimals(number: &str, decimal_number: u16, locale:
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_normalize_str() {
+        assert_eq!("2392.161184", normalize_str("2,392.161184"));
+        assert_eq!("-1234567.89", normalize_str("-1,234,567.89"));
+        assert_eq!("1500", normalize_str("1,500"));
+        assert_eq!("10.656", normalize_str("10.656"));
+        assert_eq!("66.1549", normalize_str("66.1549"));
+        assert_eq!("2392.16", normalize_str("2.392,16"));
+        assert_eq!("0.9030", normalize_str("0,9030"));
+        assert_eq!("300", normalize_str("300"));
+    }
+
+    #[test]
+    fn test_transform_i18n_es_str() {
+        assert_eq!("2392.16", transform_i18n_es_str("2.392,16"));
+        assert_eq!("1234567.89", transform_i18n_es_str("1.234.567,89"));
+        assert_eq!("1616", transform_i18n_es_str("1.616"));
+        assert_eq!("0.9030", transform_i18n_es_str("0,9030"));
+        assert_eq!("-500.03", transform_i18n_es_str("-500,03"));
+        assert_eq!("2392.161184", transform_i18n_es_str("2,392.161184"));
+        assert_eq!("66.1549", transform_i18n_es_str("66.1549"));
+    }
+
+    #[test]
+    fn test_transform_i18n_es_str_space_separators() {
+        assert_eq!("197152.00", transform_i18n_es_str("197\u{00A0}152,00"));
+        assert_eq!("197152.00", transform_i18n_es_str("197 152,00"));
+        assert_eq!(
+            "1234567.89",
+            transform_i18n_es_str("1\u{202F}234\u{202F}567,89")
+        );
+        assert_eq!("1616", transform_i18n_es_str("1\u{00A0}616"));
+        assert_eq!("1656.00", transform_i18n_str("1\u{00A0}656.00", '.'));
+    }
+
+    #[test]
+    fn test_detect_decimal_separator() {
+        assert_eq!(',', detect_decimal_separator("2.247,001.656,0000GBX122LSE"));
+        assert_eq!('.', detect_decimal_separator("2,247.001,656.0000GBX122LSE"));
+        assert_eq!('.', detect_decimal_separator("0.00 EUR 11,568.35 EUR"));
+        assert_eq!(',', detect_decimal_separator("122 1.616,0000 197.152,00"));
+        assert_eq!(',', detect_decimal_separator("Amsterdam, 28/01/2019 1.616"));
+        assert_eq!("1656.0000", transform_i18n_str("1,656.0000", '.'));
+        assert_eq!("1656", transform_i18n_str("1,656", '.'));
+    }
+
     #[test]
     fn test_decimal_to_str_locale() {
         let x = Decimal::new(2314, 2);
         assert_eq!("23,14", decimal_to_str_locale(&x, &Locale::es));
     }
 
+    #[test]
+    fn test_round_valuation() {
+        // 3.3333 EUR shares at 10.015
+        let value = Decimal::new(3_3333, 4) * Decimal::new(10_015, 3);
+        assert_eq!(round_valuation(&value), Decimal::new(33_38, 2));
+        assert_eq!(
+            round_valuation(&Decimal::new(0_125, 3)),
+            Decimal::new(12, 2)
+        );
+        assert_eq!(
+            round_valuation(&Decimal::new(0_135, 3)),
+            Decimal::new(14, 2)
+        );
+        assert_eq!(
+            round_valuation(&Decimal::new(-3000_255, 3)),
+            Decimal::new(-3000_26, 2)
+        );
+        assert_eq!("33,38", valuation_to_str_locale(&value, &Locale::es));
+        assert_eq!("33.38", valuation_to_str_locale(&value, &Locale::en));
+    }
+
+    #[test]
+    fn test_str_locale_to_decimal() {
+        let x = Decimal::new(2314, 2);
+        assert_eq!(Ok(x), str_locale_to_decimal("23,14", &Locale::es));
+        assert_eq!(Ok(x), str_locale_to_decimal("23.14", &Locale::es));
+        assert_eq!(Ok(x), str_locale_to_decimal("23.14", &Locale::en));
+        assert!(str_locale_to_decimal("23,14", &Locale::en).is_err());
+        assert_eq!("23.14", decimal_to_str_locale(&x, &Locale::en));
+    }
+
     #[test]
     fn test_valid_str_number_with_decimals() {
         assert!(valid_str_number_with_decimals("23,14", 2, &Locale::es));
@@ -0,0 +1,124 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::{bail, Result};
+use rust_decimal::Decimal;
+
+use crate::utils::decimal::round_valuation;
+
+pub const EUR_CURRENCY_STR: &str = "EUR";
+/// Currencies values can be typed in, the ones with year-end rates.
+pub const CURRENCIES: [&str; 2] = [EUR_CURRENCY_STR, "USD"];
+
+// Rates typed by the user for the years without an embedded one yet.
+static USER_RATES: LazyLock<Mutex<HashMap<(String, usize), Decimal>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// ECB euro reference rates published on the last business day of each year.
+fn embedded_rate(currency: &str, year: usize) -> Option<Decimal> {
+    let rate = match (currency, year) {
+        ("USD", 2018) => Decimal::new(1_1450, 4),
+        ("USD", 2019) => Decimal::new(1_1234, 4),
+        ("USD", 2020) => Decimal::new(1_2271, 4),
+        ("USD", 2021) => Decimal::new(1_1326, 4),
+        ("USD", 2022) => Decimal::new(1_0666, 4),
+        ("USD", 2023) => Decimal::new(1_1050, 4),
+        ("USD", 2024) => Decimal::new(1_0389, 4),
+        _ => return None,
+    };
+
+    Some(rate)
+}
+
+fn year_end_rate(currency: &str, year: usize) -> Option<Decimal> {
+    embedded_rate(currency, year).or_else(|| {
+        USER_RATES
+            .lock()
+            .ok()?
+            .get(&(currency.to_string(), year))
+            .copied()
+    })
+}
+
+pub fn has_embedded_rate(currency: &str, year: usize) -> bool {
+    embedded_rate(currency, year).is_some()
+}
+
+/// Units of `currency` per euro at the end of `year`, used when there's no embedded rate.
+/// None forgets the rate.
+pub fn set_user_rate(currency: &str, year: usize, rate: Option<Decimal>) {
+    if let Ok(mut rates) = USER_RATES.lock() {
+        let key = (currency.to_string(), year);
+        match rate.filter(|rate| rate.is_sign_positive() && !rate.is_zero()) {
+            Some(rate) => rates.insert(key, rate),
+            None => rates.remove(&key),
+        };
+    }
+}
+
+/// Year-end value in euros, rounded to cents with [`round_valuation`] for every currency.
+pub fn to_euro(amount: &Decimal, currency: &str, year: usize) -> Result<Decimal> {
+    if currency == EUR_CURRENCY_STR {
+        return Ok(round_valuation(amount));
+    }
+
+    match year_end_rate(currency, year) {
+        Some(rate) => Ok(round_valuation(&(*amount / rate))),
+        None => bail!("No hay tipo de cambio para {} en {}", currency, year),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_euro() {
+        assert_eq!(
+            to_euro(&Decimal::new(1200_00, 2), "USD", 2023).unwrap(),
+            Decimal::new(1085_97, 2)
+        );
+        assert_eq!(
+            to_euro(&Decimal::new(1200_00, 2), "EUR", 2023).unwrap(),
+            Decimal::new(1200_00, 2)
+        );
+        assert!(to_euro(&Decimal::new(1200_00, 2), "USD", 2000).is_err());
+    }
+
+    #[test]
+    fn test_to_euro_user_rate() {
+        assert!(to_euro(&Decimal::new(1200_00, 2), "USD", 1999).is_err());
+        set_user_rate("USD", 1999, Some(Decimal::new(1_2000, 4)));
+        assert_eq!(
+            to_euro(&Decimal::new(1200_00, 2), "USD", 1999).unwrap(),
+            Decimal::new(1000_00, 2)
+        );
+        // Embedded rates can't be overridden.
+        set_user_rate("USD", 2023, Some(Decimal::new(1_2000, 4)));
+        assert_eq!(
+            to_euro(&Decimal::new(1200_00, 2), "USD", 2023).unwrap(),
+            Decimal::new(1085_97, 2)
+        );
+        set_user_rate("USD", 1999, None);
+        assert!(to_euro(&Decimal::new(1200_00, 2), "USD", 1999).is_err());
+        assert!(!has_embedded_rate("USD", 1999));
+    }
+
+    #[test]
+    fn test_to_euro_fractional_prices() {
+        // 12.5 USD shares at 237.2234
+        let value = Decimal::new(12_5, 1) * Decimal::new(237_2234, 4);
+        assert_eq!(
+            to_euro(&value, "USD", 2023).unwrap(),
+            Decimal::new(2683_52, 2)
+        );
+        // 3.3333 EUR shares at 10.015
+        let value = Decimal::new(3_3333, 4) * Decimal::new(10_015, 3);
+        assert_eq!(
+            to_euro(&value, "EUR", 2023).unwrap(),
+            Decimal::new(33_38, 2)
+        );
+    }
+}
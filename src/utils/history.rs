@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+
+/// Bounded undo/redo stack of snapshots.
+#[derive(Debug, Clone)]
+pub struct History<T> {
+    undo: VecDeque<T>,
+    redo: Vec<T>,
+    max_states: usize,
+}
+
+impl<T> History<T> {
+    pub fn new(max_states: usize) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: vec![],
+            max_states,
+        }
+    }
+
+    fn push_undo(&mut self, state: T) {
+        if self.undo.len() == self.max_states {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(state);
+    }
+
+    /// Stores the state previous to a mutating action, discarding redo states.
+    pub fn save(&mut self, state: T) {
+        self.push_undo(state);
+        self.redo.clear();
+    }
+
+    /// Returns the state to restore, keeping `current` to be redone.
+    pub fn undo(&mut self, current: T) -> Option<T> {
+        let previous = self.undo.pop_back()?;
+        self.redo.push(current);
+        Some(previous)
+    }
+
+    /// Returns the state to restore, keeping `current` to be undone.
+    pub fn redo(&mut self, current: T) -> Option<T> {
+        let next = self.redo.pop()?;
+        self.push_undo(current);
+        Some(next)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = History::new(50);
+        assert!(!history.can_undo());
+        assert_eq!(history.undo(0), None);
+
+        history.save(0);
+        history.save(1);
+        assert_eq!(history.undo(2), Some(1));
+        assert_eq!(history.undo(1), Some(0));
+        assert!(!history.can_undo());
+        assert_eq!(history.redo(0), Some(1));
+        assert_eq!(history.redo(1), Some(2));
+        assert!(!history.can_redo());
+
+        history.undo(2);
+        history.save(1);
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut history = History::new(3);
+        for state in 0..10 {
+            history.save(state);
+        }
+
+        assert_eq!(history.undo(10), Some(9));
+        assert_eq!(history.undo(9), Some(8));
+        assert_eq!(history.undo(8), Some(7));
+        assert_eq!(history.undo(7), None);
+    }
+}
@@ -136,3 +136,26 @@ pub fn render_svg_cancel_icon(color: &str, size: &str) -> Dom {
 
     })
 }
+
+pub fn render_svg_spinner_icon(color: &str, size: &str) -> Dom {
+    svg!("svg", {
+        .attr("alt", "Cargando")
+        .attr("width", size)
+        .attr("height", size)
+        .attr("stroke", color)
+        .apply(svg_icon_attrs)
+        .children(&mut[
+            svg!("path", {
+                .attr("d", "M21 12a9 9 0 1 1-6.22-8.56")
+                .child(svg!("animateTransform", {
+                    .attr("attributeName", "transform")
+                    .attr("type", "rotate")
+                    .attr("from", "0 12 12")
+                    .attr("to", "360 12 12")
+                    .attr("dur", "1s")
+                    .attr("repeatCount", "indefinite")
+                }))
+            }),
+        ])
+    })
+}
@@ -1,22 +1,45 @@
+#[cfg(test)]
+use std::cell::Cell;
 use std::sync::{Arc, LazyLock};
 
 use anyhow::{bail, Result};
-use chrono::NaiveDate;
+use chrono::{Datelike, Local, NaiveDate};
+use encoding_rs::WINDOWS_1252;
 use rust_decimal::Decimal;
-use zip::read_zip;
+use zip::read_zip_entries;
 
 use crate::{
     data::{
-        AccountNotes, Aeat720Record, Aeat720Records, BalanceNotes, BrokerInformation, DEFAULT_YEAR,
+        acquisition_cost, aggregate_by_isin, derive_balances_from_transactions,
+        first_transaction_date, infer_acquisition_type, validate_isin, AccountNotes, Aeat720Record,
+        Aeat720Records, BalanceNotes, BrokerInformation, CountryDividends, CryptoBalanceNotes,
+        DividendNotes, ParseError, ParseErrors, DEFAULT_FIRST_TX_DATE, SPAIN_COUNTRY_CODE,
     },
     parsers::{
-        degiro::DegiroParser, degiro_csv::DegiroCSVParser, ib::IBParser, ib_csv::IBCSVParser,
-        pdf::read_pdf,
+        crypto::CryptoCSVParser,
+        degiro::DegiroParser,
+        degiro_account_csv::DegiroAccountCSVParser,
+        degiro_csv::DegiroCSVParser,
+        etoro::EtoroParser,
+        ib::IBParser,
+        ib_csv::IBCSVParser,
+        myinvestor::MyInvestorCSVParser,
+        pdf::{is_password_error, read_pdf, PdfPasswordError},
+        revolut::RevolutCSVParser,
+        trading212::Trading212CSVParser,
+        tsv::TsvParser,
+        us_broker::UsBrokerCSVParser,
+        xtb::XtbParser,
     },
+    reports::aeat_720::{is_720_file, parse_720_file},
 };
 
+pub mod batch;
 pub mod decimal;
+pub mod fx;
+pub mod history;
 pub mod icons;
+pub mod ticker;
 pub mod web;
 pub mod zip;
 
@@ -27,6 +50,13 @@ static DEGIRO_BROKER: LazyLock<Arc<BrokerInformation>> = LazyLock::new(|| {
     ))
 });
 
+static FLATEX_BANK: LazyLock<Arc<BrokerInformation>> = LazyLock::new(|| {
+    Arc::new(BrokerInformation::new(
+        String::from("flatex Bank"),
+        String::from("DE"),
+    ))
+});
+
 static IB_BROKER: LazyLock<Arc<BrokerInformation>> = LazyLock::new(|| {
     Arc::new(BrokerInformation::new(
         String::from("Interactive Brokers"),
@@ -34,6 +64,79 @@ static IB_BROKER: LazyLock<Arc<BrokerInformation>> = LazyLock::new(|| {
     ))
 });
 
+static ETORO_BROKER: LazyLock<Arc<BrokerInformation>> = LazyLock::new(|| {
+    Arc::new(BrokerInformation::new(
+        String::from("eToro"),
+        String::from("CY"),
+    ))
+});
+
+static XTB_BROKER: LazyLock<Arc<BrokerInformation>> = LazyLock::new(|| {
+    Arc::new(BrokerInformation::new(
+        String::from("XTB"),
+        String::from("PL"),
+    ))
+});
+
+static MYINVESTOR_BROKER: LazyLock<Arc<BrokerInformation>> = LazyLock::new(|| {
+    Arc::new(BrokerInformation::new(
+        String::from("MyInvestor"),
+        String::from(SPAIN_COUNTRY_CODE),
+    ))
+});
+
+static REVOLUT_BROKER: LazyLock<Arc<BrokerInformation>> = LazyLock::new(|| {
+    Arc::new(BrokerInformation::new(
+        String::from("Revolut"),
+        String::from("LT"),
+    ))
+});
+
+static TRADING212_BROKER: LazyLock<Arc<BrokerInformation>> = LazyLock::new(|| {
+    Arc::new(BrokerInformation::new(
+        String::from("Trading 212"),
+        String::from("CY"),
+    ))
+});
+
+static US_BROKER: LazyLock<Arc<BrokerInformation>> = LazyLock::new(|| {
+    Arc::new(BrokerInformation::new(
+        String::from("Charles Schwab"),
+        String::from("US"),
+    ))
+});
+
+// Pasted tables don't tell the broker, the user fills it in the table.
+static PASTED_TABLE_BROKER: LazyLock<Arc<BrokerInformation>> =
+    LazyLock::new(|| Arc::new(BrokerInformation::new(String::new(), String::new())));
+
+/// Notes parsed from a broker report with the report year, if detected, and the skipped lines.
+type ReportNotes = (BalanceNotes, AccountNotes, Option<usize>, ParseErrors);
+
+#[cfg(test)]
+thread_local! {
+    static PINNED_TODAY: Cell<Option<NaiveDate>> = const { Cell::new(None) };
+}
+
+/// Local date of the browser, tests pin it with [`pin_today`] to not depend on the clock.
+pub fn today() -> NaiveDate {
+    #[cfg(test)]
+    if let Some(date) = PINNED_TODAY.with(Cell::get) {
+        return date;
+    }
+    Local::now().date_naive()
+}
+
+#[cfg(test)]
+pub(crate) fn pin_today(date: Option<NaiveDate>) {
+    PINNED_TODAY.with(|today| today.set(date));
+}
+
+/// Last completed tax year, the one usually declared.
+pub fn default_year() -> usize {
+    (today().year() - 1) as usize
+}
+
 pub fn usize_to_date(date_int: usize) -> Option<NaiveDate> {
     let mut date = date_int;
     let day = date % 100;
@@ -43,21 +146,90 @@ pub fn usize_to_date(date_int: usize) -> Option<NaiveDate> {
     NaiveDate::from_ymd_opt(date as i32, month as u32, day as u32)
 }
 
-fn read_degiro_pdf(content: Vec<u8>) -> Result<(BalanceNotes, AccountNotes)> {
-    if let Ok(data) = read_pdf(&content) {
-        let parser = DegiroParser::new(data, &DEGIRO_BROKER);
-        parser.parse_pdf_content()
+fn read_pdf_text(content: &[u8], password: Option<&str>) -> Result<String> {
+    match read_pdf(content, password) {
+        Ok(data) => Ok(data),
+        Err(err) if is_password_error(&err) => Err(match password {
+            Some(_) => PdfPasswordError::Incorrect,
+            None => PdfPasswordError::Required,
+        }
+        .into()),
+        Err(_) => bail!("Error parseando el pdf del broker".to_string()),
+    }
+}
+
+fn read_broker_report(data: String) -> Result<ReportNotes> {
+    if XtbParser::is_xtb_report(&data) {
+        let parser = XtbParser::new(data, &XTB_BROKER);
+        let (balance_notes, account_notes, errors) = parser.parse_pdf_content()?;
+        Ok((balance_notes, account_notes, parser.report_year(), errors))
     } else {
-        bail!("Error parseando el pdf de Degiro".to_string());
+        read_degiro_report(data)
+    }
+}
+
+fn read_broker_pdf(content: Vec<u8>) -> Result<ReportNotes> {
+    read_broker_report(read_pdf_text(&content, None)?)
+}
+
+fn read_degiro_report(data: String) -> Result<ReportNotes> {
+    let parser = DegiroParser::new(data, &DEGIRO_BROKER);
+    let (mut balance_notes, account_notes, mut errors) = parser.parse_pdf_content()?;
+    errors.extend(parser.check_portfolio_value(&balance_notes));
+    // The cash fund line shows the same euros as the flatex account, when there's one.
+    match parser.parse_flatex_account(&FLATEX_BANK) {
+        Ok(Some(account)) => balance_notes.push(account.to_balance_note()),
+        Ok(None) => {
+            if let Some(cash) = parser.parse_cash_fund() {
+                errors.push(ParseError::new(
+                    0,
+                    format!(
+                        "Fondo de efectivo de Degiro ({} EUR): el informe no indica la cuenta, revisa si debes declararlo e introduce su código",
+                        cash.balance
+                    ),
+                ));
+                balance_notes.push(cash.to_balance_note());
+            }
+        }
+        Err(err) => log::warn!("Skipping flatex cash account: {}", err),
     }
+    Ok((balance_notes, account_notes, parser.report_year(), errors))
 }
 
-fn read_ib_html(content: Vec<u8>) -> Result<(BalanceNotes, AccountNotes)> {
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Exports saved from Excel start with a BOM, which breaks the detection of their headers.
+pub(crate) fn strip_bom(content: Vec<u8>) -> Vec<u8> {
+    match content.strip_prefix(UTF8_BOM) {
+        Some(content) => content.to_vec(),
+        None => content,
+    }
+}
+
+/// Text of a CSV export, UTF-8 or, failing that, Windows-1252, the Latin-1 superset some
+/// brokers and spreadsheets on Windows write. A leading UTF-8 BOM is dropped.
+pub(crate) fn decode_text(content: &[u8]) -> String {
+    let content = content.strip_prefix(UTF8_BOM).unwrap_or(content);
+    match std::str::from_utf8(content) {
+        Ok(text) => text.to_string(),
+        Err(_) => {
+            log::info!("Content isn't UTF-8, decoding it as Windows-1252");
+            WINDOWS_1252
+                .decode_without_bom_handling(content)
+                .0
+                .into_owned()
+        }
+    }
+}
+
+fn read_ib_html(content: Vec<u8>) -> Result<ReportNotes> {
     if let Ok(data) = String::from_utf8(content) {
         if let Ok(parser) = IBParser::new(&data, &IB_BROKER) {
-            let account_notes = parser.parse_account_notes()?;
-            let balance_notes = parser.parse_balance_notes()?;
-            Ok((balance_notes, account_notes))
+            let (account_notes, mut errors) = parser.parse_account_notes()?;
+            let (balance_notes, balance_errors) = parser.parse_balance_notes()?;
+            errors.extend(balance_errors);
+            errors.extend(parser.check_base_currency_total(&balance_notes));
+            Ok((balance_notes, account_notes, parser.report_year(), errors))
         } else {
             bail!("Unable to parse interactive brokers html");
         }
@@ -66,30 +238,130 @@ fn read_ib_html(content: Vec<u8>) -> Result<(BalanceNotes, AccountNotes)> {
     }
 }
 
-fn read_ib_csv(content: Vec<u8>) -> Result<(BalanceNotes, AccountNotes)> {
-    if let Ok(data) = String::from_utf8(content) {
-        if let Ok(parser) = IBCSVParser::new(data, &IB_BROKER) {
-            let account_notes = parser.parse_account_notes()?;
-            let balance_notes = parser.parse_balance_notes()?;
-            Ok((balance_notes, account_notes))
-        } else {
-            bail!("Unable to parse interactive brokers CSV");
+// Balances are recalculated per file with its own EUR totals before concatenating them.
+fn read_ib_combined_html(contents: Vec<Vec<u8>>) -> Result<ReportNotes> {
+    let documents = contents
+        .into_iter()
+        .map(String::from_utf8)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut balance_notes = vec![];
+    let mut account_notes = vec![];
+    let mut year = None;
+    let mut errors = vec![];
+
+    for parser in IBParser::new_combined(&documents, &IB_BROKER)? {
+        let (notes, notes_errors) = parser.parse_account_notes()?;
+        account_notes.extend(notes);
+        errors.extend(notes_errors);
+        if parser.has_open_positions() {
+            let (notes, notes_errors) = parser.parse_balance_notes()?;
+            errors.extend(notes_errors);
+            errors.extend(parser.check_base_currency_total(&notes));
+            balance_notes.extend(notes);
         }
+        year = year.or_else(|| parser.report_year());
+    }
+
+    Ok((balance_notes, account_notes, year, errors))
+}
+
+fn read_ib_csv(content: Vec<u8>) -> Result<ReportNotes> {
+    let data = decode_text(&content);
+    if let Ok(parser) = IBCSVParser::new(data, &IB_BROKER) {
+        let (account_notes, mut errors) = parser.parse_account_notes()?;
+        let (balance_notes, balance_errors) = parser.parse_balance_notes()?;
+        errors.extend(balance_errors);
+        Ok((balance_notes, account_notes, parser.report_year(), errors))
     } else {
-        bail!("Unable to get string from interactive brokers csv content");
+        bail!("Unable to parse interactive brokers CSV");
     }
 }
 
-fn read_degiro_csv(content: Vec<u8>) -> Result<(BalanceNotes, AccountNotes)> {
-    if let Ok(data) = String::from_utf8(content) {
-        let parser = DegiroCSVParser::new(data, &DEGIRO_BROKER);
-        let balance_notes = parser.parse_csv()?;
-        Ok((balance_notes, vec![]))
+fn read_degiro_csv(content: Vec<u8>) -> Result<ReportNotes> {
+    let data = decode_text(&content);
+    let parser = DegiroCSVParser::new(data, &DEGIRO_BROKER);
+    let (balance_notes, errors) = parser.parse_csv()?;
+    Ok((balance_notes, vec![], None, errors))
+}
+
+fn read_tsv_table(text: &str) -> Result<ReportNotes> {
+    let parser = TsvParser::new(text.to_string(), &PASTED_TABLE_BROKER);
+    let (balance_notes, errors) = parser.parse_balance_notes()?;
+    Ok((balance_notes, vec![], None, errors))
+}
+
+fn read_myinvestor_csv(content: Vec<u8>) -> Result<ReportNotes> {
+    let data = decode_text(&content);
+    let parser = MyInvestorCSVParser::new(data, &MYINVESTOR_BROKER);
+    let (balance_notes, errors) = parser.parse_csv()?;
+    Ok((balance_notes, vec![], None, errors))
+}
+
+fn read_revolut_csv(content: Vec<u8>) -> Result<ReportNotes> {
+    let is_positions = RevolutCSVParser::is_revolut_positions(&content);
+    let data = decode_text(&content);
+    let parser = RevolutCSVParser::new(data, &REVOLUT_BROKER);
+    if is_positions {
+        let (balance_notes, errors) = parser.parse_balance_notes()?;
+        Ok((balance_notes, vec![], None, errors))
     } else {
-        bail!("Unable to parse Degiro CSV");
+        let (account_notes, errors) = parser.parse_account_notes()?;
+        let year = account_notes
+            .iter()
+            .map(|note| note.date.year() as usize)
+            .max();
+        Ok((vec![], account_notes, year, errors))
     }
 }
 
+fn read_trading212_csv(content: Vec<u8>) -> Result<ReportNotes> {
+    let is_positions = Trading212CSVParser::is_trading212_positions(&content);
+    let data = decode_text(&content);
+    let parser = Trading212CSVParser::new(data, &TRADING212_BROKER);
+    if is_positions {
+        // Positions exports have no date, they're taken as the last completed year.
+        let (balance_notes, errors) = parser.parse_balance_notes(default_year())?;
+        Ok((balance_notes, vec![], None, errors))
+    } else {
+        let (account_notes, errors) = parser.parse_account_notes()?;
+        let year = account_notes
+            .iter()
+            .map(|note| note.date.year() as usize)
+            .max();
+        Ok((vec![], account_notes, year, errors))
+    }
+}
+
+fn read_us_broker_csv(content: Vec<u8>) -> Result<ReportNotes> {
+    let is_positions = UsBrokerCSVParser::is_us_broker_positions(&content);
+    let data = decode_text(&content);
+    let parser = UsBrokerCSVParser::new(data, &US_BROKER);
+    if is_positions {
+        let year = parser.report_year();
+        let (balance_notes, errors) =
+            parser.parse_balance_notes(year.unwrap_or_else(default_year))?;
+        Ok((balance_notes, vec![], year, errors))
+    } else {
+        let (account_notes, errors) = parser.parse_account_notes()?;
+        let year = account_notes
+            .iter()
+            .map(|note| note.date.year() as usize)
+            .max();
+        Ok((vec![], account_notes, year, errors))
+    }
+}
+
+// Transactions without ISIN can't be matched to a declared holding, and deriving holdings
+// from them would bring back the positions reported as missing the ISIN.
+fn read_etoro_xlsx(content: Vec<u8>) -> Result<ReportNotes> {
+    let parser = EtoroParser::new(content, &ETORO_BROKER)?;
+    let (mut account_notes, mut errors) = parser.parse_account_notes()?;
+    account_notes.retain(|note| validate_isin(&note.company.isin));
+    let (balance_notes, balance_errors) = parser.parse_balance_notes()?;
+    errors.extend(balance_errors);
+    Ok((balance_notes, account_notes, parser.report_year(), errors))
+}
+
 pub(crate) fn date_to_usize(year: i32, month: u32, day: u32) -> usize {
     let date = NaiveDate::from_ymd_opt(year, month, day)
         .unwrap()
@@ -98,22 +370,50 @@ pub(crate) fn date_to_usize(year: i32, month: u32, day: u32) -> usize {
     date.parse::<usize>().unwrap_or(0)
 }
 
-fn transform_to_aeat720_records(notes: (BalanceNotes, AccountNotes)) -> Result<Aeat720Records> {
+fn no_records_err_msg(year: Option<usize>) -> String {
+    let period = year.map_or(String::new(), |year| format!(" del año {}", year));
+    format!(
+        "No se ha encontrado ninguna posición ni operación en el informe{}. \
+         Comprueba que es el informe del año a declarar y que incluye las posiciones \
+         abiertas a 31 de diciembre; si es así, puede que su formato aún no esté soportado.",
+        period
+    )
+}
+
+fn transform_to_aeat720_records(notes: ReportNotes) -> Result<(Aeat720Records, ParseErrors)> {
+    let (balance_notes, account_notes, year, mut errors) = notes;
+    // Recognized reports without any note are usually from another year or have a layout
+    // the parsers don't support yet, an empty table wouldn't tell the user what went wrong.
+    if balance_notes.is_empty() && account_notes.is_empty() && errors.is_empty() {
+        log::warn!("No notes found in the report");
+        bail!(no_records_err_msg(year));
+    }
+    let year = year.unwrap_or_else(default_year);
     let mut result = vec![];
 
-    for note in notes.0.iter() {
-        let first_tx_date = {
-            let company = notes.1.iter().find(|&x| x.company == note.company);
-            match company {
-                Some(c) => c.date.format("%Y%m%d").to_string(),
-                None => NaiveDate::from_ymd_opt(DEFAULT_YEAR as i32, 1, 1)
-                    .unwrap()
-                    .format("%Y%m%d")
-                    .to_string(),
-            }
-            .parse::<usize>()
-            .unwrap_or(0)
-        };
+    let balance_notes = if balance_notes.is_empty() && !account_notes.is_empty() {
+        log::info!("No positions found, deriving them from transactions");
+        derive_balances_from_transactions(&account_notes)
+    } else {
+        aggregate_by_isin(balance_notes)
+    };
+
+    for note in balance_notes.iter() {
+        // The 720 declares owned securities, a short position is a debt with the broker.
+        if note.quantity < Decimal::ZERO {
+            errors.push(ParseError::new(
+                0,
+                format!(
+                    "Posición corta de {} ({}) no declarable como valores en el 720",
+                    note.company.name, note.company.isin
+                ),
+            ));
+            continue;
+        }
+        let first_tx_date = first_transaction_date(&account_notes, &note.company)
+            .map_or(DEFAULT_FIRST_TX_DATE, |date| {
+                date_to_usize(date.year(), date.month(), date.day())
+            });
         result.push(Aeat720Record {
             company: note.company.clone(),
             quantity: note.quantity,
@@ -121,30 +421,244 @@ fn transform_to_aeat720_records(notes: (BalanceNotes, AccountNotes)) -> Result<A
             first_tx_date,
             broker: note.broker.clone(),
             percentage: Decimal::new(100, 0),
+            year,
+            product_type: note.product_type,
+            acquisition_type: infer_acquisition_type(&account_notes, &note.company, year),
+            extinction_date: 0,
+            derived: note.derived,
+            co_holder_percentage: Decimal::ZERO,
+            acquisition_in_euro: acquisition_cost(&account_notes, note)
+                .and_then(|cost| fx::to_euro(&cost, &note.currency, year).ok()),
+            account_bic: String::new(),
+            q4_average_in_euro: None,
+            valuation_in_euro: None,
+            original_value: None,
+            issuer_country_code: String::new(),
         })
     }
 
-    Ok(result)
+    Ok((result, errors))
+}
+
+pub(crate) fn is_dividends_statement(content: &[u8]) -> bool {
+    DegiroAccountCSVParser::is_account_csv(content)
+}
+
+pub(crate) fn dividends_importer(content: Vec<u8>) -> Result<(DividendNotes, ParseErrors)> {
+    let data = decode_text(&content);
+    let parser = DegiroAccountCSVParser::new(data, &DEGIRO_BROKER);
+    parser.parse_dividends()
+}
+
+pub(crate) fn is_ib_html(content: &[u8]) -> bool {
+    infer::get(content).is_some_and(|infer_type| infer_type.extension() == "html")
 }
 
-pub(crate) fn file_importer(content: Vec<u8>) -> Result<Aeat720Records> {
+/// Interactive Brokers html statements in two steps: the positions are handed to
+/// `on_positions` as soon as they're parsed, and the transactions, slow to parse in big
+/// statements, after letting the browser show them. Returns the records completed with the
+/// transactions and the errors not reported with the positions.
+pub(crate) async fn ib_html_importer(
+    content: Vec<u8>,
+    on_positions: impl FnOnce(Aeat720Records, ParseErrors),
+) -> Result<(Aeat720Records, ParseErrors)> {
+    let data = match String::from_utf8(content) {
+        Ok(data) => data,
+        Err(_) => bail!("Unable to get string from interactive brokers html content"),
+    };
+    let parser = match IBParser::new(&data, &IB_BROKER) {
+        Ok(parser) => parser,
+        Err(_) => bail!("Unable to parse interactive brokers html"),
+    };
+    let year = parser.report_year();
+    let (balance_notes, mut errors) = parser.parse_balance_notes()?;
+    errors.extend(parser.check_base_currency_total(&balance_notes));
+
+    let mut reported = vec![];
+    if !balance_notes.is_empty() {
+        let (records, positions_errors) =
+            transform_to_aeat720_records((balance_notes.clone(), vec![], year, errors.clone()))?;
+        reported = positions_errors.clone();
+        on_positions(records, positions_errors);
+        web::yield_to_browser().await;
+    }
+
+    let (account_notes, account_errors) = parser.parse_account_notes()?;
+    errors.extend(account_errors);
+    let (records, mut errors) =
+        transform_to_aeat720_records((balance_notes, account_notes, year, errors))?;
+    errors.retain(|error| !reported.contains(error));
+
+    Ok((records, errors))
+}
+
+pub(crate) fn is_pdf(content: &[u8]) -> bool {
+    infer::archive::is_pdf(content)
+}
+
+/// Like [`file_importer`] for broker pdf reports, also returning the dividends per country
+/// of Degiro annual reports. Encrypted reports fail with [`PdfPasswordError`] until the
+/// right password is given.
+pub(crate) fn pdf_importer(
+    content: &[u8],
+    password: Option<&str>,
+) -> Result<(Aeat720Records, ParseErrors, Vec<CountryDividends>)> {
+    let data = read_pdf_text(content, password)?;
+    let dividends = if DegiroParser::is_degiro_report(&data) {
+        DegiroParser::new(data.clone(), &DEGIRO_BROKER).parse_dividends_by_country()
+    } else {
+        vec![]
+    };
+    let (records, errors) = transform_to_aeat720_records(read_broker_report(data)?)?;
+
+    Ok((records, errors, dividends))
+}
+
+pub(crate) fn is_crypto_statement(content: &[u8]) -> bool {
+    CryptoCSVParser::is_crypto_csv(content)
+}
+
+pub(crate) fn crypto_importer(content: Vec<u8>) -> Result<(CryptoBalanceNotes, ParseErrors)> {
+    let data = decode_text(&content);
+    let parser = CryptoCSVParser::new(data);
+    parser.parse_balance_notes()
+}
+
+/// Content pasted from a broker web page or report, the text of a Degiro annual report, a
+/// tab-separated table with an ISIN column or any of the HTML and CSV exports accepted by
+/// [`file_importer`].
+pub(crate) fn text_importer(text: &str) -> Result<(Aeat720Records, ParseErrors)> {
+    if text.trim().is_empty() {
+        bail!("No hay texto para importar");
+    }
+
+    if DegiroParser::is_degiro_report(text) {
+        transform_to_aeat720_records(read_degiro_report(text.to_string())?)
+    } else if TsvParser::is_tsv_table(text) {
+        log::info!("Using pasted table parser");
+        transform_to_aeat720_records(read_tsv_table(text)?)
+    } else {
+        file_importer(text.as_bytes().to_vec())
+    }
+}
+
+pub(crate) fn file_importer(content: Vec<u8>) -> Result<(Aeat720Records, ParseErrors)> {
+    let content = strip_bom(content);
     let file_type = infer::get(&content);
+    log::info!(
+        "Importing file of type {}",
+        file_type.map_or("text", |infer_type| infer_type.extension())
+    );
 
     match file_type {
         Some(infer_type) => match infer_type.extension() {
-            "zip" => file_importer(read_zip(content)?),
+            "zip" if infer::doc::is_xlsx(&content) => {
+                transform_to_aeat720_records(read_etoro_xlsx(content)?)
+            }
+            "zip" => {
+                let mut entries: Vec<Vec<u8>> = read_zip_entries(content)?
+                    .into_iter()
+                    .map(|(_, content)| content)
+                    .collect();
+                if entries.len() == 1 {
+                    file_importer(entries.remove(0))
+                } else if !entries.is_empty()
+                    && entries.iter().all(|entry| infer::text::is_html(entry))
+                {
+                    transform_to_aeat720_records(read_ib_combined_html(entries)?)
+                } else {
+                    bail!(
+                        "We expected one file or IB html statements but the zip file contains {} files",
+                        entries.len()
+                    );
+                }
+            }
+            "xlsx" => transform_to_aeat720_records(read_etoro_xlsx(content)?),
             "html" => transform_to_aeat720_records(read_ib_html(content)?),
-            "pdf" => transform_to_aeat720_records(read_degiro_pdf(content)?),
+            "pdf" => transform_to_aeat720_records(read_broker_pdf(content)?),
             _ => {
                 bail!("{} Infer types not valid", infer_type);
             }
         },
         None => {
-            if content.starts_with("Producto".as_bytes()) {
+            if is_720_file(&content) {
+                log::info!("Using AEAT 720 parser");
+                Ok((parse_720_file(&content)?, vec![]))
+            } else if content.starts_with("Producto".as_bytes()) {
+                log::info!("Using Degiro csv parser");
                 transform_to_aeat720_records(read_degiro_csv(content)?)
+            } else if MyInvestorCSVParser::is_myinvestor_csv(&content) {
+                log::info!("Using MyInvestor csv parser");
+                transform_to_aeat720_records(read_myinvestor_csv(content)?)
+            } else if RevolutCSVParser::is_revolut_statement(&content)
+                || RevolutCSVParser::is_revolut_positions(&content)
+            {
+                log::info!("Using Revolut csv parser");
+                transform_to_aeat720_records(read_revolut_csv(content)?)
+            } else if Trading212CSVParser::is_trading212_csv(&content) {
+                log::info!("Using Trading 212 csv parser");
+                transform_to_aeat720_records(read_trading212_csv(content)?)
+            } else if UsBrokerCSVParser::is_us_broker_csv(&content) {
+                log::info!("Using US broker csv parser");
+                transform_to_aeat720_records(read_us_broker_csv(content)?)
             } else {
+                log::info!("Using Interactive Brokers csv parser");
                 transform_to_aeat720_records(read_ib_csv(content)?)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::ProductType;
+
+    #[test]
+    fn test_default_year_is_last_completed_year() {
+        pin_today(NaiveDate::from_ymd_opt(2025, 1, 1));
+        assert_eq!(default_year(), 2024);
+        pin_today(NaiveDate::from_ymd_opt(2025, 12, 31));
+        assert_eq!(default_year(), 2024);
+        pin_today(None);
+    }
+
+    #[test]
+    fn test_today_follows_clock_unless_pinned() {
+        pin_today(NaiveDate::from_ymd_opt(2023, 6, 30));
+        assert_eq!(today(), NaiveDate::from_ymd_opt(2023, 6, 30).unwrap());
+        pin_today(None);
+        assert_eq!(today(), Local::now().date_naive());
+    }
+
+    #[test]
+    fn test_read_degiro_report_cash_accounts() {
+        let report = include_str!("../parsers/testdata/degiro_columns_test.txt");
+        let accounts = |notes: &BalanceNotes| {
+            notes
+                .iter()
+                .filter(|note| note.product_type == ProductType::Account)
+                .map(|note| (note.company.name.clone(), note.value_in_euro))
+                .collect::<Vec<_>>()
+        };
+
+        let (notes, _, _, errors) = read_degiro_report(report.to_string()).unwrap();
+        assert_eq!(
+            accounts(&notes),
+            [(String::from("DEGIRO"), Decimal::new(1_203_45, 2))]
+        );
+        assert!(errors
+            .iter()
+            .any(|error| error.reason.starts_with("Fondo de efectivo de Degiro")));
+
+        let flatex = "\nInforme anual de flatex\n\nCuenta de Efectivo en flatex asociada a su cuenta de DEGIRO.\n\nIBAN: DE89 3704 0044 0532 0130 00\nBalance total 1.203,45 EUR\n";
+        let (notes, _, _, errors) = read_degiro_report(format!("{}{}", report, flatex)).unwrap();
+        assert_eq!(
+            accounts(&notes),
+            [(String::from("FLATEX BANK"), Decimal::new(1_203_45, 2))]
+        );
+        assert!(!errors
+            .iter()
+            .any(|error| error.reason.starts_with("Fondo de efectivo de Degiro")));
+    }
+}
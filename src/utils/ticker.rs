@@ -0,0 +1,249 @@
+use crate::data::{validate_isin, Aeat720Record, CompanyInfo, ProductType};
+
+/// ISIN of a ticker, optionally limited to a listing exchange.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TickerIsin {
+    pub ticker: String,
+    pub exchange: Option<String>,
+    pub isin: String,
+}
+
+// Listings usually held by Spanish residents by ticker and IB exchange code, with the
+// company name for the brokers that only report tickers, like Revolut.
+const TICKERS: [(&str, &str, &str, &str); 28] = [
+    ("AAPL", "NASDAQ", "APPLE INC", "US0378331005"),
+    (
+        "AMD",
+        "NASDAQ",
+        "ADVANCED MICRO DEVICES INC",
+        "US0079031078",
+    ),
+    ("AMZN", "NASDAQ", "AMAZON.COM INC", "US0231351067"),
+    ("BABA", "NYSE", "ALIBABA GROUP HOLDING LTD", "US01609W1027"),
+    ("BRK.B", "NYSE", "BERKSHIRE HATHAWAY INC", "US0846707026"),
+    (
+        "CSPX",
+        "LSEETF",
+        "ISHARES CORE S&P 500 UCITS ETF",
+        "IE00B5BMR087",
+    ),
+    ("DIS", "NYSE", "WALT DISNEY CO", "US2546871060"),
+    ("GOOG", "NASDAQ", "ALPHABET INC", "US02079K1079"),
+    ("GOOGL", "NASDAQ", "ALPHABET INC", "US02079K3059"),
+    ("INTC", "NASDAQ", "INTEL CORP", "US4581401001"),
+    (
+        "IWDA",
+        "AEB",
+        "ISHARES CORE MSCI WORLD UCITS ETF",
+        "IE00B4L5Y983",
+    ),
+    ("JNJ", "NYSE", "JOHNSON & JOHNSON", "US4781601046"),
+    ("JPM", "NYSE", "JPMORGAN CHASE & CO", "US46625H1005"),
+    ("KO", "NYSE", "COCA-COLA CO", "US1912161007"),
+    ("MA", "NYSE", "MASTERCARD INC", "US57636Q1040"),
+    ("META", "NASDAQ", "META PLATFORMS INC", "US30303M1027"),
+    ("MSFT", "NASDAQ", "MICROSOFT CORP", "US5949181045"),
+    ("NFLX", "NASDAQ", "NETFLIX INC", "US64110L1061"),
+    ("NVDA", "NASDAQ", "NVIDIA CORP", "US67066G1040"),
+    ("PG", "NYSE", "PROCTER & GAMBLE CO", "US7427181091"),
+    ("PYPL", "NASDAQ", "PAYPAL HOLDINGS INC", "US70450Y1038"),
+    ("SPY", "ARCA", "SPDR S&P 500 ETF TRUST", "US78462F1030"),
+    ("TSLA", "NASDAQ", "TESLA INC", "US88160R1014"),
+    ("V", "NYSE", "VISA INC", "US92826C8394"),
+    ("VOO", "ARCA", "VANGUARD S&P 500 ETF", "US9229083632"),
+    ("VUSA", "AEB", "VANGUARD S&P 500 UCITS ETF", "IE00B3XXRP09"),
+    (
+        "VWCE",
+        "IBIS2",
+        "VANGUARD FTSE ALL-WORLD UCITS ETF",
+        "IE00BK5BQT80",
+    ),
+    ("WMT", "NYSE", "WALMART INC", "US9311421039"),
+];
+
+/// Brokers write share classes differently, `BRK B` in IB, `BRK.B` in Revolut or `BRK/B`,
+/// they are all compared as `BRK.B`.
+pub fn normalize_ticker(ticker: &str) -> String {
+    ticker
+        .trim()
+        .to_uppercase()
+        .split(|c: char| c.is_whitespace() || matches!(c, '.' | '/'))
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Mapping pasted by the user, one `TICKER,ISIN` or `TICKER:EXCHANGE,ISIN` per line.
+/// Lines without a valid ISIN are skipped.
+pub fn parse_ticker_mapping(text: &str) -> Vec<TickerIsin> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let Some((listing, isin)) = line.split_once(|c: char| matches!(c, ',' | ';' | '\t'))
+            else {
+                log::warn!("Skipping ticker mapping without ISIN: {}", line);
+                return None;
+            };
+            let isin = isin.trim().to_uppercase();
+            if !validate_isin(&isin) {
+                log::warn!("Skipping ticker mapping with a not valid ISIN: {}", line);
+                return None;
+            }
+            let (ticker, exchange) = match listing.split_once(':') {
+                Some((ticker, exchange)) => (ticker, Some(exchange.trim().to_uppercase())),
+                None => (listing, None),
+            };
+
+            Some(TickerIsin {
+                ticker: normalize_ticker(ticker),
+                exchange,
+                isin,
+            })
+        })
+        .collect()
+}
+
+fn embedded_mapping() -> impl Iterator<Item = TickerIsin> {
+    TICKERS
+        .iter()
+        .map(|(ticker, exchange, _, isin)| TickerIsin {
+            ticker: ticker.to_string(),
+            exchange: Some(exchange.to_string()),
+            isin: isin.to_string(),
+        })
+}
+
+/// ISIN of the ticker, the user mapping takes precedence over the embedded one. Without an
+/// exchange the ticker must resolve to a single ISIN.
+pub fn lookup_isin(ticker: &str, exchange: Option<&str>, mapping: &[TickerIsin]) -> Option<String> {
+    let ticker = normalize_ticker(ticker);
+    let matches = |entry: &TickerIsin| {
+        entry.ticker == ticker
+            && match (exchange, &entry.exchange) {
+                (Some(exchange), Some(entry_exchange)) => {
+                    exchange.eq_ignore_ascii_case(entry_exchange)
+                }
+                _ => true,
+            }
+    };
+
+    [mapping.to_vec(), embedded_mapping().collect()]
+        .iter()
+        .find_map(|entries| {
+            let mut isins: Vec<&str> = entries
+                .iter()
+                .filter(|entry| matches(entry))
+                .map(|entry| entry.isin.as_str())
+                .collect();
+            isins.sort_unstable();
+            isins.dedup();
+            match isins[..] {
+                [isin] => Some(isin.to_string()),
+                [] => None,
+                _ => {
+                    log::warn!("Ticker {} matches several ISINs: {:?}", ticker, isins);
+                    None
+                }
+            }
+        })
+        .filter(|isin| validate_isin(isin))
+}
+
+/// Company of a ticker in the embedded table, for reports without names nor ISINs.
+pub fn embedded_company(ticker: &str) -> Option<CompanyInfo> {
+    let ticker = normalize_ticker(ticker);
+    TICKERS
+        .iter()
+        .find(|(symbol, _, _, _)| *symbol == ticker)
+        .map(|(_, _, name, isin)| CompanyInfo::new(name.to_string(), isin.to_string()))
+}
+
+/// IB positions without contract information carry the ticker as name and no ISIN.
+pub fn fill_missing_isins(records: &mut [Aeat720Record], mapping: &[TickerIsin]) {
+    for record in records {
+        if record.product_type == ProductType::Account || validate_isin(&record.company.isin) {
+            continue;
+        }
+        if let Some(isin) = lookup_isin(&record.company.name, None, mapping) {
+            log::info!("Filling ISIN of {:?} with {}", record.company.name, isin);
+            record.company.isin = isin;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_isins_are_valid() {
+        for (ticker, _, _, isin) in TICKERS {
+            assert!(validate_isin(isin), "{} {}", ticker, isin);
+            assert_eq!(normalize_ticker(ticker), ticker);
+        }
+    }
+
+    #[test]
+    fn test_normalize_ticker() {
+        assert_eq!(normalize_ticker(" brk b "), "BRK.B");
+        assert_eq!(normalize_ticker("BRK.B"), "BRK.B");
+        assert_eq!(normalize_ticker("BRK/B"), "BRK.B");
+        assert_eq!(normalize_ticker("AAPL"), "AAPL");
+        assert_eq!(
+            lookup_isin("BRK B", Some("NYSE"), &[]),
+            Some(String::from("US0846707026"))
+        );
+        assert_eq!(
+            embedded_company("BRK.B"),
+            Some(CompanyInfo::new(
+                String::from("BERKSHIRE HATHAWAY INC"),
+                String::from("US0846707026")
+            ))
+        );
+        assert_eq!(embedded_company("UNKNOWN"), None);
+    }
+
+    #[test]
+    fn test_parse_ticker_mapping() {
+        let mapping = parse_ticker_mapping(
+            "ila, us0378331005\n\nRIO:LSE;GB0007188757\nBAD,US0378331006\nnoisin\n",
+        );
+
+        assert_eq!(
+            mapping,
+            vec![
+                TickerIsin {
+                    ticker: String::from("ILA"),
+                    exchange: None,
+                    isin: String::from("US0378331005"),
+                },
+                TickerIsin {
+                    ticker: String::from("RIO"),
+                    exchange: Some(String::from("LSE")),
+                    isin: String::from("GB0007188757"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lookup_isin() {
+        let mapping = parse_ticker_mapping("DUP:A,GB0007188757\nDUP:B,US5949181045");
+
+        assert_eq!(
+            lookup_isin("aapl", None, &[]),
+            Some(String::from("US0378331005"))
+        );
+        assert_eq!(
+            lookup_isin("VWCE", Some("ibis2"), &[]),
+            Some(String::from("IE00BK5BQT80"))
+        );
+        assert_eq!(lookup_isin("VWCE", Some("AEB"), &[]), None);
+        assert_eq!(
+            lookup_isin("DUP", Some("B"), &mapping),
+            Some(String::from("US5949181045"))
+        );
+        assert_eq!(lookup_isin("DUP", None, &mapping), None);
+        assert_eq!(lookup_isin("UNKNOWN", None, &mapping), None);
+    }
+}
@@ -1,9 +1,171 @@
-use crate::{data::Aeat720Information, reports::aeat_720::Aeat720Report};
+use crate::{
+    data::Aeat720Information,
+    reports::aeat_720::{preview_html, Aeat720Report, LineEnding},
+    utils::{batch::batch_archive, zip::write_zip},
+};
 
-use anyhow::{bail, Result};
-use js_sys::{Array, Uint8Array};
-use wasm_bindgen::JsValue;
-use web_sys::{Blob, BlobPropertyBag, Url};
+use std::{
+    collections::VecDeque,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::{anyhow, bail, Result};
+use futures_signals::signal::{Mutable, Signal};
+use js_sys::{Array, Function, Promise, Reflect, Uint8Array};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{console, Blob, BlobPropertyBag, Url};
+
+const DIAGNOSTICS_MAX_LINES: usize = 1000;
+const REDACTED: &str = "***";
+
+/// Masks what could identify the user or their holdings before a line leaves the browser:
+/// quoted text, so names are logged with `{:?}`, and any word with digits, like ISINs,
+/// IBANs, NIFs, quantities or amounts.
+fn redact(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut word = String::new();
+    let mut chars = line.chars();
+    let flush = |word: &mut String, result: &mut String| {
+        if word.chars().any(|c| c.is_ascii_digit()) {
+            result.push_str(REDACTED);
+        } else {
+            result.push_str(word);
+        }
+        word.clear();
+    };
+
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() {
+            word.push(c);
+            continue;
+        }
+        flush(&mut word, &mut result);
+        result.push(c);
+        if c == '"' {
+            result.push_str(REDACTED);
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => {
+                        chars.next();
+                    }
+                    '"' => {
+                        result.push(c);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    flush(&mut word, &mut result);
+
+    result
+}
+
+/// Writes the logs of the crate to the browser console and keeps the last ones, so users can
+/// attach them to an issue when an import fails.
+struct DiagnosticsLogger {
+    lines: Mutex<VecDeque<String>>,
+}
+
+impl Log for DiagnosticsLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= Level::Debug && metadata.target().starts_with(env!("CARGO_PKG_NAME"))
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!("{} {}: {}", record.level(), record.target(), record.args());
+        let message = JsValue::from_str(&line);
+        match record.level() {
+            Level::Error => console::error_1(&message),
+            Level::Warn => console::warn_1(&message),
+            Level::Info => console::info_1(&message),
+            Level::Debug | Level::Trace => console::debug_1(&message),
+        }
+        if let Ok(mut lines) = self.lines.lock() {
+            if lines.len() == DIAGNOSTICS_MAX_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(redact(&line));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: DiagnosticsLogger = DiagnosticsLogger {
+    lines: Mutex::new(VecDeque::new()),
+};
+
+pub fn init_logger() {
+    match log::set_logger(&LOGGER) {
+        Ok(()) => log::set_max_level(LevelFilter::Debug),
+        Err(err) => console::error_1(&JsValue::from_str(&err.to_string())),
+    }
+}
+
+const SERVICE_WORKER_PATH: &str = "sw.js";
+
+static OFFLINE: LazyLock<Mutable<bool>> = LazyLock::new(|| Mutable::new(false));
+
+/// Registers the service worker caching the app files so it loads without network, and
+/// starts following the connection state shown by [`is_offline`].
+pub fn init_offline_support() {
+    let window = gloo_utils::window();
+    OFFLINE.set_neq(!window.navigator().on_line());
+    for (event, offline) in [("online", false), ("offline", true)] {
+        let callback = Closure::<dyn FnMut()>::new(move || OFFLINE.set_neq(offline));
+        if let Err(err) =
+            window.add_event_listener_with_callback(event, callback.as_ref().unchecked_ref())
+        {
+            log::error!("Unable to listen to {} events: {:?}", event, err);
+        }
+        callback.forget();
+    }
+
+    spawn_local(async {
+        if let Err(err) = register_service_worker().await {
+            log::warn!("Service worker not registered: {}", err);
+        }
+    });
+}
+
+/// Service workers are missing in insecure contexts and in old browsers, so the API is looked
+/// up at runtime like the clipboard.
+async fn register_service_worker() -> Result<()> {
+    let navigator = gloo_utils::window().navigator();
+    let service_worker = Reflect::get(&navigator, &JsValue::from_str("serviceWorker"))
+        .map_err(|err| anyhow!("Unable to get service worker container: {:?}", err))?;
+    if service_worker.is_undefined() {
+        bail!("Service worker API not available");
+    }
+    let register: Function = Reflect::get(&service_worker, &JsValue::from_str("register"))
+        .ok()
+        .and_then(|register| register.dyn_into().ok())
+        .ok_or_else(|| anyhow!("Service worker register not available"))?;
+    let promise: Promise = register
+        .call1(&service_worker, &JsValue::from_str(SERVICE_WORKER_PATH))
+        .ok()
+        .and_then(|promise| promise.dyn_into().ok())
+        .ok_or_else(|| anyhow!("Unable to register service worker"))?;
+    JsFuture::from(promise)
+        .await
+        .map_err(|err| anyhow!("Unable to register service worker: {:?}", err))?;
+    log::debug!("Service worker registered");
+
+    Ok(())
+}
+
+/// Whether the browser has no network. Every import and form is generated locally, so this
+/// only tells users they can keep working.
+pub fn is_offline() -> impl Signal<Item = bool> {
+    OFFLINE.signal()
+}
 
 pub fn delete_path(path: String) -> Result<()> {
     if let Err(err) = Url::revoke_object_url(&path) {
@@ -14,41 +176,234 @@ pub fn delete_path(path: String) -> Result<()> {
     Ok(())
 }
 
-pub fn generate_720(info: &Aeat720Information) -> Result<String> {
-    let result;
-    let aeat720report = match Aeat720Report::new(info) {
-        Ok(report) => report,
+const OCTET_STREAM_TYPE: &str = "application/octet-stream";
+const HTML_TYPE: &str = "text/html;charset=utf-8";
+const TEXT_TYPE: &str = "text/plain;charset=utf-8";
+
+fn create_blob_url(content: &[u8], content_type: &str) -> Result<String> {
+    let blob_properties = BlobPropertyBag::new();
+    blob_properties.set_type(content_type);
+    let content_array = Array::new_with_length(1);
+    content_array.set(0, JsValue::from(Uint8Array::from(content)));
+
+    let blob = Blob::new_with_u8_array_sequence_and_options(
+        &JsValue::from(content_array),
+        &blob_properties,
+    );
+    match blob {
+        Ok(blob_data) => Ok(Url::create_object_url_with_blob(&blob_data).unwrap()),
+        Err(err) => {
+            log::error!("Unable to generate blob: {:?}", err);
+            bail!("Unable to generate blob");
+        }
+    }
+}
+
+fn generate_720_report(info: &Aeat720Information) -> Result<Aeat720Report> {
+    match Aeat720Report::new(info) {
+        Ok(report) => Ok(report),
         Err(err) => {
             log::error!("Unable to generate Aeat720 report: {}", err);
             bail!("unable to create AEAT 720 report");
         }
-    };
-    match aeat720report.generate() {
-        Ok(aeat720_form) => {
-            let blob_properties = BlobPropertyBag::new();
-            blob_properties.set_type("application/octet-stream");
-            let aeat720_array = Array::new_with_length(1);
-            aeat720_array.set(0, JsValue::from(Uint8Array::from(&aeat720_form[..])));
-
-            let blob = Blob::new_with_u8_array_sequence_and_options(
-                &JsValue::from(aeat720_array),
-                &blob_properties,
-            );
-            match blob {
-                Ok(blob_data) => {
-                    result = Url::create_object_url_with_blob(&blob_data).unwrap();
-                }
-                Err(err) => {
-                    log::error!("Unable to generate aeat 720 form: {:?}", err);
-                    bail!("Unable to generate AEAT 720 form blob");
-                }
-            }
-        }
+    }
+}
+
+fn generate_720_form(info: &Aeat720Information, line_ending: LineEnding) -> Result<Vec<u8>> {
+    let aeat720report = generate_720_report(info)?;
+    match aeat720report.generate(line_ending) {
+        Ok(aeat720_form) => Ok(aeat720_form),
         Err(err) => {
             log::error!("Unable to generate Aeat 720 report: {}", err);
             bail!("Unable to generate AEAT 720 from data")
         }
     }
+}
+
+const AEAT_720_FILE_NAME: &str = "fichero-720.txt";
+const AEAT_720_FILES_ZIP_NAME: &str = "ficheros-720.zip";
+
+// One 720 form per declarant, named after their NIF.
+fn generate_720_forms(
+    info: &Aeat720Information,
+    line_ending: LineEnding,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    info.declarations()
+        .iter()
+        .map(|declaration| {
+            Ok((
+                format!("fichero-720-{}.txt", declaration.personal_info.nif),
+                generate_720_form(declaration, line_ending)?,
+            ))
+        })
+        .collect()
+}
+
+/// The 720 form to submit and its file name. With a second holder there's a form per
+/// declarant, zipped like in [`generate_archive`].
+pub fn generate_720(
+    info: &Aeat720Information,
+    line_ending: LineEnding,
+) -> Result<(String, &'static str)> {
+    if info.co_holder.is_none() {
+        let path = create_blob_url(&generate_720_form(info, line_ending)?, OCTET_STREAM_TYPE)?;
+        return Ok((path, AEAT_720_FILE_NAME));
+    }
+
+    let files = generate_720_forms(info, line_ending)?;
+    let files: Vec<(&str, Vec<u8>)> = files
+        .iter()
+        .map(|(name, content)| (name.as_str(), content.clone()))
+        .collect();
+    let path = create_blob_url(&write_zip(&files)?, OCTET_STREAM_TYPE)?;
+
+    Ok((path, AEAT_720_FILES_ZIP_NAME))
+}
+
+/// Records to double check before submitting, see [`Aeat720Report::warnings`].
+pub fn generate_720_warnings(info: &Aeat720Information) -> Result<Vec<String>> {
+    Ok(generate_720_report(info)?.warnings().to_vec())
+}
+
+/// Readable HTML version of the 720 file to proofread it before submitting.
+pub fn generate_720_preview(info: &Aeat720Information) -> Result<String> {
+    let aeat720report = generate_720_report(info)?;
+
+    create_blob_url(preview_html(&aeat720report).as_bytes(), HTML_TYPE)
+}
+
+/// Bundles one 720 form per declarant and the session data into a zip file to archive the year.
+pub fn generate_archive(info: &Aeat720Information, line_ending: LineEnding) -> Result<String> {
+    let mut files = generate_720_forms(info, line_ending)?;
+    files.push((
+        String::from("sesion.json"),
+        serde_json::to_vec_pretty(info)?,
+    ));
+    let files: Vec<(&str, Vec<u8>)> = files
+        .iter()
+        .map(|(name, content)| (name.as_str(), content.clone()))
+        .collect();
+
+    create_blob_url(&write_zip(&files)?, OCTET_STREAM_TYPE)
+}
+
+/// The 720 forms of an advisor's clients, see [`batch_archive`].
+pub fn generate_batch_archive(content: Vec<u8>) -> Result<String> {
+    create_blob_url(&batch_archive(content)?, OCTET_STREAM_TYPE)
+}
+
+/// Text file with the last error shown and the captured logs, which include the detected file
+/// type and the parser used in each import, both redacted as they may quote the user's data.
+pub fn generate_diagnostics(error: &str) -> Result<String> {
+    let lines = match LOGGER.lines.lock() {
+        Ok(lines) => lines.iter().cloned().collect::<Vec<_>>().join("\n"),
+        Err(_) => bail!("Unable to read captured logs"),
+    };
+    let user_agent = gloo_utils::window()
+        .navigator()
+        .user_agent()
+        .unwrap_or_default();
+    let content = format!(
+        "burocratin {}\n{}\n\nError: {}\n\n{}\n",
+        env!("CARGO_PKG_VERSION"),
+        user_agent,
+        redact(error),
+        lines
+    );
+
+    create_blob_url(content.as_bytes(), TEXT_TYPE)
+}
+
+/// Preferences kept between visits, None when the browser doesn't allow storage.
+pub fn load_setting(key: &str) -> Option<String> {
+    gloo_utils::window()
+        .local_storage()
+        .ok()
+        .flatten()
+        .and_then(|storage| storage.get_item(key).ok().flatten())
+}
+
+pub fn save_setting(key: &str, value: &str) {
+    match gloo_utils::window().local_storage() {
+        Ok(Some(storage)) => {
+            if let Err(err) = storage.set_item(key, value) {
+                log::error!("Unable to save setting {}: {:?}", key, err);
+            }
+        }
+        _ => log::warn!("Local storage not available, setting {} not saved", key),
+    }
+}
 
-    Ok(result)
+/// Copies the text with the asynchronous clipboard API, missing in insecure contexts and in
+/// old browsers. It's looked up at runtime to tell those cases apart.
+pub async fn copy_to_clipboard(text: &str) -> Result<()> {
+    let navigator = gloo_utils::window().navigator();
+    let clipboard = Reflect::get(&navigator, &JsValue::from_str("clipboard"))
+        .map_err(|err| anyhow!("Unable to get clipboard: {:?}", err))?;
+    if clipboard.is_undefined() {
+        bail!("Clipboard API not available");
+    }
+    let write_text: Function = Reflect::get(&clipboard, &JsValue::from_str("writeText"))
+        .ok()
+        .and_then(|write_text| write_text.dyn_into().ok())
+        .ok_or_else(|| anyhow!("Clipboard writeText not available"))?;
+    let promise: Promise = write_text
+        .call1(&clipboard, &JsValue::from_str(text))
+        .ok()
+        .and_then(|promise| promise.dyn_into().ok())
+        .ok_or_else(|| anyhow!("Unable to write to clipboard"))?;
+    JsFuture::from(promise)
+        .await
+        .map_err(|err| anyhow!("Unable to write to clipboard: {:?}", err))?;
+
+    Ok(())
+}
+
+/// Asks for the password of an encrypted report, None when the user cancels.
+pub fn prompt_password(reason: &str) -> Option<String> {
+    let message = format!(
+        "{}. Introduce la contraseña, en Degiro suele ser tu número de cliente:",
+        reason
+    );
+    gloo_utils::window()
+        .prompt_with_message(&message)
+        .ok()
+        .flatten()
+}
+
+/// Lets the browser repaint before running blocking work on the main thread.
+pub async fn yield_to_browser() {
+    let promise = Promise::new(&mut |resolve, _| {
+        if let Err(err) = gloo_utils::window().set_timeout_with_callback(&resolve) {
+            log::error!("Unable to set timeout: {:?}", err);
+            let _ = resolve.call0(&JsValue::NULL);
+        }
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact("INFO burocratin::utils: Using Degiro csv parser"),
+            "INFO burocratin::utils: Using Degiro csv parser"
+        );
+        assert_eq!(
+            redact("WARN burocratin::data: Skipping line 12: \"APPLE INC,US0378331005,10\""),
+            "WARN burocratin::data: Skipping line ***: \"***\""
+        );
+        assert_eq!(
+            redact("Adding foreign bank account DE89370400440532013000 of 1.742,35 EUR"),
+            "Adding foreign bank account *** of ***.***,*** EUR"
+        );
+        assert_eq!(
+            redact(r#"field values: ["JUAN \"JR\"", "12345678Z"]"#),
+            r#"field values: ["***", "***"]"#
+        );
+        assert_eq!(redact("unterminated \"JUAN"), "unterminated \"***");
+    }
 }
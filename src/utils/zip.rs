@@ -1,23 +1,23 @@
 use anyhow::{bail, Result};
-use std::io::{Cursor, Read};
-use zip::ZipArchive;
+use std::io::{Cursor, Read, Write};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
 
-pub fn read_zip(data: Vec<u8>) -> Result<Vec<u8>> {
+/// Every file of the archive with its name, directories are skipped.
+pub fn read_zip_entries(data: Vec<u8>) -> Result<Vec<(String, Vec<u8>)>> {
     let mut archive = ZipArchive::new(Cursor::new(data))?;
+    let mut entries = Vec::with_capacity(archive.len());
 
-    if archive.len() != 1 {
-        bail!(
-            "We expected one file but the zip file contains {} files",
-            archive.len()
-        );
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        if file.is_dir() {
+            continue;
+        }
+        let mut contents = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut contents)?;
+        entries.push((file.name().to_string(), contents));
     }
 
-    let mut file = archive.by_index(0)?;
-    let mut contents = Vec::with_capacity(file.size() as usize);
-
-    file.read_exact(&mut contents)?;
-
-    Ok(contents)
+    Ok(entries)
 }
 
 #[allow(dead_code)]
@@ -38,3 +38,59 @@ pub fn read_zip_str(data: Vec<u8>) -> Result<String> {
 
     Ok(contents)
 }
+
+pub fn write_zip(files: &[(&str, Vec<u8>)]) -> Result<Vec<u8>> {
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (name, content) in files {
+        archive.start_file(*name, options)?;
+        archive.write_all(content)?;
+    }
+
+    Ok(archive.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_zip() {
+        let data = write_zip(&[
+            ("fichero-720.txt", b"720 form".to_vec()),
+            ("sesion.json", b"{}".to_vec()),
+        ])
+        .unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+
+        assert_eq!(archive.len(), 2);
+        let mut contents = String::new();
+        archive
+            .by_name("fichero-720.txt")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "720 form");
+
+        let data = write_zip(&[("fichero-720.txt", b"720 form".to_vec())]).unwrap();
+        assert_eq!(read_zip_str(data).unwrap(), "720 form");
+    }
+
+    #[test]
+    fn test_read_zip_entries() {
+        let data = write_zip(&[
+            ("clientes.json", b"[]".to_vec()),
+            ("cliente1/degiro.csv", b"Producto".to_vec()),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            read_zip_entries(data).unwrap(),
+            vec![
+                (String::from("clientes.json"), b"[]".to_vec()),
+                (String::from("cliente1/degiro.csv"), b"Producto".to_vec()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,49 @@
+// Keeps a copy of the app files so burocratin loads without network. Reports and forms never
+// leave the browser, only the files of the app itself are cached.
+const CACHE_NAME = "burocratin-v1";
+const APP_SHELL = [
+  "./",
+  "index.html",
+  "css/main.css",
+  "js/index.js",
+  "img/favicon.ico",
+  "img/burocratin.svg",
+  "img/degiro.svg",
+  "img/interactive_brokers.svg",
+];
+
+self.addEventListener("install", (event) => {
+  event.waitUntil(
+    caches.open(CACHE_NAME).then((cache) => cache.addAll(APP_SHELL)),
+  );
+});
+
+self.addEventListener("activate", (event) => {
+  event.waitUntil(
+    caches.keys().then((names) =>
+      Promise.all(
+        names.filter((name) => name !== CACHE_NAME).map((name) => caches.delete(name)),
+      ),
+    ),
+  );
+});
+
+// Network first so new versions are picked up, the cache is the fallback when offline.
+// The wasm module has a hashed name, so it's cached the first time it's fetched.
+self.addEventListener("fetch", (event) => {
+  const url = new URL(event.request.url);
+  if (event.request.method !== "GET" || url.origin !== self.location.origin) {
+    return;
+  }
+  event.respondWith(
+    fetch(event.request)
+      .then((response) => {
+        if (response.ok) {
+          const copy = response.clone();
+          caches.open(CACHE_NAME).then((cache) => cache.put(event.request, copy));
+        }
+        return response;
+      })
+      .catch(() => caches.match(event.request)),
+  );
+});